@@ -0,0 +1,54 @@
+//! Removing ANSI escape sequences (colors, cursor movement, etc.) from output
+use std::borrow::Cow;
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+
+/// Returns `text` without any ANSI escape sequences, borrowing it if there weren't any
+///
+/// This handles CSI sequences (`ESC [ ... m` for colors, cursor movement, clearing, etc.), OSC sequences (`ESC ] ...`,
+/// e.g. window titles and hyperlinks) ended by BEL or `ESC \`, and two-character escapes like `ESC 7`. An unfinished
+/// sequence at the end of the text is removed as well.
+pub(crate) fn strip(text: &str) -> Cow<'_, str> {
+    if !text.contains(ESC) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != ESC {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters and intermediates, then a final byte from '@' to '~'
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC (and the other string sequences): until BEL or ST (`ESC \`)
+            Some(']' | 'P' | 'X' | '^' | '_') => {
+                while let Some(c) = chars.next() {
+                    if c == BEL {
+                        break;
+                    }
+                    if c == ESC && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // character set selection takes one more character, e.g. `ESC ( B`
+            Some('(' | ')' | '*' | '+') => {
+                chars.next();
+            }
+            // anything else is a two-character escape
+            _ => {}
+        }
+    }
+    return Cow::Owned(stripped);
+}
@@ -0,0 +1,218 @@
+//! Exporting the transcripts of a batch of commands as one archive
+use crate::{CmdOutput, KillReason};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Collects the output of several related commands, to be written as one archive (e.g. to attach to a failed CI run)
+///
+/// The archive contains:
+/// - `index.json`, listing every command with its status, timings (relative to when the first one started), and what
+///   it depended on
+/// - for each command, a directory with `transcript.txt` (every line, with its offset and stream) and `metadata.json`
+///
+/// It can be written as a directory with [`TranscriptArchive::write_dir`], or a tar file with
+/// [`TranscriptArchive::write_tar`].
+///
+/// ```
+/// use better_commands::{run, TranscriptArchive};
+/// use std::process::Command;
+///
+/// let build = run(Command::new("echo").arg("building"));
+/// let test = run(Command::new("echo").arg("testing"));
+///
+/// let mut archive = TranscriptArchive::new();
+/// archive.add("build", &build, &[]);
+/// archive.add("test", &test, &["build"]);
+///
+/// # let path = std::env::temp_dir().join("better-commands-archive-doctest.tar");
+/// archive.write_tar(std::fs::File::create(&path).unwrap()).unwrap();
+/// # std::fs::remove_file(path).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TranscriptArchive {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    output: CmdOutput,
+    depends_on: Vec<String>,
+}
+
+impl TranscriptArchive {
+    /// Creates an empty archive
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Adds a command's output, along with the names of the commands it depended on
+    pub fn add(&mut self, name: &str, output: &CmdOutput, depends_on: &[&str]) {
+        self.entries.push(Entry {
+            name: name.to_string(),
+            output: output.clone(),
+            depends_on: depends_on.iter().map(|name| name.to_string()).collect(),
+        });
+    }
+
+    /// Writes the archive as a directory at `path`, creating it if needed
+    pub fn write_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        for (name, contents) in self.files() {
+            let path = path.as_ref().join(name);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)?;
+        }
+        return Ok(());
+    }
+
+    /// Writes the archive as an (uncompressed) tar file
+    pub fn write_tar<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        for (name, contents) in self.files() {
+            writer.write_all(&tar_header(&name, contents.len(), mtime))?;
+            writer.write_all(&contents)?;
+            // entries are padded to a multiple of 512 bytes
+            writer.write_all(&vec![0; (512 - contents.len() % 512) % 512])?;
+        }
+        // the end of the archive is marked by two empty blocks
+        writer.write_all(&[0; 1024])?;
+        return writer.flush();
+    }
+
+    /// Returns the path and contents of every file in the archive
+    fn files(&self) -> Vec<(String, Vec<u8>)> {
+        let first_start = self
+            .entries
+            .iter()
+            .map(|entry| entry.output.start_time)
+            .min();
+        let mut files = Vec::new();
+        let mut index = Vec::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            let dir = format!("{:02}-{}", i + 1, sanitize(&entry.name));
+            let started = first_start.map_or(Duration::ZERO, |first| {
+                entry.output.start_time.duration_since(first)
+            });
+            let metadata = metadata_json(entry, started, &dir);
+            files.push((format!("{}/transcript.txt", dir), transcript(&entry.output)));
+            files.push((
+                format!("{}/metadata.json", dir),
+                metadata.clone().into_bytes(),
+            ));
+            index.push(metadata);
+        }
+        let index = format!(
+            "{{\n  \"commands\": [\n    {}\n  ]\n}}\n",
+            index.join(",\n    ")
+        );
+        files.insert(0, ("index.json".to_string(), index.into_bytes()));
+        return files;
+    }
+}
+
+/// Formats every line as `[+1.234s] [stdout] content`
+fn transcript(output: &CmdOutput) -> Vec<u8> {
+    let mut transcript = String::new();
+    for line in output {
+        let _ = writeln!(
+            transcript,
+            "[+{:.3}s] [{}] {}",
+            line.offset.as_secs_f64(),
+            line.printed_to,
+            line.content
+        );
+    }
+    return transcript.into_bytes();
+}
+
+fn metadata_json(entry: &Entry, started: Duration, dir: &str) -> String {
+    let output = &entry.output;
+    let depends_on: Vec<String> = entry
+        .depends_on
+        .iter()
+        .map(|name| json_string(name))
+        .collect();
+    let kill_reason = match output.kill_reason {
+        Some(KillReason::Timeout) => "\"timeout\"",
+        Some(KillReason::OutputLimit) => "\"output_limit\"",
+        Some(KillReason::PatternMatched) => "\"pattern_matched\"",
+        Some(KillReason::LineTooLong) => "\"line_too_long\"",
+        Some(KillReason::Interrupted) => "\"interrupted\"",
+        None => "null",
+    };
+    return format!(
+        "{{\"name\": {}, \"command\": {}, \"status_code\": {}, \"kill_reason\": {}, \"started_secs\": {:.3}, \"duration_secs\": {:.3}, \"attempts\": {}, \"depends_on\": [{}], \"transcript\": {}}}",
+        json_string(&entry.name),
+        json_string(&output.command),
+        output.status_code.map_or("null".to_string(), |code| code.to_string()),
+        kill_reason,
+        started.as_secs_f64(),
+        output.duration.as_secs_f64(),
+        output.failed_attempts.len() + 1,
+        depends_on.join(", "),
+        json_string(&format!("{}/transcript.txt", dir)),
+    );
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    return escaped;
+}
+
+/// Makes a name safe to use as a directory name, and short enough to fit in a tar header
+fn sanitize(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .take(64)
+        .collect();
+    if sanitized.is_empty() || sanitized.chars().all(|c| c == '.') {
+        return "command".to_string();
+    }
+    return sanitized;
+}
+
+/// Builds a ustar header for a regular file
+fn tar_header(name: &str, size: usize, mtime: u64) -> [u8; 512] {
+    let mut header = [0; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+    header[136..148].copy_from_slice(format!("{:011o}\0", mtime).as_bytes());
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // the checksum is calculated with the checksum field itself as spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    return header;
+}
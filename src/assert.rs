@@ -0,0 +1,128 @@
+//! Assertions about a command's output, for tests
+use crate::report::status;
+use crate::CmdOutput;
+use regex::Regex;
+use std::fmt::Write;
+
+impl CmdOutput {
+    /// Returns an [`Assert`] for checking the output in tests
+    ///
+    /// ```
+    /// use better_commands::{regex::Regex, run};
+    /// use std::process::Command;
+    ///
+    /// run(Command::new("bash").arg("-c").arg("echo hello; >&2 echo 'took 12ms'; exit 2"))
+    ///     .assert()
+    ///     .code(2)
+    ///     .stdout_contains("hello")
+    ///     .stderr_matches(Regex::new(r"took \d+ms").unwrap());
+    /// ```
+    pub fn assert(&self) -> Assert<'_> {
+        return Assert { output: self };
+    }
+}
+
+/// Assertions about a [`CmdOutput`], from [`CmdOutput::assert`]
+///
+/// Each assertion panics if it fails, with a message showing everything the command printed (and when), so there's no
+/// need to print the output separately to work out what went wrong.
+#[derive(Debug, Clone, Copy)]
+pub struct Assert<'a> {
+    output: &'a CmdOutput,
+}
+
+impl Assert<'_> {
+    /// Asserts that the command exited with status 0
+    #[track_caller]
+    pub fn success(self) -> Self {
+        if !self.output.success() {
+            self.fail("expected it to succeed");
+        }
+        return self;
+    }
+
+    /// Asserts that the command failed, by exiting with a non-zero status or being killed
+    #[track_caller]
+    pub fn failure(self) -> Self {
+        if self.output.success() {
+            self.fail("expected it to fail");
+        }
+        return self;
+    }
+
+    /// Asserts that the command exited with `code`
+    #[track_caller]
+    pub fn code(self, code: i32) -> Self {
+        if self.output.status_code != Some(code) {
+            self.fail(&format!("expected it to exit with status {}", code));
+        }
+        return self;
+    }
+
+    /// Asserts that stdout contains `text`, which can span multiple lines
+    #[track_caller]
+    pub fn stdout_contains<S: AsRef<str>>(self, text: S) -> Self {
+        let stdout = self.output.stdout_string().unwrap_or_default();
+        if !stdout.contains(text.as_ref()) {
+            self.fail(&format!("expected stdout to contain {:?}", text.as_ref()));
+        }
+        return self;
+    }
+
+    /// Asserts that stderr contains `text`, which can span multiple lines
+    #[track_caller]
+    pub fn stderr_contains<S: AsRef<str>>(self, text: S) -> Self {
+        let stderr = self.output.stderr_string().unwrap_or_default();
+        if !stderr.contains(text.as_ref()) {
+            self.fail(&format!("expected stderr to contain {:?}", text.as_ref()));
+        }
+        return self;
+    }
+
+    /// Asserts that `regex` matches stdout, with its lines joined by `\n`
+    #[track_caller]
+    pub fn stdout_matches(self, regex: Regex) -> Self {
+        let stdout = self.output.stdout_string().unwrap_or_default();
+        if !regex.is_match(&stdout) {
+            self.fail(&format!("expected stdout to match /{}/", regex));
+        }
+        return self;
+    }
+
+    /// Asserts that `regex` matches stderr, with its lines joined by `\n`
+    #[track_caller]
+    pub fn stderr_matches(self, regex: Regex) -> Self {
+        let stderr = self.output.stderr_string().unwrap_or_default();
+        if !regex.is_match(&stderr) {
+            self.fail(&format!("expected stderr to match /{}/", regex));
+        }
+        return self;
+    }
+
+    /// Panics with `expected`, the command's status, and everything it printed
+    #[track_caller]
+    fn fail(&self, expected: &str) -> ! {
+        let mut message = format!(
+            "command `{}` {} after {:.3}s, {}\n",
+            self.output.command,
+            status(self.output),
+            self.output.duration.as_secs_f64(),
+            expected
+        );
+        if self.output.stdout.is_none() {
+            message.push_str("(output wasn't captured)\n");
+        } else if self.output.is_empty() {
+            message.push_str("(it didn't print anything)\n");
+        }
+        for line in self.output.iter() {
+            let _ = writeln!(
+                message,
+                "  +{:.3}s {} | {}",
+                line.offset.as_secs_f64(),
+                line.printed_to,
+                line.content
+            );
+        }
+        panic!("{}", message);
+    }
+}
@@ -0,0 +1,258 @@
+//! Recording every command that's run, for compliance and the like
+use crate::CmdOutput;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+type AuditCallback = Arc<Mutex<dyn FnMut(&AuditRecord) -> io::Result<()> + Send>>;
+
+static GLOBAL_LOG: RwLock<Option<AuditLog>> = RwLock::new(None);
+
+/// A command that was run, from an [`AuditLog`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuditRecord {
+    /// The program followed by its arguments, like [`CmdOutput::command`]
+    pub command: String,
+    /// The directory it was run in
+    pub cwd: PathBuf,
+    /// The user it was run as, or their ID if they don't have a name
+    pub user: String,
+    /// When it was started
+    pub start: SystemTime,
+    /// When it exited
+    pub end: SystemTime,
+    /// Its exit status, if it exited normally
+    pub status_code: Option<i32>,
+    /// The signal which killed it, if it was killed
+    pub signal: Option<i32>,
+}
+
+impl fmt::Display for AuditRecord {
+    /// Formats the record as one line of `key=value` pairs, as written by [`AuditLog::file`]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64()
+        };
+        let status = match (self.status_code, self.signal) {
+            (Some(code), _) => code.to_string(),
+            (None, Some(signal)) => format!("signal {}", signal),
+            (None, None) => "none".to_string(),
+        };
+        return write!(
+            f,
+            "start={:.3} end={:.3} status={:?} user={:?} cwd={:?} command={:?}",
+            seconds(self.start),
+            seconds(self.end),
+            status,
+            self.user,
+            self.cwd,
+            self.command
+        );
+    }
+}
+
+/// Somewhere to record every command that's run, set for every command with [`set_global_audit_log`], or for one
+/// [`Runner`](crate::Runner) with [`Runner::audit_log`](crate::Runner::audit_log)
+///
+/// A record is written once each command exits (or is killed), including every attempt with
+/// [`Runner::retries`](crate::Runner::retries). Since that happens in the background, a failed write doesn't stop the
+/// command; the error is available from [`AuditLog::take_error`].
+///
+/// ```
+/// use better_commands::{AuditLog, Runner};
+/// use std::process::Command;
+/// use std::sync::{Arc, Mutex};
+///
+/// let records = Arc::new(Mutex::new(Vec::new()));
+/// let log = records.clone();
+/// let runner = Runner::new().audit_log(AuditLog::callback(move |record| {
+///     log.lock().unwrap().push(record.clone());
+///     return Ok(());
+/// }));
+///
+/// runner.run(Command::new("echo").arg("hi")).unwrap();
+/// let record = &records.lock().unwrap()[0];
+/// assert_eq!("echo hi", record.command);
+/// assert_eq!(Some(0), record.status_code);
+/// ```
+#[derive(Clone)]
+pub struct AuditLog {
+    callback: AuditCallback,
+    error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("AuditLog")
+            .field("error", &self.error)
+            .finish_non_exhaustive();
+    }
+}
+
+impl AuditLog {
+    /// Passes each record to `callback`
+    pub fn callback(callback: impl FnMut(&AuditRecord) -> io::Result<()> + Send + 'static) -> Self {
+        return AuditLog {
+            callback: Arc::new(Mutex::new(callback)),
+            error: Arc::default(),
+        };
+    }
+
+    /// Appends each record to the file at `path` (creating it if needed), as a line of `key=value` pairs
+    pub fn file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = open_append(path.as_ref())?;
+        return Ok(AuditLog::callback(move |record| {
+            return writeln!(file, "{}", record);
+        }));
+    }
+
+    /// Appends each record to the file at `path` (creating it if needed), as a line of JSON
+    ///
+    /// <small>This needs the `serde` feature</small>
+    #[cfg(feature = "serde")]
+    pub fn json_lines<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = open_append(path.as_ref())?;
+        return Ok(AuditLog::callback(move |record| {
+            let json = serde_json::to_string(record)?;
+            return writeln!(file, "{}", json);
+        }));
+    }
+
+    /// Returns the last error from writing a record, if there's been one since this was last called
+    pub fn take_error(&self) -> Option<io::Error> {
+        return self.error.lock().unwrap().take();
+    }
+
+    fn write(&self, record: &AuditRecord) {
+        if let Err(e) = (self.callback.lock().unwrap())(record) {
+            *self.error.lock().unwrap() = Some(e);
+        }
+    }
+}
+
+/// Records every command that's run from now on to `log`, on top of any [`Runner::audit_log`](crate::Runner::audit_log)
+///
+/// This covers everything in this crate that runs a command, including [`run`](crate::run) and the other functions
+/// which don't use a [`Runner`](crate::Runner). Calling this again replaces the log.
+pub fn set_global_audit_log(log: AuditLog) {
+    *GLOBAL_LOG.write().unwrap() = Some(log);
+}
+
+/// Stops recording commands to the log from [`set_global_audit_log`]
+pub fn clear_global_audit_log() {
+    *GLOBAL_LOG.write().unwrap() = None;
+}
+
+/// Opens a file for appending records to, without any buffering so a record isn't lost if the program crashes
+fn open_append(path: &Path) -> io::Result<File> {
+    return OpenOptions::new().create(true).append(true).open(path);
+}
+
+/// What's needed to record a command once it's exited, taken before it's started
+#[derive(Debug)]
+pub(crate) struct Audit {
+    logs: Vec<AuditLog>,
+    cwd: PathBuf,
+    user: String,
+}
+
+impl Audit {
+    /// Returns `None` if there aren't any logs to write to
+    ///
+    /// `user` is the user the command is run as, if it's not this process's user, and `log` is the runner's log.
+    pub(crate) fn new(
+        command: &Command,
+        user: Option<User>,
+        log: Option<&AuditLog>,
+    ) -> Option<Audit> {
+        let logs: Vec<AuditLog> = GLOBAL_LOG
+            .read()
+            .unwrap()
+            .iter()
+            .chain(log)
+            .cloned()
+            .collect();
+        if logs.is_empty() {
+            return None;
+        }
+        let current_dir = std::env::current_dir().unwrap_or_default();
+        let cwd = match command.get_current_dir() {
+            Some(dir) => current_dir.join(dir),
+            None => current_dir,
+        };
+        let user = match user {
+            Some(User::Name(name)) => name,
+            Some(User::Id(uid)) => user_name(uid),
+            #[cfg(unix)]
+            None => user_name(unsafe { libc::getuid() }),
+            #[cfg(not(unix))]
+            None => std::env::var("USERNAME").unwrap_or_default(),
+        };
+        return Some(Audit { logs, cwd, user });
+    }
+
+    pub(crate) fn record(&self, output: &CmdOutput) {
+        // `Instant`s can't be turned into times directly, so this works back from now
+        let (now, system_now) = (Instant::now(), SystemTime::now());
+        let time = |instant: Instant| system_now - now.saturating_duration_since(instant);
+        let record = AuditRecord {
+            command: output.command.clone(),
+            cwd: self.cwd.clone(),
+            user: self.user.clone(),
+            start: time(output.start_time),
+            end: time(output.end_time),
+            status_code: output.status_code,
+            signal: output.signal,
+        };
+        for log in &self.logs {
+            log.write(&record);
+        }
+    }
+}
+
+/// Records a command run without a [`Runner`](crate::Runner) to the global log, if there is one
+pub(crate) fn record(command: &Command, output: &CmdOutput) {
+    if let Some(audit) = Audit::new(command, None, None) {
+        audit.record(output);
+    }
+}
+
+/// Who a command is run as, from [`Runner::user`](crate::Runner::user) or [`Runner::uid`](crate::Runner::uid)
+pub(crate) enum User {
+    Name(String),
+    Id(u32),
+}
+
+/// Looks up the name of the user with ID `uid`, or returns the ID if they don't have one
+fn user_name(uid: u32) -> String {
+    #[cfg(unix)]
+    {
+        use std::ffi::CStr;
+
+        let mut buf = vec![0 as libc::c_char; 4096];
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        loop {
+            let error = unsafe {
+                libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
+            };
+            match error {
+                libc::ERANGE => buf.resize(buf.len() * 2, 0),
+                _ => break,
+            }
+        }
+        if !result.is_null() {
+            let name = unsafe { CStr::from_ptr(passwd.pw_name) };
+            return name.to_string_lossy().into_owned();
+        }
+    }
+    return uid.to_string();
+}
@@ -0,0 +1,154 @@
+//! Different ways of reading a command's output
+
+/// How a [`Runner`](crate::Runner) reads the output of the commands it runs (see [`Runner::backend`](crate::Runner::backend))
+///
+/// Every backend gives the same [`CmdOutput`](crate::CmdOutput).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Reads stdout and stderr on two background threads, so lines are captured as soon as they're printed
+    #[default]
+    Threads,
+    /// Reads both stdout and stderr from the thread waiting for the command, using `poll()`
+    ///
+    /// This doesn't start any threads, which is cheaper when running lots of short commands. However, the output is
+    /// only read while waiting for the command (see [`RunningCommand::wait`](crate::RunningCommand::wait)), so after
+    /// [`Runner::spawn`](crate::Runner::spawn), lines aren't captured (and the command may block once the pipes fill up)
+    /// until then.
+    ///
+    /// <small>Only available on Unix, with the `poll` feature</small>
+    #[cfg(all(unix, feature = "poll"))]
+    Poll,
+}
+
+/// How the output is split into [`Line`](crate::Line)s as it's read (see [`Runner::read_mode`](crate::Runner::read_mode))
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadMode {
+    /// Waits for each line to be finished before capturing it
+    #[default]
+    Lines,
+    /// Captures whatever has been read as soon as it's read, even if it's only part of a line
+    ///
+    /// Newlines still split lines, but the end of each read does as well, so e.g. a progress bar which doesn't print
+    /// newlines shows up as it's printed rather than all at once when the command exits. Lines are at most
+    /// [`Runner::read_buffer_size`](crate::Runner::read_buffer_size) bytes long.
+    Chunks,
+}
+
+#[cfg(all(unix, feature = "poll"))]
+pub(crate) use poll::read_polled;
+
+#[cfg(all(unix, feature = "poll"))]
+mod poll {
+    use super::ReadMode;
+    use crate::capture::Capture;
+    use crate::split::LineSplitter;
+    use crate::{KillReason, Line, LineType};
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::fd::AsRawFd;
+    use std::time::{Duration, Instant};
+
+    /// How long to wait for output before checking whether the command needs to be killed
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// A stream being read, and the line it's partway through
+    struct Stream<R: Read> {
+        reader: Option<R>,
+        printed_to: LineType,
+        splitter: LineSplitter,
+    }
+
+    impl<R: Read + AsRawFd> Stream<R> {
+        fn pollfd(&self) -> libc::pollfd {
+            return libc::pollfd {
+                // negative fds are ignored by `poll()`
+                fd: self.reader.as_ref().map_or(-1, |reader| reader.as_raw_fd()),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+        }
+
+        /// Reads whatever's available, adding every finished line to `lines`
+        fn read(&mut self, capture: &Capture, lines: &mut Vec<Line>) -> io::Result<()> {
+            let mut buf = vec![0; capture.read_buffer_size];
+            let read = match self.reader.as_mut().unwrap().read(&mut buf) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let time = Instant::now();
+            if read == 0 {
+                self.reader = None;
+                if let Some(raw) = self.splitter.finish() {
+                    lines.extend(capture.line(raw, self.printed_to.clone(), time));
+                }
+                return Ok(());
+            }
+
+            let printed_to = &self.printed_to;
+            self.splitter.push(&buf[..read], |raw| {
+                lines.extend(capture.line(raw, printed_to.clone(), time));
+            });
+            capture.check_line_length(&self.splitter);
+            if capture.read_mode == ReadMode::Chunks {
+                if let Some(raw) = self.splitter.finish() {
+                    lines.extend(capture.line(raw, self.printed_to.clone(), time));
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    /// Reads each of `streams` (e.g. stdout and stderr) until they're all closed
+    ///
+    /// `should_kill` is called regularly (if `check_kill` is set); once it returns a reason, `kill` is called, and
+    /// reading continues until the streams are closed.
+    pub(crate) fn read_polled(
+        streams: Vec<(File, LineType)>,
+        capture: &Capture,
+        check_kill: bool,
+        mut should_kill: impl FnMut(Instant) -> Option<KillReason>,
+        mut kill: impl FnMut() -> io::Result<()>,
+    ) -> io::Result<(Vec<Line>, Option<KillReason>)> {
+        let mut streams: Vec<Stream<File>> = streams
+            .into_iter()
+            .map(|(reader, printed_to)| Stream {
+                reader: Some(reader),
+                printed_to,
+                splitter: capture.splitter(),
+            })
+            .collect();
+        let mut lines = Vec::new();
+        let mut kill_reason = None;
+        let timeout = if check_kill {
+            POLL_INTERVAL.as_millis() as libc::c_int
+        } else {
+            -1
+        };
+
+        while streams.iter().any(|stream| stream.reader.is_some()) {
+            if check_kill && kill_reason.is_none() {
+                kill_reason = should_kill(Instant::now());
+                if kill_reason.is_some() {
+                    kill()?;
+                }
+            }
+
+            let mut fds: Vec<libc::pollfd> = streams.iter().map(Stream::pollfd).collect();
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } == -1 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            // POLLHUP and POLLERR mean a read won't block either
+            for (stream, fd) in streams.iter_mut().zip(&fds) {
+                if fd.revents != 0 {
+                    stream.read(capture, &mut lines)?;
+                }
+            }
+        }
+        return Ok((lines, kill_reason));
+    }
+}
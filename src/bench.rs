@@ -0,0 +1,124 @@
+//! Timing commands over several runs
+use crate::{CmdOutput, Error, Runner};
+use std::process::Command;
+use std::time::Duration;
+
+/// The results of running a command several times, from [`bench()`] or [`Runner::bench`]
+///
+/// There's always at least one run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchReport {
+    runs: Vec<CmdOutput>,
+}
+
+impl BenchReport {
+    /// Panics if there aren't any runs
+    pub(crate) fn new(runs: Vec<CmdOutput>) -> Self {
+        assert!(!runs.is_empty(), "a bench report needs at least one run");
+        return BenchReport { runs };
+    }
+
+    /// Returns the output of every timed run, in order (warmup runs aren't included)
+    pub fn runs(&self) -> &[CmdOutput] {
+        return &self.runs;
+    }
+
+    /// Returns how long each run took, in order
+    pub fn durations(&self) -> Vec<Duration> {
+        return self.runs.iter().map(|run| run.duration).collect();
+    }
+
+    /// Returns the fastest run's duration
+    pub fn min(&self) -> Duration {
+        return self.durations().into_iter().min().unwrap();
+    }
+
+    /// Returns the slowest run's duration
+    pub fn max(&self) -> Duration {
+        return self.durations().into_iter().max().unwrap();
+    }
+
+    /// Returns the average duration
+    pub fn mean(&self) -> Duration {
+        return self.durations().iter().sum::<Duration>() / self.runs.len() as u32;
+    }
+
+    /// Returns the median duration (the average of the middle two, for an even number of runs)
+    pub fn median(&self) -> Duration {
+        let mut durations = self.durations();
+        durations.sort();
+        let middle = durations.len() / 2;
+        if durations.len().is_multiple_of(2) {
+            return (durations[middle - 1] + durations[middle]) / 2;
+        }
+        return durations[middle];
+    }
+
+    /// Returns the standard deviation of the durations
+    pub fn stddev(&self) -> Duration {
+        let mean = self.mean().as_secs_f64();
+        let variance = self
+            .durations()
+            .iter()
+            .map(|duration| (duration.as_secs_f64() - mean).powi(2))
+            .sum::<f64>()
+            / self.runs.len() as f64;
+        return Duration::from_secs_f64(variance.sqrt());
+    }
+
+    /// Returns how many of the runs failed (exited with anything other than 0, or were killed)
+    pub fn failures(&self) -> usize {
+        return self.runs.iter().filter(|run| !run.success()).count();
+    }
+}
+
+/// Runs a command `runs` times after one warmup run, reporting how long each run took
+///
+/// This is the same as [`Runner::bench`] with the default options and one warmup run, except it panics if the command
+/// can't be run.
+///
+/// Panics if `runs` is 0.
+///
+/// ```
+/// use better_commands::bench;
+/// use std::process::Command;
+///
+/// let report = bench(Command::new("sleep").arg("0.01"), 5);
+/// println!(
+///     "{:?} ± {:?} (min {:?}, max {:?})",
+///     report.mean(),
+///     report.stddev(),
+///     report.min(),
+///     report.max()
+/// );
+/// assert_eq!(5, report.runs().len());
+/// assert!(report.min() >= std::time::Duration::from_millis(10));
+/// ```
+pub fn bench(command: &mut Command, runs: usize) -> BenchReport {
+    return Runner::new().bench(command, 1, runs).unwrap();
+}
+
+impl Runner {
+    /// Runs a command `warmup` times without timing it (e.g. to warm up caches), then `runs` times, reporting how long
+    /// each run took
+    ///
+    /// Every run uses these options, so timeouts, retries, etc. all apply; a run that fails is still counted (see
+    /// [`BenchReport::failures`]).
+    ///
+    /// Panics if `runs` is 0.
+    pub fn bench(
+        &self,
+        command: &mut Command,
+        warmup: usize,
+        runs: usize,
+    ) -> Result<BenchReport, Error> {
+        assert!(runs > 0, "benchmarking needs at least one run");
+        for _ in 0..warmup {
+            self.run(command)?;
+        }
+        let runs = (0..runs)
+            .map(|_| self.run(command))
+            .collect::<Result<_, _>>()?;
+        return Ok(BenchReport::new(runs));
+    }
+}
@@ -0,0 +1,124 @@
+//! Reusing the outputs of commands which have already been run with the same inputs
+use crate::{CmdOutput, Error};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A directory storing the outputs of commands, to return instead of running them again (see [`Runner::cache`](crate::Runner::cache))
+///
+/// Each output is stored as JSON, in a file named after a hash of everything it depends on. Nothing is ever removed
+/// automatically; use [`CacheDir::clear`] (or just delete the directory) to start over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheDir {
+    path: PathBuf,
+}
+
+impl CacheDir {
+    /// Uses the directory at `path`, which is created when the first output is stored
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        return CacheDir {
+            path: path.as_ref().to_path_buf(),
+        };
+    }
+
+    /// Returns the path to the directory
+    pub fn path(&self) -> &Path {
+        return &self.path;
+    }
+
+    /// Removes every stored output
+    pub fn clear(&self) -> io::Result<()> {
+        return match fs::remove_dir_all(&self.path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        };
+    }
+
+    /// Returns the key for running `command` with `files` as its inputs
+    ///
+    /// This covers the command line, the working directory, the environment variables set on the command (not
+    /// everything it inherits), and the path and contents of each file; files which don't exist count too, so creating
+    /// one changes the key.
+    pub(crate) fn key(&self, command: &Command, files: &[PathBuf]) -> Result<String, Error> {
+        let mut hash = Fnv1a::new();
+        // hashed separately rather than as the command line, which would make `echo "a b"` and `echo a b` the same
+        hash.field(command.get_program().as_encoded_bytes());
+        hash.field(&(command.get_args().len() as u64).to_le_bytes());
+        for arg in command.get_args() {
+            hash.field(arg.as_encoded_bytes());
+        }
+        let current_dir = command.get_current_dir().unwrap_or(Path::new(""));
+        hash.field(current_dir.as_os_str().as_encoded_bytes());
+        let mut envs: Vec<_> = command.get_envs().collect();
+        envs.sort();
+        for (key, value) in envs {
+            hash.field(key.as_encoded_bytes());
+            // removed variables are different from empty ones
+            match value {
+                Some(value) => hash.field(value.as_encoded_bytes()),
+                None => hash.field(b"\0removed"),
+            }
+        }
+        for file in files {
+            hash.field(file.as_os_str().as_encoded_bytes());
+            match fs::read(file) {
+                Ok(contents) => hash.field(&contents),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => hash.field(b"\0missing"),
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+        return Ok(format!("{:016x}", hash.0));
+    }
+
+    /// Returns the output stored for `key`, if there is one
+    ///
+    /// Anything that can't be read (e.g. from a newer version of this crate) is ignored, so the command is just run
+    /// again.
+    pub(crate) fn get(&self, key: &str) -> Option<CmdOutput> {
+        let json = fs::read_to_string(self.file(key)).ok()?;
+        let mut output: CmdOutput = serde_json::from_str(&json).ok()?;
+        output.cached = true;
+        return Some(output);
+    }
+
+    /// Stores `output` for `key`
+    pub(crate) fn put(&self, key: &str, output: &CmdOutput) -> io::Result<()> {
+        fs::create_dir_all(&self.path)?;
+        let json = serde_json::to_string(output)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // written then renamed, so a command run at the same time never sees half of it
+        let partial = self
+            .path
+            .join(format!("{}.{}.partial", key, std::process::id()));
+        fs::write(&partial, json)?;
+        return fs::rename(&partial, self.file(key));
+    }
+
+    fn file(&self, key: &str) -> PathBuf {
+        return self.path.join(format!("{}.json", key));
+    }
+}
+
+/// 64-bit FNV-1a, which (unlike [`std::hash::Hash`]) stays the same between Rust versions and platforms, so keys do
+/// too
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        return Fnv1a(0xcbf29ce484222325);
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    /// Adds `bytes` prefixed with their length, so e.g. `ab`, `c` and `a`, `bc` aren't the same
+    fn field(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+}
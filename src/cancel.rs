@@ -0,0 +1,70 @@
+//! How commands are stopped when they need to be cancelled
+use std::time::Duration;
+
+/// How to stop a command when it's cancelled, e.g. by a timeout or [`RunningCommand::cancel`](crate::RunningCommand::cancel)
+///
+/// By default, commands are just killed. Some programs have their own way of quitting cleanly, like typing `q` or
+/// closing stdin; [`Cancellation::Stdin`] asks them to quit that way first, and only kills them if they don't exit in
+/// time.
+///
+/// ```
+/// use better_commands::{Cancellation, Runner};
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// // `cat` quits once stdin is closed
+/// let output = Runner::new()
+///     .cancellation(Cancellation::close_stdin(Duration::from_secs(5)))
+///     .timeout(Duration::from_millis(100))
+///     .run(&mut Command::new("cat"))
+///     .unwrap();
+///
+/// // it was cancelled due to the timeout, but exited by itself
+/// assert_eq!(Some(0), output.clone().status_code());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Cancellation {
+    /// Kills the command straight away
+    #[default]
+    Kill,
+    /// Writes `message` to the command's stdin and closes it, then waits up to `grace` for it to exit before killing it
+    ///
+    /// <small>The command's stdin is always piped when using this, replacing anything set with
+    /// [`Command::stdin`](std::process::Command::stdin)</small>
+    Stdin { message: Vec<u8>, grace: Duration },
+    /// Sends `SIGTERM` (to the whole process group with [`Runner::process_group`](crate::Runner::process_group)), then
+    /// waits up to `grace` for it to exit before killing it
+    ///
+    /// <small>On Windows, there's no `SIGTERM`, so this is the same as [`Cancellation::Kill`]</small>
+    Terminate { grace: Duration },
+}
+
+impl Cancellation {
+    /// Writes `message` (e.g. `"q\n"`) to stdin, then waits up to `grace` before killing the command
+    pub fn stdin<M: AsRef<[u8]>>(message: M, grace: Duration) -> Self {
+        return Cancellation::Stdin {
+            message: message.as_ref().to_vec(),
+            grace,
+        };
+    }
+
+    /// Closes stdin without writing anything, then waits up to `grace` before killing the command
+    pub fn close_stdin(grace: Duration) -> Self {
+        return Cancellation::stdin([], grace);
+    }
+
+    /// Sends `SIGTERM`, then waits up to `grace` before killing the command
+    pub fn terminate(grace: Duration) -> Self {
+        return Cancellation::Terminate { grace };
+    }
+}
+
+/// How a command was stopped when it was cancelled (see [`CmdOutput::shutdown`](crate::CmdOutput::shutdown))
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Shutdown {
+    /// It exited by itself within the grace period, after being asked to stop
+    Exited,
+    /// It was killed, either straight away or because it didn't exit in time
+    Killed,
+}
@@ -0,0 +1,379 @@
+//! Reading lines from a child's output, shared by everything that runs commands
+use crate::ansi;
+use crate::progress::parse_progress;
+use crate::ready::{Pattern, Watch};
+use crate::split::{CarriageReturns, Delimiter, LineSplitter, LongLines, RawLine};
+use crate::{Hooks, KillReason, Line, LineSink, LineType, ReadMode, SeverityRules, Watermark};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub(crate) type LineCallback = Arc<Mutex<dyn FnMut(&mut Line) + Send>>;
+/// A [`LineParser`](crate::LineParser) with its output type hidden, set by [`Runner::run_parsed`](crate::Runner::run_parsed)
+pub(crate) type ParserCallback = Arc<Mutex<dyn FnMut(&Line) + Send>>;
+pub(crate) type ProgressCallback = Arc<Mutex<dyn FnMut(f64) + Send>>;
+pub(crate) type SharedSink = Arc<Mutex<dyn LineSink>>;
+pub(crate) type WatermarkCallback = Arc<Mutex<dyn FnMut(Watermark) + Send>>;
+
+/// How many bytes are read from a stream at once by default
+pub(crate) const DEFAULT_READ_BUFFER_SIZE: usize = 8192;
+
+/// Options and state shared by the threads reading a command's output
+#[derive(Clone)]
+pub(crate) struct Capture {
+    /// When the command was started, if it has been
+    pub(crate) start: Option<Instant>,
+    pub(crate) on_line: Option<LineCallback>,
+    pub(crate) parser: Option<ParserCallback>,
+    /// From [`Runner::on_progress`](crate::Runner::on_progress)
+    pub(crate) on_progress: Option<ProgressCallback>,
+    pub(crate) sinks: Vec<SharedSink>,
+    /// From [`Runner::hooks`](crate::Runner::hooks)
+    pub(crate) hooks: Vec<Hooks>,
+    pub(crate) kill_after_bytes: Option<u64>,
+    pub(crate) kill_after_lines: Option<u64>,
+    pub(crate) max_output_bytes: Option<u64>,
+    pub(crate) max_output_lines: Option<u64>,
+    pub(crate) read_buffer_size: usize,
+    pub(crate) read_mode: ReadMode,
+    pub(crate) keep_raw: bool,
+    pub(crate) carriage_returns: CarriageReturns,
+    pub(crate) delimiter: Delimiter,
+    pub(crate) max_line_length: Option<usize>,
+    pub(crate) long_lines: LongLines,
+    pub(crate) strip_ansi: bool,
+    pub(crate) severity_rules: Option<Arc<SeverityRules>>,
+    /// Set by [`Runner::run_until_match`](crate::Runner::run_until_match)
+    pub(crate) watch: Option<Arc<Watch>>,
+    pub(crate) kill_on_match: Option<Pattern>,
+    /// Every interval (in bytes) to call back at, from [`Runner::on_watermark`](crate::Runner::on_watermark)
+    pub(crate) watermarks: Vec<(u64, WatermarkCallback)>,
+    #[cfg(feature = "tracing")]
+    pub(crate) span: Option<crate::trace::CommandSpan>,
+    #[cfg(feature = "log")]
+    pub(crate) log: Option<crate::logging::LogOutput>,
+    /// Totals across all streams
+    pub(crate) bytes: Arc<AtomicU64>,
+    pub(crate) lines: Arc<AtomicU64>,
+    /// When the last line was captured, for [`Heartbeat::since_output`](crate::Heartbeat::since_output)
+    pub(crate) last_output: Arc<Mutex<Option<Instant>>>,
+    /// Used for [`Line::seq`]
+    pub(crate) seq: Arc<AtomicU64>,
+    /// How many lines each stream has printed, for [`Line::line_number`]
+    pub(crate) line_numbers: Arc<Mutex<BTreeMap<LineType, u64>>>,
+    /// Whether any lines were thrown away for going over `max_output_bytes` or `max_output_lines`
+    pub(crate) truncated: Arc<AtomicBool>,
+    pub(crate) kill_switch: Arc<KillSwitch>,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        return Capture {
+            start: None,
+            on_line: None,
+            parser: None,
+            on_progress: None,
+            sinks: Vec::new(),
+            hooks: Vec::new(),
+            kill_after_bytes: None,
+            kill_after_lines: None,
+            max_output_bytes: None,
+            max_output_lines: None,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            read_mode: ReadMode::default(),
+            keep_raw: false,
+            carriage_returns: CarriageReturns::default(),
+            delimiter: Delimiter::default(),
+            max_line_length: None,
+            long_lines: LongLines::default(),
+            strip_ansi: false,
+            severity_rules: None,
+            watch: None,
+            kill_on_match: None,
+            watermarks: Vec::new(),
+            #[cfg(feature = "tracing")]
+            span: None,
+            #[cfg(feature = "log")]
+            log: None,
+            bytes: Arc::default(),
+            lines: Arc::default(),
+            last_output: Arc::default(),
+            seq: Arc::default(),
+            line_numbers: Arc::default(),
+            truncated: Arc::default(),
+            kill_switch: Arc::default(),
+        };
+    }
+}
+
+impl fmt::Debug for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Capture")
+            .field("start", &self.start)
+            .field("sinks", &self.sinks.len())
+            .field("hooks", &self.hooks)
+            .field("kill_after_bytes", &self.kill_after_bytes)
+            .field("kill_after_lines", &self.kill_after_lines)
+            .field("max_output_bytes", &self.max_output_bytes)
+            .field("max_output_lines", &self.max_output_lines)
+            .field("read_buffer_size", &self.read_buffer_size)
+            .field("read_mode", &self.read_mode)
+            .field("keep_raw", &self.keep_raw)
+            .field("carriage_returns", &self.carriage_returns)
+            .field("delimiter", &self.delimiter)
+            .field("max_line_length", &self.max_line_length)
+            .field("long_lines", &self.long_lines)
+            .field("strip_ansi", &self.strip_ansi)
+            .field("severity_rules", &self.severity_rules)
+            .field("kill_on_match", &self.kill_on_match)
+            .field("watermarks", &self.watermarks.len())
+            .field("bytes", &self.bytes)
+            .field("lines", &self.lines)
+            .field("kill_switch", &self.kill_switch)
+            .finish_non_exhaustive();
+    }
+}
+
+impl Capture {
+    /// Returns a copy of the options with fresh state, for a new run started at `start`
+    pub(crate) fn for_run(&self, start: Instant) -> Capture {
+        return Capture {
+            start: Some(start),
+            on_line: self.on_line.clone(),
+            parser: self.parser.clone(),
+            on_progress: self.on_progress.clone(),
+            sinks: self.sinks.clone(),
+            hooks: self.hooks.clone(),
+            kill_after_bytes: self.kill_after_bytes,
+            kill_after_lines: self.kill_after_lines,
+            max_output_bytes: self.max_output_bytes,
+            max_output_lines: self.max_output_lines,
+            read_buffer_size: self.read_buffer_size,
+            read_mode: self.read_mode,
+            keep_raw: self.keep_raw,
+            carriage_returns: self.carriage_returns,
+            delimiter: self.delimiter.clone(),
+            max_line_length: self.max_line_length,
+            long_lines: self.long_lines,
+            strip_ansi: self.strip_ansi,
+            severity_rules: self.severity_rules.clone(),
+            watch: self.watch.clone(),
+            kill_on_match: self.kill_on_match.clone(),
+            watermarks: self.watermarks.clone(),
+            #[cfg(feature = "log")]
+            log: self.log.clone(),
+            ..Default::default()
+        };
+    }
+
+    /// Flushes every sink, once the command has exited and all its lines have been read
+    pub(crate) fn flush_sinks(&self) {
+        for sink in &self.sinks {
+            sink.lock().unwrap().flush();
+        }
+    }
+
+    /// Returns a [`LineSplitter`] for one of the command's streams, set up with these options
+    pub(crate) fn splitter(&self) -> LineSplitter {
+        return LineSplitter::new(&self.delimiter.0, self.max_line_length)
+            .carriage_returns(self.carriage_returns);
+    }
+
+    /// With [`LongLines::Fail`], kills the command as soon as the line `splitter` is partway through gets too long,
+    /// rather than waiting for the end of it (which might never come)
+    pub(crate) fn check_line_length(&self, splitter: &LineSplitter) {
+        if self.long_lines == LongLines::Fail && splitter.is_over_limit() {
+            self.kill_switch.trigger(KillReason::LineTooLong);
+        }
+    }
+
+    /// Whether anything reading the output might need the command to be killed
+    pub(crate) fn can_kill(&self) -> bool {
+        return self.kill_after_bytes.is_some()
+            || self.kill_after_lines.is_some()
+            || self.kill_on_match.is_some()
+            || (self.max_line_length.is_some() && self.long_lines == LongLines::Fail);
+    }
+}
+
+/// Lets the threads reading the output ask for the command to be killed
+#[derive(Debug, Default)]
+pub(crate) struct KillSwitch {
+    reason: Mutex<Option<KillReason>>,
+    /// The line which made it trigger, for [`KillReason::PatternMatched`]
+    matched: Mutex<Option<Line>>,
+}
+
+impl KillSwitch {
+    /// Asks for the command to be killed; only the first reason given is kept
+    pub(crate) fn trigger(&self, reason: KillReason) {
+        self.reason.lock().unwrap().get_or_insert(reason);
+    }
+
+    pub(crate) fn reason(&self) -> Option<KillReason> {
+        return *self.reason.lock().unwrap();
+    }
+
+    /// Asks for the command to be killed because `line` matched [`Capture::kill_on_match`]
+    pub(crate) fn trigger_match(&self, line: &Line) {
+        let mut reason = self.reason.lock().unwrap();
+        if reason.is_none() {
+            *reason = Some(KillReason::PatternMatched);
+            *self.matched.lock().unwrap() = Some(line.clone());
+        }
+    }
+
+    /// Returns the line which matched, if that's why the command was killed
+    pub(crate) fn matched(&self) -> Option<Line> {
+        return self.matched.lock().unwrap().clone();
+    }
+}
+
+/// Reads lines from one of the child's streams until it's closed
+pub(crate) fn read_lines(
+    mut stream: impl Read,
+    printed_to: LineType,
+    capture: &Capture,
+) -> io::Result<Vec<Line>> {
+    let mut splitter = capture.splitter();
+    let mut lines = Vec::new();
+    let mut buf = vec![0; capture.read_buffer_size];
+    loop {
+        let read = match stream.read(&mut buf) {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        // every line finished by this read was printed by now, and taking the time here means handling earlier lines
+        // (e.g. with `on_line`) doesn't make later ones look later than they were
+        let time = Instant::now();
+        if read == 0 {
+            if let Some(raw) = splitter.finish() {
+                lines.extend(capture.line(raw, printed_to.clone(), time));
+            }
+            return Ok(lines);
+        }
+        splitter.push(&buf[..read], |raw| {
+            lines.extend(capture.line(raw, printed_to.clone(), time));
+        });
+        capture.check_line_length(&splitter);
+        if capture.read_mode == ReadMode::Chunks {
+            if let Some(raw) = splitter.finish() {
+                lines.extend(capture.line(raw, printed_to.clone(), time));
+            }
+        }
+    }
+}
+
+impl Capture {
+    /// Turns a line split out of the output into a [`Line`], running everything that handles lines as they're captured
+    ///
+    /// `time` is when the read which finished the line returned. Returns `None` if the line's past
+    /// [`Runner::max_output_bytes`](crate::Runner::max_output_bytes) or
+    /// [`Runner::max_output_lines`](crate::Runner::max_output_lines), so it isn't kept.
+    pub(crate) fn line(&self, raw: RawLine, printed_to: LineType, time: Instant) -> Option<Line> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+
+        let read = raw.len as u64;
+        let previous_bytes = self.bytes.fetch_add(read, Ordering::Relaxed);
+        let total_bytes = previous_bytes + read;
+        let total_lines = self.lines.fetch_add(1, Ordering::Relaxed) + 1;
+        *self.last_output.lock().unwrap() = Some(time);
+        for (interval, callback) in &self.watermarks {
+            // only called once per line, even if it's long enough to pass several watermarks
+            if total_bytes / interval > previous_bytes / interval {
+                (callback.lock().unwrap())(Watermark {
+                    threshold: total_bytes / interval * interval,
+                    bytes: total_bytes,
+                    lines: total_lines,
+                    elapsed: self.start.map_or(Duration::ZERO, |start| {
+                        time.saturating_duration_since(start)
+                    }),
+                });
+            }
+        }
+        if self.kill_after_bytes.is_some_and(|max| total_bytes > max)
+            || self.kill_after_lines.is_some_and(|max| total_lines > max)
+        {
+            self.kill_switch.trigger(KillReason::OutputLimit);
+        }
+        if raw.truncated && self.long_lines == LongLines::Fail {
+            self.kill_switch.trigger(KillReason::LineTooLong);
+        }
+        if self.max_output_bytes.is_some_and(|max| total_bytes > max)
+            || self.max_output_lines.is_some_and(|max| total_lines > max)
+        {
+            // it's still read, so the command doesn't get stuck on a full pipe, but nothing else sees it
+            self.truncated.store(true, Ordering::Relaxed);
+            return None;
+        }
+
+        let mut content = raw.decode();
+        if self.strip_ansi {
+            if let Cow::Owned(stripped) = ansi::strip(&content) {
+                content = stripped;
+            }
+        }
+        let line_number = {
+            let mut line_numbers = self.line_numbers.lock().unwrap();
+            let line_number = line_numbers.entry(printed_to.clone()).or_default();
+            *line_number += 1;
+            *line_number
+        };
+        let mut line = Line::new(printed_to, time, seq, content);
+        line.line_number = line_number;
+        line.global_index = total_lines - 1;
+        line.truncated = raw.truncated;
+        line.terminated = raw.terminated;
+        if self.keep_raw {
+            line.raw = Some(raw.bytes);
+        }
+        line.offset = self.start.map_or(Duration::ZERO, |start| {
+            time.saturating_duration_since(start)
+        });
+        if let Some(rules) = &self.severity_rules {
+            if line.printed_to == LineType::Stderr {
+                line.severity = rules.classify(&line.content);
+            }
+        }
+        if let Some(callback) = &self.on_line {
+            (callback.lock().unwrap())(&mut line);
+        }
+        if let Some(parser) = &self.parser {
+            (parser.lock().unwrap())(&line);
+        }
+        if let Some(callback) = &self.on_progress {
+            if let Some(progress) = parse_progress(&line.content) {
+                (callback.lock().unwrap())(progress);
+            }
+        }
+        for sink in &self.sinks {
+            sink.lock().unwrap().accept(line.clone());
+        }
+        for hooks in &self.hooks {
+            hooks.line(&line);
+        }
+        #[cfg(feature = "tracing")]
+        if let Some(span) = &self.span {
+            span.line(&line);
+        }
+        #[cfg(feature = "log")]
+        if let Some(log) = &self.log {
+            log.line(&line);
+        }
+        if let Some(watch) = &self.watch {
+            watch.check(&line);
+        }
+        if let Some(pattern) = &self.kill_on_match {
+            if pattern.is_match(&line.content) {
+                self.kill_switch.trigger_match(&line);
+            }
+        }
+        return Some(line);
+    }
+}
@@ -0,0 +1,127 @@
+//! Recording command outputs to a file, and returning them later without running anything
+use crate::fake::next_output;
+use crate::{command_line, CmdOutput, CommandRunner, Error, Runner};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// Whether a [`Cassette`] is running commands or playing back what it recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Runs commands for real, keeping their outputs to [save](Cassette::save)
+    Record,
+    /// Returns recorded outputs instead of running anything
+    Replay,
+}
+
+/// Records command outputs to a file, so tests can play them back later without running anything
+///
+/// In [record mode](Cassette::record), commands are run with a [`Runner`] and their outputs kept until
+/// [`Cassette::save`] writes them to the file as JSON. In [replay mode](Cassette::replay), the file is loaded and each
+/// command gets the output recorded for the same command line, so code which runs commands can be tested without the
+/// programs it runs being installed.
+///
+/// If the same command was recorded more than once, the outputs are returned in the order they were recorded; once
+/// they run out, the last one is returned again.
+///
+/// ```
+/// use better_commands::Cassette;
+/// use std::process::Command;
+///
+/// let path = std::env::temp_dir().join("echo-cassette.json");
+///
+/// let cassette = Cassette::record(&path);
+/// cassette.run(Command::new("echo").arg("hi")).unwrap();
+/// cassette.save().unwrap();
+///
+/// // nothing is actually run here
+/// let cassette = Cassette::replay(&path).unwrap();
+/// let output = cassette.run(Command::new("echo").arg("hi")).unwrap();
+/// assert_eq!(Some("hi".to_string()), output.stdout_string());
+/// assert!(cassette.run(Command::new("echo").arg("bye")).is_err());
+/// ```
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    runner: Runner,
+    /// Every output recorded, and (in replay mode) whether it's been played back yet (see [`next_output`])
+    outputs: Mutex<Vec<(CmdOutput, bool)>>,
+}
+
+impl Cassette {
+    /// Creates a cassette which runs commands for real, for [`Cassette::save`] to write to `path`
+    pub fn record<P: AsRef<Path>>(path: P) -> Self {
+        return Cassette {
+            path: path.as_ref().to_path_buf(),
+            mode: CassetteMode::Record,
+            runner: Runner::new(),
+            outputs: Mutex::new(Vec::new()),
+        };
+    }
+
+    /// Loads a cassette saved to `path`, to play back the outputs it recorded
+    pub fn replay<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let json = fs::read_to_string(path.as_ref())?;
+        let outputs: Vec<CmdOutput> = serde_json::from_str(&json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        return Ok(Cassette {
+            path: path.as_ref().to_path_buf(),
+            mode: CassetteMode::Replay,
+            runner: Runner::new(),
+            outputs: Mutex::new(outputs.into_iter().map(|output| (output, false)).collect()),
+        });
+    }
+
+    /// Sets the runner used to run commands while recording
+    ///
+    /// <small>This does nothing when replaying</small>
+    pub fn runner(mut self, runner: Runner) -> Self {
+        self.runner = runner;
+        return self;
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        return self.mode;
+    }
+
+    /// Runs a command, or returns the output recorded for it
+    ///
+    /// When replaying, this returns [`Error::NotRecorded`] if nothing was recorded for the command.
+    pub fn run(&self, command: &mut Command) -> Result<CmdOutput, Error> {
+        match self.mode {
+            CassetteMode::Record => {
+                let output = self.runner.run(command)?;
+                self.outputs.lock().unwrap().push((output.clone(), false));
+                return Ok(output);
+            }
+            CassetteMode::Replay => {
+                let command_line = command_line(command);
+                return next_output(&mut self.outputs.lock().unwrap(), &command_line)
+                    .ok_or(Error::NotRecorded(command_line));
+            }
+        }
+    }
+
+    /// Writes everything recorded so far to the cassette's file
+    ///
+    /// <small>This does nothing when replaying</small>
+    pub fn save(&self) -> io::Result<()> {
+        if self.mode == CassetteMode::Replay {
+            return Ok(());
+        }
+        let outputs = self.outputs.lock().unwrap();
+        let outputs: Vec<&CmdOutput> = outputs.iter().map(|(output, _)| output).collect();
+        let json = serde_json::to_string_pretty(&outputs)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        return fs::write(&self.path, json);
+    }
+}
+
+impl CommandRunner for Cassette {
+    fn run(&self, command: &mut Command) -> Result<CmdOutput, Error> {
+        return Cassette::run(self, command);
+    }
+}
@@ -0,0 +1,240 @@
+//! Running commands in their own cgroup (v2), to cap and measure the whole process tree
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Used to give each cgroup a unique name
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Limits for the cgroup a command is run in (see [`Runner::cgroup`](crate::Runner::cgroup))
+///
+/// The limits cover the command and everything it starts. Each command gets a new cgroup, which is removed once it
+/// exits, after killing anything still left in it.
+///
+/// By default, the cgroup is created under this process's own cgroup. That needs write access to it, and for the
+/// limits to work, the `memory` and `cpu` controllers have to be available in it; use [`CgroupLimits::parent`] to
+/// create it somewhere else (e.g. a cgroup delegated to this process by systemd).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CgroupLimits {
+    memory_max: Option<u64>,
+    cpu_max: Option<f64>,
+    parent: Option<PathBuf>,
+}
+
+impl CgroupLimits {
+    pub fn new() -> Self {
+        return CgroupLimits::default();
+    }
+
+    /// Sets the most memory the command and everything it starts can use, in bytes (`memory.max`)
+    ///
+    /// If they need more and it can't be reclaimed, the OOM killer kills one of them.
+    pub fn memory_max(mut self, bytes: u64) -> Self {
+        self.memory_max = Some(bytes);
+        return self;
+    }
+
+    /// Sets how many CPUs' worth of time the command and everything it starts can use, e.g. `0.5` for half of one
+    /// (`cpu.max`)
+    pub fn cpu_max(mut self, cpus: f64) -> Self {
+        self.cpu_max = Some(cpus);
+        return self;
+    }
+
+    /// Creates the cgroup in `path` (e.g. `/sys/fs/cgroup/my-service`), rather than this process's own cgroup
+    pub fn parent<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.parent = Some(path.as_ref().to_path_buf());
+        return self;
+    }
+}
+
+/// What the cgroup a command was run in recorded (see [`CmdOutput::cgroup_stats`](crate::CmdOutput::cgroup_stats))
+///
+/// Unlike [`ResourceUsage`](crate::ResourceUsage), these cover everything the command started, too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CgroupStats {
+    /// The most memory used at once, in bytes, if the `memory` controller was enabled (and the kernel is new enough to
+    /// report it, 5.19 or later)
+    pub memory_peak: Option<u64>,
+    /// Whether the OOM killer killed anything for going over [`CgroupLimits::memory_max`]
+    pub oom_killed: bool,
+    /// The total CPU time used
+    pub cpu_time: Option<Duration>,
+}
+
+/// A cgroup made for one command, which is removed when this is dropped
+#[derive(Debug)]
+pub(crate) struct Cgroup {
+    path: PathBuf,
+    /// `cgroup.procs`, which the child writes itself to before it runs the command
+    ///
+    /// This is only kept to hold the file descriptor open
+    _procs: File,
+    /// The file descriptor of `procs`, for the child to use
+    pub(crate) procs_fd: i32,
+}
+
+impl Cgroup {
+    /// Creates a cgroup with `limits`
+    #[cfg(target_os = "linux")]
+    pub(crate) fn create(limits: &CgroupLimits) -> io::Result<Cgroup> {
+        use std::os::fd::AsRawFd;
+
+        let parent = match &limits.parent {
+            Some(parent) => parent.clone(),
+            None => own_cgroup()?,
+        };
+        let mut controllers = Vec::new();
+        if limits.memory_max.is_some() {
+            controllers.push("memory");
+        }
+        if limits.cpu_max.is_some() {
+            controllers.push("cpu");
+        }
+        for controller in controllers {
+            enable_controller(&parent, controller)?;
+        }
+
+        let name = format!(
+            "better-commands-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = parent.join(name);
+        fs::create_dir(&path)?;
+        let procs = match OpenOptions::new()
+            .write(true)
+            .open(path.join("cgroup.procs"))
+        {
+            Ok(procs) => procs,
+            Err(e) => {
+                let _ = fs::remove_dir(&path);
+                return Err(e);
+            }
+        };
+        let cgroup = Cgroup {
+            procs_fd: procs.as_raw_fd(),
+            _procs: procs,
+            path,
+        };
+        if let Some(bytes) = limits.memory_max {
+            cgroup.write("memory.max", &bytes.to_string())?;
+        }
+        if let Some(cpus) = limits.cpu_max {
+            const PERIOD: u64 = 100_000;
+            let quota = ((cpus * PERIOD as f64) as u64).max(1000);
+            cgroup.write("cpu.max", &format!("{} {}", quota, PERIOD))?;
+        }
+        return Ok(cgroup);
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn create(_limits: &CgroupLimits) -> io::Result<Cgroup> {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "cgroups are only supported on Linux",
+        ));
+    }
+
+    fn write(&self, file: &str, contents: &str) -> io::Result<()> {
+        return fs::write(self.path.join(file), contents).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("failed to write {}: {}", self.path.join(file).display(), e),
+            )
+        });
+    }
+
+    fn read(&self, file: &str) -> Option<String> {
+        return fs::read_to_string(self.path.join(file)).ok();
+    }
+
+    /// Kills everything in the cgroup
+    pub(crate) fn kill(&self) -> io::Result<()> {
+        return self.write("cgroup.kill", "1");
+    }
+
+    pub(crate) fn stats(&self) -> CgroupStats {
+        let field = |file: &str, key: &str| -> Option<u64> {
+            return self.read(file)?.lines().find_map(|line| {
+                let (name, value) = line.split_once(' ')?;
+                return (name == key).then(|| value.parse().ok())?;
+            });
+        };
+        return CgroupStats {
+            memory_peak: self
+                .read("memory.peak")
+                .and_then(|peak| peak.trim().parse().ok()),
+            oom_killed: field("memory.events", "oom_kill").is_some_and(|kills| kills > 0),
+            cpu_time: field("cpu.stat", "usage_usec").map(Duration::from_micros),
+        };
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        let _ = self.kill();
+        // it can only be removed once everything in it has exited, which takes a moment after killing them
+        for _ in 0..100 {
+            match fs::remove_dir(&self.path) {
+                Err(e) if e.kind() == io::ErrorKind::ResourceBusy => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+/// Returns the directory of this process's cgroup, going by `/proc/self/cgroup` and where cgroup2 is mounted
+#[cfg(target_os = "linux")]
+fn own_cgroup() -> io::Result<PathBuf> {
+    let unsupported = |reason: &str| io::Error::new(io::ErrorKind::Unsupported, reason.to_string());
+    let cgroups = fs::read_to_string("/proc/self/cgroup")?;
+    let own = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| unsupported("this process isn't in a cgroup v2 hierarchy"))?;
+    let mounts = fs::read_to_string("/proc/self/mountinfo")?;
+    // the mount point is the fifth field, and the filesystem type comes after the " - " separator
+    let mount = mounts
+        .lines()
+        .find(|line| {
+            line.split_once(" - ")
+                .is_some_and(|(_, fs)| fs.starts_with("cgroup2 "))
+        })
+        .and_then(|line| line.split(' ').nth(4))
+        .ok_or_else(|| unsupported("cgroup2 isn't mounted"))?;
+    return Ok(Path::new(mount).join(own.trim_start_matches('/')));
+}
+
+/// Makes `controller` available to cgroups created in `parent`
+#[cfg(target_os = "linux")]
+fn enable_controller(parent: &Path, controller: &str) -> io::Result<()> {
+    let enabled = fs::read_to_string(parent.join("cgroup.subtree_control"))?;
+    if enabled
+        .split_whitespace()
+        .any(|enabled| enabled == controller)
+    {
+        return Ok(());
+    }
+    return fs::write(
+        parent.join("cgroup.subtree_control"),
+        format!("+{}", controller),
+    )
+    .map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "the {} controller isn't available in {}: {}",
+                controller,
+                parent.display(),
+                e
+            ),
+        )
+    });
+}
@@ -0,0 +1,117 @@
+use crate::{CmdOutput, Error, Runner};
+use std::process::Command;
+
+/// Runs commands one after another depending on whether the previous one succeeded, like `a && b || c` in a shell
+///
+/// As in a shell, each condition checks the result of the last command that actually ran, so in
+/// `Chain::new(a).and_then(b).or_else(c)`, `c` runs if either `a` or `b` failed.
+///
+/// ```
+/// use better_commands::Chain;
+/// use std::process::Command;
+///
+/// let output = Chain::new(Command::new("false"))
+///     .and_then(Command::new("echo"))
+///     .or_else(Command::new("true"))
+///     .run()
+///     .unwrap();
+///
+/// assert!(output.stages()[1].is_none()); // skipped
+/// assert!(output.success());
+/// ```
+#[derive(Debug)]
+pub struct Chain {
+    stages: Vec<(Condition, Command)>,
+    runner: Runner,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Condition {
+    Always,
+    /// `&&`
+    OnSuccess,
+    /// `||`
+    OnFailure,
+}
+
+impl Chain {
+    /// Creates a chain starting with `command`, which always runs
+    pub fn new(command: Command) -> Self {
+        return Chain {
+            stages: vec![(Condition::Always, command)],
+            runner: Runner::new(),
+        };
+    }
+
+    /// Runs `command` only if the last command that ran succeeded (`&&`)
+    pub fn and_then(mut self, command: Command) -> Self {
+        self.stages.push((Condition::OnSuccess, command));
+        return self;
+    }
+
+    /// Runs `command` only if the last command that ran failed (`||`)
+    pub fn or_else(mut self, command: Command) -> Self {
+        self.stages.push((Condition::OnFailure, command));
+        return self;
+    }
+
+    /// Sets the [`Runner`] used to run each command, e.g. to give each one a timeout
+    pub fn runner(mut self, runner: Runner) -> Self {
+        self.runner = runner;
+        return self;
+    }
+
+    /// Runs the chain, returning the output of every command that ran
+    ///
+    /// A command counts as successful if it exited with 0 and wasn't killed by the [`Runner`] (e.g. due to a timeout).
+    pub fn run(&mut self) -> Result<ChainOutput, Error> {
+        let mut stages: Vec<Option<CmdOutput>> = Vec::new();
+        let mut succeeded = true;
+        for (condition, command) in &mut self.stages {
+            let should_run = match condition {
+                Condition::Always => true,
+                Condition::OnSuccess => succeeded,
+                Condition::OnFailure => !succeeded,
+            };
+            if !should_run {
+                stages.push(None);
+                continue;
+            }
+
+            let output = self.runner.run(command)?;
+            succeeded = output.success();
+            stages.push(Some(output));
+        }
+        return Ok(ChainOutput { stages, succeeded });
+    }
+}
+
+/// The output of a [`Chain`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainOutput {
+    stages: Vec<Option<CmdOutput>>,
+    succeeded: bool,
+}
+
+impl ChainOutput {
+    /// Returns the output of each command in the chain, in order, or `None` for commands that were skipped
+    pub fn stages(&self) -> &[Option<CmdOutput>] {
+        return &self.stages;
+    }
+
+    /// Returns the output of the last command that ran
+    pub fn last_output(&self) -> &CmdOutput {
+        // the first command always runs
+        return self.stages.iter().rev().flatten().next().unwrap();
+    }
+
+    /// Returns whether the chain as a whole succeeded, i.e. whether the last command that ran succeeded
+    pub fn success(&self) -> bool {
+        return self.succeeded;
+    }
+
+    /// Returns the exit status code of the last command that ran, which is what a shell would report for the whole chain
+    pub fn status_code(&self) -> Option<i32> {
+        return self.last_output().status_code;
+    }
+}
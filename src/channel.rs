@@ -0,0 +1,242 @@
+//! Sending lines to a slow consumer without buffering them forever
+use crate::{Line, LineSink};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::Waker;
+use std::time::{Duration, Instant};
+
+/// What a [`ChannelSink`] does with a line when the channel's full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// Waits for there to be room, which holds up reading the output, and eventually the command itself once its pipe
+    /// fills up
+    #[default]
+    Block,
+    /// Throws away the oldest line in the channel to make room, so the consumer always gets the latest ones
+    DropOldest,
+    /// Throws away the new line, so the consumer gets the earliest ones
+    DropNewest,
+}
+
+/// Creates a channel holding up to `capacity` lines, returning a sink to give to [`Runner::sink`](crate::Runner::sink)
+/// and the receiving end
+///
+/// This is for handing lines to something slower than the command, like a UI, without them piling up in memory; when
+/// the channel's full, `backpressure` decides what happens. Panics if `capacity` is 0.
+///
+/// ```
+/// use better_commands::{line_channel, Backpressure, Runner};
+/// use std::process::Command;
+///
+/// let (sink, receiver) = line_channel(100, Backpressure::DropOldest);
+/// let runner = Runner::new().sink(sink);
+/// runner.run(Command::new("seq").arg("1000")).unwrap();
+///
+/// // nothing was reading while it ran, so only the last 100 lines are left
+/// assert_eq!(900, receiver.dropped());
+/// assert_eq!("901", receiver.recv().unwrap().content);
+/// ```
+///
+/// <small>The lines are still kept in the [`CmdOutput`](crate::CmdOutput) too; use
+/// [`Runner::max_output_lines`](crate::Runner::max_output_lines) to limit that as well.</small>
+pub fn line_channel(capacity: usize, backpressure: Backpressure) -> (ChannelSink, LineReceiver) {
+    assert!(capacity > 0, "a channel needs room for at least one line");
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            lines: VecDeque::new(),
+            senders: 1,
+            receiving: true,
+            dropped: 0,
+            waker: None,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        capacity,
+    });
+    let sink = ChannelSink {
+        shared: shared.clone(),
+        backpressure,
+    };
+    return (sink, LineReceiver { shared });
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+}
+
+#[derive(Debug)]
+struct State {
+    lines: VecDeque<Line>,
+    /// How many [`ChannelSink`]s are left; once there are none, nothing else will be sent
+    senders: usize,
+    /// Whether the [`LineReceiver`] is still around; if not, lines are just thrown away
+    receiving: bool,
+    dropped: u64,
+    /// The task waiting for a line, when it's being received as a [`LineStream`](crate::LineStream)
+    waker: Option<Waker>,
+}
+
+impl State {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        return self.state.lock().unwrap();
+    }
+}
+
+/// The sending end of a [`line_channel`], which can be cloned to send from several places
+#[derive(Debug)]
+pub struct ChannelSink {
+    shared: Arc<Shared>,
+    backpressure: Backpressure,
+}
+
+impl Clone for ChannelSink {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        return ChannelSink {
+            shared: self.shared.clone(),
+            backpressure: self.backpressure,
+        };
+    }
+}
+
+impl Drop for ChannelSink {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        state.wake();
+        drop(state);
+        // so a receiver waiting for more finds out there won't be any
+        self.shared.not_empty.notify_all();
+    }
+}
+
+impl LineSink for ChannelSink {
+    fn accept(&mut self, line: Line) {
+        let mut state = self.shared.lock();
+        if state.lines.len() >= self.shared.capacity {
+            match self.backpressure {
+                Backpressure::Block => {
+                    state = self
+                        .shared
+                        .not_full
+                        .wait_while(state, |state| {
+                            state.receiving && state.lines.len() >= self.shared.capacity
+                        })
+                        .unwrap();
+                }
+                Backpressure::DropOldest => {
+                    state.lines.pop_front();
+                    state.dropped += 1;
+                }
+                Backpressure::DropNewest => {
+                    state.dropped += 1;
+                    return;
+                }
+            }
+        }
+        if state.receiving {
+            state.lines.push_back(line);
+            state.wake();
+            self.shared.not_empty.notify_one();
+        }
+    }
+}
+
+/// The receiving end of a [`line_channel`]
+///
+/// Like [`std::sync::mpsc::Receiver`], it can be iterated over, which ends once every [`ChannelSink`] has been dropped
+/// (i.e. the [`Runner`](crate::Runner) it was given to, and its clones) and every line has been received.
+#[derive(Debug)]
+pub struct LineReceiver {
+    shared: Arc<Shared>,
+}
+
+impl LineReceiver {
+    /// Waits for the next line, returning `None` once there won't be any more
+    pub fn recv(&self) -> Option<Line> {
+        let state = self.shared.lock();
+        let state = self
+            .shared
+            .not_empty
+            .wait_while(state, |state| state.lines.is_empty() && state.senders > 0)
+            .unwrap();
+        return self.take(state);
+    }
+
+    /// Waits up to `timeout` for the next line
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Line> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        while state.lines.is_empty() && state.senders > 0 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+            state = self
+                .shared
+                .not_empty
+                .wait_timeout(state, remaining)
+                .unwrap()
+                .0;
+        }
+        return self.take(state);
+    }
+
+    /// Returns the next line if there's one waiting, without waiting for one
+    pub fn try_recv(&self) -> Option<Line> {
+        return self.take(self.shared.lock());
+    }
+
+    /// Returns how many lines have been thrown away so far, since the channel was full (see [`Backpressure`])
+    pub fn dropped(&self) -> u64 {
+        return self.shared.lock().dropped;
+    }
+
+    /// Returns the next line if there's one waiting, or whether there won't be any more; otherwise, `waker` is woken
+    /// once that changes
+    #[cfg(feature = "futures")]
+    pub(crate) fn poll_recv(&self, waker: &Waker) -> std::task::Poll<Option<Line>> {
+        let mut state = self.shared.lock();
+        if state.lines.is_empty() && state.senders > 0 {
+            state.waker = Some(waker.clone());
+            return std::task::Poll::Pending;
+        }
+        return std::task::Poll::Ready(self.take(state));
+    }
+
+    fn take(&self, mut state: MutexGuard<'_, State>) -> Option<Line> {
+        let line = state.lines.pop_front()?;
+        self.shared.not_full.notify_one();
+        return Some(line);
+    }
+}
+
+impl Iterator for LineReceiver {
+    type Item = Line;
+
+    fn next(&mut self) -> Option<Line> {
+        return self.recv();
+    }
+}
+
+impl Drop for LineReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiving = false;
+        state.lines.clear();
+        // so nothing's left waiting for room that'll never come
+        self.shared.not_full.notify_all();
+    }
+}
@@ -0,0 +1,131 @@
+//! Running commands inside containers
+use crate::{command_line, CmdOutput, CommandRunner, Error, Runner};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The exit code `docker exec` and `podman exec` use when they couldn't run anything, e.g. if the container isn't
+/// running
+const EXEC_FAILED: i32 = 125;
+
+/// Runs commands inside a running container with `docker exec` (or `podman exec`), returning the same [`CmdOutput`]
+/// as running them locally
+///
+/// The command's stdout and stderr are kept separate, so each [`Line`](crate::Line) has the right
+/// [`LineType`](crate::LineType), and the exit code is the command's. Since it implements [`CommandRunner`], code
+/// taking a `&impl CommandRunner` can run commands locally or in a container without any changes.
+///
+/// The command's program, arguments, working directory, and the environment variables set on it are passed on;
+/// everything else comes from the [`Runner`] (see [`ContainerRunner::runner`]), e.g. [`Runner::stdin`] to give the
+/// command some input.
+///
+/// ```no_run
+/// use better_commands::ContainerRunner;
+/// use std::process::Command;
+///
+/// let postgres = ContainerRunner::docker("my-postgres").user("postgres");
+/// let output = postgres.run(Command::new("psql").arg("-c").arg("select 1")).unwrap();
+/// assert_eq!(Some(0), output.status_code());
+/// ```
+///
+/// <small>Environment variables removed with [`Command::env_remove`] are still there, since `exec` can only set them.
+/// Killing a command (e.g. for a timeout) only kills the local `docker exec`, which might leave the command running in
+/// the container.</small>
+#[derive(Debug, Clone)]
+pub struct ContainerRunner {
+    program: PathBuf,
+    container: String,
+    user: Option<String>,
+    runner: Runner,
+}
+
+impl ContainerRunner {
+    /// Runs commands in `container` (a name or ID) with Docker
+    pub fn docker<S: Into<String>>(container: S) -> Self {
+        return ContainerRunner::with_program("docker", container);
+    }
+
+    /// Runs commands in `container` (a name or ID) with Podman
+    pub fn podman<S: Into<String>>(container: S) -> Self {
+        return ContainerRunner::with_program("podman", container);
+    }
+
+    /// Runs commands in `container` with `program`, which needs to take the same arguments as `docker exec`
+    pub fn with_program<P: AsRef<Path>, S: Into<String>>(program: P, container: S) -> Self {
+        return ContainerRunner {
+            program: program.as_ref().to_path_buf(),
+            container: container.into(),
+            user: None,
+            runner: Runner::new(),
+        };
+    }
+
+    /// Runs commands as `user` (a name or UID, optionally with `:group`) instead of the container's default user
+    pub fn user<S: Into<String>>(mut self, user: S) -> Self {
+        self.user = Some(user.into());
+        return self;
+    }
+
+    /// Sets the runner used to run `docker exec`, e.g. to give each command a timeout
+    pub fn runner(mut self, runner: Runner) -> Self {
+        self.runner = runner;
+        return self;
+    }
+
+    /// Returns the container the commands are run in
+    pub fn container(&self) -> &str {
+        return &self.container;
+    }
+
+    /// Runs `command` in the container, returning its output
+    ///
+    /// [`CmdOutput::command`] is the command as it would be run locally, not the `docker exec` command running it. If
+    /// nothing could be run at all (e.g. the container isn't running), this returns [`Error::Spawn`] with the error
+    /// from `docker`.
+    pub fn run(&self, command: &mut Command) -> Result<CmdOutput, Error> {
+        crate::check_not_empty(command)?;
+        let mut exec = Command::new(&self.program);
+        // `-i` passes stdin through, but there's no `-t`, since a TTY would mix stdout and stderr together
+        exec.arg("exec").arg("-i");
+        if let Some(user) = &self.user {
+            exec.arg("--user").arg(user);
+        }
+        if let Some(dir) = command.get_current_dir() {
+            exec.arg("--workdir").arg(dir);
+        }
+        for (key, value) in command.get_envs() {
+            if let Some(value) = value {
+                let mut assignment = key.to_os_string();
+                assignment.push("=");
+                assignment.push(value);
+                exec.arg("--env").arg(assignment);
+            }
+        }
+        exec.arg("--")
+            .arg(&self.container)
+            .arg(command.get_program())
+            .args(command.get_args());
+
+        let mut output = self.runner.run(&mut exec)?;
+        if output.status_code == Some(EXEC_FAILED) && output.kill_reason.is_none() {
+            let message = format!(
+                "couldn't run command in {}: {}",
+                self.container,
+                output.stderr_string().unwrap_or_default()
+            );
+            return Err(Error::Spawn(io::Error::other(message)));
+        }
+        let command_line = command_line(command);
+        for attempt in &mut output.failed_attempts {
+            attempt.command = command_line.clone();
+        }
+        output.command = command_line;
+        return Ok(output);
+    }
+}
+
+impl CommandRunner for ContainerRunner {
+    fn run(&self, command: &mut Command) -> Result<CmdOutput, Error> {
+        return ContainerRunner::run(self, command);
+    }
+}
@@ -0,0 +1,118 @@
+//! Letting the functions handling a command's output stop it early
+use crate::{command_line, CmdOutput};
+use std::io::{self, BufRead, BufReader, Lines};
+use std::process::{Child, ChildStderr, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often to check whether the command has exited, since it can't be waited for while it might need killing
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A handle to the command, given to the functions passed to [`run_funcs_with_control`]
+///
+/// It can be cloned and sent to other threads, and keeps working (doing nothing) once the command has exited.
+#[derive(Debug, Clone)]
+pub struct RunControl {
+    child: Arc<Mutex<Child>>,
+    pid: u32,
+    start: Instant,
+}
+
+impl RunControl {
+    /// Kills the command straight away, if it's still running
+    ///
+    /// Its output is closed, so the functions reading it will run out of lines soon after. The [`CmdOutput`] has the
+    /// signal it was killed with (see [`CmdOutput::signal`]).
+    ///
+    /// <small>Only the command itself is killed, not anything it started; those keep its output open if they inherited
+    /// it, so the functions won't run out of lines until they exit too.</small>
+    pub fn kill(&self) -> io::Result<()> {
+        let mut child = self.child.lock().unwrap();
+        // once it's been reaped, its PID could belong to something else
+        if child.try_wait()?.is_some() {
+            return Ok(());
+        }
+        return child.kill();
+    }
+
+    /// Returns how long the command has been running
+    pub fn elapsed(&self) -> Duration {
+        return self.start.elapsed();
+    }
+
+    /// Returns the OS-assigned process ID of the command
+    pub fn pid(&self) -> u32 {
+        return self.pid;
+    }
+
+    /// Waits for the command to exit, without holding the lock so it can still be killed
+    fn wait(&self) -> io::Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.child.lock().unwrap().try_wait()? {
+                return Ok(status);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Same as [`run_funcs`](crate::run_funcs), but each function also gets a [`RunControl`], so it can kill the command
+/// as soon as it sees something it doesn't like
+///
+/// ```
+/// use better_commands::run_funcs_with_control;
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// let output = run_funcs_with_control(
+///     Command::new("bash").arg("-c").arg("echo 'FATAL: out of disk space'; exec sleep 60"),
+///     |lines, control| {
+///         for line in lines {
+///             if line.unwrap().starts_with("FATAL") {
+///                 control.kill().unwrap();
+///             }
+///         }
+///     },
+///     |_, _| {},
+/// );
+///
+/// assert!(output.clone().duration() < Duration::from_secs(60));
+/// assert_eq!(None, output.status_code());
+/// ```
+pub fn run_funcs_with_control(
+    command: &mut Command,
+    stdout_func: impl FnOnce(Lines<BufReader<ChildStdout>>, RunControl) + Send + 'static,
+    stderr_func: impl FnOnce(Lines<BufReader<ChildStderr>>, RunControl) + Send + 'static,
+) -> CmdOutput {
+    let start = Instant::now();
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let stdout_lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let stderr_lines = BufReader::new(child.stderr.take().unwrap()).lines();
+    let control = RunControl {
+        pid: child.id(),
+        child: Arc::new(Mutex::new(child)),
+        start,
+    };
+
+    let stdout_control = control.clone();
+    let stdout_thread = thread::spawn(move || stdout_func(stdout_lines, stdout_control));
+    let stderr_control = control.clone();
+    let stderr_thread = thread::spawn(move || stderr_func(stderr_lines, stderr_control));
+
+    let status = control.wait().unwrap();
+    let end = Instant::now();
+
+    stdout_thread.join().unwrap();
+    stderr_thread.join().unwrap();
+
+    let mut output = CmdOutput::new(command_line(command), None, status.code(), start, end);
+    output.set_exit_status(status);
+    crate::finished(command, &output);
+    return output;
+}
@@ -0,0 +1,98 @@
+//! Options every [`Runner`] starts with, set once for the whole application
+use crate::{Backend, Backoff, CarriageReturns, LongLines, Runner};
+use std::sync::RwLock;
+use std::time::Duration;
+
+static DEFAULTS: RwLock<Option<Defaults>> = RwLock::new(None);
+
+/// The options [`Runner::new`] starts with, set with [`configure`]
+///
+/// Everything here matches the [`Runner`] builder method of the same name, and defaults to what a [`Runner`] does
+/// without it, so only the fields you care about need setting.
+#[derive(Debug, Clone, Default)]
+pub struct Defaults {
+    /// See [`Runner::timeout`]
+    pub timeout: Option<Duration>,
+    /// See [`Runner::retries`]
+    pub retries: u32,
+    /// See [`Runner::backoff`]
+    pub backoff: Backoff,
+    /// See [`Runner::process_group`]
+    pub process_group: bool,
+    /// See [`Runner::strip_ansi`]
+    pub strip_ansi: bool,
+    /// See [`Runner::keep_raw`]; invalid UTF-8 is always replaced in [`Line::content`](crate::Line::content), so this
+    /// is how to get the original bytes
+    pub keep_raw: bool,
+    /// See [`Runner::carriage_returns`]
+    pub carriage_returns: CarriageReturns,
+    /// See [`Runner::max_line_length`]
+    pub max_line_length: Option<(usize, LongLines)>,
+    /// See [`Runner::max_output_bytes`]
+    pub max_output_bytes: Option<u64>,
+    /// See [`Runner::max_output_lines`]
+    pub max_output_lines: Option<u64>,
+    /// See [`Runner::backend`]
+    pub backend: Backend,
+}
+
+impl Defaults {
+    /// Sets these options on `runner`
+    pub(crate) fn apply(&self, mut runner: Runner) -> Runner {
+        if let Some(timeout) = self.timeout {
+            runner = runner.timeout(timeout);
+        }
+        runner = runner
+            .retries(self.retries)
+            .backoff(self.backoff)
+            .process_group(self.process_group)
+            .strip_ansi(self.strip_ansi)
+            .keep_raw(self.keep_raw)
+            .carriage_returns(self.carriage_returns)
+            .backend(self.backend);
+        if let Some((bytes, long_lines)) = self.max_line_length {
+            runner = runner.max_line_length(bytes, long_lines);
+        }
+        if let Some(bytes) = self.max_output_bytes {
+            runner = runner.max_output_bytes(bytes);
+        }
+        if let Some(lines) = self.max_output_lines {
+            runner = runner.max_output_lines(lines);
+        }
+        return runner;
+    }
+}
+
+/// Sets the options every [`Runner`] made with [`Runner::new`] starts with, from then on
+///
+/// This is for setting policies once at startup instead of at every call site; they can still be changed on each
+/// [`Runner`] afterwards. Calling this again replaces the defaults entirely.
+///
+/// ```
+/// use better_commands::{configure, Defaults, KillReason, Runner};
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// configure(Defaults {
+///     timeout: Some(Duration::from_millis(100)),
+///     strip_ansi: true,
+///     ..Defaults::default()
+/// });
+///
+/// let output = Runner::new().run(Command::new("sleep").arg("1")).unwrap();
+/// assert_eq!(Some(KillReason::Timeout), output.kill_reason());
+/// ```
+///
+/// <small>The functions which don't use a [`Runner`], like [`run`](crate::run) and [`run_funcs`](crate::run_funcs),
+/// aren't affected; neither is [`Runner::default`].</small>
+pub fn configure(defaults: Defaults) {
+    *DEFAULTS.write().unwrap() = Some(defaults);
+}
+
+/// Creates a [`Runner`] with the defaults from [`configure`], if it's been called
+pub(crate) fn new_runner() -> Runner {
+    return match &*DEFAULTS.read().unwrap() {
+        Some(defaults) => defaults.apply(Runner::default()),
+        None => Runner::default(),
+    };
+}
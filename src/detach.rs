@@ -0,0 +1,180 @@
+//! Starting commands which keep running on their own, like daemons
+use crate::{check_not_empty, Error};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Starts a command fully detached from this process, with its output thrown away
+///
+/// See [`DetachOptions`] for how it's detached, and to save its output to files instead.
+pub fn spawn_detached(command: &mut Command) -> Result<DetachedChild, Error> {
+    return DetachOptions::new().spawn(command);
+}
+
+/// Options for starting a command detached from this process, like a daemon
+///
+/// On Unix, the command is double-forked into a new session, so it has no controlling terminal and isn't this
+/// process's child; it won't be killed when this process exits or its terminal is closed, and doesn't need to be
+/// waited for. On Windows, it's started with `DETACHED_PROCESS` in a new process group.
+///
+/// Its stdin is always `/dev/null` (or `NUL`), and its stdout and stderr are thrown away unless they're saved to files
+/// (which are appended to, and created if they don't exist).
+///
+/// ```
+/// use better_commands::DetachOptions;
+/// use std::process::Command;
+///
+/// let log = std::env::temp_dir().join("detached-example.log");
+/// let child = DetachOptions::new()
+///     .stdout(&log)
+///     .stderr(&log)
+///     .spawn(Command::new("bash").arg("-c").arg("echo started"))
+///     .unwrap();
+/// println!("started {} (logging to {})", child.pid(), child.stdout_path().unwrap().display());
+/// ```
+///
+/// <small>On Unix, this uses [`CommandExt::pre_exec`](std::os::unix::process::CommandExt::pre_exec), which stays set
+/// on `command` afterwards, so don't reuse it for anything else</small>
+#[derive(Debug, Clone, Default)]
+pub struct DetachOptions {
+    stdout: Option<PathBuf>,
+    stderr: Option<PathBuf>,
+}
+
+impl DetachOptions {
+    pub fn new() -> Self {
+        return DetachOptions::default();
+    }
+
+    /// Appends the command's stdout to `path`
+    pub fn stdout<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.stdout = Some(path.as_ref().to_path_buf());
+        return self;
+    }
+
+    /// Appends the command's stderr to `path`, which can be the same file as stdout
+    pub fn stderr<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.stderr = Some(path.as_ref().to_path_buf());
+        return self;
+    }
+
+    /// Starts the command, returning once it's been started
+    pub fn spawn(&self, command: &mut Command) -> Result<DetachedChild, Error> {
+        check_not_empty(command)?;
+        command
+            .stdin(Stdio::null())
+            .stdout(output_file(self.stdout.as_deref()).map_err(Error::Spawn)?)
+            .stderr(output_file(self.stderr.as_deref()).map_err(Error::Spawn)?);
+        let pid = spawn(command).map_err(Error::Spawn)?;
+        return Ok(DetachedChild {
+            pid,
+            stdout: self.stdout.clone(),
+            stderr: self.stderr.clone(),
+        });
+    }
+}
+
+fn output_file(path: Option<&Path>) -> io::Result<Stdio> {
+    return match path {
+        Some(path) => {
+            let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+            Ok(Stdio::from(file))
+        }
+        None => Ok(Stdio::null()),
+    };
+}
+
+/// Forks twice so the command ends up in its own session, not as this process's child, returning its PID
+#[cfg(unix)]
+fn spawn(command: &mut Command) -> io::Result<u32> {
+    use std::io::Read;
+    use std::os::fd::FromRawFd;
+    use std::os::unix::process::CommandExt;
+
+    // the middle process sends the command's PID back through this
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let (mut pid_reader, pid_writer) =
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    let write_fd = fds[1];
+    for fd in fds {
+        // so the command itself doesn't keep the pipe open after it's started
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    unsafe {
+        // only async-signal-safe functions can be used in here
+        command.pre_exec(move || {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            match libc::fork() {
+                -1 => return Err(io::Error::last_os_error()),
+                // the grandchild, which carries on to run the command; it isn't the session leader, so it can't get a
+                // controlling terminal again
+                0 => return Ok(()),
+                pid => {
+                    let pid = pid as u32;
+                    libc::write(
+                        write_fd,
+                        &pid as *const u32 as *const libc::c_void,
+                        std::mem::size_of::<u32>(),
+                    );
+                    libc::_exit(0);
+                }
+            }
+        });
+    }
+    // this returns once the grandchild has started the command (or failed to)
+    let mut middle = command.spawn()?;
+    drop(pid_writer);
+    middle.wait()?;
+
+    let mut pid = [0; 4];
+    pid_reader.read_exact(&mut pid)?;
+    return Ok(u32::from_ne_bytes(pid));
+}
+
+#[cfg(windows)]
+fn spawn(command: &mut Command) -> io::Result<u32> {
+    use std::os::windows::process::CommandExt;
+
+    const DETACHED_PROCESS: u32 = 0x00000008;
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+    return Ok(command
+        .creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP)
+        .spawn()?
+        .id());
+}
+
+/// A command started with [`spawn_detached`] or [`DetachOptions::spawn`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetachedChild {
+    pid: u32,
+    stdout: Option<PathBuf>,
+    stderr: Option<PathBuf>,
+}
+
+impl DetachedChild {
+    /// Returns the command's process ID
+    ///
+    /// <small>Once it's exited, this might belong to something else</small>
+    pub fn pid(&self) -> u32 {
+        return self.pid;
+    }
+
+    /// Returns the file its stdout is saved to, if it is
+    pub fn stdout_path(&self) -> Option<&Path> {
+        return self.stdout.as_deref();
+    }
+
+    /// Returns the file its stderr is saved to, if it is
+    pub fn stderr_path(&self) -> Option<&Path> {
+        return self.stderr.as_deref();
+    }
+}
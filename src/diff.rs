@@ -0,0 +1,59 @@
+//! Line-by-line diffs, for snapshots and [`CmdOutput::diff`](crate::CmdOutput::diff)
+use std::fmt::Write;
+
+/// One step in turning the old lines into the new ones, by index into each
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DiffOp {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// Diffs two lists, using the longest common subsequence so unchanged lines line up
+///
+/// Removals come before additions where lines were changed, like `diff -u`.
+pub(crate) fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<DiffOp> {
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push(DiffOp::Same(i, j));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(DiffOp::Removed(i));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Added(j));
+            j += 1;
+        }
+    }
+    return ops;
+}
+
+/// Shows a diff with `-` before removed lines, `+` before added ones, and two spaces before the rest
+pub(crate) fn unified<S: AsRef<str>>(old: &[S], new: &[S]) -> String {
+    let old: Vec<&str> = old.iter().map(AsRef::as_ref).collect();
+    let new: Vec<&str> = new.iter().map(AsRef::as_ref).collect();
+    let mut shown = String::new();
+    for op in diff(&old, &new) {
+        let _ = match op {
+            DiffOp::Same(i, _) => writeln!(shown, "  {}", old[i]),
+            DiffOp::Removed(i) => writeln!(shown, "- {}", old[i]),
+            DiffOp::Added(j) => writeln!(shown, "+ {}", new[j]),
+        };
+    }
+    return shown;
+}
@@ -0,0 +1,166 @@
+//! Controlling the environment commands run with
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// A variable whose value was changed for a command, compared to this process's environment (see
+/// [`CmdOutput::envs_diff`](crate::CmdOutput::envs_diff))
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnvChange {
+    pub key: String,
+    /// The value this process has, if it's set
+    pub before: Option<String>,
+    /// The value the command got, if it was set
+    pub after: Option<String>,
+}
+
+/// Clears `command`'s environment, except for `keep` and anything set on the [`Command`] itself
+///
+/// Each variable is removed one at a time rather than with [`Command::env_clear`], which [`SavedEnv::restore`]
+/// couldn't undo. A variable set to the same value this process has counts as inherited, since that's what restoring
+/// leaves behind.
+pub(crate) fn clear_except(command: &mut Command, keep: &[OsString]) {
+    for (key, value) in std::env::vars_os() {
+        let set_on_command = command
+            .get_envs()
+            .any(|(k, v)| k == key && v != Some(value.as_os_str()));
+        if !set_on_command && !keep.contains(&key) {
+            command.env_remove(key);
+        }
+    }
+}
+
+/// The variables set on a [`Command`] before a [`Runner`](crate::Runner) changed them, so they can be put back once
+/// it's been started
+pub(crate) struct SavedEnv(Vec<(OsString, Option<OsString>)>);
+
+impl SavedEnv {
+    pub(crate) fn save(command: &Command) -> Self {
+        return SavedEnv(
+            command
+                .get_envs()
+                .map(|(key, value)| (key.to_os_string(), value.map(OsStr::to_os_string)))
+                .collect(),
+        );
+    }
+
+    /// Puts `command`'s variables back how they were
+    ///
+    /// [`Command`] can't forget a variable once it's been set, so ones which weren't set before are set to this
+    /// process's value instead (or removed, if it doesn't have one), which is what they'd have inherited.
+    pub(crate) fn restore(self, command: &mut Command) {
+        let changed: Vec<OsString> = command
+            .get_envs()
+            .map(|(key, _)| key.to_os_string())
+            .collect();
+        for key in changed {
+            let value = match self.0.iter().find(|(k, _)| *k == key) {
+                Some((_, value)) => value.clone(),
+                None => std::env::var_os(&key),
+            };
+            match value {
+                Some(value) => command.env(key, value),
+                None => command.env_remove(key),
+            };
+        }
+    }
+}
+
+/// Reads a `.env` file, returning the variables in it in order
+///
+/// Each line is `KEY=value`, optionally starting with `export `; values can be wrapped in single quotes (taken
+/// literally) or double quotes (which support `\n`, `\"`, and `\\`). Blank lines and lines starting with `#` are skipped.
+pub(crate) fn read_env_file(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let contents = fs::read_to_string(path)?;
+    let mut vars = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let invalid = |reason: &str| {
+            return io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", i + 1, reason),
+            );
+        };
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid("expected KEY=value"))?;
+        let key = key.trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(invalid("invalid variable name"));
+        }
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            quoted
+                .strip_suffix('\'')
+                .ok_or_else(|| invalid("unclosed single quote"))?
+                .to_string()
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            let quoted = quoted
+                .strip_suffix('"')
+                .ok_or_else(|| invalid("unclosed double quote"))?;
+            unescape(quoted)
+        } else {
+            // unquoted values can have a comment after them
+            match value.find(" #") {
+                Some(comment) => value[..comment].trim_end().to_string(),
+                None => value.to_string(),
+            }
+        };
+        vars.push((key.to_string(), value));
+    }
+    return Ok(vars);
+}
+
+fn unescape(quoted: &str) -> String {
+    let mut value = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            Some(other) => value.push(other),
+            None => value.push('\\'),
+        }
+    }
+    return value;
+}
+
+/// Works out which variables `command` will get a different value for than this process has
+///
+/// [`Command`] doesn't say whether its environment was cleared, so this assumes it wasn't.
+pub(crate) fn diff(command: &Command) -> Vec<EnvChange> {
+    let parent: BTreeMap<OsString, OsString> = std::env::vars_os().collect();
+    let mut child = parent.clone();
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => child.insert(key.to_os_string(), value.to_os_string()),
+            None => child.remove(key),
+        };
+    }
+
+    let mut keys: Vec<&OsString> = parent.keys().chain(child.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    let lossy = |value: Option<&OsString>| value.map(|value| value.to_string_lossy().into_owned());
+    return keys
+        .into_iter()
+        .filter(|key| parent.get(*key) != child.get(*key))
+        .map(|key| EnvChange {
+            key: key.to_string_lossy().into_owned(),
+            before: lossy(parent.get(key)),
+            after: lossy(child.get(key)),
+        })
+        .collect();
+}
@@ -0,0 +1,178 @@
+use crate::{exit_signal, CmdOutput, KillReason, LineType, TemplateError};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+/// An error from running a command
+#[derive(Debug)]
+pub enum Error {
+    /// The command couldn't be started
+    Spawn(io::Error),
+    /// Something went wrong while reading the command's output or waiting for it to exit
+    Io(io::Error),
+    /// The command wasn't run because it would have exceeded a [`RateLimiter`](crate::RateLimiter)'s limits
+    RateLimited,
+    /// A template couldn't be expanded (see [`Runner::env_templated`](crate::Runner::env_templated))
+    Template(TemplateError),
+    /// An environment file couldn't be read or parsed (see [`Runner::env_from_file`](crate::Runner::env_from_file))
+    EnvFile(PathBuf, io::Error),
+    /// The command's program was empty, e.g. from parsing an empty string, so there was nothing to run
+    EmptyCommand,
+    /// The command exited or timed out before printing a matching line (see
+    /// [`Runner::run_until_match`](crate::Runner::run_until_match)); this has everything it printed
+    NoMatch(Box<CmdOutput>),
+    /// The command printed a line longer than [`Runner::max_line_length`](crate::Runner::max_line_length) allows,
+    /// with [`LongLines::Fail`](crate::LongLines::Fail), so it was killed; this has everything it printed up to then
+    LineTooLong(Box<CmdOutput>),
+    /// A function handling the command's output returned an error or panicked (see
+    /// [`try_run_funcs`](crate::try_run_funcs))
+    Callback(Box<CallbackError>),
+    /// There's no output for this command line, from a [`FakeRunner`](crate::FakeRunner) or a `Cassette` being replayed
+    NotRecorded(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Spawn(e) => write!(f, "failed to start command: {}", e),
+            Error::Io(e) => write!(f, "failed to run command: {}", e),
+            Error::RateLimited => write!(f, "command was rate limited"),
+            Error::Template(e) => write!(f, "failed to expand template: {}", e),
+            Error::EnvFile(path, e) => {
+                write!(
+                    f,
+                    "failed to load environment file {}: {}",
+                    path.display(),
+                    e
+                )
+            }
+            Error::EmptyCommand => write!(f, "command has no program to run"),
+            Error::NoMatch(output) => match output.kill_reason() {
+                Some(KillReason::Timeout) => write!(f, "timed out waiting for a matching line"),
+                _ => write!(f, "command exited without printing a matching line"),
+            },
+            Error::LineTooLong(_) => write!(f, "command printed a line that was too long"),
+            Error::Callback(e) => write!(f, "{}", e),
+            Error::NotRecorded(command) => write!(f, "no output recorded for command: {}", command),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Spawn(e) | Error::Io(e) | Error::EnvFile(_, e) => Some(e),
+            Error::RateLimited
+            | Error::EmptyCommand
+            | Error::NoMatch(_)
+            | Error::LineTooLong(_) => None,
+            Error::Template(e) => Some(e),
+            Error::Callback(e) => Some(e.as_ref()),
+            Error::NotRecorded(_) => None,
+        }
+    }
+}
+
+/// A function handling one of a command's streams failed, from [`try_run_funcs`](crate::try_run_funcs) or
+/// [`try_run_funcs_with_lines`](crate::try_run_funcs_with_lines)
+#[derive(Debug)]
+pub struct CallbackError {
+    /// Which stream the function was handling
+    pub printed_to: LineType,
+    /// What went wrong
+    pub failure: CallbackFailure,
+    /// The command's output, since it's still waited for; with `try_run_funcs_with_lines`, this has the lines from
+    /// the other function if it succeeded
+    pub output: CmdOutput,
+}
+
+/// How a function handling a command's output failed (see [`CallbackError`])
+#[derive(Debug)]
+pub enum CallbackFailure {
+    /// It returned an error
+    Error(Box<dyn std::error::Error + Send + Sync>),
+    /// It panicked, with this message
+    Panic(String),
+}
+
+impl fmt::Display for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match &self.failure {
+            CallbackFailure::Error(e) => write!(f, "{} handler failed: {}", self.printed_to, e),
+            CallbackFailure::Panic(message) => {
+                write!(f, "{} handler panicked: {}", self.printed_to, message)
+            }
+        };
+    }
+}
+
+impl std::error::Error for CallbackError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match &self.failure {
+            CallbackFailure::Error(e) => Some(e.as_ref()),
+            CallbackFailure::Panic(_) => None,
+        };
+    }
+}
+
+/// A command didn't succeed, from [`CmdOutput::exit_ok`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitStatusError {
+    pub(crate) command: String,
+    pub(crate) status: Option<ExitStatus>,
+    pub(crate) kill_reason: Option<KillReason>,
+}
+
+impl ExitStatusError {
+    /// Returns the command that was run, like [`CmdOutput::command`]
+    pub fn command(&self) -> &str {
+        return &self.command;
+    }
+
+    /// Returns the status it exited with, if there was one (see [`CmdOutput::exit_status`])
+    pub fn status(&self) -> Option<ExitStatus> {
+        return self.status;
+    }
+
+    /// Returns the status code it exited with, if it wasn't killed by a signal
+    pub fn code(&self) -> Option<i32> {
+        return self.status?.code();
+    }
+
+    /// Returns why it was killed, if it was killed by this crate
+    pub fn kill_reason(&self) -> Option<KillReason> {
+        return self.kill_reason;
+    }
+}
+
+impl fmt::Display for ExitStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` ", self.command)?;
+        return match (self.kill_reason, self.status) {
+            (Some(KillReason::Timeout), _) => write!(f, "timed out"),
+            (Some(KillReason::OutputLimit), _) => write!(f, "was killed for printing too much"),
+            (Some(KillReason::PatternMatched), _) => {
+                write!(f, "was killed for printing a matching line")
+            }
+            (Some(KillReason::LineTooLong), _) => {
+                write!(f, "was killed for printing a line that was too long")
+            }
+            (Some(KillReason::Interrupted), _) => write!(f, "was interrupted"),
+            (None, Some(status)) => match (status.code(), exit_signal(&status)) {
+                (Some(code), _) => write!(f, "exited with status {}", code),
+                (None, Some(signal)) => write!(f, "was killed by signal {}", signal),
+                (None, None) => write!(f, "exited with {}", status),
+            },
+            (None, None) => write!(f, "didn't exit normally"),
+        };
+    }
+}
+
+impl std::error::Error for ExitStatusError {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        return Error::Io(e);
+    }
+}
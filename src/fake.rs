@@ -0,0 +1,84 @@
+//! A [`CommandRunner`] which doesn't run anything, for tests
+use crate::{command_line, CmdOutput, CommandRunner, Error, Line};
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// A [`CommandRunner`] which returns outputs given to it ahead of time, instead of running anything
+///
+/// Outputs are given for a command line, as shown by [`CmdOutput::command`] (e.g. `"git status --short"`). If more
+/// than one is given for the same command line, they're returned in the order they were given; once they run out, the
+/// last one is returned again. Running a command with no outputs given returns [`Error::NotRecorded`].
+///
+/// Every command it's asked to run is kept, so tests can check what was run with [`FakeRunner::invocations`].
+///
+/// See [`CommandRunner`] for an example.
+#[derive(Debug, Default)]
+pub struct FakeRunner {
+    outputs: Mutex<Vec<(CmdOutput, bool)>>,
+    invocations: Mutex<Vec<String>>,
+}
+
+impl FakeRunner {
+    pub fn new() -> Self {
+        return FakeRunner::default();
+    }
+
+    /// Makes `command` exit with `status_code`, after printing `lines`
+    ///
+    /// <small>The lines' timestamps are kept, so use [`Line::from_stdout`] and [`Line::from_stderr`] in the order they
+    /// should have been printed</small>
+    pub fn respond<S: AsRef<str>>(self, command: S, status_code: i32, lines: Vec<Line>) -> Self {
+        let now = Instant::now();
+        let start = lines.iter().map(|line| line.time).min().unwrap_or(now);
+        let output = CmdOutput::new(
+            command.as_ref().to_string(),
+            Some(lines),
+            Some(status_code),
+            start,
+            now.max(start),
+        );
+        return self.respond_with(command, output);
+    }
+
+    /// Makes `command` return `output`, e.g. one loaded with `serde`
+    pub fn respond_with<S: AsRef<str>>(self, command: S, mut output: CmdOutput) -> Self {
+        output.command = command.as_ref().to_string();
+        self.outputs.lock().unwrap().push((output, false));
+        return self;
+    }
+
+    /// Returns the command lines of every command it's been asked to run, in order
+    pub fn invocations(&self) -> Vec<String> {
+        return self.invocations.lock().unwrap().clone();
+    }
+}
+
+impl CommandRunner for FakeRunner {
+    fn run(&self, command: &mut Command) -> Result<CmdOutput, Error> {
+        let command_line = command_line(command);
+        self.invocations.lock().unwrap().push(command_line.clone());
+        return next_output(&mut self.outputs.lock().unwrap(), &command_line)
+            .ok_or(Error::NotRecorded(command_line));
+    }
+}
+
+/// Returns the first output for `command_line` which hasn't been returned yet, or the last one if they all have been
+///
+/// Shared with `Cassette`, which plays back outputs the same way.
+pub(crate) fn next_output(
+    outputs: &mut [(CmdOutput, bool)],
+    command_line: &str,
+) -> Option<CmdOutput> {
+    let mut matching = outputs
+        .iter_mut()
+        .filter(|(output, _)| output.command == command_line)
+        .peekable();
+    while let Some((output, returned)) = matching.next() {
+        if !*returned || matching.peek().is_none() {
+            *returned = true;
+            return Some(output.clone());
+        }
+    }
+    return None;
+}
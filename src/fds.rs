@@ -0,0 +1,57 @@
+//! Extra file descriptors passed to commands, on Unix
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// Where the child moves each pipe to: the write end of the pipe, and the file descriptor it becomes
+pub(crate) type FdMapping = Vec<(i32, i32)>;
+
+/// The write ends of pipes for [`Runner::capture_fd`](crate::Runner::capture_fd), which are closed once the child has
+/// them, so reading stops when the command exits
+pub(crate) struct ExtraFds {
+    /// This is only kept to hold them open until then
+    _writers: Vec<OwnedFd>,
+    pub(crate) mapping: FdMapping,
+}
+
+impl ExtraFds {
+    /// Creates a pipe for each of `fds`, returning the read end of each one along with the fd it's for
+    pub(crate) fn create(fds: &[u32]) -> io::Result<(ExtraFds, Vec<(File, u32)>)> {
+        // every write end goes above all of `fds`, so moving one into place can't replace another
+        let lowest = fds.iter().max().map_or(3, |&fd| fd as i32 + 1);
+        let mut writers = Vec::new();
+        let mut readers = Vec::new();
+        for &fd in fds {
+            let (reader, writer) = io::pipe()?;
+            let moved = unsafe { libc::fcntl(writer.as_raw_fd(), libc::F_DUPFD_CLOEXEC, lowest) };
+            if moved == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            writers.push(unsafe { OwnedFd::from_raw_fd(moved) });
+            readers.push((File::from(OwnedFd::from(reader)), fd));
+        }
+        let mapping = writers
+            .iter()
+            .zip(fds)
+            .map(|(writer, &fd)| (writer.as_raw_fd(), fd as i32))
+            .collect();
+        return Ok((
+            ExtraFds {
+                _writers: writers,
+                mapping,
+            },
+            readers,
+        ));
+    }
+}
+
+/// Moves each pipe into place; called in the child before the command runs
+pub(crate) fn move_into_place(mapping: &[(i32, i32)]) -> io::Result<()> {
+    for &(writer, fd) in mapping {
+        // `dup2` clears close-on-exec on the new one, so the command gets it
+        if unsafe { libc::dup2(writer, fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    return Ok(());
+}
@@ -0,0 +1,153 @@
+//! Grouping failed runs by what went wrong
+use crate::{ansi, CmdOutput, KillReason};
+use regex::Regex;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Words which make a stderr line look like *the* error, rather than just noise around it
+const ERROR_WORDS: &[&str] = &[
+    "error",
+    "fatal",
+    "panic",
+    "exception",
+    "traceback",
+    "failed",
+    "failure",
+    "segmentation fault",
+    "abort",
+];
+
+/// A summary of why a run failed, which stays the same between runs failing the same way
+///
+/// This is built from the signal the command was killed by, its exit code, why this crate killed it (if it did), and
+/// the most relevant stderr line with anything likely to change between runs (numbers, addresses, hashes, and
+/// timestamps) replaced with placeholders. Two failures with the same fingerprint are probably the same problem, so
+/// this can be used to group recurring failures across lots of runs. See [`CmdOutput::fingerprint`].
+///
+/// [`Fingerprint::id`] gives a short, stable ID for it, and [`Display`](fmt::Display) shows it in a readable form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+    /// The signal the command was killed by, if any
+    pub signal: Option<i32>,
+    /// The command's exit code, if it had one
+    pub status_code: Option<i32>,
+    /// Why this crate killed the command, if it did
+    pub kill_reason: Option<KillReason>,
+    /// The normalized error line, if stderr had anything in it
+    pub error_line: Option<String>,
+}
+
+impl Fingerprint {
+    /// Returns a stable ID for this fingerprint, as 16 hex digits
+    ///
+    /// <small>This uses FNV-1a rather than [`std::hash::Hash`], so it doesn't change between Rust versions or
+    /// platforms</small>
+    pub fn id(&self) -> String {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in self.to_string().bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        return format!("{:016x}", hash);
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.signal, self.status_code) {
+            (Some(signal), _) => write!(f, "signal {}", signal)?,
+            (None, Some(code)) => write!(f, "exit {}", code)?,
+            (None, None) => write!(f, "no status")?,
+        }
+        match self.kill_reason {
+            Some(KillReason::Timeout) => write!(f, " (timeout)")?,
+            Some(KillReason::OutputLimit) => write!(f, " (output limit)")?,
+            Some(KillReason::PatternMatched) => write!(f, " (pattern matched)")?,
+            Some(KillReason::LineTooLong) => write!(f, " (line too long)")?,
+            Some(KillReason::Interrupted) => write!(f, " (interrupted)")?,
+            None => {}
+        }
+        if let Some(line) = &self.error_line {
+            write!(f, ": {}", line)?;
+        }
+        return Ok(());
+    }
+}
+
+impl CmdOutput {
+    /// Returns a [`Fingerprint`] of why the command failed, or `None` if it succeeded
+    ///
+    /// ```
+    /// use better_commands::run;
+    /// use std::process::Command;
+    ///
+    /// let first = run(Command::new("bash").arg("-c").arg(">&2 echo 'error: port 8080 in use'; exit 1"));
+    /// let second = run(Command::new("bash").arg("-c").arg(">&2 echo 'error: port 9090 in use'; exit 1"));
+    ///
+    /// let fingerprint = first.fingerprint().unwrap();
+    /// assert_eq!("exit 1: error: port <n> in use", fingerprint.to_string());
+    /// assert_eq!(fingerprint.id(), second.fingerprint().unwrap().id());
+    /// ```
+    pub fn fingerprint(&self) -> Option<Fingerprint> {
+        if self.success() {
+            return None;
+        }
+        return Some(Fingerprint {
+            signal: self.signal,
+            status_code: self.status_code,
+            kill_reason: self.kill_reason,
+            error_line: self.error_line().map(normalize),
+        });
+    }
+
+    /// Finds the first stderr line that looks like an error, falling back to the last non-empty one
+    fn error_line(&self) -> Option<&str> {
+        let stderr = self.stderr()?;
+        let error = stderr.iter().find(|line| {
+            let content = line.content.to_lowercase();
+            ERROR_WORDS.iter().any(|word| content.contains(word))
+        });
+        return error
+            .or_else(|| {
+                stderr
+                    .iter()
+                    .rev()
+                    .find(|line| !line.content.trim().is_empty())
+            })
+            .map(|line| line.content.as_str());
+    }
+}
+
+/// Removes colors and whitespace differences, and replaces things which change between runs with placeholders
+fn normalize(line: &str) -> String {
+    static PATTERNS: OnceLock<Vec<(Regex, &str)>> = OnceLock::new();
+    let patterns =
+        PATTERNS.get_or_init(|| {
+            return [
+            (
+                r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(\.\d+)?(Z|[+-]\d{2}:?\d{2})?",
+                "<time>",
+            ),
+            (r"\b\d{2}:\d{2}:\d{2}(\.\d+)?\b", "<time>"),
+            (
+                r"\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b",
+                "<uuid>",
+            ),
+            (r"\b0x[0-9a-fA-F]+\b", "<addr>"),
+            // things like commit hashes and build IDs, which have to have a letter and a digit in them (so this doesn't
+            // catch words like "deadbeef", or plain numbers)
+            (r"\b[0-9a-fA-F]*([0-9][a-fA-F]|[a-fA-F][0-9])[0-9a-fA-F]*\b", "<hex>"),
+            (r"\d+", "<n>"),
+            (r"\s+", " "),
+        ]
+        .into_iter()
+        .map(|(pattern, replacement)| (Regex::new(pattern).unwrap(), replacement))
+        .collect();
+        });
+
+    let mut line = ansi::strip(line).into_owned();
+    for (pattern, replacement) in patterns {
+        line = pattern.replace_all(&line, *replacement).into_owned();
+    }
+    return line.trim().to_string();
+}
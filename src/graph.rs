@@ -0,0 +1,255 @@
+use crate::{CmdOutput, Error, Runner};
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+
+/// Runs named commands which depend on each other, like a Makefile
+///
+/// Each task only runs once everything it depends on has succeeded; if any of them fail, it's skipped (along with
+/// everything depending on it), but tasks which don't depend on the failed one carry on. Up to
+/// [`TaskGraph::parallelism`] tasks run at once.
+///
+/// ```
+/// use better_commands::TaskGraph;
+/// use std::process::Command;
+///
+/// let mut test = Command::new("bash");
+/// test.arg("-c").arg("echo testing; exit 1");
+/// let output = TaskGraph::new()
+///     .task("build", Command::new("true"), &[])
+///     .task("lint", Command::new("true"), &["build"])
+///     .task("test", test, &["build"])
+///     .task("release", Command::new("true"), &["lint", "test"])
+///     .parallelism(2)
+///     .run()
+///     .unwrap();
+///
+/// assert!(!output.success());
+/// assert_eq!(vec!["test"], output.failed());
+/// assert_eq!(vec!["release"], output.skipped());
+/// assert_eq!("testing", output.get("test").unwrap().clone().lines().unwrap()[0].content);
+/// ```
+#[derive(Debug)]
+pub struct TaskGraph {
+    tasks: Vec<Task>,
+    runner: Runner,
+    parallelism: usize,
+}
+
+#[derive(Debug)]
+struct Task {
+    name: String,
+    command: Command,
+    /// The indexes of the tasks this depends on, which always come before it
+    depends_on: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Waiting,
+    Running,
+    Succeeded,
+    /// Failed, or skipped because something it depends on failed
+    Failed,
+}
+
+impl Default for TaskGraph {
+    fn default() -> Self {
+        return TaskGraph::new();
+    }
+}
+
+impl TaskGraph {
+    /// Creates an empty graph
+    pub fn new() -> Self {
+        return TaskGraph {
+            tasks: Vec::new(),
+            runner: Runner::new(),
+            parallelism: 1,
+        };
+    }
+
+    /// Adds a task called `name`, which runs `command` once every task in `depends_on` has succeeded
+    ///
+    /// Panics if there's already a task called `name`, or any of `depends_on` haven't been added yet (which means
+    /// there can't be any cycles).
+    pub fn task<S: Into<String>>(mut self, name: S, command: Command, depends_on: &[&str]) -> Self {
+        let name = name.into();
+        assert!(
+            self.index(&name).is_none(),
+            "there's already a task called {:?}",
+            name
+        );
+        let depends_on = depends_on
+            .iter()
+            .map(|dependency| match self.index(dependency) {
+                Some(index) => index,
+                None => panic!(
+                    "task {:?} depends on {:?}, which hasn't been added yet",
+                    name, dependency
+                ),
+            })
+            .collect();
+        self.tasks.push(Task {
+            name,
+            command,
+            depends_on,
+        });
+        return self;
+    }
+
+    /// Sets the [`Runner`] used to run each task, e.g. to give each one a timeout
+    pub fn runner(mut self, runner: Runner) -> Self {
+        self.runner = runner;
+        return self;
+    }
+
+    /// Sets how many tasks can run at the same time (1 by default)
+    ///
+    /// Panics if `parallelism` is 0.
+    pub fn parallelism(mut self, parallelism: usize) -> Self {
+        assert!(
+            parallelism > 0,
+            "a TaskGraph needs to be able to run at least one task"
+        );
+        self.parallelism = parallelism;
+        return self;
+    }
+
+    /// Runs every task, in an order that respects their dependencies, returning the output of each one
+    ///
+    /// A task counts as successful if it exited with 0 and wasn't killed by the [`Runner`] (e.g. due to a timeout). If
+    /// a task can't be run at all (e.g. its program doesn't exist), nothing else is started, and the error is returned
+    /// once the tasks that were already running finish.
+    pub fn run(&mut self) -> Result<TaskGraphOutput, Error> {
+        let count = self.tasks.len();
+        let depends_on: Vec<Vec<usize>> = self
+            .tasks
+            .iter()
+            .map(|task| task.depends_on.clone())
+            .collect();
+        let mut commands: Vec<Option<&mut Command>> = self
+            .tasks
+            .iter_mut()
+            .map(|task| Some(&mut task.command))
+            .collect();
+        let mut states = vec![State::Waiting; count];
+        let mut outputs: Vec<Option<CmdOutput>> = vec![None; count];
+        let mut error = None;
+        let runner = &self.runner;
+        let parallelism = self.parallelism;
+
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            let mut running = 0;
+            loop {
+                // dependencies always come first, so one pass is enough to skip everything depending on a failure
+                for index in 0..count {
+                    if error.is_some() || states[index] != State::Waiting {
+                        continue;
+                    }
+                    let dependencies = depends_on[index]
+                        .iter()
+                        .map(|&dependency| states[dependency]);
+                    if dependencies.clone().any(|state| state == State::Failed) {
+                        states[index] = State::Failed;
+                    } else if running < parallelism
+                        && dependencies.clone().all(|state| state == State::Succeeded)
+                    {
+                        states[index] = State::Running;
+                        running += 1;
+                        let command = commands[index].take().unwrap();
+                        let sender = sender.clone();
+                        scope.spawn(move || {
+                            let _ = sender.send((index, runner.run(command)));
+                        });
+                    }
+                }
+                if running == 0 {
+                    return;
+                }
+                let (index, result) = receiver.recv().unwrap();
+                running -= 1;
+                match result {
+                    Ok(output) => {
+                        states[index] = match output.success() {
+                            true => State::Succeeded,
+                            false => State::Failed,
+                        };
+                        outputs[index] = Some(output);
+                    }
+                    Err(e) => {
+                        states[index] = State::Failed;
+                        error.get_or_insert(e);
+                    }
+                }
+            }
+        });
+
+        if let Some(e) = error {
+            return Err(e);
+        }
+        let tasks = self
+            .tasks
+            .iter()
+            .zip(outputs)
+            .map(|(task, output)| (task.name.clone(), output))
+            .collect();
+        return Ok(TaskGraphOutput { tasks });
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        return self.tasks.iter().position(|task| task.name == name);
+    }
+}
+
+/// The output of a [`TaskGraph`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskGraphOutput {
+    tasks: Vec<(String, Option<CmdOutput>)>,
+}
+
+impl TaskGraphOutput {
+    /// Returns the name and output of each task, in the order they were added, with `None` for tasks that were
+    /// skipped
+    pub fn tasks(&self) -> &[(String, Option<CmdOutput>)] {
+        return &self.tasks;
+    }
+
+    /// Returns the output of the task called `name`, or `None` if it was skipped (or there's no such task)
+    pub fn get(&self, name: &str) -> Option<&CmdOutput> {
+        return self
+            .tasks
+            .iter()
+            .find(|(task, _)| task == name)
+            .and_then(|(_, output)| output.as_ref());
+    }
+
+    /// Returns whether every task ran and succeeded
+    pub fn success(&self) -> bool {
+        return self
+            .tasks
+            .iter()
+            .all(|(_, output)| output.as_ref().is_some_and(CmdOutput::success));
+    }
+
+    /// Returns the names of the tasks which ran and failed
+    pub fn failed(&self) -> Vec<&str> {
+        return self
+            .tasks
+            .iter()
+            .filter(|(_, output)| output.as_ref().is_some_and(|output| !output.success()))
+            .map(|(name, _)| name.as_str())
+            .collect();
+    }
+
+    /// Returns the names of the tasks which were skipped, since something they depend on failed
+    pub fn skipped(&self) -> Vec<&str> {
+        return self
+            .tasks
+            .iter()
+            .filter(|(_, output)| output.is_none())
+            .map(|(name, _)| name.as_str())
+            .collect();
+    }
+}
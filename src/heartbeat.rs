@@ -0,0 +1,65 @@
+//! Regular updates while a command is running
+use crate::capture::Capture;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub(crate) type HeartbeatCallback = Arc<Mutex<dyn FnMut(Heartbeat) + Send>>;
+
+/// How a command is doing, passed to [`Runner::heartbeat`](crate::Runner::heartbeat)'s callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// How long the command has been running for
+    pub elapsed: Duration,
+    /// The total lines printed so far (across every stream)
+    pub lines: u64,
+    /// The total bytes printed so far (across every stream)
+    pub bytes: u64,
+    /// How long it's been since the command last printed a line (or since it started, if it hasn't yet)
+    pub since_output: Duration,
+}
+
+/// A thread calling back every interval until this is dropped
+#[derive(Debug)]
+pub(crate) struct Heartbeats {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Heartbeats {
+    /// Starts calling `callback` every `interval`, for the command `capture` is reading the output of
+    pub(crate) fn start(interval: Duration, callback: HeartbeatCallback, capture: Capture) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let start = capture.start.unwrap_or_else(Instant::now);
+        let thread = thread::spawn(move || loop {
+            // nothing is ever sent, so this only stops waiting early once it's dropped
+            if stopped.recv_timeout(interval) != Err(RecvTimeoutError::Timeout) {
+                return;
+            }
+            let now = Instant::now();
+            let last_output = capture.last_output.lock().unwrap().unwrap_or(start);
+            (callback.lock().unwrap())(Heartbeat {
+                elapsed: now.saturating_duration_since(start),
+                lines: capture.lines.load(Ordering::Relaxed),
+                bytes: capture.bytes.load(Ordering::Relaxed),
+                since_output: now.saturating_duration_since(last_output),
+            });
+        });
+        return Heartbeats {
+            stop: Some(stop),
+            thread: Some(thread),
+        };
+    }
+}
+
+impl Drop for Heartbeats {
+    /// Stops calling back, waiting for a callback that's already running to finish
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Callbacks for each stage of running a command, for things like metrics and logging
+use crate::{CmdOutput, Line};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+type SpawnCallback = Arc<Mutex<dyn FnMut(u32) + Send>>;
+type LineCallback = Arc<Mutex<dyn FnMut(&Line) + Send>>;
+type ExitCallback = Arc<Mutex<dyn FnMut(&CmdOutput) + Send>>;
+
+/// Callbacks run when a command is started, prints a line, and exits, given to [`Runner::hooks`](crate::Runner::hooks)
+///
+/// Everything which runs commands with a [`Runner`](crate::Runner) runs these, including
+/// [`ContainerRunner`](crate::ContainerRunner), [`CommandPool`](crate::CommandPool), and
+/// [`Supervisor`](crate::Supervisor), so something watching commands only has to be set up in one place.
+///
+/// ```
+/// use better_commands::{Hooks, Runner};
+/// use std::process::Command;
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+///
+/// let lines = Arc::new(AtomicU64::new(0));
+/// let counter = lines.clone();
+/// let hooks = Hooks::new()
+///     .on_spawn(|pid| println!("started {}", pid))
+///     .on_line(move |_| {
+///         counter.fetch_add(1, Ordering::Relaxed);
+///     })
+///     .on_exit(|output| println!("{} exited with {:?}", output.command(), output.clone().status_code()));
+///
+/// Runner::new().hooks(hooks).run(Command::new("seq").arg("3")).unwrap();
+/// assert_eq!(3, lines.load(Ordering::Relaxed));
+/// ```
+#[derive(Clone, Default)]
+pub struct Hooks {
+    on_spawn: Option<SpawnCallback>,
+    on_line: Option<LineCallback>,
+    on_exit: Option<ExitCallback>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Hooks")
+            .field("on_spawn", &self.on_spawn.is_some())
+            .field("on_line", &self.on_line.is_some())
+            .field("on_exit", &self.on_exit.is_some())
+            .finish();
+    }
+}
+
+impl Hooks {
+    /// Creates a [`Hooks`] without any callbacks
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Runs `callback` with the command's PID as soon as it's started
+    pub fn on_spawn(mut self, callback: impl FnMut(u32) + Send + 'static) -> Self {
+        self.on_spawn = Some(Arc::new(Mutex::new(callback)));
+        return self;
+    }
+
+    /// Runs `callback` on each line the command prints, as soon as it's captured
+    ///
+    /// This sees the line after [`Runner::on_line`](crate::Runner::on_line) has changed it.
+    pub fn on_line(mut self, callback: impl FnMut(&Line) + Send + 'static) -> Self {
+        self.on_line = Some(Arc::new(Mutex::new(callback)));
+        return self;
+    }
+
+    /// Runs `callback` with the command's output once it's exited
+    ///
+    /// With [`Runner::retries`](crate::Runner::retries), this is run for every attempt.
+    pub fn on_exit(mut self, callback: impl FnMut(&CmdOutput) + Send + 'static) -> Self {
+        self.on_exit = Some(Arc::new(Mutex::new(callback)));
+        return self;
+    }
+
+    pub(crate) fn spawned(&self, pid: u32) {
+        if let Some(callback) = &self.on_spawn {
+            (callback.lock().unwrap())(pid);
+        }
+    }
+
+    pub(crate) fn line(&self, line: &Line) {
+        if let Some(callback) = &self.on_line {
+            (callback.lock().unwrap())(line);
+        }
+    }
+
+    pub(crate) fn exited(&self, output: &CmdOutput) {
+        if let Some(callback) = &self.on_exit {
+            (callback.lock().unwrap())(output);
+        }
+    }
+}
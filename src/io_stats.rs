@@ -0,0 +1,81 @@
+#[cfg(target_os = "linux")]
+use std::io;
+
+/// I/O statistics for a command, from `/proc/<pid>/io` (see [`Runner::io_stats`](crate::Runner::io_stats))
+///
+/// These only cover the command's own process, not anything it started. See `proc_pid_io(5)` for exactly what each
+/// one counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IoStats {
+    /// Bytes read, including from pipes, terminals, and the page cache (`rchar`)
+    pub read_chars: u64,
+    /// Bytes written, including to pipes and terminals (`wchar`)
+    pub write_chars: u64,
+    /// Number of read syscalls (`syscr`)
+    pub read_syscalls: u64,
+    /// Number of write syscalls (`syscw`)
+    pub write_syscalls: u64,
+    /// Bytes actually fetched from storage (`read_bytes`)
+    pub read_bytes: u64,
+    /// Bytes sent to storage (`write_bytes`)
+    pub write_bytes: u64,
+    /// Bytes that were written but never made it to storage, e.g. from truncating a file (`cancelled_write_bytes`)
+    pub cancelled_write_bytes: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl IoStats {
+    /// Parses the contents of `/proc/<pid>/io`
+    fn parse(contents: &str) -> IoStats {
+        let mut stats = IoStats::default();
+        for line in contents.lines() {
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim(), value.trim().parse().unwrap_or(0)),
+                None => continue,
+            };
+            match key {
+                "rchar" => stats.read_chars = value,
+                "wchar" => stats.write_chars = value,
+                "syscr" => stats.read_syscalls = value,
+                "syscw" => stats.write_syscalls = value,
+                "read_bytes" => stats.read_bytes = value,
+                "write_bytes" => stats.write_bytes = value,
+                "cancelled_write_bytes" => stats.cancelled_write_bytes = value,
+                _ => {}
+            }
+        }
+        return stats;
+    }
+}
+
+/// Waits for the process to exit *without* reaping it, so its `/proc` entry is still around
+///
+/// Returns whether it's exited; if `block` is false, this returns immediately.
+#[cfg(target_os = "linux")]
+pub(crate) fn wait_exited(pid: u32, block: bool) -> io::Result<bool> {
+    let mut flags = libc::WEXITED | libc::WNOWAIT;
+    if !block {
+        flags |= libc::WNOHANG;
+    }
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, flags) } == 0 {
+            // with WNOHANG, nothing is filled in if it hasn't exited yet
+            return Ok(unsafe { info.si_pid() } != 0);
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Reads the I/O statistics of a process that's exited but hasn't been reaped yet
+#[cfg(target_os = "linux")]
+pub(crate) fn read(pid: u32) -> io::Result<IoStats> {
+    return Ok(IoStats::parse(&std::fs::read_to_string(format!(
+        "/proc/{}/io",
+        pid
+    ))?));
+}
@@ -0,0 +1,239 @@
+//! Running commands in new Linux namespaces, set up with `unshare(2)`
+use std::io;
+use std::ops::{BitOr, BitOrAssign};
+
+/// Which namespaces to run a command in (see [`Runner::isolate`](crate::Runner::isolate))
+///
+/// These can be combined with `|`, e.g. `Isolation::Network | Isolation::Pid`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Isolation {
+    bits: u8,
+}
+
+// these are named like enum variants, since that's how they're used
+#[allow(non_upper_case_globals)]
+impl Isolation {
+    /// A new network namespace, with nothing but a loopback interface (which is down), so no network access at all
+    pub const Network: Isolation = Isolation { bits: 1 };
+    /// A new PID namespace, where the command is PID 1 and can't see or signal anything outside it
+    ///
+    /// Like any PID 1, the command ignores signals it doesn't handle, except `SIGKILL`; when it exits, everything
+    /// else in the namespace is killed.
+    pub const Pid: Isolation = Isolation { bits: 1 << 1 };
+    /// A new mount namespace, so anything the command mounts or unmounts doesn't affect anything else
+    ///
+    /// With [`Isolation::Pid`], a new `/proc` is mounted too, so it only shows what's in the namespace.
+    pub const Mount: Isolation = Isolation { bits: 1 << 2 };
+    /// A new user namespace, where the command runs as root but has no privileges outside it
+    ///
+    /// This lets the other namespaces be created without being root, as long as unprivileged user namespaces are
+    /// allowed (they usually are).
+    pub const User: Isolation = Isolation { bits: 1 << 3 };
+    /// A new IPC namespace, for System V IPC and POSIX message queues
+    pub const Ipc: Isolation = Isolation { bits: 1 << 4 };
+    /// A new UTS namespace, so the command can change the hostname without affecting anything else
+    pub const Uts: Isolation = Isolation { bits: 1 << 5 };
+
+    /// No namespaces
+    pub const fn empty() -> Isolation {
+        return Isolation { bits: 0 };
+    }
+
+    /// Every namespace
+    pub const fn all() -> Isolation {
+        return Isolation { bits: (1 << 6) - 1 };
+    }
+
+    /// Returns whether this includes all of `other`
+    pub const fn contains(self, other: Isolation) -> bool {
+        return self.bits & other.bits == other.bits;
+    }
+
+    pub const fn is_empty(self) -> bool {
+        return self.bits == 0;
+    }
+}
+
+impl BitOr for Isolation {
+    type Output = Isolation;
+
+    fn bitor(self, other: Isolation) -> Isolation {
+        return Isolation {
+            bits: self.bits | other.bits,
+        };
+    }
+}
+
+impl BitOrAssign for Isolation {
+    fn bitor_assign(&mut self, other: Isolation) {
+        self.bits |= other.bits;
+    }
+}
+
+/// Everything needed to enter the namespaces in the child, worked out beforehand since it can't allocate
+#[derive(Debug, Clone)]
+pub(crate) struct Namespaces {
+    isolation: Isolation,
+    /// The contents of `uid_map` and `gid_map`, for [`Isolation::User`], mapping root to the user it'd run as
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+}
+
+impl Namespaces {
+    /// `uid` and `gid` are who the command would run as without [`Isolation::User`]
+    pub(crate) fn new(isolation: Isolation, uid: u32, gid: u32) -> Namespaces {
+        return Namespaces {
+            isolation,
+            uid_map: format!("0 {} 1", uid).into_bytes(),
+            gid_map: format!("0 {} 1", gid).into_bytes(),
+        };
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        return self.isolation.is_empty();
+    }
+
+    /// Moves the child into new namespaces; called in the child before the command runs
+    ///
+    /// For [`Isolation::Pid`], this forks, since only the children of whatever calls `unshare` go in the new PID
+    /// namespace. The child carries on to run the command, and this process waits for it, then exits the same way.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn enter(&self) -> io::Result<()> {
+        let isolation = self.isolation;
+        let mut flags = 0;
+        for (namespace, flag) in [
+            (Isolation::User, libc::CLONE_NEWUSER),
+            (Isolation::Network, libc::CLONE_NEWNET),
+            (Isolation::Pid, libc::CLONE_NEWPID),
+            (Isolation::Mount, libc::CLONE_NEWNS),
+            (Isolation::Ipc, libc::CLONE_NEWIPC),
+            (Isolation::Uts, libc::CLONE_NEWUTS),
+        ] {
+            if isolation.contains(namespace) {
+                flags |= flag;
+            }
+        }
+        unsafe {
+            check(libc::unshare(flags))?;
+            if isolation.contains(Isolation::User) {
+                // this has to be denied before an unprivileged process can write gid_map
+                write_file(b"/proc/self/setgroups\0", b"deny")?;
+                write_file(b"/proc/self/uid_map\0", &self.uid_map)?;
+                write_file(b"/proc/self/gid_map\0", &self.gid_map)?;
+            }
+            if isolation.contains(Isolation::Mount) {
+                // otherwise mounts would still propagate back out
+                check(libc::mount(
+                    std::ptr::null(),
+                    c"/".as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                ))?;
+            }
+            if isolation.contains(Isolation::Pid) {
+                fork_into_pid_namespace()?;
+                if isolation.contains(Isolation::Mount) {
+                    check(libc::mount(
+                        c"proc".as_ptr(),
+                        c"/proc".as_ptr(),
+                        c"proc".as_ptr(),
+                        libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                        std::ptr::null(),
+                    ))?;
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(());
+}
+
+/// Writes `contents` to the file at `path`, which has to end with a NUL byte
+#[cfg(target_os = "linux")]
+unsafe fn write_file(path: &[u8], contents: &[u8]) -> io::Result<()> {
+    let fd = libc::open(
+        path.as_ptr() as *const libc::c_char,
+        libc::O_WRONLY | libc::O_CLOEXEC,
+    );
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let written = libc::write(fd, contents.as_ptr() as *const libc::c_void, contents.len());
+    libc::close(fd);
+    if written == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(());
+}
+
+/// The PID (outside the namespace) of the command, for [`forward_signal`]
+#[cfg(target_os = "linux")]
+static PID_1: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(0);
+
+#[cfg(target_os = "linux")]
+extern "C" fn forward_signal(signal: libc::c_int) {
+    unsafe { libc::kill(PID_1.load(std::sync::atomic::Ordering::SeqCst), signal) };
+}
+
+/// Forks after `unshare(CLONE_NEWPID)`, returning in the child, which will be PID 1
+///
+/// The parent stays behind, passing on signals and waiting for the child to exit, then exits the same way, so it
+/// looks like the command to whatever started it.
+#[cfg(target_os = "linux")]
+unsafe fn fork_into_pid_namespace() -> io::Result<()> {
+    let child = libc::fork();
+    if child == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if child == 0 {
+        // so killing the parent (e.g. when the command times out) kills it too
+        check(libc::prctl(
+            libc::PR_SET_PDEATHSIG,
+            libc::SIGKILL as libc::c_ulong,
+        ))?;
+        return Ok(());
+    }
+
+    PID_1.store(child, std::sync::atomic::Ordering::SeqCst);
+    let mut action: libc::sigaction = std::mem::zeroed();
+    action.sa_sigaction = forward_signal as *const () as libc::sighandler_t;
+    for signal in [
+        libc::SIGHUP,
+        libc::SIGINT,
+        libc::SIGQUIT,
+        libc::SIGTERM,
+        libc::SIGUSR1,
+        libc::SIGUSR2,
+    ] {
+        libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+    // this includes the pipe `std` uses to find out whether the command started, which would otherwise stay open
+    // until the command exits
+    if libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0) == -1 {
+        for fd in 3..1024 {
+            libc::close(fd);
+        }
+    }
+
+    let mut status = 0;
+    while libc::waitpid(child, &mut status, 0) == -1 {
+        if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            libc::_exit(127);
+        }
+    }
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+        libc::_exit(128 + signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status));
+}
@@ -0,0 +1,82 @@
+//! Parsing commands which print one JSON object per line, like `cargo build --message-format=json`
+use crate::{CmdOutput, Error, Line, Runner};
+use serde::de::DeserializeOwned;
+use std::process::Command;
+
+/// The output of [`run_json`], with every line of stdout parsed as JSON
+#[derive(Debug, Clone)]
+pub struct JsonOutput<T> {
+    /// Every line which was parsed successfully, in order
+    pub values: Vec<T>,
+    /// Every non-empty line of stdout which couldn't be parsed as a `T`
+    pub errors: Vec<JsonLineError>,
+    /// The regular output, including everything printed to stderr
+    pub output: CmdOutput,
+}
+
+/// A line of stdout which couldn't be parsed, from [`run_json`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonLineError {
+    pub line: Line,
+    /// What was wrong with it, from [`serde_json`]
+    pub error: String,
+}
+
+/// Runs a command, parsing each line it prints to stdout as JSON
+///
+/// This is the same as [`Runner::run_json`] with the default options.
+///
+/// ```
+/// use better_commands::run_json;
+/// use serde::Deserialize;
+/// use std::process::Command;
+///
+/// #[derive(Deserialize)]
+/// struct Message {
+///     reason: String,
+/// }
+///
+/// let script = r#"echo '{"reason": "compiler-artifact"}'; echo 'not json'; echo '{"reason": "build-finished"}'"#;
+/// let parsed = run_json::<Message>(Command::new("bash").arg("-c").arg(script)).unwrap();
+///
+/// assert_eq!("compiler-artifact", parsed.values[0].reason);
+/// assert_eq!("build-finished", parsed.values[1].reason);
+/// assert_eq!("not json", parsed.errors[0].line.content);
+/// ```
+pub fn run_json<T: DeserializeOwned>(command: &mut Command) -> Result<JsonOutput<T>, Error> {
+    return Runner::new().run_json(command);
+}
+
+impl Runner {
+    /// Runs a command, parsing each line it prints to stdout as JSON
+    ///
+    /// Lines which can't be parsed as a `T` end up in [`JsonOutput::errors`] rather than failing the whole thing, and
+    /// empty lines are skipped. Stderr isn't parsed; it's only in [`JsonOutput::output`].
+    ///
+    /// <small>This needs the `serde` feature</small>
+    pub fn run_json<T: DeserializeOwned>(
+        &self,
+        command: &mut Command,
+    ) -> Result<JsonOutput<T>, Error> {
+        let output = self.run(command)?;
+        let mut values = Vec::new();
+        let mut errors = Vec::new();
+        for line in output.stdout().unwrap_or_default() {
+            if line.content.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line.content) {
+                Ok(value) => values.push(value),
+                Err(e) => errors.push(JsonLineError {
+                    line: line.clone(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+        return Ok(JsonOutput {
+            values,
+            errors,
+            output,
+        });
+    }
+}
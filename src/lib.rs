@@ -1,60 +1,512 @@
 #![doc = include_str!("../README.md")]
-use std::cmp::Ordering;
+#![allow(clippy::needless_return)]
+use crate::capture::{read_lines, Capture};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io::{BufRead, BufReader, Lines};
-use std::process::{ChildStderr, ChildStdout, Command, Stdio};
+use std::path::Path;
+use std::process::{ChildStderr, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
+mod ansi;
+mod archive;
+mod assert;
+mod audit;
+mod backend;
+mod bench;
+#[cfg(feature = "serde")]
+mod cache;
+mod cancel;
+mod capture;
+#[cfg(feature = "serde")]
+mod cassette;
+#[cfg(feature = "cgroups")]
+mod cgroup;
+mod chain;
+mod channel;
+mod container;
+mod control;
+mod defaults;
+mod detach;
+mod diff;
+mod env;
+mod error;
+mod fake;
+#[cfg(unix)]
+mod fds;
+mod fingerprint;
+mod graph;
+mod heartbeat;
+mod hooks;
+mod io_stats;
+#[cfg(feature = "namespaces")]
+mod isolation;
+#[cfg(feature = "serde")]
+mod json;
+mod limit;
+#[cfg(feature = "log")]
+mod logging;
+mod multiplex;
+mod output_diff;
+mod parse;
+mod pipeline;
+mod pool;
+mod pre_exec;
+mod progress;
+mod ready;
+mod redact;
+mod replay;
+pub mod report;
+mod retry;
+mod rlimit;
+mod runner;
+mod running;
+mod rusage;
+mod schedule;
+mod scope;
+mod search;
+mod select;
+#[cfg(feature = "serde")]
+mod serialize;
+mod severity;
+#[cfg(unix)]
+mod signals;
+mod sink;
+#[cfg(feature = "smol")]
+mod smol_backend;
+mod snapshot;
+mod split;
+#[cfg(all(feature = "ssh", unix))]
+mod ssh;
+mod std_output;
+mod stdio;
+#[cfg(feature = "futures")]
+mod stream;
+mod supervisor;
+#[cfg(feature = "metrics")]
+mod telemetry;
+mod template;
+#[cfg(test)]
 mod tests;
+#[cfg(feature = "tracing")]
+mod trace;
+#[cfg(feature = "notify")]
+mod watch;
+mod watermark;
+mod workdir;
+
+pub use archive::TranscriptArchive;
+pub use assert::Assert;
+pub use audit::{clear_global_audit_log, set_global_audit_log, AuditLog, AuditRecord};
+pub use backend::{Backend, ReadMode};
+pub use bench::{bench, BenchReport};
+#[cfg(feature = "serde")]
+pub use cache::CacheDir;
+pub use cancel::{Cancellation, Shutdown};
+#[cfg(feature = "serde")]
+pub use cassette::{Cassette, CassetteMode};
+#[cfg(feature = "cgroups")]
+pub use cgroup::{CgroupLimits, CgroupStats};
+pub use chain::{Chain, ChainOutput};
+pub use channel::{line_channel, Backpressure, ChannelSink, LineReceiver};
+pub use container::ContainerRunner;
+pub use control::{run_funcs_with_control, RunControl};
+pub use defaults::{configure, Defaults};
+pub use detach::{spawn_detached, DetachOptions, DetachedChild};
+pub use env::EnvChange;
+pub use error::{CallbackError, CallbackFailure, Error, ExitStatusError};
+pub use fake::FakeRunner;
+pub use fingerprint::Fingerprint;
+pub use graph::{TaskGraph, TaskGraphOutput};
+pub use heartbeat::Heartbeat;
+pub use hooks::Hooks;
+pub use io_stats::IoStats;
+#[cfg(feature = "namespaces")]
+pub use isolation::Isolation;
+#[cfg(feature = "serde")]
+pub use json::{run_json, JsonLineError, JsonOutput};
+pub use limit::RateLimiter;
+pub use multiplex::{MultiplexPrinter, MultiplexSink};
+pub use output_diff::{LineChange, OutputDiff};
+pub use parse::{LineParser, ParsedOutput};
+pub use pipeline::{Pipeline, PipelineOutput};
+pub use pool::CommandPool;
+pub use progress::parse_progress;
+pub use ready::{run_until_match, MatchInfo, Pattern};
+pub use redact::RedactionRules;
+pub use regex;
+pub use retry::Backoff;
+pub use rlimit::Rlimit;
+pub use runner::{CommandRunner, Runner};
+pub use running::RunningCommand;
+pub use rusage::ResourceUsage;
+pub use schedule::{Overlap, Schedule, ScheduleHandle};
+pub use scope::{scope, CommandScope, ScopedCommand};
+#[cfg(feature = "serde")]
+pub use serialize::SCHEMA_VERSION;
+pub use severity::{Severity, SeverityRules};
+pub use sink::{FileSink, LineSink, NullSink, Tee, WriteSink};
+#[cfg(feature = "smol")]
+pub use smol_backend::{run_async, spawn_async, AsyncChild};
+pub use snapshot::UPDATE_SNAPSHOTS_VAR;
+pub use split::{CarriageReturns, Delimiter, LongLines};
+#[cfg(all(feature = "ssh", unix))]
+pub use ssh::{SshOptions, SshRunner};
+pub use stdio::{StdinMode, StdoutMode};
+#[cfg(feature = "futures")]
+pub use stream::{run_stream_async, LineStream, OutputFuture};
+pub use supervisor::{Restart, Supervisor, SupervisorEvent};
+pub use template::{Template, TemplateError};
+#[cfg(feature = "notify")]
+pub use watch::{Watch, WatchError, WatchHandle};
+pub use watermark::Watermark;
 
 /// Holds the output for a command
 ///
 /// Features the lines printed (see [`Line`]), the status code, the start time, end time, and duration
 ///
+/// With the `serde` feature, this can be serialized and deserialized; see `SCHEMA_VERSION` for details.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CmdOutput {
-    lines: Option<Vec<Line>>,
+    command: String,
+    /// Stored separately so [`CmdOutput::stdout`] and [`CmdOutput::stderr`] don't need to filter everything; these are
+    /// always either both `Some` or both `None`
+    stdout: Option<Vec<Line>>,
+    stderr: Option<Vec<Line>>,
+    /// Lines printed to extra file descriptors (see [`Runner::capture_fd`]) and lines added with
+    /// [`CmdOutput::insert_line`] which aren't stdout or stderr
+    other_lines: Vec<Line>,
+    /// Whether the lines are in the order they were printed; if not, they're kept in the order they were given in, with
+    /// all of stdout first (see [`run_funcs_with_lines_unsorted`])
+    sorted: bool,
     status_code: Option<i32>,
+    signal: Option<i32>,
+    /// The status the command actually exited with, which can have more in it than `status_code` and `signal` (like
+    /// whether it dumped core)
+    exit_status: Option<ExitStatus>,
     start_time: Instant,
     end_time: Instant,
     duration: Duration,
+    kill_reason: Option<KillReason>,
+    /// The line which matched [`Runner::kill_on_match`], if that's why it was killed
+    kill_match: Option<Line>,
+    failed_attempts: Vec<CmdOutput>,
+    io_stats: Option<IoStats>,
+    resource_usage: Option<ResourceUsage>,
+    shutdown: Option<Shutdown>,
+    limit_exceeded: Option<Rlimit>,
+    /// Whether lines were thrown away for going over [`Runner::max_output_bytes`] or [`Runner::max_output_lines`]
+    truncated: bool,
+    #[cfg(feature = "cgroups")]
+    cgroup_stats: Option<CgroupStats>,
+    original_locale: Option<String>,
+    envs_diff: Vec<EnvChange>,
+    /// Shared between clones, so the directory is only removed once they've all been dropped
+    workdir: Option<Arc<workdir::TempDir>>,
+    /// Set when this came from a `CacheDir` instead of running the command
+    cached: bool,
 }
 
 impl CmdOutput {
+    pub(crate) fn new(
+        command: String,
+        lines: Option<Vec<Line>>,
+        status_code: Option<i32>,
+        start_time: Instant,
+        end_time: Instant,
+    ) -> Self {
+        return CmdOutput::with_order(command, lines, true, status_code, start_time, end_time);
+    }
+
+    /// Same as [`CmdOutput::new`], but only sorts the lines if `sort` is set, otherwise keeping them as they are
+    pub(crate) fn with_order(
+        command: String,
+        lines: Option<Vec<Line>>,
+        sort: bool,
+        status_code: Option<i32>,
+        start_time: Instant,
+        end_time: Instant,
+    ) -> Self {
+        let (stdout, stderr, other_lines) = match lines {
+            Some(mut lines) => {
+                if lines.iter().any(|line| line.line_number == 0) {
+                    if sort {
+                        lines.sort();
+                    }
+                    number_lines(&mut lines);
+                }
+                for line in &mut lines {
+                    line.offset = line.time.saturating_duration_since(start_time);
+                }
+                let (mut stdout, mut stderr, mut other_lines) =
+                    (Vec::new(), Vec::new(), Vec::new());
+                for line in lines {
+                    match line.printed_to {
+                        LineType::Stdout => stdout.push(line),
+                        LineType::Stderr => stderr.push(line),
+                        _ => other_lines.push(line),
+                    }
+                }
+                if sort {
+                    stdout.sort();
+                    stderr.sort();
+                    other_lines.sort();
+                }
+                (Some(stdout), Some(stderr), other_lines)
+            }
+            None => (None, None, Vec::new()),
+        };
+        return CmdOutput {
+            command,
+            stdout,
+            stderr,
+            other_lines,
+            sorted: sort,
+            status_code,
+            signal: None,
+            exit_status: None,
+            start_time,
+            end_time,
+            duration: end_time.duration_since(start_time),
+            kill_reason: None,
+            kill_match: None,
+            failed_attempts: Vec::new(),
+            io_stats: None,
+            resource_usage: None,
+            shutdown: None,
+            limit_exceeded: None,
+            truncated: false,
+            #[cfg(feature = "cgroups")]
+            cgroup_stats: None,
+            original_locale: None,
+            envs_diff: Vec::new(),
+            workdir: None,
+            cached: false,
+        };
+    }
+
+    /// Sets the status the command exited with, along with the signal that killed it, if it was killed
+    pub(crate) fn set_exit_status(&mut self, status: ExitStatus) {
+        self.signal = exit_signal(&status);
+        self.exit_status = Some(status);
+    }
+
     /// Returns only lines printed to stdout
     ///
     /// <small>This is an [`Option`] because [`run_funcs`] cannot provide `lines`</small>
-    pub fn stdout(self) -> Option<Vec<Line>> {
-        self.lines.and_then(|lines| {
-            Some(
-                lines
-                    .into_iter()
-                    .filter(|line| line.printed_to == LineType::Stdout)
-                    .collect(),
-            )
-        })
+    pub fn stdout(&self) -> Option<&[Line]> {
+        return self.stdout.as_deref();
     }
 
     /// Returns only lines printed to stderr
     ///
     /// <small>This is an [`Option`] because [`run_funcs`] cannot provide `lines`</small>
-    pub fn stderr(self) -> Option<Vec<Line>> {
-        self.lines.and_then(|lines| {
-            Some(
-                lines
-                    .into_iter()
-                    .filter(|line| line.printed_to == LineType::Stderr)
-                    .collect(),
-            )
-        })
-    }
-
-    /// Returns all lines printed by the [`Command`]\
+    pub fn stderr(&self) -> Option<&[Line]> {
+        return self.stderr.as_deref();
+    }
+
+    /// Returns only lines printed to the extra file descriptor `fd` (see [`Runner::capture_fd`])
+    pub fn fd_lines(&self, fd: u32) -> Vec<&Line> {
+        return self
+            .other_lines
+            .iter()
+            .filter(|line| line.printed_to == LineType::Fd(fd))
+            .collect();
+    }
+
+    /// Adds a line which wasn't printed by the command, like a [`Line::system`] note that it's being retried, in with
+    /// the lines that were
+    ///
+    /// The line goes wherever its [`Line::time`] puts it (or at the end of its stream, if the lines aren't sorted), and
+    /// every line is renumbered (see [`Line::line_number`]) to match. If the lines weren't captured (e.g. with
+    /// [`run_funcs`]), this line is the only one.
+    ///
+    /// ```
+    /// use better_commands::{run, Line, LineType};
+    /// use std::process::Command;
+    ///
+    /// let mut output = run(Command::new("echo").arg("hi"));
+    /// output.insert_line(Line::system("timeout reached"));
+    ///
+    /// assert_eq!("[stdout] hi\n[system] timeout reached", output.merged_string().unwrap());
+    /// assert_eq!(LineType::System, output.last_line().unwrap().printed_to);
+    /// ```
+    pub fn insert_line(&mut self, mut line: Line) {
+        line.offset = line.time.saturating_duration_since(self.start_time);
+        let stdout = self.stdout.get_or_insert_with(Vec::new);
+        let stderr = self.stderr.get_or_insert_with(Vec::new);
+        let lines = match line.printed_to {
+            LineType::Stdout => stdout,
+            LineType::Stderr => stderr,
+            _ => &mut self.other_lines,
+        };
+        let index = match self.sorted {
+            true => lines.partition_point(|other| *other <= line),
+            false => lines.len(),
+        };
+        lines.insert(index, line);
+
+        let merged = Merged::new(
+            self.stdout.iter_mut().flatten(),
+            self.stderr.iter_mut().flatten(),
+            self.sorted,
+        );
+        number_lines(Merged::new(
+            merged,
+            self.other_lines.iter_mut(),
+            self.sorted,
+        ));
+    }
+
+    /// Returns all lines printed by the [`Command`], in the order they were printed\
     /// Note: All functions are *guaranteed* to return either `Some()` or `None`, not either
     ///
+    /// How accurate the order is:
+    /// - Lines printed to the same stream are always in the order they were printed.
+    /// - With [`Runner`], each line's [`Line::time`] is when the `read()` which finished it returned, so it's within
+    ///   about a millisecond of when it was printed (depending on how busy the system is). Lines printed to stdout
+    ///   and stderr closer together than that can be out of order; [`Runner::merge_stderr`] avoids that by reading
+    ///   both from one pipe.
+    /// - With [`run_funcs_with_lines`] and friends, which read whole lines at a time, lines can be timestamped a little
+    ///   later than that.
+    /// - Either way, programs often buffer what they print themselves (usually stdout, when it's not a terminal), so
+    ///   it might reach the pipe long after it was "printed"; nothing on this end can fix that.
+    ///
     /// <small>This is an [`Option`] because [`run_funcs`] cannot provide `lines`</small>
     pub fn lines(self) -> Option<Vec<Line>> {
-        return self.lines;
+        let (stdout, stderr) = (self.stdout?, self.stderr?);
+        let merged = Merged::new(stdout.into_iter(), stderr.into_iter(), self.sorted);
+        return Some(Merged::new(merged, self.other_lines.into_iter(), self.sorted).collect());
+    }
+
+    /// Returns everything printed to stdout, as the lines joined with newlines
+    ///
+    /// <small>This is an [`Option`] because [`run_funcs`] cannot provide `lines`</small>
+    pub fn stdout_string(&self) -> Option<String> {
+        return self.stdout().map(join_lines);
+    }
+
+    /// Returns everything printed to stderr, as the lines joined with newlines
+    ///
+    /// <small>This is an [`Option`] because [`run_funcs`] cannot provide `lines`</small>
+    pub fn stderr_string(&self) -> Option<String> {
+        return self.stderr().map(join_lines);
+    }
+
+    /// Returns every line in the order they were printed, each prefixed with `[stdout]` or `[stderr]` (or e.g. `[fd 3]`),
+    /// joined with newlines
+    ///
+    /// ```
+    /// use better_commands::run;
+    /// use std::process::Command;
+    ///
+    /// let output = run(Command::new("bash").arg("-c").arg("echo hi; sleep 0.1; >&2 echo oops"));
+    /// assert_eq!("[stdout] hi\n[stderr] oops", output.merged_string().unwrap());
+    /// ```
+    ///
+    /// <small>This is an [`Option`] because [`run_funcs`] cannot provide `lines`</small>
+    pub fn merged_string(&self) -> Option<String> {
+        self.stdout.as_ref()?;
+        let lines: Vec<String> = self
+            .iter()
+            .map(|line| format!("[{}] {}", line.printed_to, line.content))
+            .collect();
+        return Some(lines.join("\n"));
+    }
+
+    /// Returns a short summary of how the command went, followed by every line it printed, for showing to people
+    ///
+    /// The first line has the command, how it exited, and how long it took; the second has how many lines it printed
+    /// to each stream; after that, each line is written like `[+0.002s stdout] hi` (see [`Line`]'s `Display`).
+    ///
+    /// ```
+    /// use better_commands::run;
+    /// use std::process::Command;
+    ///
+    /// let output = run(Command::new("bash").arg("-c").arg("echo hi; exit 1"));
+    /// let pretty = output.pretty();
+    /// assert!(pretty.starts_with("`bash -c echo hi; exit 1` exited with status 1 after "));
+    /// assert!(pretty.contains("\n1 line (1 stdout, 0 stderr)\n[+"));
+    /// assert!(pretty.ends_with(" stdout] hi"));
+    /// ```
+    pub fn pretty(&self) -> String {
+        let mut pretty = format!(
+            "`{}` {} after {:.3}s\n",
+            self.command,
+            report::status(self),
+            self.duration.as_secs_f64()
+        );
+        let (Some(stdout), Some(stderr)) = (&self.stdout, &self.stderr) else {
+            pretty.push_str("(output wasn't captured)");
+            return pretty;
+        };
+        pretty.push_str(&format!(
+            "{} {} ({} stdout, {} stderr",
+            self.len(),
+            if self.len() == 1 { "line" } else { "lines" },
+            stdout.len(),
+            stderr.len()
+        ));
+        if !self.other_lines.is_empty() {
+            pretty.push_str(&format!(", {} other", self.other_lines.len()));
+        }
+        pretty.push(')');
+        for line in self {
+            pretty.push_str(&format!("\n{}", line));
+        }
+        return pretty;
+    }
+
+    /// Iterates over every line, in the order they were printed
+    ///
+    /// This is the same as iterating over `&output`; if lines weren't captured (e.g. with [`run_funcs`]), it's empty.
+    ///
+    /// ```
+    /// use better_commands::run;
+    /// use std::process::Command;
+    ///
+    /// let output = run(Command::new("echo").arg("a\nb"));
+    /// for line in &output {
+    ///     println!("{}", line.content);
+    /// }
+    /// assert_eq!(2, output.len());
+    /// assert_eq!("b", output.last_line().unwrap().content);
+    /// ```
+    pub fn iter(&self) -> LineIter<'_> {
+        let merged = Merged::new(
+            self.stdout.as_deref().unwrap_or_default().iter(),
+            self.stderr.as_deref().unwrap_or_default().iter(),
+            self.sorted,
+        );
+        return LineIter {
+            merged: Merged::new(merged, self.other_lines.iter(), self.sorted),
+        };
+    }
+
+    /// Returns how many lines were printed in total
+    pub fn len(&self) -> usize {
+        return self.stdout.as_ref().map_or(0, Vec::len)
+            + self.stderr.as_ref().map_or(0, Vec::len)
+            + self.other_lines.len();
+    }
+
+    /// Returns whether no lines were printed (or they weren't captured)
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// Returns the last line printed, to any stream
+    pub fn last_line(&self) -> Option<&Line> {
+        let last_stdout = self.stdout.as_ref().and_then(|lines| lines.last());
+        let last_stderr = self.stderr.as_ref().and_then(|lines| lines.last());
+        let last_fd = self.other_lines.last();
+        if !self.sorted {
+            return last_fd.or(last_stderr).or(last_stdout);
+        }
+        return last_stdout.max(last_stderr).max(last_fd);
     }
 
     /// Returns the exit status code, if there was one
@@ -64,6 +516,60 @@ impl CmdOutput {
         return self.status_code;
     }
 
+    /// Returns the signal which killed the command, if it was killed by one
+    ///
+    /// <small>This is always `None` on Windows</small>
+    pub fn signal(&self) -> Option<i32> {
+        return self.signal;
+    }
+
+    /// Returns the status the command exited with, for passing to anything that takes an [`ExitStatus`]
+    ///
+    /// Outputs which weren't captured from a real command (e.g. from a [`FakeRunner`], or deserialized with the `serde`
+    /// feature) have one rebuilt from the status code or signal, so this is only `None` if there's neither.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        return match (self.exit_status, self.status_code, self.signal) {
+            (Some(status), _, _) => Some(status),
+            (None, None, None) => None,
+            (None, status_code, signal) => Some(std_output::exit_status(status_code, signal)),
+        };
+    }
+
+    /// Returns whether the command exited with 0, without being killed by this crate (e.g. for a timeout)
+    ///
+    /// ```
+    /// use better_commands::run;
+    /// use std::process::Command;
+    ///
+    /// assert!(run(&mut Command::new("true")).success());
+    /// assert!(!run(&mut Command::new("false")).success());
+    /// ```
+    pub fn success(&self) -> bool {
+        return self.status_code == Some(0) && self.kill_reason.is_none();
+    }
+
+    /// Returns an error if the command didn't succeed (see [`CmdOutput::success`]), for using `?` on a command that has
+    /// to work
+    ///
+    /// ```
+    /// use better_commands::run;
+    /// use std::process::Command;
+    ///
+    /// let error = run(Command::new("bash").arg("-c").arg("exit 3")).exit_ok().unwrap_err();
+    /// assert_eq!(Some(3), error.code());
+    /// assert_eq!("`bash -c exit 3` exited with status 3", error.to_string());
+    /// ```
+    pub fn exit_ok(&self) -> Result<(), ExitStatusError> {
+        if self.success() {
+            return Ok(());
+        }
+        return Err(ExitStatusError {
+            command: self.command.clone(),
+            status: self.exit_status(),
+            kill_reason: self.kill_reason,
+        });
+    }
+
     /// Returns the duration the command ran for
     pub fn duration(self) -> Duration {
         return self.duration;
@@ -78,83 +584,436 @@ impl CmdOutput {
     pub fn end_time(self) -> Instant {
         return self.end_time;
     }
+
+    /// Returns how long after the command started it printed its first line to `stream`, if it printed anything there
+    ///
+    /// This is useful for checking how quickly a service starts responding.
+    pub fn first_line_latency(&self, stream: LineType) -> Option<Duration> {
+        let first = match stream {
+            LineType::Stdout => self.stdout()?.first(),
+            LineType::Stderr => self.stderr()?.first(),
+            _ => self
+                .other_lines
+                .iter()
+                .find(|line| line.printed_to == stream),
+        };
+        return first.map(|line| line.offset);
+    }
+
+    /// Returns the command that was run, as the program followed by its arguments (separated by spaces)
+    pub fn command(&self) -> &str {
+        return &self.command;
+    }
+
+    /// Returns why the command was killed, if it was killed by this crate (e.g. due to a timeout)
+    pub fn kill_reason(&self) -> Option<KillReason> {
+        return self.kill_reason;
+    }
+
+    /// Returns how the command was stopped, if it was cancelled (by [`RunningCommand::cancel`], [`RunningCommand::shutdown`],
+    /// or one of the reasons in [`KillReason`])
+    ///
+    /// This says whether it exited cleanly within its grace period, or had to be killed (see [`Cancellation`]).
+    pub fn shutdown(&self) -> Option<Shutdown> {
+        return self.shutdown;
+    }
+
+    /// Returns the resource limit the command was killed for going over, if it was (see [`Runner::limit`])
+    ///
+    /// <small>This only covers limits which are enforced by killing the command ([`Rlimit::Cpu`] and
+    /// [`Rlimit::FileSize`]); going over the others makes things like allocating memory or opening files fail instead,
+    /// which the command might handle however it likes</small>
+    pub fn limit_exceeded(&self) -> Option<Rlimit> {
+        return self.limit_exceeded;
+    }
+
+    /// Returns what was recorded by the cgroup the command was run in, if it was (see [`Runner::cgroup`])
+    #[cfg(feature = "cgroups")]
+    pub fn cgroup_stats(&self) -> Option<CgroupStats> {
+        return self.cgroup_stats;
+    }
+
+    /// Returns the line which made the command get killed, if it was killed by [`Runner::kill_on_match`]
+    pub fn kill_match(&self) -> Option<&Line> {
+        return self.kill_match.as_ref();
+    }
+
+    /// Returns the output of every earlier attempt at running the command, oldest first
+    ///
+    /// This is only populated when using [`Runner::retries`]; the output itself is from the final attempt.
+    pub fn failed_attempts(&self) -> &[CmdOutput] {
+        return &self.failed_attempts;
+    }
+
+    /// Returns the command's I/O statistics, if they were recorded (see [`Runner::io_stats`])
+    pub fn io_stats(&self) -> Option<IoStats> {
+        return self.io_stats;
+    }
+
+    /// Returns how much CPU time and memory the command used, if it was recorded
+    ///
+    /// This is recorded for every command run with a [`Runner`] on Unix.
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new().run(Command::new("bash").arg("-c").arg("for i in $(seq 10000); do :; done")).unwrap();
+    /// let usage = output.resource_usage().unwrap();
+    /// println!("used {:?} of CPU time and {} bytes of memory", usage.user_time + usage.system_time, usage.max_rss);
+    /// ```
+    pub fn resource_usage(&self) -> Option<ResourceUsage> {
+        return self.resource_usage;
+    }
+
+    /// Returns the locale the command would've run with if it hadn't been pinned (see [`Runner::pin_locale`])
+    ///
+    /// This is `None` if the locale wasn't pinned, or if neither `LC_ALL` nor `LANG` was set.
+    pub fn original_locale(&self) -> Option<&str> {
+        return self.original_locale.as_deref();
+    }
+
+    /// Returns every environment variable the command got a different value for than this process has, sorted by name
+    ///
+    /// This includes anything set by [`Runner`] (like [`Runner::env_from_file`]) or directly on the [`Command`], and
+    /// variables removed by [`Runner::env_clear_except`]. It's only recorded for commands run with a [`Runner`].
+    pub fn envs_diff(&self) -> &[EnvChange] {
+        return &self.envs_diff;
+    }
+
+    /// Returns the temporary directory the command was run in, if it was run with [`Runner::current_dir_temp`]
+    pub fn workdir(&self) -> Option<&Path> {
+        return self.workdir.as_ref().map(|dir| dir.path());
+    }
+
+    /// Returns whether some of the output was thrown away, for going over [`Runner::max_output_bytes`] or
+    /// [`Runner::max_output_lines`]
+    ///
+    /// If so, the lines are only the ones printed before the limit was reached.
+    pub fn truncated(&self) -> bool {
+        return self.truncated;
+    }
+
+    /// Returns whether this was stored earlier by `Runner::cache` (with the `serde` feature), rather than the command
+    /// actually being run
+    pub fn cached(&self) -> bool {
+        return self.cached;
+    }
+
+    /// Returns a copy of this output with everything matching `rules` scrubbed out
+    ///
+    /// This covers the content of every line (and its raw bytes, see [`Line::raw`]), as well as the command itself, so
+    /// the result is safe to share or archive. Raw bytes which aren't valid UTF-8 are removed.
+    ///
+    /// ```
+    /// use better_commands::{run, RedactionRules};
+    /// use std::process::Command;
+    ///
+    /// let rules = RedactionRules::new().literal("hunter2");
+    /// let output = run(Command::new("echo").arg("password: hunter2")).redacted(&rules);
+    ///
+    /// assert_eq!("echo password: [REDACTED]", output.command());
+    /// assert_eq!("password: [REDACTED]", output.lines().unwrap()[0].content);
+    /// ```
+    pub fn redacted(&self, rules: &RedactionRules) -> CmdOutput {
+        let mut output = self.clone();
+        output.command = rules.apply(&output.command).into_owned();
+        let lines = output.stdout.iter_mut().flatten();
+        let lines = lines.chain(output.stderr.iter_mut().flatten());
+        let lines = lines.chain(output.other_lines.iter_mut());
+        for line in lines.chain(output.kill_match.iter_mut()) {
+            line.content = rules.apply(&line.content).into_owned();
+            for annotation in &mut line.annotations {
+                *annotation = rules.apply(annotation).into_owned();
+            }
+            // raw bytes which aren't valid UTF-8 can't be reliably scrubbed, so they're dropped
+            line.raw = line
+                .raw
+                .take()
+                .and_then(|raw| String::from_utf8(raw).ok())
+                .map(|raw| rules.apply(&raw).into_owned().into_bytes());
+        }
+        for change in &mut output.envs_diff {
+            for value in [&mut change.before, &mut change.after]
+                .into_iter()
+                .flatten()
+            {
+                *value = rules.apply(value).into_owned();
+            }
+        }
+        output.failed_attempts = self
+            .failed_attempts
+            .iter()
+            .map(|attempt| attempt.redacted(rules))
+            .collect();
+        return output;
+    }
+}
+
+/// Numbers lines which weren't captured by this crate (e.g. ones made with [`Line::from_stdout`]), in the order they're
+/// in
+fn number_lines<'a>(lines: impl IntoIterator<Item = &'a mut Line>) {
+    let mut line_numbers: BTreeMap<LineType, u64> = BTreeMap::new();
+    for (index, line) in lines.into_iter().enumerate() {
+        let line_number = line_numbers.entry(line.printed_to.clone()).or_default();
+        *line_number += 1;
+        line.line_number = *line_number;
+        line.global_index = index as u64;
+    }
+}
+
+/// Joins the content of `lines` with newlines
+fn join_lines(lines: &[Line]) -> String {
+    let contents: Vec<&str> = lines.iter().map(|line| line.content.as_str()).collect();
+    return contents.join("\n");
+}
+
+impl<'a> IntoIterator for &'a CmdOutput {
+    type Item = &'a Line;
+    type IntoIter = LineIter<'a>;
+
+    fn into_iter(self) -> LineIter<'a> {
+        return self.iter();
+    }
+}
+
+/// An iterator over the lines of a [`CmdOutput`], in the order they were printed (see [`CmdOutput::iter`])
+pub struct LineIter<'a> {
+    merged: Merged<Merged<std::slice::Iter<'a, Line>>, std::slice::Iter<'a, Line>>,
 }
 
-/// Specifies what a line was printed to - stdout or stderr
+impl<'a> Iterator for LineIter<'a> {
+    type Item = &'a Line;
+
+    fn next(&mut self) -> Option<&'a Line> {
+        return self.merged.next();
+    }
+}
+
+impl fmt::Debug for LineIter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_struct("LineIter").finish_non_exhaustive();
+    }
+}
+
+/// Merges two already-sorted iterators of lines into one sorted iterator, or just chains them if `interleave` isn't set
+pub(crate) struct Merged<I: Iterator, J: Iterator<Item = I::Item> = I> {
+    stdout: std::iter::Peekable<I>,
+    stderr: std::iter::Peekable<J>,
+    interleave: bool,
+}
+
+impl<I: Iterator, J: Iterator<Item = I::Item>> Merged<I, J> {
+    fn new(stdout: I, stderr: J, interleave: bool) -> Self {
+        return Merged {
+            stdout: stdout.peekable(),
+            stderr: stderr.peekable(),
+            interleave,
+        };
+    }
+}
+
+impl<I: Iterator, J: Iterator<Item = I::Item>> Iterator for Merged<I, J>
+where
+    I::Item: Ord,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        return match (self.stdout.peek(), self.stderr.peek()) {
+            (Some(out), Some(err)) if self.interleave && err < out => self.stderr.next(),
+            (Some(_), _) => self.stdout.next(),
+            (None, _) => self.stderr.next(),
+        };
+    }
+}
+
+/// Why a command was killed by this crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KillReason {
+    /// The command ran for longer than the timeout (see [`Runner::timeout`])
+    Timeout,
+    /// The command printed more than it was allowed to (see [`Runner::kill_after_bytes`] and [`Runner::kill_after_lines`])
+    OutputLimit,
+    /// The command printed a line matching a pattern (see [`Runner::kill_on_match`])
+    PatternMatched,
+    /// The command printed a line longer than the limit, with [`LongLines::Fail`] (see [`Runner::max_line_length`])
+    LineTooLong,
+    /// This process got `SIGINT` or `SIGTERM` while the command was running, and passed it on (see
+    /// [`Runner::forward_signals`])
+    ///
+    /// <small>The command isn't necessarily killed by this; it's up to the command what it does with the signal</small>
+    Interrupted,
+}
+
+/// Specifies what a line was printed to - stdout, stderr, or another file descriptor
+///
+/// Lines can also come from something other than the command, like a note that it's being retried; see
+/// [`Line::system`] and [`Line::custom`].
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum LineType {
     Stdout,
     Stderr,
+    /// An extra file descriptor passed to the command with [`Runner::capture_fd`]
+    Fd(u32),
+    /// Added by whatever's running the command, e.g. "timeout reached", rather than printed by it
+    System,
+    /// Added by something else, named by the string, e.g. a test harness's `"harness"`
+    Custom(String),
+}
+
+impl fmt::Display for LineType {
+    /// Writes `stdout`, `stderr`, `system`, the name of a custom source, or e.g. `fd 3`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            LineType::Stdout => write!(f, "stdout"),
+            LineType::Stderr => write!(f, "stderr"),
+            LineType::Fd(fd) => write!(f, "fd {}", fd),
+            LineType::System => write!(f, "system"),
+            LineType::Custom(source) => write!(f, "{}", source),
+        };
+    }
 }
 
+/// Used for [`Line::seq`] when lines aren't captured by this crate
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
 /// A single line from the output of a command
-#[derive(Debug, Clone, PartialEq, Eq, Ord)]
+///
+/// Lines are ordered by when they were printed, then by [`Line::seq`] if they were printed at the same time.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Line {
-    /// Which stream the line was printed to
-    pub printed_to: LineType,
+    // `time` and `seq` have to stay first, since the ordering is derived from them
     /// When the line was printed
     pub time: Instant,
+    /// The order the line was captured in, starting from 0 for each command
+    ///
+    /// This keeps the order stable for lines captured at the same [`Instant`]. Lines created with
+    /// [`Line::from_stdout`] or [`Line::from_stderr`] get this from a counter shared by the whole process instead.
+    pub seq: u64,
+    /// Which stream the line was printed to
+    pub printed_to: LineType,
+    /// Which line of its stream this is, counting from 1, e.g. for "stderr line 37"
+    ///
+    /// Like `global_index`, this is set when the line is captured, so it stays the same if other lines are filtered
+    /// out. Lines created with [`Line::from_stdout`] or [`Line::from_stderr`] have 0 until they're part of a
+    /// [`CmdOutput`], which numbers them in the order they're in.
+    pub line_number: u64,
+    /// Where the line is among every line the command printed (across every stream), counting from 0
+    pub global_index: u64,
+    /// How long after the command started the line was printed, for "t+1.203s"-style timestamps
+    ///
+    /// This is set when the line is captured; lines created with [`Line::from_stdout`] or [`Line::from_stderr`] have
+    /// this set once they're part of a [`CmdOutput`].
+    pub offset: Duration,
     /// The content printed to the line
     pub content: String,
+    /// Whether the end of the line was cut off, for being longer than [`Runner::max_line_length`]
+    pub truncated: bool,
+    /// Whether the line was ended by a newline (or [`Runner::delimiter`])
+    ///
+    /// This is only `false` for the last line of a stream which ended without one, like the output of `printf hi`,
+    /// or with [`ReadMode::Chunks`], a line which was cut off by the end of a read.
+    pub terminated: bool,
+    /// Notes attached to the line while it was being handled (see [`Line::annotate`])
+    pub annotations: Vec<String>,
+    /// Set by [`Runner::classify_severity`], see [`Line::severity`]
+    severity: Option<Severity>,
+    /// The exact bytes printed, without the newline, if they were kept (see [`Runner::keep_raw`])
+    ///
+    /// `content` has any invalid UTF-8 replaced, so this is for when the output needs to be reproduced exactly.
+    pub raw: Option<Vec<u8>>,
 }
 
 impl Line {
-    /// Creates a [`Line`] from a string printed to stdout
-    pub fn from_stdout<S: AsRef<str>>(content: S) -> Self {
+    pub(crate) fn new(printed_to: LineType, time: Instant, seq: u64, content: String) -> Self {
         return Line {
-            content: content.as_ref().to_string(),
-            printed_to: LineType::Stdout,
-            time: Instant::now(),
+            time,
+            seq,
+            printed_to,
+            line_number: 0,
+            global_index: 0,
+            offset: Duration::ZERO,
+            content,
+            truncated: false,
+            terminated: true,
+            annotations: Vec::new(),
+            severity: None,
+            raw: None,
         };
     }
 
+    /// Creates a [`Line`] from a string printed to stdout
+    pub fn from_stdout<S: AsRef<str>>(content: S) -> Self {
+        return Line::new(
+            LineType::Stdout,
+            Instant::now(),
+            NEXT_SEQ.fetch_add(1, atomic::Ordering::Relaxed),
+            content.as_ref().to_string(),
+        );
+    }
+
     /// Creates a [`Line`] from a string printed to stderr
     pub fn from_stderr<S: AsRef<str>>(content: S) -> Self {
-        return Line {
-            content: content.as_ref().to_string(),
-            printed_to: LineType::Stderr,
-            time: Instant::now(),
-        };
+        return Line::new(
+            LineType::Stderr,
+            Instant::now(),
+            NEXT_SEQ.fetch_add(1, atomic::Ordering::Relaxed),
+            content.as_ref().to_string(),
+        );
     }
-}
 
-impl PartialOrd for Line {
-    fn ge(&self, other: &Line) -> bool {
-        if self.time >= other.time {
-            return true;
-        }
-        return false;
+    /// Creates a [`Line`] which wasn't printed by the command, like "--- retry 2 ---" (see [`LineType::System`])
+    ///
+    /// Add it to an output with [`CmdOutput::insert_line`].
+    pub fn system<S: AsRef<str>>(content: S) -> Self {
+        return Line::new(
+            LineType::System,
+            Instant::now(),
+            NEXT_SEQ.fetch_add(1, atomic::Ordering::Relaxed),
+            content.as_ref().to_string(),
+        );
     }
 
-    fn gt(&self, other: &Self) -> bool {
-        if self.time > other.time {
-            return true;
-        }
-        return false;
+    /// Creates a [`Line`] from `source`, which isn't the command (see [`LineType::Custom`])
+    pub fn custom<S: Into<String>, C: AsRef<str>>(source: S, content: C) -> Self {
+        return Line::new(
+            LineType::Custom(source.into()),
+            Instant::now(),
+            NEXT_SEQ.fetch_add(1, atomic::Ordering::Relaxed),
+            content.as_ref().to_string(),
+        );
     }
 
-    fn le(&self, other: &Self) -> bool {
-        if self.time <= other.time {
-            return true;
-        }
-        return false;
+    /// Attaches a note to the line, like "start of test X"
+    ///
+    /// This is meant for use while the output is being handled, e.g. in [`Runner::on_line`], so markers worked out in
+    /// real time are kept alongside the output.
+    pub fn annotate<S: AsRef<str>>(&mut self, annotation: S) {
+        self.annotations.push(annotation.as_ref().to_string());
     }
 
-    fn lt(&self, other: &Self) -> bool {
-        if self.time < other.time {
-            return true;
-        }
-        return false;
+    /// Returns whether the line is an error, warning, or note, if it was printed to stderr and matched one of the
+    /// rules given to [`Runner::classify_severity`]
+    pub fn severity(&self) -> Option<Severity> {
+        return self.severity;
     }
+}
 
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        if self < other {
-            return Some(Ordering::Less);
-        }
-        if self > other {
-            return Some(Ordering::Greater);
-        }
-        return Some(Ordering::Equal);
+impl fmt::Display for Line {
+    /// Writes how long after the command started the line was printed, where it was printed to, and its content, e.g.
+    /// `[+1.203s stderr] oops`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(
+            f,
+            "[+{:.3}s {}] {}",
+            self.offset.as_secs_f64(),
+            self.printed_to,
+            self.content
+        );
     }
 }
 
@@ -167,7 +1026,7 @@ impl PartialOrd for Line {
 /// use std::process::Command;
 /// let cmd = run(&mut Command::new("echo").arg("hi"));
 ///
-/// // prints the following: [Line { printed_to: Stdout, time: Instant { tv_sec: 16316, tv_nsec: 283884648 }, content: "hi" }]
+/// // prints the following: [Line { time: Instant { tv_sec: 16316, tv_nsec: 283884648 }, seq: 0, printed_to: Stdout, line_number: 1, global_index: 0, offset: 1.181477ms, content: "hi", truncated: false, terminated: true, annotations: [], severity: None, raw: None }]
 /// // (timestamp varies)
 /// assert_eq!("hi", cmd.lines().unwrap()[0].content);
 /// ```
@@ -183,53 +1042,90 @@ pub fn run(command: &mut Command) -> CmdOutput {
     let child_stdout = child.stdout.take().unwrap();
     let child_stderr = child.stderr.take().unwrap();
 
-    let stdout_lines = BufReader::new(child_stdout).lines();
+    // shared between both threads so lines printed at the same time still have a consistent order
+    let capture = Capture::default();
+
+    let stdout_capture = capture.clone();
     let stdout_thread = thread::spawn(move || {
-        let mut lines: Vec<Line> = Vec::new();
-        for line in stdout_lines {
-            lines.push(Line {
-                content: line.unwrap(),
-                printed_to: LineType::Stdout,
-                time: Instant::now(),
-            });
-        }
-        return lines;
+        return read_lines(child_stdout, LineType::Stdout, &stdout_capture).unwrap();
     });
 
-    let stderr_lines = BufReader::new(child_stderr).lines();
     let stderr_thread = thread::spawn(move || {
-        let mut lines: Vec<Line> = Vec::new();
-        for line in stderr_lines {
-            let time = Instant::now();
-            lines.push(Line {
-                content: line.unwrap(),
-                printed_to: LineType::Stderr,
-                time: time,
-            });
-        }
-        return lines;
+        return read_lines(child_stderr, LineType::Stderr, &capture).unwrap();
     });
 
-    let status = child.wait().unwrap().code();
+    let status = child.wait().unwrap();
     let end = Instant::now();
 
     let mut lines = stdout_thread.join().unwrap();
     lines.append(&mut stderr_thread.join().unwrap());
     lines.sort();
 
-    return CmdOutput {
-        lines: Some(lines),
-        status_code: status,
-        start_time: start,
-        end_time: end,
-        duration: end.duration_since(start),
-    };
+    let mut output = CmdOutput::new(
+        command_line(command),
+        Some(lines),
+        status.code(),
+        start,
+        end,
+    );
+    output.set_exit_status(status);
+    finished(command, &output);
+    return output;
+}
+
+/// Runs a command, only capturing what it prints to stderr
+///
+/// Stdout is left however you set it up on the [`Command`] (inherited by default), so it can go to a file or another
+/// process while errors are still captured. Since only one stream is read, this doesn't need to spawn any threads.
+///
+/// The [`CmdOutput`] *will* contain `Some(lines)`, but only lines printed to stderr.
+///
+/// Example:
+///
+/// ```
+/// use better_commands::run_stderr_only;
+/// use std::fs::File;
+/// use std::process::Command;
+///
+/// # let path = std::env::temp_dir().join("better-commands-run_stderr_only-doctest");
+/// let cmd = run_stderr_only(
+///     Command::new("bash")
+///         .arg("-c")
+///         .arg("echo data; >&2 echo oops")
+///         .stdout(File::create(&path).unwrap()),
+/// );
+///
+/// assert_eq!("oops", cmd.lines().unwrap()[0].content);
+/// # std::fs::remove_file(path).unwrap();
+/// ```
+pub fn run_stderr_only(command: &mut Command) -> CmdOutput {
+    let start = Instant::now();
+    let mut child = command.stderr(Stdio::piped()).spawn().unwrap();
+
+    let child_stderr = child.stderr.take().unwrap();
+    let lines = read_lines(child_stderr, LineType::Stderr, &Capture::default()).unwrap();
+
+    let status = child.wait().unwrap();
+    let end = Instant::now();
+
+    let mut output = CmdOutput::new(
+        command_line(command),
+        Some(lines),
+        status.code(),
+        start,
+        end,
+    );
+    output.set_exit_status(status);
+    finished(command, &output);
+    return output;
 }
 
 /// Runs a command while simultaneously running a provided [`Fn`] as the command prints line-by-line
 ///
 /// The [`CmdOutput`] *will* be None; this does *not* handle the lines - if you need them, use [`run`] or [`run_funcs_with_lines`]
 ///
+/// If either function panics, so does this; use [`try_run_funcs`] to handle that (or errors from the functions) instead.
+///
 /// Example:
 ///
 /// ```
@@ -249,6 +1145,7 @@ pub fn run(command: &mut Command) -> CmdOutput {
 ///     }
 /// });
 /// ```
+#[allow(clippy::unused_unit)]
 pub fn run_funcs(
     command: &mut Command,
     stdout_func: impl FnOnce(Lines<BufReader<ChildStdout>>) -> () + std::marker::Send + 'static,
@@ -271,24 +1168,82 @@ pub fn run_funcs(
     let stderr_lines = BufReader::new(child_stderr).lines();
     let stderr_thread = thread::spawn(move || stderr_func(stderr_lines));
 
-    let status = child.wait().unwrap().code();
+    let status = child.wait().unwrap();
     let end = Instant::now();
 
     stdout_thread.join().unwrap();
     stderr_thread.join().unwrap();
 
-    return CmdOutput {
-        lines: None,
-        status_code: status,
-        start_time: start,
-        end_time: end,
-        duration: end.duration_since(start),
-    };
+    let mut output = CmdOutput::new(command_line(command), None, status.code(), start, end);
+    output.set_exit_status(status);
+    finished(command, &output);
+    return output;
+}
+
+/// Same as [`run_funcs`], but moves `stdout_data` into the stdout function's thread and `stderr_data` into the stderr
+/// function's, so each one can have its own values (like a database connection) without cloning them
+///
+/// The functions also don't need to be `'static`, so they can borrow from the caller.
+///
+/// ```
+/// use better_commands::run_funcs_with_data;
+/// use std::process::Command;
+///
+/// let prefix = String::from("> ");
+/// let mut stdout_log = Vec::new();
+/// let mut stderr_log = Vec::new();
+/// run_funcs_with_data(
+///     Command::new("bash").arg("-c").arg("echo out; >&2 echo err"),
+///     &mut stdout_log,
+///     |lines, log| {
+///         for line in lines {
+///             log.push(format!("{}{}", prefix, line.unwrap()));
+///         }
+///     },
+///     &mut stderr_log,
+///     |lines, log| {
+///         for line in lines {
+///             log.push(line.unwrap());
+///         }
+///     },
+/// );
+///
+/// assert_eq!(vec!["> out"], stdout_log);
+/// assert_eq!(vec!["err"], stderr_log);
+/// ```
+pub fn run_funcs_with_data<A: Send, B: Send>(
+    command: &mut Command,
+    stdout_data: A,
+    stdout_func: impl FnOnce(Lines<BufReader<ChildStdout>>, A) + Send,
+    stderr_data: B,
+    stderr_func: impl FnOnce(Lines<BufReader<ChildStderr>>, B) + Send,
+) -> CmdOutput {
+    let start = Instant::now();
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let stdout_lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let stderr_lines = BufReader::new(child.stderr.take().unwrap()).lines();
+
+    let status = thread::scope(|scope| {
+        scope.spawn(move || stdout_func(stdout_lines, stdout_data));
+        scope.spawn(move || stderr_func(stderr_lines, stderr_data));
+        return child.wait().unwrap();
+    });
+    let end = Instant::now();
+
+    let mut output = CmdOutput::new(command_line(command), None, status.code(), start, end);
+    output.set_exit_status(status);
+    finished(command, &output);
+    return output;
 }
 
 /// Runs a command while simultaneously running a provided [`Fn`] as the command prints line-by-line, including line handling
 ///
-/// The [`CmdOutput`] *will* contain `Some(lines)`, not a None.
+/// The [`CmdOutput`] *will* contain `Some(lines)`, not a None. The lines your functions return are sorted by when they were created; use [`run_funcs_with_lines_unsorted`] to keep them as they are.
 ///
 /// Example:
 ///
@@ -313,7 +1268,7 @@ pub fn run_funcs(
 ///     }
 /// });
 ///
-/// // prints the following: [Line { printed_to: Stdout, time: Instant { tv_sec: 16316, tv_nsec: 283884648 }, content: "hi" }]
+/// // prints the following: [Line { time: Instant { tv_sec: 16316, tv_nsec: 283884648 }, seq: 0, printed_to: Stdout, line_number: 1, global_index: 0, offset: 1.181477ms, content: "hi", truncated: false, terminated: true, annotations: [], severity: None, raw: None }]
 /// // (timestamp varies)
 /// assert_eq!("hi", cmd.lines().unwrap()[0].content);
 /// ```
@@ -332,6 +1287,44 @@ pub fn run_funcs_with_lines(
     command: &mut Command,
     stdout_func: impl FnOnce(Lines<BufReader<ChildStdout>>) -> Vec<Line> + std::marker::Send + 'static,
     stderr_func: impl FnOnce(Lines<BufReader<ChildStderr>>) -> Vec<Line> + std::marker::Send + 'static,
+) -> CmdOutput {
+    return funcs_with_lines(command, true, stdout_func, stderr_func);
+}
+
+/// Same as [`run_funcs_with_lines`], but keeps the lines exactly as your functions return them, instead of sorting them
+///
+/// This is for when your functions put the lines in some other order on purpose. [`CmdOutput::stdout`] and
+/// [`CmdOutput::stderr`] are in the order they were returned in, and [`CmdOutput::lines`] has all of stdout's lines,
+/// then all of stderr's.
+///
+/// ```
+/// use better_commands::{run_funcs_with_lines_unsorted, Line};
+/// use std::process::Command;
+///
+/// let cmd = run_funcs_with_lines_unsorted(
+///     &mut Command::new("echo").arg("a\nb"),
+///     |stdout_lines| {
+///         let mut lines: Vec<Line> = stdout_lines.map(|line| Line::from_stdout(line.unwrap())).collect();
+///         lines.reverse();
+///         return lines;
+///     },
+///     |_| Vec::new(),
+/// );
+/// assert_eq!(Some("b\na".to_string()), cmd.stdout_string());
+/// ```
+pub fn run_funcs_with_lines_unsorted(
+    command: &mut Command,
+    stdout_func: impl FnOnce(Lines<BufReader<ChildStdout>>) -> Vec<Line> + std::marker::Send + 'static,
+    stderr_func: impl FnOnce(Lines<BufReader<ChildStderr>>) -> Vec<Line> + std::marker::Send + 'static,
+) -> CmdOutput {
+    return funcs_with_lines(command, false, stdout_func, stderr_func);
+}
+
+fn funcs_with_lines(
+    command: &mut Command,
+    sort: bool,
+    stdout_func: impl FnOnce(Lines<BufReader<ChildStdout>>) -> Vec<Line> + std::marker::Send + 'static,
+    stderr_func: impl FnOnce(Lines<BufReader<ChildStderr>>) -> Vec<Line> + std::marker::Send + 'static,
 ) -> CmdOutput {
     // https://stackoverflow.com/a/72831067/16432246
     let start = Instant::now();
@@ -353,16 +1346,210 @@ pub fn run_funcs_with_lines(
     let mut lines = stdout_thread.join().unwrap();
     let mut lines_printed_to_stderr = stderr_thread.join().unwrap();
     lines.append(&mut lines_printed_to_stderr);
-    lines.sort();
 
-    let status = child.wait().unwrap().code();
+    let status = child.wait().unwrap();
     let end = Instant::now();
 
-    return CmdOutput {
-        lines: Some(lines),
-        status_code: status,
-        start_time: start,
-        end_time: end,
-        duration: end.duration_since(start),
+    let mut output = CmdOutput::with_order(
+        command_line(command),
+        Some(lines),
+        sort,
+        status.code(),
+        start,
+        end,
+    );
+    output.set_exit_status(status);
+    finished(command, &output);
+    return output;
+}
+
+/// Same as [`run_funcs`], but the functions return a [`Result`], and if either of them fails (or panics), this returns
+/// [`Error::Callback`] instead of panicking
+///
+/// Starting or waiting for the command failing is an error too, rather than a panic. The command's still waited for
+/// if a function fails, so [`CallbackError::output`] has how it exited; the function's reader is dropped, so the
+/// command gets an error (or `SIGPIPE`) if it prints anything else there, rather than getting stuck.
+///
+/// ```
+/// use better_commands::{try_run_funcs, CallbackFailure, Error};
+/// use std::process::Command;
+///
+/// let result = try_run_funcs(
+///     Command::new("echo").arg("not a number"),
+///     |lines| {
+///         for line in lines {
+///             line?.parse::<u32>()?;
+///         }
+///         return Ok::<(), Box<dyn std::error::Error + Send + Sync>>(());
+///     },
+///     |_| Ok(()),
+/// );
+///
+/// let Err(Error::Callback(e)) = result else {
+///     panic!("expected the stdout function to fail");
+/// };
+/// assert!(matches!(e.failure, CallbackFailure::Error(_)));
+/// assert_eq!(Some(0), e.output.status_code());
+/// ```
+pub fn try_run_funcs<E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+    command: &mut Command,
+    stdout_func: impl FnOnce(Lines<BufReader<ChildStdout>>) -> Result<(), E> + Send + 'static,
+    stderr_func: impl FnOnce(Lines<BufReader<ChildStderr>>) -> Result<(), E> + Send + 'static,
+) -> Result<CmdOutput, Error> {
+    return try_funcs(
+        command,
+        false,
+        move |lines| stdout_func(lines).map(|()| Vec::new()),
+        move |lines| stderr_func(lines).map(|()| Vec::new()),
+    );
+}
+
+/// Same as [`run_funcs_with_lines`], but the functions return a [`Result`], and if either of them fails (or panics),
+/// this returns [`Error::Callback`] instead of panicking (see [`try_run_funcs`])
+///
+/// ```
+/// use better_commands::{try_run_funcs_with_lines, CallbackFailure, Error, Line};
+/// use std::io;
+/// use std::process::Command;
+///
+/// let result = try_run_funcs_with_lines(
+///     Command::new("bash").arg("-c").arg("echo fine; >&2 echo oops"),
+///     |lines| {
+///         return lines
+///             .map(|line| Ok(Line::from_stdout(line?)))
+///             .collect::<io::Result<Vec<Line>>>();
+///     },
+///     |_| panic!("not handling stderr"),
+/// );
+///
+/// let Err(Error::Callback(e)) = result else {
+///     panic!("expected the stderr function to panic");
+/// };
+/// assert!(matches!(e.failure, CallbackFailure::Panic(ref message) if message == "not handling stderr"));
+/// assert_eq!(Some("fine".to_string()), e.output.stdout_string());
+/// ```
+pub fn try_run_funcs_with_lines<E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+    command: &mut Command,
+    stdout_func: impl FnOnce(Lines<BufReader<ChildStdout>>) -> Result<Vec<Line>, E> + Send + 'static,
+    stderr_func: impl FnOnce(Lines<BufReader<ChildStderr>>) -> Result<Vec<Line>, E> + Send + 'static,
+) -> Result<CmdOutput, Error> {
+    return try_funcs(command, true, stdout_func, stderr_func);
+}
+
+fn try_funcs<E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+    command: &mut Command,
+    keep_lines: bool,
+    stdout_func: impl FnOnce(Lines<BufReader<ChildStdout>>) -> Result<Vec<Line>, E> + Send + 'static,
+    stderr_func: impl FnOnce(Lines<BufReader<ChildStderr>>) -> Result<Vec<Line>, E> + Send + 'static,
+) -> Result<CmdOutput, Error> {
+    check_not_empty(command)?;
+    let start = Instant::now();
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(Error::Spawn)?;
+
+    let stdout_lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let stderr_lines = BufReader::new(child.stderr.take().unwrap()).lines();
+
+    // errors are converted on the thread, so they don't need to be `Send` themselves
+    let stdout_thread = thread::spawn(move || stdout_func(stdout_lines).map_err(Into::into));
+    let stderr_thread = thread::spawn(move || stderr_func(stderr_lines).map_err(Into::into));
+
+    let status = child.wait();
+    let end = Instant::now();
+    let results = [
+        (LineType::Stdout, callback_result(stdout_thread.join())),
+        (LineType::Stderr, callback_result(stderr_thread.join())),
+    ];
+    let status = status.map_err(Error::Io)?;
+
+    let mut lines = Vec::new();
+    let mut failed = None;
+    for (printed_to, result) in results {
+        match result {
+            Ok(mut printed) => lines.append(&mut printed),
+            Err(failure) => {
+                failed.get_or_insert((printed_to, failure));
+            }
+        }
+    }
+    let mut output = CmdOutput::new(
+        command_line(command),
+        keep_lines.then_some(lines),
+        status.code(),
+        start,
+        end,
+    );
+    output.set_exit_status(status);
+    finished(command, &output);
+    if let Some((printed_to, failure)) = failed {
+        return Err(Error::Callback(Box::new(CallbackError {
+            printed_to,
+            failure,
+            output,
+        })));
+    }
+    return Ok(output);
+}
+
+/// Turns how a function handling output finished into a [`CallbackFailure`], if it returned an error or panicked
+fn callback_result<T>(
+    joined: thread::Result<Result<T, Box<dyn std::error::Error + Send + Sync>>>,
+) -> Result<T, CallbackFailure> {
+    return match joined {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(CallbackFailure::Error(e)),
+        Err(panic) => {
+            let message = match panic.downcast::<String>() {
+                Ok(message) => *message,
+                Err(panic) => match panic.downcast::<&str>() {
+                    Ok(message) => message.to_string(),
+                    Err(_) => "(no message)".to_string(),
+                },
+            };
+            Err(CallbackFailure::Panic(message))
+        }
     };
 }
+
+/// Returns [`Error::EmptyCommand`] if `command`'s program is empty (or only whitespace), which can't be run
+pub(crate) fn check_not_empty(command: &Command) -> Result<(), Error> {
+    if command.get_program().to_string_lossy().trim().is_empty() {
+        return Err(Error::EmptyCommand);
+    }
+    return Ok(());
+}
+
+/// Formats a [`Command`] as the program followed by its arguments, separated by spaces
+pub(crate) fn command_line(command: &Command) -> String {
+    let mut line = command.get_program().to_string_lossy().to_string();
+    for arg in command.get_args() {
+        line.push(' ');
+        line.push_str(&arg.to_string_lossy());
+    }
+    return line;
+}
+
+/// Records a command run without a [`Runner`] to the global [`AuditLog`] (and metrics, with the `metrics` feature),
+/// once it's exited
+pub(crate) fn finished(command: &Command, output: &CmdOutput) {
+    audit::record(command, output);
+    #[cfg(feature = "metrics")]
+    telemetry::record(&telemetry::program(command), output);
+}
+
+/// Returns the signal which killed the process, if any
+pub(crate) fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        return status.signal();
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        return None;
+    }
+}
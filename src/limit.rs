@@ -0,0 +1,132 @@
+use crate::Error;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Limits how quickly and how many commands can be run (see [`Runner::rate_limit`](crate::Runner::rate_limit))
+///
+/// Clones of a [`RateLimiter`] share the same limits, so giving clones of one limiter to several [`Runner`](crate::Runner)s
+/// limits all of them together.
+///
+/// ```
+/// use better_commands::{RateLimiter, Runner};
+///
+/// let limiter = RateLimiter::new().max_spawns_per_second(10).max_concurrent(4);
+/// let build = Runner::new().rate_limit(limiter.clone());
+/// let test = Runner::new().rate_limit(limiter);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    max_spawns_per_second: Option<usize>,
+    max_concurrent: Option<usize>,
+    error_on_exceed: bool,
+    state: Arc<(Mutex<LimiterState>, Condvar)>,
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    running: usize,
+    /// When each command in the last second was started, oldest first
+    recent_spawns: VecDeque<Instant>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        return RateLimiter {
+            max_spawns_per_second: None,
+            max_concurrent: None,
+            error_on_exceed: false,
+            state: Arc::new((Mutex::new(LimiterState::default()), Condvar::new())),
+        };
+    }
+}
+
+impl RateLimiter {
+    /// Creates a [`RateLimiter`] with no limits
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Only allows `max` commands to be started in any one-second window
+    ///
+    /// # Panics
+    ///
+    /// If `max` is 0, since nothing could ever be started
+    pub fn max_spawns_per_second(mut self, max: usize) -> Self {
+        assert!(max > 0, "max_spawns_per_second can't be 0");
+        self.max_spawns_per_second = Some(max);
+        return self;
+    }
+
+    /// Only allows `max` commands to be running at the same time
+    ///
+    /// # Panics
+    ///
+    /// If `max` is 0, since nothing could ever be started
+    pub fn max_concurrent(mut self, max: usize) -> Self {
+        assert!(max > 0, "max_concurrent can't be 0");
+        self.max_concurrent = Some(max);
+        return self;
+    }
+
+    /// Returns [`Error::RateLimited`] when a limit would be exceeded, instead of waiting until the command can be run
+    pub fn error_on_exceed(mut self) -> Self {
+        self.error_on_exceed = true;
+        return self;
+    }
+
+    /// Waits until a command can be started (or errors, see [`RateLimiter::error_on_exceed`])
+    ///
+    /// The command counts as running until the returned [`Permit`] is dropped.
+    pub(crate) fn acquire(&self) -> Result<Permit, Error> {
+        let (state, freed) = &*self.state;
+        let mut state = state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            while let Some(&oldest) = state.recent_spawns.front() {
+                if now.duration_since(oldest) < Duration::from_secs(1) {
+                    break;
+                }
+                state.recent_spawns.pop_front();
+            }
+
+            let too_many_running = self.max_concurrent.is_some_and(|max| state.running >= max);
+            let too_many_spawns = self
+                .max_spawns_per_second
+                .is_some_and(|max| state.recent_spawns.len() >= max);
+            if !too_many_running && !too_many_spawns {
+                state.running += 1;
+                state.recent_spawns.push_back(now);
+                return Ok(Permit {
+                    state: Arc::clone(&self.state),
+                });
+            }
+            if self.error_on_exceed {
+                return Err(Error::RateLimited);
+            }
+
+            // a permit being dropped wakes this up early; otherwise wake up when the oldest spawn leaves the window
+            state = match state.recent_spawns.front() {
+                Some(&oldest) if too_many_spawns => {
+                    let wait = (oldest + Duration::from_secs(1)).saturating_duration_since(now);
+                    freed.wait_timeout(state, wait).unwrap().0
+                }
+                _ => freed.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+/// Marks a command as running for a [`RateLimiter`] until dropped
+#[derive(Debug)]
+pub(crate) struct Permit {
+    state: Arc<(Mutex<LimiterState>, Condvar)>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let (state, freed) = &*self.state;
+        state.lock().unwrap().running -= 1;
+        freed.notify_all();
+    }
+}
@@ -0,0 +1,84 @@
+//! [`log`] support: logging each line a command prints
+use crate::capture::Capture;
+use crate::{Line, LineType, Runner};
+use log::Level;
+use std::path::Path;
+use std::process::Command;
+
+/// Which levels to log each stream at, from [`Runner::log_output`]
+#[derive(Debug, Clone)]
+pub(crate) struct LogOutput {
+    stdout: Level,
+    stderr: Level,
+    /// Worked out from the program when the command is started, if it isn't set
+    target: Option<String>,
+}
+
+impl LogOutput {
+    pub(crate) fn line(&self, line: &Line) {
+        let level = match line.printed_to {
+            LineType::Stdout => self.stdout,
+            LineType::Stderr => self.stderr,
+            // these are usually machine-readable status updates or notes, so they're treated like stdout
+            LineType::Fd(_) | LineType::System | LineType::Custom(_) => self.stdout,
+        };
+        let target = self.target.as_deref().unwrap_or("better_commands");
+        log::log!(target: target, level, "{}", line.content);
+    }
+}
+
+impl Runner {
+    /// Logs every line the command prints, at `stdout` for stdout and `stderr` for stderr
+    ///
+    /// The target is `better_commands::<program>` (e.g. `better_commands::cargo`) unless it's set with
+    /// [`Runner::log_target`], so output from different programs can be filtered separately.
+    ///
+    /// <small>This needs the `log` feature</small>
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use log::Level;
+    /// use std::process::Command;
+    ///
+    /// let runner = Runner::new().log_output(Level::Info, Level::Warn);
+    /// runner.run(Command::new("echo").arg("logged at info, with target better_commands::echo")).unwrap();
+    /// ```
+    pub fn log_output(mut self, stdout: Level, stderr: Level) -> Self {
+        let target = self.capture.log.take().and_then(|log| log.target);
+        self.capture.log = Some(LogOutput {
+            stdout,
+            stderr,
+            target,
+        });
+        return self;
+    }
+
+    /// Sets the target lines are logged with (see [`Runner::log_output`])
+    ///
+    /// If [`Runner::log_output`] hasn't been used, this also turns logging on, at `INFO` for stdout and `WARN` for stderr.
+    ///
+    /// <small>This needs the `log` feature</small>
+    pub fn log_target<S: Into<String>>(mut self, target: S) -> Self {
+        let log = self.capture.log.get_or_insert(LogOutput {
+            stdout: Level::Info,
+            stderr: Level::Warn,
+            target: None,
+        });
+        log.target = Some(target.into());
+        return self;
+    }
+}
+
+impl Capture {
+    /// Returns these options with the log target filled in for `command`, if it wasn't set
+    pub(crate) fn with_log_target(mut self, command: &Command) -> Capture {
+        if let Some(log) = &mut self.log {
+            if log.target.is_none() {
+                let program = command.get_program();
+                let name = Path::new(program).file_name().unwrap_or(program);
+                log.target = Some(format!("better_commands::{}", name.to_string_lossy()));
+            }
+        }
+        return self;
+    }
+}
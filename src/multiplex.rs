@@ -0,0 +1,148 @@
+//! Printing the output of several commands at once, each line prefixed with which command it's from
+use crate::{Line, LineSink};
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+
+/// Colors given to each prefix in turn: cyan, yellow, green, magenta, blue, then the bright versions
+const COLORS: &[&str] = &[
+    "\x1b[36m", "\x1b[33m", "\x1b[32m", "\x1b[35m", "\x1b[34m", "\x1b[96m", "\x1b[93m", "\x1b[92m",
+    "\x1b[95m", "\x1b[94m",
+];
+const RESET: &str = "\x1b[0m";
+
+/// Prints lines from several commands running at once, each prefixed with the command's name, like `docker compose`
+///
+/// Give each command its own sink from [`MultiplexPrinter::sink`]; each line is printed as soon as it's captured, so
+/// the output of every command is interleaved live. Prefixes are padded to the same width, and get a different color
+/// for each command when printing to a terminal.
+///
+/// ```
+/// use better_commands::{MultiplexPrinter, Runner};
+/// use std::process::Command;
+/// use std::thread;
+///
+/// let printer = MultiplexPrinter::new();
+/// let web = Runner::new().sink(printer.sink("web"));
+/// let db = Runner::new().sink(printer.sink("db"));
+///
+/// // prints something like:
+/// // web | starting web server
+/// // db  | starting database
+/// thread::scope(|scope| {
+///     scope.spawn(|| web.run(Command::new("echo").arg("starting web server")));
+///     scope.spawn(|| db.run(Command::new("echo").arg("starting database")));
+/// });
+/// ```
+#[derive(Clone)]
+pub struct MultiplexPrinter {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    writer: Box<dyn Write + Send>,
+    colors: bool,
+    /// The longest name so far, which every prefix is padded to
+    width: usize,
+    sinks: usize,
+}
+
+impl MultiplexPrinter {
+    /// Creates a printer which prints to stdout, using colors if it's a terminal
+    pub fn new() -> Self {
+        let colors = io::stdout().is_terminal();
+        return MultiplexPrinter::with_writer(io::stdout()).colors(colors);
+    }
+
+    /// Creates a printer which writes to `writer` instead, without colors
+    pub fn with_writer(writer: impl Write + Send + 'static) -> Self {
+        return MultiplexPrinter {
+            inner: Arc::new(Mutex::new(Inner {
+                writer: Box::new(writer),
+                colors: false,
+                width: 0,
+                sinks: 0,
+            })),
+        };
+    }
+
+    /// Sets whether the prefixes are colored
+    pub fn colors(self, colors: bool) -> Self {
+        self.inner.lock().unwrap().colors = colors;
+        return self;
+    }
+
+    /// Returns a sink which prints each line prefixed with `name`
+    ///
+    /// Prefixes are padded to the longest name given so far, so create every sink before starting the commands to
+    /// keep them lined up.
+    pub fn sink<S: Into<String>>(&self, name: S) -> MultiplexSink {
+        let name = name.into();
+        let mut inner = self.inner.lock().unwrap();
+        inner.width = inner.width.max(name.chars().count());
+        let color = COLORS[inner.sinks % COLORS.len()];
+        inner.sinks += 1;
+        return MultiplexSink {
+            inner: Arc::clone(&self.inner),
+            name,
+            color,
+        };
+    }
+}
+
+impl Default for MultiplexPrinter {
+    fn default() -> Self {
+        return MultiplexPrinter::new();
+    }
+}
+
+impl fmt::Debug for MultiplexPrinter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        return f
+            .debug_struct("MultiplexPrinter")
+            .field("colors", &inner.colors)
+            .field("width", &inner.width)
+            .field("sinks", &inner.sinks)
+            .finish_non_exhaustive();
+    }
+}
+
+/// A [`LineSink`] printing lines for one command, from [`MultiplexPrinter::sink`]
+pub struct MultiplexSink {
+    inner: Arc<Mutex<Inner>>,
+    name: String,
+    color: &'static str,
+}
+
+impl fmt::Debug for MultiplexSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("MultiplexSink")
+            .field("name", &self.name)
+            .finish_non_exhaustive();
+    }
+}
+
+impl LineSink for MultiplexSink {
+    /// Prints the line; errors (e.g. from stdout being closed) are ignored
+    fn accept(&mut self, line: Line) {
+        let mut inner = self.inner.lock().unwrap();
+        let (color, reset) = match inner.colors {
+            true => (self.color, RESET),
+            false => ("", ""),
+        };
+        let width = inner.width;
+        // written in one go, so lines from different commands can't be mixed together
+        let printed = format!(
+            "{}{:<width$} |{} {}\n",
+            color,
+            self.name,
+            reset,
+            line.content,
+            width = width
+        );
+        let _ = inner.writer.write_all(printed.as_bytes());
+        let _ = inner.writer.flush();
+    }
+}
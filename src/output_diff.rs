@@ -0,0 +1,140 @@
+//! Comparing two outputs line by line
+use crate::diff::{diff, DiffOp};
+use crate::{CmdOutput, Line};
+use std::fmt::Write;
+
+/// How a line differs between two outputs, from [`CmdOutput::diff`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineChange {
+    /// The line is in both
+    Unchanged(String),
+    /// The line is only in the other output
+    Added(String),
+    /// The line is only in this output
+    Removed(String),
+    /// The line was replaced with a different one
+    Changed { old: String, new: String },
+}
+
+/// The differences between two outputs' streams, from [`CmdOutput::diff`]
+///
+/// Lines are compared by their content, ignoring when they were printed. Each stream has every line from both outputs,
+/// in order, including unchanged ones for context.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OutputDiff {
+    pub stdout: Vec<LineChange>,
+    pub stderr: Vec<LineChange>,
+}
+
+impl OutputDiff {
+    /// Whether both outputs printed exactly the same lines
+    pub fn is_empty(&self) -> bool {
+        return self
+            .stdout
+            .iter()
+            .chain(&self.stderr)
+            .all(|change| matches!(change, LineChange::Unchanged(_)));
+    }
+
+    /// Shows the differences like `diff -u`, with a section for each stream which changed
+    ///
+    /// Removed lines start with `-`, added ones with `+`, and unchanged ones with two spaces. Changed lines show up as
+    /// the old line removed and the new one added. Returns an empty string if there aren't any differences.
+    pub fn pretty(&self) -> String {
+        let mut shown = String::new();
+        for (name, changes) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if changes
+                .iter()
+                .all(|change| matches!(change, LineChange::Unchanged(_)))
+            {
+                continue;
+            }
+            let _ = writeln!(shown, "--- {} ---", name);
+            for change in changes {
+                let _ = match change {
+                    LineChange::Unchanged(line) => writeln!(shown, "  {}", line),
+                    LineChange::Added(line) => writeln!(shown, "+ {}", line),
+                    LineChange::Removed(line) => writeln!(shown, "- {}", line),
+                    LineChange::Changed { old, new } => writeln!(shown, "- {}\n+ {}", old, new),
+                };
+            }
+        }
+        return shown;
+    }
+}
+
+impl CmdOutput {
+    /// Compares this output's lines with `other`'s, for each stream
+    ///
+    /// ```
+    /// use better_commands::{run, LineChange};
+    /// use std::process::Command;
+    ///
+    /// let old = run(Command::new("bash").arg("-c").arg("echo a; echo b; echo c"));
+    /// let new = run(Command::new("bash").arg("-c").arg("echo a; echo B; echo c; echo d"));
+    ///
+    /// let diff = old.diff(&new);
+    /// assert_eq!(
+    ///     vec![
+    ///         LineChange::Unchanged("a".to_string()),
+    ///         LineChange::Changed { old: "b".to_string(), new: "B".to_string() },
+    ///         LineChange::Unchanged("c".to_string()),
+    ///         LineChange::Added("d".to_string()),
+    ///     ],
+    ///     diff.stdout
+    /// );
+    /// assert!(diff.stderr.is_empty());
+    /// print!("{}", diff.pretty());
+    /// ```
+    pub fn diff(&self, other: &CmdOutput) -> OutputDiff {
+        return OutputDiff {
+            stdout: diff_stream(
+                self.stdout().unwrap_or_default(),
+                other.stdout().unwrap_or_default(),
+            ),
+            stderr: diff_stream(
+                self.stderr().unwrap_or_default(),
+                other.stderr().unwrap_or_default(),
+            ),
+        };
+    }
+}
+
+fn diff_stream(old: &[Line], new: &[Line]) -> Vec<LineChange> {
+    let old: Vec<&str> = old.iter().map(|line| line.content.as_str()).collect();
+    let new: Vec<&str> = new.iter().map(|line| line.content.as_str()).collect();
+
+    let mut changes = Vec::new();
+    // removed and added lines next to each other are paired up as changes
+    let mut removed: Vec<&str> = Vec::new();
+    let mut added: Vec<&str> = Vec::new();
+    let flush = |changes: &mut Vec<LineChange>, removed: &mut Vec<&str>, added: &mut Vec<&str>| {
+        let paired = removed.len().min(added.len());
+        for (old, new) in removed.iter().zip(added.iter()) {
+            changes.push(LineChange::Changed {
+                old: old.to_string(),
+                new: new.to_string(),
+            });
+        }
+        for old in &removed[paired..] {
+            changes.push(LineChange::Removed(old.to_string()));
+        }
+        for new in &added[paired..] {
+            changes.push(LineChange::Added(new.to_string()));
+        }
+        removed.clear();
+        added.clear();
+    };
+    for op in diff(&old, &new) {
+        match op {
+            DiffOp::Same(i, _) => {
+                flush(&mut changes, &mut removed, &mut added);
+                changes.push(LineChange::Unchanged(old[i].to_string()));
+            }
+            DiffOp::Removed(i) => removed.push(old[i]),
+            DiffOp::Added(j) => added.push(new[j]),
+        }
+    }
+    flush(&mut changes, &mut removed, &mut added);
+    return changes;
+}
@@ -0,0 +1,100 @@
+//! Turning lines into typed values as they're captured
+use crate::{CmdOutput, Error, Line, Runner};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+/// Turns lines into something more useful, like progress percentages or `key=value` pairs
+///
+/// Used with [`Runner::run_parsed`], which runs it on each line as it's captured, so the output doesn't need to be
+/// gone through again afterwards. Closures taking a `&Line` and returning an `Option` implement this too.
+///
+/// ```
+/// use better_commands::{Line, LineParser};
+///
+/// /// Parses lines like `progress: 42%`
+/// struct Progress;
+///
+/// impl LineParser for Progress {
+///     type Out = u8;
+///
+///     fn parse(&mut self, line: &Line) -> Option<u8> {
+///         return line.content.strip_prefix("progress: ")?.strip_suffix('%')?.parse().ok();
+///     }
+/// }
+/// ```
+pub trait LineParser {
+    type Out;
+
+    /// Parses a line, returning `None` to skip it
+    fn parse(&mut self, line: &Line) -> Option<Self::Out>;
+}
+
+impl<F, T> LineParser for F
+where
+    F: FnMut(&Line) -> Option<T>,
+{
+    type Out = T;
+
+    fn parse(&mut self, line: &Line) -> Option<T> {
+        return self(line);
+    }
+}
+
+/// The output of [`Runner::run_parsed`]
+#[derive(Debug, Clone)]
+pub struct ParsedOutput<T> {
+    /// Everything the parser returned, in the order the lines were captured
+    pub values: Vec<T>,
+    pub output: CmdOutput,
+}
+
+impl Runner {
+    /// Runs a command, running `parser` on each line (from both stdout and stderr) as it's captured
+    ///
+    /// The parser runs after [`Runner::on_line`], so it sees any changes made there. It's shared between stdout and
+    /// stderr, so it's only ever parsing one line at a time. With [`Runner::retries`], only the values from the final
+    /// attempt are kept, but the parser itself isn't reset between attempts.
+    ///
+    /// ```
+    /// use better_commands::{Line, Runner};
+    /// use std::process::Command;
+    ///
+    /// let parsed = Runner::new()
+    ///     .run_parsed(
+    ///         Command::new("bash").arg("-c").arg("echo name=foo; echo hi; echo version=1.2"),
+    ///         |line: &Line| {
+    ///             let (key, value) = line.content.split_once('=')?;
+    ///             return Some((key.to_string(), value.to_string()));
+    ///         },
+    ///     )
+    ///     .unwrap();
+    ///
+    /// assert_eq!(("version".to_string(), "1.2".to_string()), parsed.values[1]);
+    /// assert_eq!(3, parsed.output.len());
+    /// ```
+    pub fn run_parsed<P>(
+        &self,
+        command: &mut Command,
+        mut parser: P,
+    ) -> Result<ParsedOutput<P::Out>, Error>
+    where
+        P: LineParser + Send + 'static,
+        P::Out: Send + 'static,
+    {
+        let values = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = self.clone();
+        let parsed = Arc::clone(&values);
+        runner.capture.parser = Some(Arc::new(Mutex::new(move |line: &Line| {
+            if let Some(value) = parser.parse(line) {
+                parsed.lock().unwrap().push(value);
+            }
+        })));
+
+        let output = runner.run_with(command, |command| {
+            values.lock().unwrap().clear();
+            return runner.spawn(command)?.wait();
+        })?;
+        let values = std::mem::take(&mut *values.lock().unwrap());
+        return Ok(ParsedOutput { values, output });
+    }
+}
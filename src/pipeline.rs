@@ -0,0 +1,164 @@
+use crate::capture::{read_lines, Capture};
+use crate::{check_not_empty, command_line, CmdOutput, Error, Line, LineType};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Instant;
+
+/// Runs several commands with the stdout of each one connected to the stdin of the next, like `a | b | c` in a shell
+///
+/// The pipes are set up directly between the processes, so nothing passes through this crate except the final stdout
+/// and the stderr of each stage.
+///
+/// ```
+/// use better_commands::Pipeline;
+/// use std::process::Command;
+///
+/// let mut echo = Command::new("echo");
+/// echo.arg("b\na\nc");
+/// let mut head = Command::new("head");
+/// head.arg("-n2");
+///
+/// let output = Pipeline::new(echo)
+///     .pipe(Command::new("sort"))
+///     .pipe(head)
+///     .run()
+///     .unwrap();
+///
+/// let contents: Vec<String> = output.lines().into_iter().map(|(_, line)| line.content.clone()).collect();
+/// assert_eq!(vec!["a", "b"], contents);
+/// assert_eq!(vec![Some(0), Some(0), Some(0)], output.status_codes());
+/// ```
+#[derive(Debug)]
+pub struct Pipeline {
+    commands: Vec<Command>,
+}
+
+impl Pipeline {
+    /// Creates a pipeline starting with `command`
+    pub fn new(command: Command) -> Self {
+        return Pipeline {
+            commands: vec![command],
+        };
+    }
+
+    /// Adds a command to the end of the pipeline, which reads the previous command's stdout as its stdin
+    pub fn pipe(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        return self;
+    }
+
+    /// Runs the pipeline, returning the output of each stage once every stage has exited
+    pub fn run(&mut self) -> Result<PipelineOutput, Error> {
+        for command in &self.commands {
+            check_not_empty(command)?;
+        }
+        let start = Instant::now();
+        let last = self.commands.len() - 1;
+        let mut children: Vec<Child> = Vec::new();
+        for (i, command) in self.commands.iter_mut().enumerate() {
+            if let Some(previous) = children.last_mut() {
+                command.stdin(Stdio::from(previous.stdout.take().unwrap()));
+            }
+            let spawned = command
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn();
+            if i != 0 {
+                // the command holds onto the pipe otherwise, so the previous stage wouldn't get SIGPIPE if this one exits early
+                command.stdin(Stdio::inherit());
+            }
+
+            match spawned {
+                Ok(child) => children.push(child),
+                Err(e) => {
+                    for mut child in children {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(Error::Spawn(e));
+                }
+            }
+        }
+
+        let final_stdout = children[last].stdout.take().unwrap();
+        let stderrs: Vec<_> = children
+            .iter_mut()
+            .map(|child| child.stderr.take().unwrap())
+            .collect();
+
+        let capture = Capture::default().for_run(start);
+        return thread::scope(|scope| {
+            let stdout_thread =
+                scope.spawn(|| read_lines(final_stdout, LineType::Stdout, &capture));
+            let stderr_threads: Vec<_> = stderrs
+                .into_iter()
+                .map(|stderr| scope.spawn(|| read_lines(stderr, LineType::Stderr, &capture)))
+                .collect();
+
+            let mut ends = Vec::new();
+            let mut statuses = Vec::new();
+            for child in &mut children {
+                statuses.push(child.wait()?);
+                ends.push(Instant::now());
+            }
+
+            let mut final_stdout = Some(stdout_thread.join().unwrap()?);
+            let mut stages = Vec::new();
+            for (i, stderr_thread) in stderr_threads.into_iter().enumerate() {
+                let mut lines = stderr_thread.join().unwrap()?;
+                if i == last {
+                    lines.append(&mut final_stdout.take().unwrap());
+                    lines.sort();
+                }
+                let mut stage = CmdOutput::new(
+                    command_line(&self.commands[i]),
+                    Some(lines),
+                    statuses[i].code(),
+                    start,
+                    ends[i],
+                );
+                stage.set_exit_status(statuses[i]);
+                crate::finished(&self.commands[i], &stage);
+                stages.push(stage);
+            }
+            return Ok(PipelineOutput { stages });
+        });
+    }
+}
+
+/// The output of a [`Pipeline`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineOutput {
+    stages: Vec<CmdOutput>,
+}
+
+impl PipelineOutput {
+    /// Returns the output of each stage, in order
+    ///
+    /// Each stage's lines are only what it printed to stderr, except for the last stage, which also has its stdout.
+    pub fn stages(&self) -> &[CmdOutput] {
+        return &self.stages;
+    }
+
+    /// Returns every captured line in the order they were printed, along with the index of the stage that printed it
+    pub fn lines(&self) -> Vec<(usize, &Line)> {
+        let mut lines: Vec<(usize, &Line)> = self
+            .stages
+            .iter()
+            .enumerate()
+            .flat_map(|(i, stage)| stage.iter().map(move |line| (i, line)))
+            .collect();
+        lines.sort_by(|a, b| a.1.cmp(b.1));
+        return lines;
+    }
+
+    /// Returns the exit status code of each stage, in order
+    pub fn status_codes(&self) -> Vec<Option<i32>> {
+        return self.stages.iter().map(|stage| stage.status_code).collect();
+    }
+
+    /// Returns the exit status code of the last stage, which is what a shell would report for the whole pipeline
+    pub fn status_code(&self) -> Option<i32> {
+        return self.stages.last().unwrap().status_code;
+    }
+}
@@ -0,0 +1,107 @@
+use crate::running::Reader;
+use crate::{CmdOutput, Error, RateLimiter, Runner};
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs commands using a fixed set of threads to read their output, instead of starting new ones for every command
+///
+/// Each running command needs two threads (one for stdout and one for stderr), so a pool for `max_concurrent` commands
+/// keeps `2 * max_concurrent` threads around; running more commands than that at once waits for one to finish. This
+/// is useful when running lots of short commands, where starting threads is a noticeable part of the cost; otherwise,
+/// [`Runner::run`] is simpler.
+///
+/// ```
+/// use better_commands::{CommandPool, Runner};
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// let pool = CommandPool::new(4).runner(Runner::new().timeout(Duration::from_secs(10)));
+/// for i in 0..10 {
+///     let output = pool.run(Command::new("echo").arg(i.to_string())).unwrap();
+///     assert_eq!(i.to_string(), output.lines().unwrap()[0].content);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct CommandPool {
+    runner: Runner,
+    /// Makes sure there are always enough free threads to read both streams of every running command
+    slots: RateLimiter,
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl CommandPool {
+    /// Creates a pool which can run up to `max_concurrent` commands at the same time
+    ///
+    /// Panics if `max_concurrent` is 0.
+    pub fn new(max_concurrent: usize) -> Self {
+        assert!(
+            max_concurrent > 0,
+            "a CommandPool needs to be able to run at least one command"
+        );
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..max_concurrent * 2)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || work(&receiver))
+            })
+            .collect();
+        return CommandPool {
+            runner: Runner::new(),
+            slots: RateLimiter::new().max_concurrent(max_concurrent),
+            jobs: Some(jobs),
+            workers,
+        };
+    }
+
+    /// Sets the [`Runner`] used to run each command, e.g. to give each one a timeout
+    pub fn runner(mut self, runner: Runner) -> Self {
+        self.runner = runner;
+        return self;
+    }
+
+    /// Runs a command on the pool, returning a [`CmdOutput`] (which *will* contain `Some(lines)`, not a None)
+    ///
+    /// This is the same as [`Runner::run`], including retries, except the output is read by the pool's threads. If the
+    /// pool is already running as many commands as it can, this waits for one to finish first.
+    pub fn run(&self, command: &mut Command) -> Result<CmdOutput, Error> {
+        return self.runner.run_with(command, |command| {
+            let _slot = self.slots.acquire()?;
+            let running = self.runner.spawn_with(command, |job| {
+                let (sender, receiver) = mpsc::channel();
+                let job: Job = Box::new(move || {
+                    let _ = sender.send(job());
+                });
+                self.jobs.as_ref().unwrap().send(job).unwrap();
+                Reader::Pooled(receiver)
+            })?;
+            return running.wait();
+        });
+    }
+}
+
+impl Drop for CommandPool {
+    fn drop(&mut self) {
+        // closing the channel stops the workers once they've finished their current jobs
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Runs jobs until the pool is dropped
+fn work(jobs: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = jobs.lock().unwrap().recv();
+        match job {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
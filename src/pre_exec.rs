@@ -0,0 +1,332 @@
+//! Options applied in the child between forking and running the command, on Unix
+use crate::Rlimit;
+use std::io;
+use std::process::Command;
+
+/// Everything [`Runner`](crate::Runner) sets up in the child before it runs the command
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PreExec {
+    /// From [`Runner::parent_death_signal`](crate::Runner::parent_death_signal)
+    pub(crate) parent_death_signal: Option<i32>,
+    /// From [`Runner::uid`](crate::Runner::uid) and friends
+    pub(crate) uid: Option<u32>,
+    pub(crate) gid: Option<u32>,
+    /// Supplementary groups, from [`Runner::user`](crate::Runner::user)
+    pub(crate) groups: Option<Vec<u32>>,
+    /// From [`Runner::limit`](crate::Runner::limit)
+    pub(crate) limits: Vec<Rlimit>,
+    /// From [`Runner::nice`](crate::Runner::nice)
+    pub(crate) nice: Option<i32>,
+    /// From [`Runner::cpu_affinity`](crate::Runner::cpu_affinity)
+    pub(crate) cpu_affinity: Option<Vec<usize>>,
+    /// From [`Runner::process_group`](crate::Runner::process_group)
+    pub(crate) process_group: bool,
+    /// The file descriptor of the `cgroup.procs` file to join, from [`Runner::cgroup`](crate::Runner::cgroup)
+    #[cfg(feature = "cgroups")]
+    pub(crate) cgroup_procs: Option<i32>,
+    /// The pipes to move into place, from [`Runner::capture_fd`](crate::Runner::capture_fd)
+    #[cfg(unix)]
+    pub(crate) fds: Option<crate::fds::FdMapping>,
+    /// From [`Runner::chroot`](crate::Runner::chroot)
+    #[cfg(unix)]
+    pub(crate) root: Option<std::ffi::CString>,
+    /// Whether the command has its own working directory, which is kept after changing the root
+    #[cfg(unix)]
+    pub(crate) keep_current_dir: bool,
+    /// From [`Runner::isolate`](crate::Runner::isolate)
+    #[cfg(feature = "namespaces")]
+    pub(crate) namespaces: Option<crate::isolation::Namespaces>,
+}
+
+/// Keeps the hook added by [`PreExec::apply`] working until this is dropped, which should be right after spawning
+///
+/// Hooks can't be removed from a [`Command`], so they'd otherwise still run whenever it's run again afterwards (e.g.
+/// with another [`Runner`](crate::Runner), or without one), using options meant for an earlier run.
+#[cfg(unix)]
+pub(crate) struct Hook(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+#[cfg(unix)]
+impl Drop for Hook {
+    fn drop(&mut self) {
+        self.0.store(false, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+impl PreExec {
+    fn is_empty(&self) -> bool {
+        return self.parent_death_signal.is_none()
+            && self.uid.is_none()
+            && self.gid.is_none()
+            && self.groups.is_none()
+            && self.limits.is_empty()
+            && self.nice.is_none()
+            && self.cpu_affinity.is_none()
+            && !self.process_group
+            && !self.joins_cgroup()
+            && !self.passes_fds()
+            && !self.changes_root()
+            && !self.enters_namespaces();
+    }
+
+    #[cfg(feature = "cgroups")]
+    fn joins_cgroup(&self) -> bool {
+        return self.cgroup_procs.is_some();
+    }
+
+    #[cfg(not(feature = "cgroups"))]
+    fn joins_cgroup(&self) -> bool {
+        return false;
+    }
+
+    #[cfg(unix)]
+    fn passes_fds(&self) -> bool {
+        return self.fds.is_some();
+    }
+
+    #[cfg(not(unix))]
+    fn passes_fds(&self) -> bool {
+        return false;
+    }
+
+    #[cfg(unix)]
+    fn changes_root(&self) -> bool {
+        return self.root.is_some();
+    }
+
+    #[cfg(not(unix))]
+    fn changes_root(&self) -> bool {
+        return false;
+    }
+
+    #[cfg(feature = "namespaces")]
+    fn enters_namespaces(&self) -> bool {
+        return self
+            .namespaces
+            .as_ref()
+            .is_some_and(|namespaces| !namespaces.is_empty());
+    }
+
+    #[cfg(not(feature = "namespaces"))]
+    fn enters_namespaces(&self) -> bool {
+        return false;
+    }
+
+    /// Adds a hook to `command` to set everything up in the child, which only does anything until the returned
+    /// [`Hook`] is dropped
+    #[cfg(unix)]
+    pub(crate) fn apply(&self, command: &mut Command) -> Option<Hook> {
+        use std::os::unix::process::CommandExt;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        if self.is_empty() {
+            return None;
+        }
+        let options = self.clone();
+        let parent = std::process::id();
+        let active = Arc::new(AtomicBool::new(true));
+        let hook = Hook(active.clone());
+        unsafe {
+            // only async-signal-safe functions can be used in here, so nothing which allocates
+            command.pre_exec(move || {
+                if !active.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+                return options.run(parent);
+            });
+        }
+        return Some(hook);
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn apply(&self, _command: &mut Command) -> Option<()> {
+        return None;
+    }
+
+    /// Runs in the child; `parent` is this process's PID
+    #[cfg(unix)]
+    fn run(&self, parent: u32) -> io::Result<()> {
+        // this has to come first, while it still has permission to move itself
+        #[cfg(feature = "cgroups")]
+        if let Some(fd) = self.cgroup_procs {
+            if unsafe { libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(mapping) = &self.fds {
+            crate::fds::move_into_place(mapping)?;
+        }
+        if self.process_group && unsafe { libc::setpgid(0, 0) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        for limit in &self.limits {
+            limit.apply()?;
+        }
+        // these come before changing credentials too, since only root can raise the priority
+        if let Some(nice) = self.nice {
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(cpus) = &self.cpu_affinity {
+            let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            for &cpu in cpus {
+                if cpu >= libc::CPU_SETSIZE as usize {
+                    return Err(io::Error::from_raw_os_error(libc::EINVAL));
+                }
+                unsafe { libc::CPU_SET(cpu, &mut set) };
+            }
+            let size = std::mem::size_of::<libc::cpu_set_t>();
+            if unsafe { libc::sched_setaffinity(0, size, &set) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        // this needs root, so it has to come before changing credentials
+        if let Some(root) = &self.root {
+            if unsafe { libc::chroot(root.as_ptr()) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            // otherwise the working directory is left outside of it
+            if !self.keep_current_dir && unsafe { libc::chdir(c"/".as_ptr()) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        // this has to come before the parent death signal, since changing credentials clears it
+        self.set_credentials()?;
+        #[cfg(target_os = "linux")]
+        if let Some(signal) = self.parent_death_signal {
+            if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, signal as libc::c_ulong) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            // the parent might've died before that was set, and it'd never be sent
+            if unsafe { libc::getppid() } as u32 != parent {
+                unsafe { libc::raise(signal) };
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = parent;
+        // this comes last, since entering a PID namespace means forking again
+        #[cfg(all(feature = "namespaces", target_os = "linux"))]
+        if let Some(namespaces) = &self.namespaces {
+            namespaces.enter()?;
+        }
+        return Ok(());
+    }
+
+    /// Sets the groups, then the group, then the user, since only root can change the first two
+    #[cfg(unix)]
+    fn set_credentials(&self) -> io::Result<()> {
+        let check = |result: libc::c_int| {
+            if result == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            return Ok(());
+        };
+        unsafe {
+            match &self.groups {
+                Some(groups) => check(libc::setgroups(
+                    groups.len() as _,
+                    groups.as_ptr() as *const libc::gid_t,
+                ))?,
+                // like `std`, root's own supplementary groups are dropped when switching to another user
+                None if self.uid.is_some() && libc::getuid() == 0 => {
+                    check(libc::setgroups(0, std::ptr::null()))?
+                }
+                None => {}
+            }
+            if let Some(gid) = self.gid {
+                check(libc::setgid(gid as libc::gid_t))?;
+            }
+            if let Some(uid) = self.uid {
+                check(libc::setuid(uid as libc::uid_t))?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// A user account, from [`lookup_user`]
+#[cfg(unix)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Account {
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    pub(crate) groups: Vec<u32>,
+    pub(crate) home: String,
+}
+
+/// Looks up a user by name, returning [`io::ErrorKind::NotFound`] if there isn't one
+#[cfg(unix)]
+pub(crate) fn lookup_user(name: &str) -> io::Result<Account> {
+    use std::ffi::{CStr, CString};
+
+    let c_name = CString::new(name).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "user name contains a NUL byte")
+    })?;
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    loop {
+        let error = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match error {
+            0 => break,
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            error => return Err(io::Error::from_raw_os_error(error)),
+        }
+    }
+    if result.is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no such user: {}", name),
+        ));
+    }
+
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        #[cfg(target_vendor = "apple")]
+        let found = unsafe {
+            libc::getgrouplist(
+                c_name.as_ptr(),
+                passwd.pw_gid as libc::c_int,
+                groups.as_mut_ptr() as *mut libc::c_int,
+                &mut count,
+            )
+        };
+        #[cfg(not(target_vendor = "apple"))]
+        let found = unsafe {
+            libc::getgrouplist(
+                c_name.as_ptr(),
+                passwd.pw_gid,
+                groups.as_mut_ptr(),
+                &mut count,
+            )
+        };
+        if found != -1 {
+            groups.truncate(count as usize);
+            break;
+        }
+        // there wasn't enough room; `count` might've been set to how much is needed
+        groups.resize((count as usize).max(groups.len() * 2), 0);
+    }
+
+    // these are u32 on most platforms, but not all of them
+    #[allow(clippy::unnecessary_cast)]
+    return Ok(Account {
+        uid: passwd.pw_uid as u32,
+        gid: passwd.pw_gid as u32,
+        groups: groups.into_iter().map(|group| group as u32).collect(),
+        home: unsafe { CStr::from_ptr(passwd.pw_dir) }
+            .to_string_lossy()
+            .into_owned(),
+    });
+}
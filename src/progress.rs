@@ -0,0 +1,76 @@
+//! Working out how far along a command is from the progress it prints
+use regex::{Captures, Regex};
+use std::sync::OnceLock;
+
+/// Returns how far along `text` says something is, from 0.0 to 1.0, if there's a progress indicator in it
+///
+/// This recognizes:
+/// - percentages, like `45%` or `12.5 %`
+/// - amounts of data, like `1.2MB/5.0MB` or `512 KiB / 2 MiB`
+/// - counts, like `[12/100]` or `3/4`
+///
+/// If there's more than one, a percentage is used over an amount of data, which is used over a count. Anything past
+/// the end (like `120%`) is treated as 1.0. This is what [`Runner::on_progress`](crate::Runner::on_progress) uses.
+///
+/// ```
+/// use better_commands::parse_progress;
+///
+/// assert_eq!(Some(0.45), parse_progress("Downloading... 45%"));
+/// assert_eq!(Some(0.12), parse_progress("[12/100] Compiling foo.c"));
+/// assert_eq!(Some(0.25), parse_progress("1.25MB/5.0MB"));
+/// assert_eq!(None, parse_progress("Done"));
+/// ```
+pub fn parse_progress(text: &str) -> Option<f64> {
+    static PATTERNS: OnceLock<[Regex; 3]> = OnceLock::new();
+    let [percent, data, count] = PATTERNS.get_or_init(|| {
+        return [
+            Regex::new(r"(\d+(?:\.\d+)?)\s?%").unwrap(),
+            Regex::new(
+                r"(?i)(\d+(?:\.\d+)?)\s*([kmgt]i?b|b)\s*/\s*(\d+(?:\.\d+)?)\s*([kmgt]i?b|b)\b",
+            )
+            .unwrap(),
+            // not part of a path, a date, or a version number
+            Regex::new(r"(?:^|[^\w./])(\d+)\s*/\s*(\d+)(?:$|[^\w./])").unwrap(),
+        ];
+    });
+
+    if let Some(captures) = percent.captures(text) {
+        return fraction(number(&captures, 1), 100.0);
+    }
+    if let Some(captures) = data.captures(text) {
+        return fraction(
+            number(&captures, 1) * unit_bytes(&captures[2]),
+            number(&captures, 3) * unit_bytes(&captures[4]),
+        );
+    }
+    if let Some(captures) = count.captures(text) {
+        return fraction(number(&captures, 1), number(&captures, 2));
+    }
+    return None;
+}
+
+fn number(captures: &Captures, group: usize) -> f64 {
+    // the patterns only match valid numbers, but a huge one can still be infinite
+    return captures[group].parse().unwrap_or(f64::INFINITY);
+}
+
+/// Returns `done` out of `total`, unless there's nothing to do
+fn fraction(done: f64, total: f64) -> Option<f64> {
+    if !(total > 0.0 && total.is_finite() && done.is_finite()) {
+        return None;
+    }
+    return Some((done / total).clamp(0.0, 1.0));
+}
+
+/// Returns how many bytes are in `unit`, e.g. 1000 for `kB` and 1024 for `KiB`
+fn unit_bytes(unit: &str) -> f64 {
+    let unit = unit.to_ascii_lowercase();
+    let base: f64 = if unit.contains('i') { 1024.0 } else { 1000.0 };
+    return match unit.as_bytes()[0] {
+        b'k' => base,
+        b'm' => base.powi(2),
+        b'g' => base.powi(3),
+        b't' => base.powi(4),
+        _ => 1.0,
+    };
+}
@@ -0,0 +1,161 @@
+//! Waiting for a command to print something, e.g. a server saying it's ready
+use crate::{Backend, Error, Line, Runner, RunningCommand};
+use regex::Regex;
+use std::process::Command;
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often to check whether the command has exited while waiting for a match
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What to look for in a line (see [`run_until_match`])
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Matches lines containing this text
+    Substring(String),
+    /// Matches lines this regex matches
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub(crate) fn is_match(&self, content: &str) -> bool {
+        return match self {
+            Pattern::Substring(text) => content.contains(text.as_str()),
+            Pattern::Regex(regex) => regex.is_match(content),
+        };
+    }
+}
+
+impl From<&str> for Pattern {
+    fn from(text: &str) -> Self {
+        return Pattern::Substring(text.to_string());
+    }
+}
+
+impl From<String> for Pattern {
+    fn from(text: String) -> Self {
+        return Pattern::Substring(text);
+    }
+}
+
+impl From<Regex> for Pattern {
+    fn from(regex: Regex) -> Self {
+        return Pattern::Regex(regex);
+    }
+}
+
+/// The line that matched, from [`run_until_match`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchInfo {
+    /// The first line which matched the pattern
+    pub line: Line,
+    /// How long it took for the line to be printed after the command started
+    pub elapsed: Duration,
+}
+
+/// Watches the captured lines for a [`Pattern`], sending the first match
+#[derive(Debug)]
+pub(crate) struct Watch {
+    pattern: Pattern,
+    /// Taken once something matches, so only the first match is sent
+    sender: Mutex<Option<SyncSender<Line>>>,
+}
+
+impl Watch {
+    pub(crate) fn check(&self, line: &Line) {
+        let mut sender = self.sender.lock().unwrap();
+        if sender.is_some() && self.pattern.is_match(&line.content) {
+            let _ = sender.take().unwrap().send(line.clone());
+        }
+    }
+}
+
+/// Starts a command and waits until it prints a line matching `pattern` (to stdout or stderr), then returns it while it
+/// keeps running
+///
+/// This is the same as [`Runner::run_until_match`] with the default options.
+pub fn run_until_match(
+    command: &mut Command,
+    pattern: impl Into<Pattern>,
+    timeout: Duration,
+) -> Result<(RunningCommand, MatchInfo), Error> {
+    return Runner::new().run_until_match(command, pattern, timeout);
+}
+
+impl Runner {
+    /// Starts a command and waits until it prints a line matching `pattern` (to stdout or stderr), then returns it while
+    /// it keeps running
+    ///
+    /// This is for long-running commands like databases and servers, where you need to wait for something like
+    /// "listening on port 8080" before using them. The output keeps being captured in the background; call
+    /// [`RunningCommand::wait`] to get all of it (including the lines before the match) once the command exits.
+    ///
+    /// If the command exits (and everything it printed has been checked), or `timeout` passes, before anything matches,
+    /// this returns [`Error::NoMatch`] with the output so far; if it timed out, the command is killed and the output has
+    /// a [`KillReason::Timeout`](crate::KillReason::Timeout).
+    ///
+    /// This always uses [`Backend::Threads`], since the output has to be read while waiting.
+    ///
+    /// ```
+    /// use better_commands::{regex::Regex, Runner};
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    ///
+    /// let server = "echo starting; sleep 0.1; echo 'listening on port 8080'; sleep 60";
+    /// let (mut running, info) = Runner::new()
+    ///     .run_until_match(
+    ///         Command::new("bash").arg("-c").arg(server),
+    ///         Regex::new(r"listening on port \d+").unwrap(),
+    ///         Duration::from_secs(10),
+    ///     )
+    ///     .unwrap();
+    /// assert_eq!("listening on port 8080", info.line.content);
+    ///
+    /// // ... use the server ...
+    /// running.kill().unwrap();
+    /// ```
+    pub fn run_until_match(
+        &self,
+        command: &mut Command,
+        pattern: impl Into<Pattern>,
+        timeout: Duration,
+    ) -> Result<(RunningCommand, MatchInfo), Error> {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let mut runner = self.clone().backend(Backend::Threads);
+        runner.capture.watch = Some(Arc::new(Watch {
+            pattern: pattern.into(),
+            sender: Mutex::new(Some(sender)),
+        }));
+
+        let mut running = runner.spawn(command)?;
+        let give_up = running.start + timeout;
+        loop {
+            let now = Instant::now();
+            if now >= give_up {
+                // makes `wait` kill it with the right reason
+                running.deadline = Some(now);
+                return Err(Error::NoMatch(Box::new(running.wait()?)));
+            }
+            match receiver.recv_timeout(POLL_INTERVAL.min(give_up - now)) {
+                Ok(line) => {
+                    let elapsed = line.offset;
+                    return Ok((running, MatchInfo { line, elapsed }));
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    // once everything's been read, nothing else can match
+                    if running.has_exited()? && running.readers_finished() {
+                        if let Ok(line) = receiver.try_recv() {
+                            let elapsed = line.offset;
+                            return Ok((running, MatchInfo { line, elapsed }));
+                        }
+                        return Err(Error::NoMatch(Box::new(running.wait()?)));
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    unreachable!("the watch is kept by the command")
+                }
+            }
+        }
+    }
+}
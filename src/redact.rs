@@ -0,0 +1,77 @@
+use regex::{NoExpand, Regex};
+use std::borrow::Cow;
+
+/// A set of rules for scrubbing sensitive text (passwords, tokens, etc.) out of output
+///
+/// Everything matched by a rule is replaced with the replacement text, which defaults to `[REDACTED]`.
+///
+/// ```
+/// use better_commands::RedactionRules;
+/// use better_commands::regex::Regex;
+///
+/// let rules = RedactionRules::new()
+///     .literal("hunter2")
+///     .regex(Regex::new(r"token=\S+").unwrap());
+///
+/// assert_eq!("pw=[REDACTED] [REDACTED]", rules.apply("pw=hunter2 token=abc123"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RedactionRules {
+    literals: Vec<String>,
+    patterns: Vec<Regex>,
+    replacement: String,
+}
+
+impl Default for RedactionRules {
+    fn default() -> Self {
+        return RedactionRules {
+            literals: Vec::new(),
+            patterns: Vec::new(),
+            replacement: "[REDACTED]".to_string(),
+        };
+    }
+}
+
+impl RedactionRules {
+    /// Creates an empty set of rules, which doesn't redact anything
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Redacts every occurrence of `secret`
+    pub fn literal<S: AsRef<str>>(mut self, secret: S) -> Self {
+        if !secret.as_ref().is_empty() {
+            self.literals.push(secret.as_ref().to_string());
+        }
+        return self;
+    }
+
+    /// Redacts everything matching `pattern`
+    pub fn regex(mut self, pattern: Regex) -> Self {
+        self.patterns.push(pattern);
+        return self;
+    }
+
+    /// Sets the text that redacted sections are replaced with
+    pub fn replacement<S: AsRef<str>>(mut self, replacement: S) -> Self {
+        self.replacement = replacement.as_ref().to_string();
+        return self;
+    }
+
+    /// Applies the rules to some text, returning the scrubbed version
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for literal in &self.literals {
+            if text.contains(literal.as_str()) {
+                text = Cow::Owned(text.replace(literal.as_str(), &self.replacement));
+            }
+        }
+        for pattern in &self.patterns {
+            if pattern.is_match(&text) {
+                let replaced = pattern.replace_all(&text, NoExpand(&self.replacement));
+                text = Cow::Owned(replaced.into_owned());
+            }
+        }
+        return text;
+    }
+}
@@ -0,0 +1,50 @@
+//! Playing captured output back with its original timing
+use crate::CmdOutput;
+use std::io::{self, Write};
+use std::thread;
+use std::time::Duration;
+
+impl CmdOutput {
+    /// Writes the captured lines to `sink` again, waiting between them as long as the command originally did
+    ///
+    /// The delays are divided by `speed`, so `2.0` plays it back twice as fast, and `0.5` at half speed. Lines are
+    /// written in the order they were printed (see [`CmdOutput::iter`]), stdout and stderr both going to `sink`, and
+    /// `sink` is flushed after each one so they show up when they would have.
+    ///
+    /// <small>Lines created with [`Line::from_stdout`](crate::Line::from_stdout) or
+    /// [`Line::from_stderr`](crate::Line::from_stderr) don't have timing info, so they're written without any
+    /// delay</small>
+    ///
+    /// ```
+    /// use better_commands::run;
+    /// use std::process::Command;
+    ///
+    /// let output = run(Command::new("bash").arg("-c").arg("echo a; sleep 0.1; echo b"));
+    ///
+    /// let mut replayed = Vec::new();
+    /// output.replay(&mut replayed, 10.0).unwrap();
+    /// assert_eq!(b"a\nb\n", replayed.as_slice());
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `speed` isn't a positive, finite number
+    pub fn replay(&self, mut sink: impl Write, speed: f32) -> io::Result<()> {
+        assert!(
+            speed.is_finite() && speed > 0.0,
+            "replay speed must be positive, got {}",
+            speed
+        );
+        let mut elapsed = Duration::ZERO;
+        for line in self.iter() {
+            if line.offset > elapsed {
+                thread::sleep((line.offset - elapsed).div_f32(speed));
+                elapsed = line.offset;
+            }
+            sink.write_all(line.content.as_bytes())?;
+            sink.write_all(b"\n")?;
+            sink.flush()?;
+        }
+        return Ok(());
+    }
+}
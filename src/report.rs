@@ -0,0 +1,218 @@
+//! Reports of command runs, for CI systems and people to read
+use crate::{CmdOutput, KillReason, Line};
+use std::fmt::Write;
+
+/// Generates a JUnit-style XML report, with each command as a test case
+///
+/// Each test case is named after its command, with how long it took, a `<failure>` if it failed (exited with
+/// anything other than 0, or was killed), and everything it printed in `<system-out>` and `<system-err>`.
+///
+/// ```
+/// use better_commands::{report, run};
+/// use std::process::Command;
+///
+/// let outputs = [
+///     run(Command::new("echo").arg("hi")),
+///     run(Command::new("bash").arg("-c").arg("exit 3")),
+/// ];
+/// let xml = report::junit(&outputs);
+///
+/// assert!(xml.contains(r#"<testsuite name="better-commands" tests="2" failures="1""#));
+/// assert!(xml.contains(r#"<failure message="exited with status 3"/>"#));
+/// ```
+pub fn junit(outputs: &[CmdOutput]) -> String {
+    let failures = outputs.iter().filter(|output| !output.success()).count();
+    let total: f64 = outputs
+        .iter()
+        .map(|output| output.duration.as_secs_f64())
+        .sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuite name=\"better-commands\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        outputs.len(),
+        failures,
+        total
+    );
+    for output in outputs {
+        let _ = writeln!(
+            xml,
+            "  <testcase name=\"{}\" classname=\"better_commands\" time=\"{:.3}\">",
+            xml_escape(&output.command),
+            output.duration.as_secs_f64()
+        );
+        if !output.success() {
+            let _ = writeln!(
+                xml,
+                "    <failure message=\"{}\"/>",
+                xml_escape(&failure_message(output))
+            );
+        }
+        if let Some(stdout) = output.stdout_string() {
+            let _ = writeln!(xml, "    <system-out>{}</system-out>", xml_escape(&stdout));
+        }
+        if let Some(stderr) = output.stderr_string() {
+            let _ = writeln!(xml, "    <system-err>{}</system-err>", xml_escape(&stderr));
+        }
+        xml.push_str("  </testcase>\n");
+    }
+    xml.push_str("</testsuite>\n");
+    return xml;
+}
+
+/// Renders a standalone HTML page showing each command's output, for people to read
+///
+/// The page starts with a summary of every command's status and duration, followed by a section for each command
+/// with its stdout and stderr in collapsible blocks. Each line is shown with how long after the command started it
+/// was printed.
+///
+/// ```
+/// use better_commands::{report, run};
+/// use std::process::Command;
+///
+/// let html = report::html(&[run(Command::new("echo").arg("hi"))]);
+/// std::fs::write(std::env::temp_dir().join("report.html"), html).unwrap();
+/// ```
+pub fn html(outputs: &[CmdOutput]) -> String {
+    let failures = outputs.iter().filter(|output| !output.success()).count();
+    let total: f64 = outputs
+        .iter()
+        .map(|output| output.duration.as_secs_f64())
+        .sum();
+
+    let mut html = String::from(HTML_HEADER);
+    let _ = writeln!(
+        html,
+        "<h1>{} commands, {} failed, {:.3}s total</h1>",
+        outputs.len(),
+        failures,
+        total
+    );
+    html.push_str("<table>\n<tr><th>#</th><th>Command</th><th>Status</th><th>Duration</th></tr>\n");
+    for (i, output) in outputs.iter().enumerate() {
+        let _ = writeln!(
+            html,
+            "<tr class=\"{}\"><td><a href=\"#command-{}\">{}</a></td><td><code>{}</code></td><td>{}</td><td>{:.3}s</td></tr>",
+            status_class(output),
+            i,
+            i + 1,
+            xml_escape(&output.command),
+            xml_escape(&status(output)),
+            output.duration.as_secs_f64()
+        );
+    }
+    html.push_str("</table>\n");
+
+    for (i, output) in outputs.iter().enumerate() {
+        let _ = writeln!(
+            html,
+            "<section id=\"command-{}\" class=\"{}\">\n<h2><code>{}</code></h2>\n<p>{} after {:.3}s</p>",
+            i,
+            status_class(output),
+            xml_escape(&output.command),
+            xml_escape(&status(output)),
+            output.duration.as_secs_f64()
+        );
+        if let Some(stdout) = output.stdout() {
+            html_lines(&mut html, "stdout", stdout);
+        }
+        if let Some(stderr) = output.stderr() {
+            html_lines(&mut html, "stderr", stderr);
+        }
+        html.push_str("</section>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    return html;
+}
+
+const HTML_HEADER: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Command report</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.25em 0.5em; text-align: left; }
+tr.failed td, section.failed h2 { color: #b00; }
+pre { background: #f4f4f4; padding: 0.5em; overflow-x: auto; }
+.time { color: #888; user-select: none; }
+.stderr { color: #a50; }
+</style>
+</head>
+<body>
+"#;
+
+/// Writes one of a command's streams as a collapsible block, open if it has anything in it
+fn html_lines(html: &mut String, name: &str, lines: &[Line]) {
+    let _ = writeln!(
+        html,
+        "<details{}>\n<summary>{} ({} lines)</summary>\n<pre class=\"{}\">",
+        if lines.is_empty() { "" } else { " open" },
+        name,
+        lines.len(),
+        name
+    );
+    for line in lines {
+        let _ = writeln!(
+            html,
+            "<span class=\"time\">+{:.3}s</span> {}",
+            line.offset.as_secs_f64(),
+            xml_escape(&line.content)
+        );
+    }
+    html.push_str("</pre>\n</details>\n");
+}
+
+fn status_class(output: &CmdOutput) -> &'static str {
+    return if output.success() {
+        "succeeded"
+    } else {
+        "failed"
+    };
+}
+
+/// Describes how a command exited, e.g. "succeeded" or "exited with status 1"
+pub(crate) fn status(output: &CmdOutput) -> String {
+    if output.success() {
+        return "succeeded".to_string();
+    }
+    return failure_message(output);
+}
+
+/// Describes why a command failed, e.g. "exited with status 1"
+fn failure_message(output: &CmdOutput) -> String {
+    let mut message = match (output.status_code, output.signal) {
+        (Some(code), _) => format!("exited with status {}", code),
+        (None, Some(signal)) => format!("killed by signal {}", signal),
+        (None, None) => "exited without a status".to_string(),
+    };
+    match output.kill_reason {
+        Some(KillReason::Timeout) => message.push_str(" (timed out)"),
+        Some(KillReason::OutputLimit) => message.push_str(" (printed too much)"),
+        Some(KillReason::PatternMatched) => message.push_str(" (printed a matching line)"),
+        Some(KillReason::LineTooLong) => message.push_str(" (printed a line that was too long)"),
+        Some(KillReason::Interrupted) => message.push_str(" (interrupted)"),
+        None => {}
+    }
+    return message;
+}
+
+/// Escapes text for use in XML (and HTML), dropping control characters XML doesn't allow
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    return escaped;
+}
@@ -0,0 +1,92 @@
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::time::Duration;
+
+/// How long to wait between attempts when retrying a command (see [`Runner::backoff`](crate::Runner::backoff))
+///
+/// ```
+/// use better_commands::Backoff;
+/// use std::time::Duration;
+///
+/// let backoff = Backoff::exponential(Duration::from_millis(200));
+/// assert_eq!(Duration::from_millis(200), backoff.delay(0));
+/// assert_eq!(Duration::from_millis(800), backoff.delay(2));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    kind: BackoffKind,
+    jitter: bool,
+    max_delay: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BackoffKind {
+    Fixed(Duration),
+    Exponential(Duration),
+}
+
+impl Default for Backoff {
+    /// Doesn't wait at all between attempts
+    fn default() -> Self {
+        return Backoff::none();
+    }
+}
+
+impl Backoff {
+    /// Doesn't wait at all between attempts
+    pub fn none() -> Self {
+        return Backoff::fixed(Duration::ZERO);
+    }
+
+    /// Waits the same amount of time between every attempt
+    pub fn fixed(delay: Duration) -> Self {
+        return Backoff {
+            kind: BackoffKind::Fixed(delay),
+            jitter: false,
+            max_delay: None,
+        };
+    }
+
+    /// Waits `base` after the first attempt, then doubles the wait after each attempt after that
+    pub fn exponential(base: Duration) -> Self {
+        return Backoff {
+            kind: BackoffKind::Exponential(base),
+            jitter: false,
+            max_delay: None,
+        };
+    }
+
+    /// Randomizes each delay to somewhere between half and all of its normal length
+    ///
+    /// This keeps lots of processes that failed at the same time from all retrying at the same time.
+    pub fn with_jitter(mut self) -> Self {
+        self.jitter = true;
+        return self;
+    }
+
+    /// Caps each delay at `max_delay`
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        return self;
+    }
+
+    /// Returns how long to wait after the given attempt failed (starting at 0 for the first attempt)
+    ///
+    /// If jitter is enabled, this returns a different value every time.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let mut delay = match self.kind {
+            BackoffKind::Fixed(delay) => delay,
+            BackoffKind::Exponential(base) => {
+                base.saturating_mul(2u32.saturating_pow(attempt.min(31)))
+            }
+        };
+        if let Some(max_delay) = self.max_delay {
+            delay = delay.min(max_delay);
+        }
+        if self.jitter {
+            // avoids pulling in `rand` just for this; see also `shuffle_vec` in the tests
+            let random = RandomState::new().build_hasher().finish();
+            delay = delay / 2 + delay.mul_f64((random as f64 / u64::MAX as f64) / 2.0);
+        }
+        return delay;
+    }
+}
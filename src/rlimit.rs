@@ -0,0 +1,95 @@
+//! Resource limits for commands, set with `setrlimit(2)`
+use crate::ResourceUsage;
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::time::Duration;
+
+/// A resource limit for a command (see [`Runner::limit`](crate::Runner::limit))
+///
+/// The command can't raise these itself, and neither can anything it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Rlimit {
+    /// CPU time, in seconds; it gets `SIGXCPU` once it's used this much, and is killed a second later if that didn't
+    /// stop it
+    Cpu(u64),
+    /// Address space (virtual memory), in bytes; allocations past this fail
+    As(u64),
+    /// The data segment (the heap, mostly), in bytes
+    Data(u64),
+    /// The stack, in bytes
+    Stack(u64),
+    /// The largest file it can write, in bytes; it gets `SIGXFSZ` if it tries to write more
+    FileSize(u64),
+    /// How many files it can have open at once (one more than the largest file descriptor it can open)
+    NoFile(u64),
+    /// How many processes the user it's running as can have at once
+    NProc(u64),
+    /// The largest core dump it can write, in bytes; `0` turns them off
+    Core(u64),
+}
+
+impl Rlimit {
+    pub const KIB: u64 = 1024;
+    pub const MIB: u64 = 1024 * 1024;
+    pub const GIB: u64 = 1024 * 1024 * 1024;
+
+    /// Sets this as both the soft and hard limit (except for [`Rlimit::Cpu`]); called in the child before the command
+    /// runs
+    #[cfg(unix)]
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        let (resource, value) = match *self {
+            Rlimit::Cpu(value) => (libc::RLIMIT_CPU, value),
+            Rlimit::As(value) => (libc::RLIMIT_AS, value),
+            Rlimit::Data(value) => (libc::RLIMIT_DATA, value),
+            Rlimit::Stack(value) => (libc::RLIMIT_STACK, value),
+            Rlimit::FileSize(value) => (libc::RLIMIT_FSIZE, value),
+            Rlimit::NoFile(value) => (libc::RLIMIT_NOFILE, value),
+            Rlimit::NProc(value) => (libc::RLIMIT_NPROC, value),
+            Rlimit::Core(value) => (libc::RLIMIT_CORE, value),
+        };
+        // going over the hard CPU limit means SIGKILL straight away, so there has to be a gap for it to get SIGXCPU
+        let max = match self {
+            Rlimit::Cpu(_) => value.saturating_add(1),
+            _ => value,
+        };
+        let limit = libc::rlimit {
+            rlim_cur: value as libc::rlim_t,
+            rlim_max: max as libc::rlim_t,
+        };
+        if unsafe { libc::setrlimit(resource, &limit) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(());
+    }
+}
+
+/// Works out which limit (if any) a command was killed for going over, from the signal it was killed by
+pub(crate) fn exceeded(
+    limits: &[Rlimit],
+    signal: Option<i32>,
+    usage: Option<ResourceUsage>,
+) -> Option<Rlimit> {
+    #[cfg(unix)]
+    {
+        let signal = signal?;
+        return limits.iter().copied().find(|limit| match *limit {
+            // it's sent SIGXCPU at the soft limit, and SIGKILL if it's still going at the hard limit
+            Rlimit::Cpu(seconds) => {
+                signal == libc::SIGXCPU
+                    || (signal == libc::SIGKILL
+                        && usage.is_some_and(|usage| {
+                            usage.user_time + usage.system_time >= Duration::from_secs(seconds)
+                        }))
+            }
+            Rlimit::FileSize(_) => signal == libc::SIGXFSZ,
+            _ => false,
+        });
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (limits, signal, usage);
+        return None;
+    }
+}
@@ -0,0 +1,1613 @@
+use crate::audit::{Audit, User};
+use crate::capture::{read_lines, Capture};
+use crate::env;
+use crate::heartbeat::{HeartbeatCallback, Heartbeats};
+use crate::pre_exec::PreExec;
+use crate::running::Reader;
+use crate::sink::StreamSink;
+use crate::workdir::TempDir;
+use crate::{
+    check_not_empty, command_line, AuditLog, Backend, Backoff, Cancellation, CarriageReturns,
+    CmdOutput, Delimiter, Error, Heartbeat, Hooks, Line, LineSink, LineType, LongLines, Pattern,
+    RateLimiter, ReadMode, Rlimit, RunningCommand, SeverityRules, StdinMode, StdoutMode, Template,
+    Watermark, WriteSink,
+};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type RetryPredicate = Arc<dyn Fn(&CmdOutput) -> bool + Send + Sync>;
+/// Reads one of a command's streams until it's closed
+pub(crate) type ReadJob = Box<dyn FnOnce() -> io::Result<Vec<Line>> + Send>;
+
+/// Runs commands with extra options, like timeouts and retries
+///
+/// A [`Runner`] only holds the options, so one can be reused to run as many commands as you want.
+///
+/// With the `tracing` feature, each command is run in a `command` span (with the command line, PID, and status code as
+/// fields), and each line it prints is logged as an event in it: at `DEBUG` for stdout, and `WARN` for stderr.
+///
+/// ```
+/// use better_commands::{Backoff, Runner};
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// let runner = Runner::new()
+///     .timeout(Duration::from_secs(10))
+///     .retries(3)
+///     .backoff(Backoff::exponential(Duration::from_millis(200)).with_jitter());
+///
+/// let output = runner.run(Command::new("echo").arg("hi")).unwrap();
+/// assert_eq!("hi", output.lines().unwrap()[0].content);
+/// ```
+#[derive(Clone, Default)]
+pub struct Runner {
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    retries: u32,
+    backoff: Backoff,
+    retry_if: Option<RetryPredicate>,
+    rate_limit: Option<RateLimiter>,
+    pub(crate) capture: Capture,
+    process_group: bool,
+    forward_signals: bool,
+    pre_exec: PreExec,
+    user: Option<String>,
+    root: Option<PathBuf>,
+    #[cfg(feature = "cgroups")]
+    cgroup: Option<crate::CgroupLimits>,
+    #[cfg(feature = "namespaces")]
+    isolation: crate::Isolation,
+    cancellation: Cancellation,
+    stdin: Option<StdinMode>,
+    stdout: StdoutMode,
+    stderr: StdoutMode,
+    capture_fds: Vec<u32>,
+    heartbeat: Option<(Duration, HeartbeatCallback)>,
+    merge_stderr: bool,
+    io_stats: bool,
+    template_context: HashMap<String, String>,
+    templated_envs: Vec<(String, Template)>,
+    env_keep: Option<Vec<OsString>>,
+    env_files: Vec<PathBuf>,
+    temp_dir: Option<TempDirMode>,
+    locale: Option<String>,
+    backend: Backend,
+    audit_log: Option<AuditLog>,
+    #[cfg(feature = "serde")]
+    cache: Option<crate::CacheDir>,
+    #[cfg(feature = "serde")]
+    cache_key_files: Vec<PathBuf>,
+}
+
+impl fmt::Debug for Runner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Runner")
+            .field("timeout", &self.timeout)
+            .field("retries", &self.retries)
+            .field("backoff", &self.backoff)
+            .field("rate_limit", &self.rate_limit)
+            .field("capture", &self.capture)
+            .field("process_group", &self.process_group)
+            .field("forward_signals", &self.forward_signals)
+            .field("pre_exec", &self.pre_exec)
+            .field("user", &self.user)
+            .field("root", &self.root)
+            .field("cancellation", &self.cancellation)
+            .field("io_stats", &self.io_stats)
+            .field("template_context", &self.template_context)
+            .field("templated_envs", &self.templated_envs)
+            .field("env_keep", &self.env_keep)
+            .field("env_files", &self.env_files)
+            .field("temp_dir", &self.temp_dir)
+            .field("locale", &self.locale)
+            .field("backend", &self.backend)
+            .field("audit_log", &self.audit_log)
+            .finish_non_exhaustive();
+    }
+}
+
+impl Runner {
+    /// Creates a [`Runner`] with the default options, which runs commands just like [`run`](crate::run)
+    ///
+    /// If [`configure`](crate::configure) has been called, it starts with those options instead.
+    pub fn new() -> Self {
+        return crate::defaults::new_runner();
+    }
+
+    /// Kills the command if it's still running after `timeout`
+    ///
+    /// The output will have a [`KillReason::Timeout`](crate::KillReason::Timeout) if this happens.
+    ///
+    /// Only the command itself is killed; if it started other processes which are still holding its stdout or stderr
+    /// open, this will wait for them too. Use [`Runner::process_group`] to kill those as well.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        return self;
+    }
+
+    /// Kills the command if it's still running at `deadline`, like [`Runner::timeout`] but at a fixed time
+    ///
+    /// This is for sharing one time budget between lots of commands, without working out how much is left for each
+    /// one. If both are set, whichever comes first is used. Retries aren't started once the deadline has passed, and
+    /// the [`Runner::backoff`] between them is cut short if it would go past it.
+    ///
+    /// ```
+    /// use better_commands::{KillReason, Runner};
+    /// use std::process::Command;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let runner = Runner::new().deadline(Instant::now() + Duration::from_millis(500));
+    /// runner.run(Command::new("sleep").arg("0.1")).unwrap();
+    /// // this has less than 400ms left
+    /// let output = runner.run(Command::new("sleep").arg("1")).unwrap();
+    /// assert_eq!(Some(KillReason::Timeout), output.kill_reason());
+    /// ```
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        return self;
+    }
+
+    /// Re-runs the command up to `retries` more times if it fails
+    ///
+    /// By default, a run has failed if it exited with anything other than 0 or was killed by a timeout; use [`Runner::retry_if`] to change that.
+    ///
+    /// The output of each failed attempt is available from [`CmdOutput::failed_attempts`] on the final output.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        return self;
+    }
+
+    /// Sets how long to wait between retries (no waiting by default)
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        return self;
+    }
+
+    /// Decides whether a run failed (and should be retried) using `predicate` instead of the default check
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    ///
+    /// // also retry if the command printed "try again", even if it succeeded
+    /// let runner = Runner::new().retries(3).retry_if(|output| {
+    ///     output.clone().status_code() != Some(0)
+    ///         || output.clone().lines().unwrap().iter().any(|line| line.content.contains("try again"))
+    /// });
+    /// ```
+    pub fn retry_if(
+        mut self,
+        predicate: impl Fn(&CmdOutput) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.retry_if = Some(Arc::new(predicate));
+        return self;
+    }
+
+    /// Limits how quickly and how many commands this runner can run
+    ///
+    /// Each retry counts as a separate command.
+    pub fn rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limit = Some(limiter);
+        return self;
+    }
+
+    /// Sends each line to `sink` as soon as it's captured (after [`Runner::on_line`] has run)
+    ///
+    /// This can be called multiple times to send lines to several sinks. The sinks are shared by every command this
+    /// runner (and its clones) runs.
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    /// use std::sync::mpsc;
+    ///
+    /// let (sender, receiver) = mpsc::channel();
+    /// Runner::new().sink(sender).run(Command::new("echo").arg("hi")).unwrap();
+    /// assert_eq!("hi", receiver.recv().unwrap().content);
+    /// ```
+    pub fn sink(mut self, sink: impl LineSink + 'static) -> Self {
+        self.capture.sinks.push(Arc::new(Mutex::new(sink)));
+        return self;
+    }
+
+    /// Same as [`Runner::sink`], but only sends lines printed to stdout
+    pub fn stdout_sink(self, sink: impl LineSink + 'static) -> Self {
+        return self.sink(StreamSink {
+            stream: LineType::Stdout,
+            sink,
+        });
+    }
+
+    /// Same as [`Runner::sink`], but only sends lines printed to stderr
+    pub fn stderr_sink(self, sink: impl LineSink + 'static) -> Self {
+        return self.sink(StreamSink {
+            stream: LineType::Stderr,
+            sink,
+        });
+    }
+
+    /// Writes each line to `writer` as soon as it's captured; this is short for `.sink(WriteSink::new(writer))`
+    ///
+    /// The lines are still kept in the output as well.
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::fs::File;
+    /// use std::process::Command;
+    ///
+    /// let path = std::env::temp_dir().join("better-commands-write_to-doctest.log");
+    /// let output = Runner::new()
+    ///     .write_to(File::create(&path).unwrap())
+    ///     .run(Command::new("echo").arg("hi"))
+    ///     .unwrap();
+    ///
+    /// assert_eq!("hi\n", std::fs::read_to_string(&path).unwrap());
+    /// assert_eq!(1, output.len());
+    /// # std::fs::remove_file(path).unwrap();
+    /// ```
+    pub fn write_to(self, writer: impl Write + Send + 'static) -> Self {
+        return self.sink(WriteSink::new(writer));
+    }
+
+    /// Kills the command once it's printed more than `bytes` bytes in total (across stdout and stderr)
+    ///
+    /// This is meant for catching misbehaving commands, like one stuck in a loop printing the same thing; the output will
+    /// have a [`KillReason::OutputLimit`](crate::KillReason::OutputLimit) if this happens.
+    pub fn kill_after_bytes(mut self, bytes: u64) -> Self {
+        self.capture.kill_after_bytes = Some(bytes);
+        return self;
+    }
+
+    /// Calls `callback` every time the command's total output (across stdout and stderr) passes another multiple of
+    /// `every_bytes`
+    ///
+    /// This gives early warning that a command is printing far more than expected, before it hits a limit like
+    /// [`Runner::kill_after_bytes`] or fills up a disk. It can be called multiple times to watch several intervals. Like
+    /// [`Runner::on_line`], the callback runs as lines are captured, so it should be quick.
+    ///
+    /// Panics if `every_bytes` is 0.
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let runner = Runner::new().on_watermark(10 * 1024 * 1024, |watermark| {
+    ///     eprintln!(
+    ///         "warning: command has printed {} MiB ({} lines) in {:?}",
+    ///         watermark.threshold / 1024 / 1024,
+    ///         watermark.lines,
+    ///         watermark.elapsed
+    ///     );
+    /// });
+    /// ```
+    pub fn on_watermark(
+        mut self,
+        every_bytes: u64,
+        callback: impl FnMut(Watermark) + Send + 'static,
+    ) -> Self {
+        assert!(
+            every_bytes > 0,
+            "watermarks need to be at least 1 byte apart"
+        );
+        self.capture
+            .watermarks
+            .push((every_bytes, Arc::new(Mutex::new(callback))));
+        return self;
+    }
+
+    /// Calls `callback` every `interval` while the command is running, with how long it's been running, how much it's
+    /// printed, and how long it's been since it last printed anything
+    ///
+    /// This is handy for printing "still running..." notices, or noticing that a command seems to be stuck. The
+    /// callback runs on its own thread, so it doesn't hold up reading the output. Panics if `interval` is 0.
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    ///
+    /// let runner = Runner::new().heartbeat(Duration::from_secs(30), |heartbeat| {
+    ///     if heartbeat.since_output > Duration::from_secs(60) {
+    ///         eprintln!(
+    ///             "still running after {:?}, but nothing's been printed in a while",
+    ///             heartbeat.elapsed
+    ///         );
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// <small>With `Backend::Poll` (see [`Backend`](crate::Backend)), this still starts a thread for the callback.</small>
+    pub fn heartbeat(
+        mut self,
+        interval: Duration,
+        callback: impl FnMut(Heartbeat) + Send + 'static,
+    ) -> Self {
+        assert!(!interval.is_zero(), "heartbeats need a non-zero interval");
+        self.heartbeat = Some((interval, Arc::new(Mutex::new(callback))));
+        return self;
+    }
+
+    /// Kills the command once it's printed more than `lines` lines in total (across stdout and stderr)
+    ///
+    /// The output will have a [`KillReason::OutputLimit`](crate::KillReason::OutputLimit) if this happens.
+    pub fn kill_after_lines(mut self, lines: u64) -> Self {
+        self.capture.kill_after_lines = Some(lines);
+        return self;
+    }
+
+    /// Stops keeping the command's output once it's printed more than `bytes` bytes in total (across stdout and stderr)
+    ///
+    /// This keeps a command which prints far too much from using up memory (or the space logs are stored in), without
+    /// stopping it; the rest of its output is still read, but thrown away before anything else sees it (including
+    /// [`Runner::on_line`] and sinks). The output will have [`CmdOutput::truncated`] set if this happens. To kill the
+    /// command as well, use [`Runner::kill_after_bytes`] with the same limit.
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .max_output_bytes(1024 * 1024)
+    ///     .run(Command::new("seq").arg("1000000"))
+    ///     .unwrap();
+    /// assert!(output.truncated());
+    /// assert_eq!(Some(0), output.clone().status_code());
+    /// assert_eq!("1", output.lines().unwrap()[0].content);
+    /// ```
+    pub fn max_output_bytes(mut self, bytes: u64) -> Self {
+        self.capture.max_output_bytes = Some(bytes);
+        return self;
+    }
+
+    /// Stops keeping the command's output once it's printed more than `lines` lines in total (across stdout and
+    /// stderr)
+    ///
+    /// Like [`Runner::max_output_bytes`], the command keeps running, and the output will have
+    /// [`CmdOutput::truncated`] set if this happens.
+    pub fn max_output_lines(mut self, lines: u64) -> Self {
+        self.capture.max_output_lines = Some(lines);
+        return self;
+    }
+
+    /// Kills the command as soon as it prints a line matching `pattern` (to stdout or stderr)
+    ///
+    /// This is the opposite of [`Runner::run_until_match`]: useful for stopping a command once it's printed something
+    /// like "FATAL", or once it's printed what you wanted from it. The output will have a
+    /// [`KillReason::PatternMatched`](crate::KillReason::PatternMatched), and
+    /// [`CmdOutput::kill_match`](crate::CmdOutput::kill_match) will have the line which matched.
+    ///
+    /// ```
+    /// use better_commands::{KillReason, Runner};
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .kill_on_match("FATAL")
+    ///     .run(Command::new("bash").arg("-c").arg("echo starting; echo 'FATAL: out of disk'; sleep 60"))
+    ///     .unwrap();
+    ///
+    /// assert_eq!(Some(KillReason::PatternMatched), output.kill_reason());
+    /// assert_eq!("FATAL: out of disk", output.kill_match().unwrap().content);
+    /// ```
+    pub fn kill_on_match(mut self, pattern: impl Into<Pattern>) -> Self {
+        self.capture.kill_on_match = Some(pattern.into());
+        return self;
+    }
+
+    /// Sets how the command is stopped when it's cancelled (see [`Cancellation`])
+    ///
+    /// This is used for timeouts, the output limits, [`Runner::kill_on_match`], and [`RunningCommand::cancel`]; by
+    /// default, the command is just killed.
+    pub fn cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        return self;
+    }
+
+    /// Runs each command in a fresh, empty temporary directory, which is removed once the output is dropped
+    ///
+    /// The directory is available from [`CmdOutput::workdir`] until then, e.g. to check files the command made. Each
+    /// attempt (see [`Runner::retries`]) gets its own directory. Use [`Runner::keep_temp_dir`] to leave the directories
+    /// around instead.
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .current_dir_temp()
+    ///     .run(Command::new("bash").arg("-c").arg("echo hi > greeting.txt"))
+    ///     .unwrap();
+    ///
+    /// let dir = output.workdir().unwrap().to_path_buf();
+    /// assert_eq!("hi\n", std::fs::read_to_string(dir.join("greeting.txt")).unwrap());
+    ///
+    /// drop(output);
+    /// assert!(!dir.exists());
+    /// ```
+    pub fn current_dir_temp(mut self) -> Self {
+        self.temp_dir = Some(TempDirMode::Remove);
+        return self;
+    }
+
+    /// Same as [`Runner::current_dir_temp`], but leaves the directories around after the output is dropped if `keep` is set
+    pub fn keep_temp_dir(mut self, keep: bool) -> Self {
+        self.temp_dir = Some(match keep {
+            true => TempDirMode::Keep,
+            false => TempDirMode::Remove,
+        });
+        return self;
+    }
+
+    /// Starts the command in its own process group, so that it and everything it starts are killed together
+    ///
+    /// When enabled, timeouts, dropping a [`RunningCommand`], and [`RunningCommand::kill_tree`] kill the whole group,
+    /// rather than leaving grandchildren (e.g. everything started by `bash -c "a | b"`) running.
+    ///
+    /// <small>Only supported on Unix; on Windows, this does nothing, so anything the command started keeps running after
+    /// it's killed</small>
+    pub fn process_group(mut self, process_group: bool) -> Self {
+        self.process_group = process_group;
+        return self;
+    }
+
+    /// Passes `SIGINT` (e.g. from Ctrl-C) and `SIGTERM` on to the command if this process gets them while it's running
+    ///
+    /// Instead of this process exiting and leaving the command orphaned, the signal is sent to the command (or its whole
+    /// process group with [`Runner::process_group`]), and the run carries on until it exits, returning its output as
+    /// usual with [`KillReason::Interrupted`](crate::KillReason::Interrupted). It's then up to you whether to exit too.
+    ///
+    /// This process's handlers for those signals are replaced while any command is running with this, and put back
+    /// afterwards.
+    ///
+    /// <small>Pressing Ctrl-C in a terminal already sends `SIGINT` to the command too, unless it's in its own process
+    /// group, so it'll get the signal twice. This is only supported on Unix; elsewhere, it does nothing.</small>
+    pub fn forward_signals(mut self, forward: bool) -> Self {
+        self.forward_signals = forward;
+        return self;
+    }
+
+    /// Sends `signal` (e.g. `libc::SIGKILL`) to the command if this process dies, so it isn't left running
+    ///
+    /// This is set with `PR_SET_PDEATHSIG`, so it works even if this process is killed with `SIGKILL` or crashes,
+    /// when nothing else (like dropping a [`RunningCommand`]) gets a chance to run.
+    ///
+    /// <small>This is only supported on Linux; elsewhere, it does nothing. The signal is actually sent when the
+    /// *thread* which started the command exits, so only use this from threads which outlive the command.</small>
+    pub fn parent_death_signal(mut self, signal: i32) -> Self {
+        self.pre_exec.parent_death_signal = Some(signal);
+        return self;
+    }
+
+    /// Runs the command as another user, e.g. `"nobody"`, with their groups
+    ///
+    /// This also sets `USER`, `LOGNAME`, and `HOME` to match, unless they're set on the command. Use [`Runner::uid`]
+    /// and [`Runner::gid`] instead to just switch IDs, or to override the user's. If there's no such user,
+    /// running the command fails with [`Error::Spawn`].
+    ///
+    /// <small>This usually needs this process to be running as root. It's only supported on Unix; elsewhere, it does
+    /// nothing.</small>
+    pub fn user<S: AsRef<str>>(mut self, name: S) -> Self {
+        self.user = Some(name.as_ref().to_string());
+        return self;
+    }
+
+    /// Runs the command with this user ID
+    ///
+    /// <small>This usually needs this process to be running as root. It's only supported on Unix; elsewhere, it does
+    /// nothing.</small>
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.pre_exec.uid = Some(uid);
+        return self;
+    }
+
+    /// Runs the command with this group ID
+    ///
+    /// <small>This usually needs this process to be running as root. It's only supported on Unix; elsewhere, it does
+    /// nothing.</small>
+    pub fn gid(mut self, gid: u32) -> Self {
+        self.pre_exec.gid = Some(gid);
+        return self;
+    }
+
+    /// Sets a resource limit for the command, like how much CPU time or memory it can use, replacing any earlier
+    /// limit on the same resource
+    ///
+    /// If it's killed for going over one, [`CmdOutput::limit_exceeded`] says which.
+    ///
+    /// ```
+    /// use better_commands::{Rlimit, Runner};
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .limit(Rlimit::Cpu(1))
+    ///     .limit(Rlimit::As(2 * Rlimit::GIB))
+    ///     .run(Command::new("bash").arg("-c").arg("while true; do :; done"))
+    ///     .unwrap();
+    /// assert_eq!(Some(Rlimit::Cpu(1)), output.limit_exceeded());
+    /// ```
+    ///
+    /// <small>This is only supported on Unix; elsewhere, it does nothing.</small>
+    pub fn limit(mut self, limit: Rlimit) -> Self {
+        self.pre_exec
+            .limits
+            .retain(|other| std::mem::discriminant(other) != std::mem::discriminant(&limit));
+        self.pre_exec.limits.push(limit);
+        return self;
+    }
+
+    /// Sets what the command reads as stdin, replacing anything set with [`Command::stdin`]
+    ///
+    /// ```
+    /// use better_commands::{Runner, StdinMode};
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .stdin(StdinMode::piped("b\na\n"))
+    ///     .run(&mut Command::new("sort"))
+    ///     .unwrap();
+    /// assert_eq!(Some("a\nb".to_string()), output.stdout_string());
+    /// ```
+    pub fn stdin(mut self, mode: StdinMode) -> Self {
+        self.stdin = Some(mode);
+        return self;
+    }
+
+    /// Sets where the command's stdout goes; by default, it's captured
+    ///
+    /// With [`StdoutMode::Inherit`], it's printed straight to this process's stdout, e.g. so interactive programs
+    /// work, while still recording how long it took and its exit status.
+    pub fn stdout(mut self, mode: StdoutMode) -> Self {
+        self.stdout = mode;
+        return self;
+    }
+
+    /// Sets where the command's stderr goes; by default, it's captured
+    pub fn stderr(mut self, mode: StdoutMode) -> Self {
+        self.stderr = mode;
+        return self;
+    }
+
+    /// Sends stderr to the same pipe as stdout, like `2>&1`, so lines are captured in exactly the order they were
+    /// printed
+    ///
+    /// Normally, stdout and stderr are read from separate pipes, so lines printed to both within a few milliseconds
+    /// can end up out of order (see [`CmdOutput::lines`]). With this, there's only one pipe, so that can't happen, but
+    /// every line is captured as stdout. This replaces [`Runner::stdout`] and [`Runner::stderr`].
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .merge_stderr(true)
+    ///     .run(Command::new("bash").arg("-c").arg("echo a; echo b >&2; echo c"))
+    ///     .unwrap();
+    /// assert_eq!(Some("a\nb\nc".to_string()), output.stdout_string());
+    /// ```
+    ///
+    /// <small>The command can still print to the two out of order if it buffers its own output; e.g. C programs
+    /// usually only buffer stdout when it's not a terminal.</small>
+    pub fn merge_stderr(mut self, merge: bool) -> Self {
+        self.merge_stderr = merge;
+        return self;
+    }
+
+    /// Passes the command a pipe as file descriptor `fd` (e.g. `3`), capturing what it writes there as lines with
+    /// [`LineType::Fd`]
+    ///
+    /// This is for programs which write machine-readable status somewhere other than stdout and stderr, like `gpg
+    /// --status-fd 3`. Use [`CmdOutput::fd_lines`] to get just those lines.
+    ///
+    /// ```
+    /// use better_commands::{LineType, Runner};
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .capture_fd(3)
+    ///     .run(Command::new("bash").arg("-c").arg("echo working; echo 50% >&3"))
+    ///     .unwrap();
+    /// assert_eq!("50%", output.fd_lines(3)[0].content);
+    /// assert_eq!(Some("working".to_string()), output.stdout_string());
+    /// ```
+    ///
+    /// <small>This is only supported on Unix; elsewhere, it does nothing. `fd` has to be 3 or more, since 0 to 2 are
+    /// stdin, stdout, and stderr.</small>
+    pub fn capture_fd(mut self, fd: u32) -> Self {
+        assert!(
+            fd > 2,
+            "file descriptors 0 to 2 are stdin, stdout, and stderr"
+        );
+        if !self.capture_fds.contains(&fd) {
+            self.capture_fds.push(fd);
+        }
+        return self;
+    }
+
+    /// Sets the command's nice value, from -20 (the highest priority) to 19 (the lowest), e.g. `10` so a batch job
+    /// doesn't slow down everything else
+    ///
+    /// If it can't be set (usually since only root can go below 0), running the command fails with
+    /// [`Error::Spawn`].
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new().nice(10).run(&mut Command::new("nice")).unwrap();
+    /// assert_eq!(Some("10".to_string()), output.stdout_string());
+    /// ```
+    ///
+    /// <small>This is only supported on Unix; elsewhere, it does nothing.</small>
+    pub fn nice(mut self, nice: i32) -> Self {
+        self.pre_exec.nice = Some(nice);
+        return self;
+    }
+
+    /// Only lets the command (and anything it starts) run on these CPUs, e.g. `&[0, 1]`, counting from 0
+    ///
+    /// If none of them can be used, running the command fails with [`Error::Spawn`].
+    ///
+    /// <small>This is only supported on Linux; elsewhere, it does nothing.</small>
+    pub fn cpu_affinity(mut self, cpus: &[usize]) -> Self {
+        self.pre_exec.cpu_affinity = Some(cpus.to_vec());
+        return self;
+    }
+
+    /// Runs the command with `root` as its root directory, like `chroot`, e.g. to run something inside an unpacked
+    /// image
+    ///
+    /// The program is looked up inside `root`, so everything it needs (like shared libraries) has to be in there too.
+    /// It starts in `/` inside `root`, unless it's given a directory with [`Command::current_dir`], which is still a
+    /// path from outside (e.g. `/srv/jail/build` to start in `/build`).
+    ///
+    /// This needs root (or `CAP_SYS_CHROOT`); if the root can't be changed, running the command fails with
+    /// [`Error::Spawn`]. Other options which need root, like [`Runner::user`], still work, since the root is changed
+    /// before switching users.
+    ///
+    /// ```no_run
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .chroot("/srv/rootfs")
+    ///     .run(Command::new("cat").arg("/etc/os-release"))
+    ///     .unwrap();
+    /// ```
+    ///
+    /// <small>This is only supported on Unix; elsewhere, running the command fails with [`Error::Spawn`]</small>
+    pub fn chroot<P: AsRef<Path>>(mut self, root: P) -> Self {
+        self.root = Some(root.as_ref().to_path_buf());
+        return self;
+    }
+
+    /// Runs the command in a new cgroup (v2) with `limits`, which cover everything it starts too
+    ///
+    /// [`CmdOutput::cgroup_stats`] has the peak memory use and CPU time of the whole process tree, and whether the
+    /// OOM killer fired. Killing the command (e.g. when it times out) kills everything in the cgroup.
+    ///
+    /// If the cgroup can't be created or set up, running the command fails with [`Error::Spawn`] (see
+    /// [`CgroupLimits`](crate::CgroupLimits) for what's needed).
+    ///
+    /// ```no_run
+    /// use better_commands::{CgroupLimits, Rlimit, Runner};
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .cgroup(CgroupLimits::new().memory_max(512 * Rlimit::MIB).cpu_max(2.0))
+    ///     .run(Command::new("make").arg("-j8"))
+    ///     .unwrap();
+    /// if output.cgroup_stats().unwrap().oom_killed {
+    ///     println!("ran out of memory");
+    /// }
+    /// ```
+    ///
+    /// <small>This needs the `cgroups` feature, and is only supported on Linux</small>
+    #[cfg(feature = "cgroups")]
+    pub fn cgroup(mut self, limits: crate::CgroupLimits) -> Self {
+        self.cgroup = Some(limits);
+        return self;
+    }
+
+    /// Runs the command in new namespaces, e.g. `Isolation::Network | Isolation::Pid` to cut it off from the network
+    /// and every other process
+    ///
+    /// Creating namespaces needs `CAP_SYS_ADMIN` (e.g. running as root), unless
+    /// [`Isolation::User`](crate::Isolation::User) is included too. If they can't be created, running the command fails
+    /// with [`Error::Spawn`].
+    ///
+    /// ```no_run
+    /// use better_commands::{Isolation, Runner};
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .isolate(Isolation::User | Isolation::Network | Isolation::Pid | Isolation::Mount)
+    ///     .run(Command::new("ps").arg("-e"))
+    ///     .unwrap();
+    /// // there's nothing else in there
+    /// assert_eq!(2, output.stdout().unwrap().len());
+    /// ```
+    ///
+    /// <small>This needs the `namespaces` feature, and is only supported on Linux</small>
+    #[cfg(feature = "namespaces")]
+    pub fn isolate(mut self, isolation: crate::Isolation) -> Self {
+        self.isolation = isolation;
+        return self;
+    }
+
+    /// Records the command's I/O statistics (bytes read and written, syscalls, etc.) from `/proc/<pid>/io` when it exits
+    ///
+    /// They're available from [`CmdOutput::io_stats`]. This is only supported on Linux; elsewhere, there won't be any.
+    pub fn io_stats(mut self, io_stats: bool) -> Self {
+        self.io_stats = io_stats;
+        return self;
+    }
+
+    /// Adds variables that can be used in templates (see [`Runner::env_templated`])
+    pub fn template_context<K: Into<String>, V: Into<String>>(
+        mut self,
+        context: impl IntoIterator<Item = (K, V)>,
+    ) -> Self {
+        self.template_context
+            .extend(context.into_iter().map(|(k, v)| (k.into(), v.into())));
+        return self;
+    }
+
+    /// Sets an environment variable for the command from a template, expanded when the command is started
+    ///
+    /// Each `{name}` in the template is replaced with the variable `name` from [`Runner::template_context`]. If a
+    /// variable isn't in the context, running the command returns an [`Error::Template`].
+    ///
+    /// ```
+    /// use better_commands::{Runner, Template};
+    /// use std::process::Command;
+    ///
+    /// let runner = Runner::new()
+    ///     .template_context([("home", "/home/me"), ("app", "my-app")])
+    ///     .env_templated("CACHE_DIR", Template::parse("{home}/.cache/{app}").unwrap());
+    ///
+    /// let output = runner.run(Command::new("bash").arg("-c").arg("echo $CACHE_DIR")).unwrap();
+    /// assert_eq!("/home/me/.cache/my-app", output.lines().unwrap()[0].content);
+    /// ```
+    pub fn env_templated<K: Into<String>>(mut self, key: K, template: Template) -> Self {
+        self.templated_envs.push((key.into(), template));
+        return self;
+    }
+
+    /// Clears the command's environment, except for the variables in `keys`
+    ///
+    /// Anything set on the [`Command`] itself, or by this runner (e.g. [`Runner::env_from_file`]), is still passed
+    /// along. This makes commands run the same way no matter what's set in the environment they're started from.
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .env_clear_except(["PATH"])
+    ///     .run(Command::new("env").env("GREETING", "hi"))
+    ///     .unwrap();
+    ///
+    /// let vars: Vec<String> = output.lines().unwrap().into_iter().map(|line| line.content).collect();
+    /// assert!(vars.iter().any(|var| var.starts_with("PATH=")));
+    /// assert!(vars.contains(&"GREETING=hi".to_string()));
+    /// assert!(!vars.iter().any(|var| var.starts_with("HOME=")));
+    /// ```
+    pub fn env_clear_except<I, S>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let keys = keys.into_iter().map(|key| key.as_ref().to_os_string());
+        self.env_keep = Some(keys.collect());
+        return self;
+    }
+
+    /// Sets environment variables from a `.env` file, read when the command is started
+    ///
+    /// Each line is `KEY=value`, optionally starting with `export `. Values can be wrapped in single quotes (taken
+    /// literally) or double quotes (which support escapes like `\n` and `\"`), and blank lines and lines starting with
+    /// `#` are skipped. If the file can't be read or parsed, running the command returns an [`Error::EnvFile`].
+    ///
+    /// This can be called multiple times; later files override earlier ones.
+    pub fn env_from_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.env_files.push(path.as_ref().to_path_buf());
+        return self;
+    }
+
+    /// Runs the command with its locale set to `locale` (e.g. `"C"` or `"C.UTF-8"`), by setting `LC_ALL` and `LANG`
+    ///
+    /// This stops localized messages and number formats (like `1,5` instead of `1.5`) from breaking anything parsing the
+    /// output. `LANGUAGE` is also removed, since it can override the locale for messages. The locale the command would've
+    /// had otherwise is available from [`CmdOutput::original_locale`].
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .pin_locale("C")
+    ///     .run(Command::new("bash").arg("-c").arg("echo $LC_ALL"))
+    ///     .unwrap();
+    /// assert_eq!("C", output.lines().unwrap()[0].content);
+    /// ```
+    pub fn pin_locale<S: Into<String>>(mut self, locale: S) -> Self {
+        self.locale = Some(locale.into());
+        return self;
+    }
+
+    /// Sets how many bytes are read from stdout and stderr at once (8 KiB by default)
+    ///
+    /// Lines longer than this are still captured in full with [`ReadMode::Lines`]; this only changes how much is read
+    /// per system call. Panics if `size` is 0.
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "the read buffer size must be at least 1 byte");
+        self.capture.read_buffer_size = size;
+        return self;
+    }
+
+    /// Sets when output is split into lines ([`ReadMode::Lines`] by default)
+    ///
+    /// ```
+    /// use better_commands::{ReadMode, Runner};
+    /// use std::process::Command;
+    ///
+    /// // capture a progress bar as it's drawn, rather than once the command is done
+    /// let runner = Runner::new().read_mode(ReadMode::Chunks);
+    /// let output = runner
+    ///     .run(Command::new("bash").arg("-c").arg("for i in 1 2 3; do echo -n .; sleep 0.1; done"))
+    ///     .unwrap();
+    /// assert_eq!(3, output.len());
+    /// ```
+    pub fn read_mode(mut self, mode: ReadMode) -> Self {
+        self.capture.read_mode = mode;
+        return self;
+    }
+
+    /// Sets how a `\r` which isn't part of a `\r\n` is handled ([`CarriageReturns::Keep`] by default)
+    ///
+    /// ```
+    /// use better_commands::{CarriageReturns, Runner};
+    /// use std::process::Command;
+    ///
+    /// let progress = r"printf 'downloading 0%%\rdownloading 50%%\rdownloading 100%%\ndone\n'";
+    ///
+    /// let runner = Runner::new().carriage_returns(CarriageReturns::Split);
+    /// let output = runner.run(Command::new("bash").arg("-c").arg(progress)).unwrap();
+    /// assert_eq!(4, output.len());
+    ///
+    /// let runner = Runner::new().carriage_returns(CarriageReturns::Coalesce);
+    /// let output = runner.run(Command::new("bash").arg("-c").arg(progress)).unwrap();
+    /// assert_eq!(Some("downloading 100%\ndone".to_string()), output.stdout_string());
+    /// ```
+    pub fn carriage_returns(mut self, carriage_returns: CarriageReturns) -> Self {
+        self.capture.carriage_returns = carriage_returns;
+        return self;
+    }
+
+    /// Sets what separates one line of the output from the next (`\n` by default), e.g. `b'\0'` for `find -print0` or
+    /// `git -z`
+    ///
+    /// It can be a single byte or several (see [`Delimiter`]). Panics if it's empty.
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .delimiter(b'\0')
+    ///     .run(Command::new("printf").arg("a file\\0another\\nfile\\0"))
+    ///     .unwrap();
+    /// let files: Vec<String> = output.lines().unwrap().into_iter().map(|line| line.content).collect();
+    /// assert_eq!(vec!["a file", "another\nfile"], files);
+    /// ```
+    ///
+    /// <small>`\r\n` is only treated as a newline with the default delimiter.</small>
+    pub fn delimiter<D: Into<Delimiter>>(mut self, delimiter: D) -> Self {
+        let delimiter = delimiter.into();
+        assert!(!delimiter.0.is_empty(), "lines need a delimiter");
+        self.capture.delimiter = delimiter;
+        return self;
+    }
+
+    /// Limits how many bytes of each line are kept, so a command printing one enormous line can't use up all the
+    /// memory
+    ///
+    /// With [`LongLines::Truncate`], anything past the limit is thrown away as it's read, and the line has
+    /// [`Line::truncated`] set. With [`LongLines::Fail`], the command is killed as soon as a line goes over, and it fails
+    /// with [`Error::LineTooLong`].
+    ///
+    /// ```
+    /// use better_commands::{LongLines, Runner};
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .max_line_length(5, LongLines::Truncate)
+    ///     .run(Command::new("echo").arg("hello world"))
+    ///     .unwrap();
+    /// let line = &output.lines().unwrap()[0];
+    /// assert_eq!("hello", line.content);
+    /// assert!(line.truncated);
+    /// ```
+    ///
+    /// <small>A multi-byte character cut in half by the limit is dropped entirely, so the content stays valid.</small>
+    pub fn max_line_length(mut self, bytes: usize, long_lines: LongLines) -> Self {
+        self.capture.max_line_length = Some(bytes);
+        self.capture.long_lines = long_lines;
+        return self;
+    }
+
+    /// Removes ANSI escape sequences (colors, cursor movement, etc.) from each line's [`Line::content`]
+    ///
+    /// Use [`Runner::keep_raw`] as well to keep the original text, with the escape sequences, in [`Line::raw`].
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .strip_ansi(true)
+    ///     .run(Command::new("printf").arg("\\033[1;31merror:\\033[0m oops\\n"))
+    ///     .unwrap();
+    /// assert_eq!("error: oops", output.stdout().unwrap()[0].content);
+    /// ```
+    pub fn strip_ansi(mut self, strip_ansi: bool) -> Self {
+        self.capture.strip_ansi = strip_ansi;
+        return self;
+    }
+
+    /// Keeps the exact bytes of each line in [`Line::raw`], as well as the decoded [`Line::content`]
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .keep_raw(true)
+    ///     .run(Command::new("printf").arg("caf\\xe9\\n"))
+    ///     .unwrap();
+    /// let line = &output.stdout().unwrap()[0];
+    /// assert_eq!("caf\u{FFFD}", line.content);
+    /// assert_eq!(Some(b"caf\xe9".to_vec()), line.raw);
+    /// ```
+    pub fn keep_raw(mut self, keep_raw: bool) -> Self {
+        self.capture.keep_raw = keep_raw;
+        return self;
+    }
+
+    /// Sets how the output of commands is read ([`Backend::Threads`] by default)
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        return self;
+    }
+
+    /// Runs `callback` on each line as soon as it's captured, before it's stored
+    ///
+    /// The callback can modify the line, e.g. to [annotate](Line::annotate) it. It's shared between stdout and
+    /// stderr, so it's only ever running for one line at a time.
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let mut current_test = None;
+    /// let runner = Runner::new().on_line(move |line| {
+    ///     if let Some(name) = line.content.strip_prefix("running ") {
+    ///         current_test = Some(name.to_string());
+    ///     } else if let Some(name) = &current_test {
+    ///         line.annotate(format!("output of {}", name));
+    ///     }
+    /// });
+    ///
+    /// let output = runner.run(Command::new("echo").arg("running foo\nok")).unwrap();
+    /// assert_eq!(vec!["output of foo"], output.lines().unwrap()[1].annotations);
+    /// ```
+    pub fn on_line(mut self, callback: impl FnMut(&mut Line) + Send + 'static) -> Self {
+        self.capture.on_line = Some(Arc::new(Mutex::new(callback)));
+        return self;
+    }
+
+    /// Tags each line printed to stderr with its [`Severity`](crate::Severity), using the first of `rules` that matches
+    /// it (see [`Line::severity`])
+    ///
+    /// This happens before [`Runner::on_line`], so callbacks can see it, and [`CmdOutput::errors`] can be used
+    /// afterwards to check whether anything went wrong.
+    ///
+    /// ```
+    /// use better_commands::{Runner, Severity, SeverityRules};
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .classify_severity(SeverityRules::defaults())
+    ///     .run(
+    ///         Command::new("bash")
+    ///             .arg("-c")
+    ///             .arg("echo 'main.c:3:5: warning: unused variable' >&2; echo 'main.c:4:1: error: expected ;' >&2"),
+    ///     )
+    ///     .unwrap();
+    /// let lines = output.stderr().unwrap();
+    /// assert_eq!(Some(Severity::Warning), lines[0].severity());
+    /// assert_eq!("main.c:4:1: error: expected ;", output.errors().last().unwrap().content);
+    /// ```
+    pub fn classify_severity(mut self, rules: SeverityRules) -> Self {
+        self.capture.severity_rules = Some(Arc::new(rules));
+        return self;
+    }
+
+    /// Calls `callback` with how far along the command is, from 0.0 to 1.0, whenever it prints a line with its progress
+    /// in it (see [`parse_progress`](crate::parse_progress) for what's recognized), e.g. for drawing a progress bar
+    ///
+    /// Progress bars are usually redrawn with a `\r` rather than printing new lines, so this is best used with
+    /// [`CarriageReturns::Split`].
+    ///
+    /// ```
+    /// use better_commands::{CarriageReturns, Runner};
+    /// use std::process::Command;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let progress = Arc::new(Mutex::new(Vec::new()));
+    /// let seen = progress.clone();
+    /// Runner::new()
+    ///     .carriage_returns(CarriageReturns::Split)
+    ///     .on_progress(move |fraction| seen.lock().unwrap().push(fraction))
+    ///     .run(Command::new("printf").arg("downloading 25%%\\rdownloading 50%%\\rdone\\n"))
+    ///     .unwrap();
+    ///
+    /// assert_eq!(vec![0.25, 0.5], *progress.lock().unwrap());
+    /// ```
+    pub fn on_progress(mut self, callback: impl FnMut(f64) + Send + 'static) -> Self {
+        self.capture.on_progress = Some(Arc::new(Mutex::new(callback)));
+        return self;
+    }
+
+    /// Runs the callbacks in `hooks` when each command is started, prints a line, and exits
+    ///
+    /// This can be called multiple times to add several sets of hooks, e.g. one for metrics and one for logging.
+    pub fn hooks(mut self, hooks: Hooks) -> Self {
+        self.capture.hooks.push(hooks);
+        return self;
+    }
+
+    /// Records every command run with this runner to `log`, on top of the global one from
+    /// [`set_global_audit_log`](crate::set_global_audit_log)
+    pub fn audit_log(mut self, log: AuditLog) -> Self {
+        self.audit_log = Some(log);
+        return self;
+    }
+
+    /// Stores successful outputs in `cache`, and returns them instead of running the command again when nothing it
+    /// depends on has changed
+    ///
+    /// An output is reused if the command line, working directory, environment variables set on the [`Command`], and
+    /// the contents of the files from [`Runner::cache_key_files`] are all the same. [`CmdOutput::cached`] says whether
+    /// it was. This only applies to [`Runner::run`].
+    ///
+    /// ```
+    /// use better_commands::{CacheDir, Runner};
+    /// use std::process::Command;
+    ///
+    /// let cache = CacheDir::new(std::env::temp_dir().join("better-commands-cache-example"));
+    /// # cache.clear().unwrap();
+    /// let input = std::env::temp_dir().join("better-commands-cache-example.txt");
+    /// std::fs::write(&input, "1").unwrap();
+    /// let runner = Runner::new().cache(cache).cache_key_files([&input]);
+    ///
+    /// let mut command = Command::new("date");
+    /// command.arg("+%N");
+    /// let first = runner.run(&mut command).unwrap();
+    /// let second = runner.run(&mut command).unwrap();
+    /// assert!(second.cached());
+    /// assert_eq!(first.stdout_string(), second.stdout_string());
+    ///
+    /// // it's run again once the input changes
+    /// std::fs::write(&input, "2").unwrap();
+    /// assert!(!runner.run(&mut command).unwrap().cached());
+    /// ```
+    ///
+    /// <small>This needs the `serde` feature</small>
+    #[cfg(feature = "serde")]
+    pub fn cache(mut self, cache: crate::CacheDir) -> Self {
+        self.cache = Some(cache);
+        return self;
+    }
+
+    /// Adds files the command's output depends on, for [`Runner::cache`]
+    ///
+    /// The command is run again whenever any of them change (or are created or removed).
+    ///
+    /// <small>This needs the `serde` feature</small>
+    #[cfg(feature = "serde")]
+    pub fn cache_key_files<I, P>(mut self, files: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.cache_key_files
+            .extend(files.into_iter().map(|file| file.as_ref().to_path_buf()));
+        return self;
+    }
+
+    /// Runs a command using these options, returning a [`CmdOutput`] (which *will* contain `Some(lines)`, not a None)
+    ///
+    /// Unlike [`run`](crate::run), this returns an [`Error`] instead of panicking if the command can't be run.
+    pub fn run(&self, command: &mut Command) -> Result<CmdOutput, Error> {
+        #[cfg(feature = "serde")]
+        if let Some(cache) = &self.cache {
+            let key = cache.key(command, &self.cache_key_files)?;
+            if let Some(output) = cache.get(&key) {
+                return Ok(output);
+            }
+            let output = self.run_with(command, |command| self.spawn(command)?.wait())?;
+            if output.success() {
+                // the output's still fine if it can't be stored, it just means running it again next time
+                let _ = cache.put(&key, &output);
+            }
+            return Ok(output);
+        }
+        return self.run_with(command, |command| self.spawn(command)?.wait());
+    }
+
+    /// Runs a command with retries, using `run_once` to run each attempt
+    pub(crate) fn run_with(
+        &self,
+        command: &mut Command,
+        mut run_once: impl FnMut(&mut Command) -> Result<CmdOutput, Error>,
+    ) -> Result<CmdOutput, Error> {
+        let mut failed_attempts: Vec<CmdOutput> = Vec::new();
+        let mut attempt = 0;
+        loop {
+            let mut output = run_once(command)?;
+            let out_of_time = self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+            if attempt >= self.retries || out_of_time || !self.failed(&output) {
+                output.failed_attempts = failed_attempts;
+                return Ok(output);
+            }
+
+            let mut delay = self.backoff.delay(attempt);
+            if let Some(deadline) = self.deadline {
+                // don't sleep past the deadline just to find out there's no time left for another attempt
+                delay = delay.min(deadline.saturating_duration_since(Instant::now()));
+            }
+            thread::sleep(delay);
+            if self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                output.failed_attempts = failed_attempts;
+                return Ok(output);
+            }
+            failed_attempts.push(output);
+            attempt += 1;
+        }
+    }
+
+    fn failed(&self, output: &CmdOutput) -> bool {
+        return match &self.retry_if {
+            Some(predicate) => predicate(output),
+            None => !output.success(),
+        };
+    }
+
+    /// Starts a command using these options, returning a handle to it instead of waiting for it to finish
+    ///
+    /// The command is killed if the [`RunningCommand`] is dropped before [`RunningCommand::wait`] is called (e.g. if
+    /// the thread panics), unless [`RunningCommand::detach_on_drop`] is used. Retries aren't supported here, only by [`Runner::run`].
+    ///
+    /// ```
+    /// use better_commands::Runner;
+    /// use std::process::Command;
+    ///
+    /// let running = Runner::new().spawn(Command::new("echo").arg("hi")).unwrap();
+    /// println!("started process {}", running.id());
+    ///
+    /// let output = running.wait().unwrap();
+    /// assert_eq!("hi", output.lines().unwrap()[0].content);
+    /// ```
+    pub fn spawn(&self, command: &mut Command) -> Result<RunningCommand, Error> {
+        return self.spawn_with(command, |job| Reader::Thread(thread::spawn(job)));
+    }
+
+    /// Same as [`Runner::spawn`], but uses `start_reader` to start reading each stream in the background
+    pub(crate) fn spawn_with(
+        &self,
+        command: &mut Command,
+        start_reader: impl Fn(ReadJob) -> Reader,
+    ) -> Result<RunningCommand, Error> {
+        // the environment is only changed for this run, so `command` can be run again afterwards as it was
+        let saved_env = env::SavedEnv::save(command);
+        let running = self.start(command, start_reader);
+        saved_env.restore(command);
+        return running;
+    }
+
+    /// Does the work of [`Runner::spawn_with`], setting up `command`'s environment for this run
+    fn start(
+        &self,
+        command: &mut Command,
+        start_reader: impl Fn(ReadJob) -> Reader,
+    ) -> Result<RunningCommand, Error> {
+        check_not_empty(command)?;
+        let permit = match &self.rate_limit {
+            Some(limiter) => Some(limiter.acquire()?),
+            None => None,
+        };
+        if let Some(keep) = &self.env_keep {
+            env::clear_except(command, keep);
+        }
+        for path in &self.env_files {
+            let vars = env::read_env_file(path).map_err(|e| Error::EnvFile(path.clone(), e))?;
+            command.envs(vars);
+        }
+        for (key, template) in &self.templated_envs {
+            let value = template
+                .expand(&self.template_context)
+                .map_err(Error::Template)?;
+            command.env(key, value);
+        }
+        #[allow(unused_mut)]
+        let mut pre_exec = self.pre_exec_for(command)?;
+        // this is done by the hook rather than `CommandExt::process_group`, which would stay set on `command`
+        pre_exec.process_group = self.process_group;
+        let mut original_locale = None;
+        if let Some(locale) = &self.locale {
+            original_locale = current_locale(command);
+            command
+                .env("LC_ALL", locale)
+                .env("LANG", locale)
+                .env_remove("LANGUAGE");
+        }
+        let command_line = command_line(command);
+        let envs_diff = env::diff(command);
+        #[cfg(feature = "cgroups")]
+        let cgroup = match &self.cgroup {
+            Some(limits) => {
+                let cgroup = crate::cgroup::Cgroup::create(limits).map_err(Error::Spawn)?;
+                pre_exec.cgroup_procs = Some(cgroup.procs_fd);
+                Some(cgroup)
+            }
+            None => None,
+        };
+        // pipes read along with stdout and stderr
+        let mut extra_streams: Vec<(std::fs::File, LineType)> = Vec::new();
+        #[cfg(unix)]
+        let extra_fds = match self.capture_fds.is_empty() {
+            true => None,
+            false => {
+                let (fds, readers) =
+                    crate::fds::ExtraFds::create(&self.capture_fds).map_err(Error::Spawn)?;
+                pre_exec.fds = Some(fds.mapping.clone());
+                extra_streams.extend(
+                    readers
+                        .into_iter()
+                        .map(|(reader, fd)| (reader, LineType::Fd(fd))),
+                );
+                Some(fds)
+            }
+        };
+        let hook = pre_exec.apply(command);
+        if let Some(mode) = &self.stdin {
+            command.stdin(mode.stdio());
+        }
+        if let Cancellation::Stdin { .. } = self.cancellation {
+            command.stdin(Stdio::piped());
+        }
+        let mut previous_dir = None;
+        let workdir = match self.temp_dir {
+            Some(mode) => {
+                let dir = TempDir::create(mode == TempDirMode::Remove).map_err(Error::Spawn)?;
+                // a command without one runs in this process's
+                previous_dir = command
+                    .get_current_dir()
+                    .map(Path::to_path_buf)
+                    .or_else(|| std::env::current_dir().ok());
+                command.current_dir(dir.path());
+                Some(Arc::new(dir))
+            }
+            None => None,
+        };
+        let user = match (&self.user, pre_exec.uid) {
+            (Some(name), _) => Some(User::Name(name.clone())),
+            (None, Some(uid)) => Some(User::Id(uid)),
+            (None, None) => None,
+        };
+        let audit = Audit::new(command, user, self.audit_log.as_ref());
+        #[cfg(unix)]
+        let handlers = match self.forward_signals {
+            true => Some(crate::signals::Handlers::install().map_err(Error::Spawn)?),
+            false => None,
+        };
+        match self.merge_stderr {
+            true => {
+                let (reader, writer) = io::pipe().map_err(Error::Spawn)?;
+                command.stdout(writer.try_clone().map_err(Error::Spawn)?);
+                command.stderr(writer);
+                extra_streams.push((pipe_file(reader), LineType::Stdout));
+            }
+            false => {
+                command
+                    .stdout(self.stdout.stdio())
+                    .stderr(self.stderr.stdio());
+            }
+        }
+        let start = Instant::now();
+        let spawned = command.spawn();
+        // the hook stays on `command`, but it's only for this run
+        drop(hook);
+        // the directory's removed along with the output, so it can't be left there for the next run
+        if let Some(dir) = previous_dir {
+            command.current_dir(dir);
+        }
+        if self.merge_stderr {
+            // `command` keeps the write end otherwise, so the pipe would never be closed; this leaves it set up the
+            // same way as without `merge_stderr`, so it can still be run again afterwards
+            command
+                .stdout(self.stdout.stdio())
+                .stderr(self.stderr.stdio());
+        }
+        let mut child = spawned.map_err(|e| Error::Spawn(self.explain_spawn_error(e)))?;
+        // the child has its own copies now, and reading only stops once they're all closed
+        #[cfg(unix)]
+        drop(extra_fds);
+        for hooks in &self.capture.hooks {
+            hooks.spawned(child.id());
+        }
+
+        #[cfg(unix)]
+        let forwarder = handlers.map(|handlers| {
+            crate::signals::Forwarder::new(handlers, child.id(), self.process_group)
+        });
+
+        if let Some(StdinMode::Piped(input)) = &self.stdin {
+            if let Some(mut stdin) = child.stdin.take() {
+                let input = input.clone();
+                // it's fine if the command quits without reading it all
+                thread::spawn(move || stdin.write_all(&input));
+            }
+        }
+
+        let child_stdout = child.stdout.take();
+        let child_stderr = child.stderr.take();
+        let capture = self.capture.for_run(start);
+        #[cfg(feature = "tracing")]
+        let capture = capture.traced(&command_line, child.id());
+        #[cfg(feature = "log")]
+        let capture = capture.with_log_target(command);
+        let mut readers = Vec::new();
+        #[cfg(all(unix, feature = "poll"))]
+        let mut polled = None;
+        match self.backend {
+            Backend::Threads => {
+                if let Some(child_stdout) = child_stdout {
+                    let stdout_capture = capture.clone();
+                    readers.push(start_reader(Box::new(move || {
+                        read_lines(child_stdout, LineType::Stdout, &stdout_capture)
+                    })));
+                }
+                if let Some(child_stderr) = child_stderr {
+                    let stderr_capture = capture.clone();
+                    readers.push(start_reader(Box::new(move || {
+                        read_lines(child_stderr, LineType::Stderr, &stderr_capture)
+                    })));
+                }
+                for (reader, printed_to) in extra_streams {
+                    let extra_capture = capture.clone();
+                    readers.push(start_reader(Box::new(move || {
+                        read_lines(reader, printed_to, &extra_capture)
+                    })));
+                }
+            }
+            #[cfg(all(unix, feature = "poll"))]
+            Backend::Poll => {
+                let mut streams = Vec::new();
+                if let Some(child_stdout) = child_stdout {
+                    let child_stdout = std::os::fd::OwnedFd::from(child_stdout);
+                    streams.push((child_stdout.into(), LineType::Stdout));
+                }
+                if let Some(child_stderr) = child_stderr {
+                    let child_stderr = std::os::fd::OwnedFd::from(child_stderr);
+                    streams.push((child_stderr.into(), LineType::Stderr));
+                }
+                streams.extend(extra_streams);
+                polled = Some(streams);
+            }
+        }
+
+        return Ok(RunningCommand {
+            child,
+            command_line,
+            start,
+            deadline: match (self.timeout.map(|timeout| start + timeout), self.deadline) {
+                (Some(timeout), Some(deadline)) => Some(timeout.min(deadline)),
+                (timeout, deadline) => timeout.or(deadline),
+            },
+            readers,
+            #[cfg(all(unix, feature = "poll"))]
+            polled,
+            heartbeats: self.heartbeat.as_ref().map(|(interval, callback)| {
+                Heartbeats::start(*interval, callback.clone(), capture.clone())
+            }),
+            capture,
+            process_group: self.process_group,
+            cancellation: self.cancellation.clone(),
+            collect_io_stats: self.io_stats,
+            io_stats: None,
+            reaped: None,
+            resource_usage: None,
+            shutdown: None,
+            limits: pre_exec.limits,
+            #[cfg(feature = "cgroups")]
+            cgroup,
+            #[cfg(unix)]
+            forwarder,
+            detach_on_drop: false,
+            exited: false,
+            original_locale,
+            envs_diff,
+            workdir,
+            audit,
+            #[cfg(feature = "metrics")]
+            program: crate::telemetry::program(command),
+            _permit: permit,
+        });
+    }
+}
+
+impl Runner {
+    /// Returns what to set up in the child, looking up [`Runner::user`] and setting its environment variables on
+    /// `command`
+    fn pre_exec_for(&self, command: &mut Command) -> Result<PreExec, Error> {
+        let mut pre_exec = self.pre_exec.clone();
+        #[cfg(unix)]
+        if let Some(user) = &self.user {
+            let account = crate::pre_exec::lookup_user(user).map_err(Error::Spawn)?;
+            for (key, value) in [("USER", user), ("LOGNAME", user), ("HOME", &account.home)] {
+                if !command.get_envs().any(|(k, _)| k == OsStr::new(key)) {
+                    command.env(key, value);
+                }
+            }
+            pre_exec.uid.get_or_insert(account.uid);
+            pre_exec.gid.get_or_insert(account.gid);
+            pre_exec.groups = Some(account.groups);
+        }
+        #[cfg(unix)]
+        if let Some(root) = &self.root {
+            use std::os::unix::ffi::OsStrExt;
+            let root = std::ffi::CString::new(root.as_os_str().as_bytes()).map_err(|_| {
+                Error::Spawn(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "root directory contains a NUL byte",
+                ))
+            })?;
+            pre_exec.root = Some(root);
+            pre_exec.keep_current_dir = command.get_current_dir().is_some();
+        }
+        #[cfg(not(unix))]
+        if self.root.is_some() {
+            return Err(Error::Spawn(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "changing the root directory is only supported on Unix",
+            )));
+        }
+        #[cfg(not(unix))]
+        let _ = command;
+        #[cfg(all(feature = "namespaces", target_os = "linux"))]
+        if !self.isolation.is_empty() {
+            let uid = pre_exec.uid.unwrap_or_else(|| unsafe { libc::getuid() });
+            let gid = pre_exec.gid.unwrap_or_else(|| unsafe { libc::getgid() });
+            pre_exec.namespaces = Some(crate::isolation::Namespaces::new(self.isolation, uid, gid));
+        }
+        #[cfg(all(feature = "namespaces", not(target_os = "linux")))]
+        if !self.isolation.is_empty() {
+            return Err(Error::Spawn(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "namespaces are only supported on Linux",
+            )));
+        }
+        return Ok(pre_exec);
+    }
+
+    /// Adds what was probably missing to errors from the child failing to set things up
+    fn explain_spawn_error(&self, error: io::Error) -> io::Error {
+        if let Some(root) = &self.root {
+            let reason = match error.kind() {
+                io::ErrorKind::PermissionDenied => Some("this needs root, or CAP_SYS_CHROOT"),
+                io::ErrorKind::NotFound => Some("it or the program inside it doesn't exist"),
+                _ => None,
+            };
+            if let Some(reason) = reason {
+                return io::Error::new(
+                    error.kind(),
+                    format!(
+                        "couldn't run the command in {} ({}): {}",
+                        root.display(),
+                        reason,
+                        error
+                    ),
+                );
+            }
+        }
+        #[cfg(feature = "namespaces")]
+        if !self.isolation.is_empty() && error.kind() == io::ErrorKind::PermissionDenied {
+            let reason = match self.isolation.contains(crate::Isolation::User) {
+                true => "unprivileged user namespaces might be disabled",
+                false => "this needs CAP_SYS_ADMIN (e.g. running as root), or Isolation::User too",
+            };
+            return io::Error::new(
+                error.kind(),
+                format!("couldn't create namespaces ({}): {}", reason, error),
+            );
+        }
+        return error;
+    }
+}
+
+/// Turns the read end of a pipe into a [`File`](std::fs::File), so it can be read like any other stream
+fn pipe_file(reader: io::PipeReader) -> std::fs::File {
+    #[cfg(unix)]
+    return std::os::fd::OwnedFd::from(reader).into();
+    #[cfg(windows)]
+    return std::os::windows::io::OwnedHandle::from(reader).into();
+}
+
+/// Something which runs commands, so code running them can be given a [`FakeRunner`](crate::FakeRunner) in tests
+///
+/// This is implemented by [`Runner`], [`FakeRunner`](crate::FakeRunner), [`ContainerRunner`](crate::ContainerRunner),
+/// (with the `serde` feature) `Cassette`, and (with the `ssh` feature) `SshRunner`.
+///
+/// ```
+/// use better_commands::{CommandRunner, FakeRunner, Line, Runner};
+/// use std::process::Command;
+///
+/// fn current_branch(runner: &impl CommandRunner) -> String {
+///     let output = runner
+///         .run(Command::new("git").args(["branch", "--show-current"]))
+///         .unwrap();
+///     return output.stdout_string().unwrap_or_default();
+/// }
+///
+/// let fake = FakeRunner::new().respond(
+///     "git branch --show-current",
+///     0,
+///     vec![Line::from_stdout("main")],
+/// );
+/// assert_eq!("main", current_branch(&fake));
+/// assert_eq!(vec!["git branch --show-current"], fake.invocations());
+/// ```
+pub trait CommandRunner {
+    /// Runs a command, returning its output
+    fn run(&self, command: &mut Command) -> Result<CmdOutput, Error>;
+}
+
+impl CommandRunner for Runner {
+    fn run(&self, command: &mut Command) -> Result<CmdOutput, Error> {
+        return Runner::run(self, command);
+    }
+}
+
+/// What to do with the directories made by [`Runner::current_dir_temp`] once they're no longer needed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TempDirMode {
+    Remove,
+    Keep,
+}
+
+/// Returns the locale `command` will run with, going by `LC_ALL` and then `LANG`
+fn current_locale(command: &Command) -> Option<String> {
+    for key in ["LC_ALL", "LANG"] {
+        let value = match command.get_envs().find(|(k, _)| *k == key) {
+            Some((_, value)) => value.map(|v| v.to_os_string()),
+            None => std::env::var_os(key),
+        };
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            return Some(value.to_string_lossy().into_owned());
+        }
+    }
+    return None;
+}
@@ -0,0 +1,464 @@
+use crate::audit::Audit;
+use crate::capture::Capture;
+use crate::heartbeat::Heartbeats;
+use crate::limit::Permit;
+use crate::workdir::TempDir;
+#[cfg(all(unix, feature = "poll"))]
+use crate::LineType;
+use crate::{
+    Cancellation, CmdOutput, EnvChange, Error, IoStats, KillReason, Line, ResourceUsage, Rlimit,
+    Shutdown,
+};
+#[cfg(all(unix, feature = "poll"))]
+use std::fs::File;
+use std::io::{self, Write};
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often to check whether the command has exited when it might need to be killed
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A handle to a command that's still running, from [`Runner::spawn`](crate::Runner::spawn)
+///
+/// Output is captured in the background; call [`RunningCommand::wait`] to get it once the command exits. Timeouts and
+/// other reasons to kill the command are only checked while waiting.
+///
+/// If this is dropped before the command is waited for, the command is killed, so it can't be left running forever if
+/// the thread using it panics. Use [`RunningCommand::detach_on_drop`] to let it keep running instead.
+#[derive(Debug)]
+pub struct RunningCommand {
+    pub(crate) child: Child,
+    pub(crate) command_line: String,
+    pub(crate) start: Instant,
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) readers: Vec<Reader>,
+    /// The pipes to read when waiting, with [`Backend::Poll`](crate::Backend::Poll)
+    #[cfg(all(unix, feature = "poll"))]
+    pub(crate) polled: Option<Vec<(File, LineType)>>,
+    pub(crate) capture: Capture,
+    /// From [`Runner::heartbeat`](crate::Runner::heartbeat), stopped once the command exits
+    pub(crate) heartbeats: Option<Heartbeats>,
+    pub(crate) process_group: bool,
+    pub(crate) cancellation: Cancellation,
+    /// Whether to get [`IoStats`] before the child is reaped
+    pub(crate) collect_io_stats: bool,
+    pub(crate) io_stats: Option<IoStats>,
+    /// Set once the child has been reaped, since [`Child`] can't be told about it when it's reaped with `wait4`
+    pub(crate) reaped: Option<ExitStatus>,
+    pub(crate) resource_usage: Option<ResourceUsage>,
+    /// How it was stopped, once it's been cancelled
+    pub(crate) shutdown: Option<Shutdown>,
+    /// From [`Runner::limit`](crate::Runner::limit)
+    pub(crate) limits: Vec<Rlimit>,
+    /// From [`Runner::cgroup`](crate::Runner::cgroup), which is removed when this is dropped
+    #[cfg(feature = "cgroups")]
+    pub(crate) cgroup: Option<crate::cgroup::Cgroup>,
+    /// Set by [`Runner::forward_signals`](crate::Runner::forward_signals)
+    #[cfg(unix)]
+    pub(crate) forwarder: Option<crate::signals::Forwarder>,
+    pub(crate) detach_on_drop: bool,
+    pub(crate) exited: bool,
+    pub(crate) original_locale: Option<String>,
+    pub(crate) envs_diff: Vec<EnvChange>,
+    /// Set by [`Runner::current_dir_temp`](crate::Runner::current_dir_temp)
+    pub(crate) workdir: Option<Arc<TempDir>>,
+    /// Set if the command's being recorded to an [`AuditLog`](crate::AuditLog)
+    pub(crate) audit: Option<Audit>,
+    /// The label for the command's metrics
+    #[cfg(feature = "metrics")]
+    pub(crate) program: String,
+    pub(crate) _permit: Option<Permit>,
+}
+
+impl RunningCommand {
+    /// Returns the OS-assigned process ID of the command
+    pub fn id(&self) -> u32 {
+        return self.child.id();
+    }
+
+    /// Returns the time the command was started at
+    pub fn start_time(&self) -> Instant {
+        return self.start;
+    }
+
+    /// Kills the command
+    ///
+    /// The output is still available from [`RunningCommand::wait`] afterwards.
+    pub fn kill(&mut self) -> io::Result<()> {
+        // its PID might belong to something else by now
+        if self.reaped.is_some() {
+            return Ok(());
+        }
+        return self.child.kill();
+    }
+
+    /// Kills the command and everything it started
+    ///
+    /// This only works if the command was started with [`Runner::process_group`](crate::Runner::process_group) on
+    /// Unix, or in a cgroup with `Runner::cgroup` (with the `cgroups` feature); otherwise it's the same as
+    /// [`RunningCommand::kill`].
+    pub fn kill_tree(&mut self) -> io::Result<()> {
+        #[cfg(feature = "cgroups")]
+        if let Some(cgroup) = &self.cgroup {
+            // this catches everything, even things which left the process group
+            return cgroup.kill();
+        }
+        #[cfg(unix)]
+        if self.process_group {
+            return self.send_signal(libc::SIGKILL);
+        }
+        return self.kill();
+    }
+
+    /// Sends `signal` to the command, or its whole process group with [`Runner::process_group`](crate::Runner::process_group)
+    #[cfg(unix)]
+    fn send_signal(&self, signal: libc::c_int) -> io::Result<()> {
+        // its PID might belong to something else by now
+        if self.reaped.is_some() && !self.process_group {
+            return Ok(());
+        }
+        return crate::signals::send(self.child.id(), self.process_group, signal);
+    }
+
+    /// Asks the command to stop with `SIGTERM`, waiting up to `grace` for it to exit before killing it
+    ///
+    /// This sends `SIGTERM` to the whole process group with [`Runner::process_group`](crate::Runner::process_group).
+    /// The result (also available from [`CmdOutput::shutdown`]) says whether it exited by itself or had to be killed.
+    /// Use [`Cancellation::terminate`] to do this when the command times out.
+    ///
+    /// <small>On Windows, there's no `SIGTERM`, so this kills it straight away</small>
+    ///
+    /// ```
+    /// use better_commands::{Runner, Shutdown};
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    ///
+    /// // exits cleanly when it gets SIGTERM
+    /// let mut running = Runner::new()
+    ///     .spawn(Command::new("bash").arg("-c").arg("trap 'echo bye; exit 0' TERM; while true; do sleep 0.01; done"))
+    ///     .unwrap();
+    /// std::thread::sleep(Duration::from_millis(200));
+    /// assert_eq!(Shutdown::Exited, running.shutdown(Duration::from_secs(5)).unwrap());
+    /// assert_eq!(Some("bye".to_string()), running.wait().unwrap().stdout_string());
+    /// ```
+    pub fn shutdown(&mut self, grace: Duration) -> io::Result<Shutdown> {
+        #[cfg(unix)]
+        {
+            self.send_signal(libc::SIGTERM)?;
+            return self.wait_or_kill(grace);
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = grace;
+            self.kill_tree()?;
+            self.shutdown = Some(Shutdown::Killed);
+            return Ok(Shutdown::Killed);
+        }
+    }
+
+    /// Waits up to `grace` for the command to exit after it's been asked to, killing it if it doesn't
+    fn wait_or_kill(&mut self, grace: Duration) -> io::Result<Shutdown> {
+        let give_up = Instant::now() + grace;
+        let shutdown = loop {
+            if self.try_reap()?.is_some() {
+                break Shutdown::Exited;
+            }
+            let now = Instant::now();
+            if now >= give_up {
+                self.kill_tree()?;
+                break Shutdown::Killed;
+            }
+            thread::sleep(POLL_INTERVAL.min(give_up - now));
+        };
+        self.shutdown = Some(shutdown);
+        return Ok(shutdown);
+    }
+
+    /// Stops the command the way it was set up to be cancelled (see [`Runner::cancellation`](crate::Runner::cancellation))
+    ///
+    /// With [`Cancellation::Kill`], this is the same as [`RunningCommand::kill_tree`]. With [`Cancellation::Stdin`], it
+    /// writes the message to stdin and closes it, then waits for the command to exit by itself, only killing it if the
+    /// grace period runs out. [`Cancellation::Terminate`] is the same as [`RunningCommand::shutdown`].
+    ///
+    /// The output is still available from [`RunningCommand::wait`] afterwards, with [`CmdOutput::shutdown`] saying
+    /// whether it exited by itself or was killed.
+    pub fn cancel(&mut self) -> io::Result<()> {
+        match self.cancellation.clone() {
+            Cancellation::Kill => {
+                self.kill_tree()?;
+                self.shutdown = Some(Shutdown::Killed);
+            }
+            Cancellation::Stdin { message, grace } => {
+                let give_up = Instant::now() + grace;
+                if let Some(mut stdin) = self.child.stdin.take() {
+                    // it might've already quit, or closed stdin itself
+                    match stdin.write_all(&message).and_then(|_| stdin.flush()) {
+                        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+                        _ => {}
+                    }
+                }
+                self.wait_or_kill(give_up.saturating_duration_since(Instant::now()))?;
+            }
+            Cancellation::Terminate { grace } => {
+                self.shutdown(grace)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Lets the command keep running if this handle is dropped, instead of killing it
+    pub fn detach_on_drop(&mut self) {
+        self.detach_on_drop = true;
+    }
+
+    /// Waits for the command to exit, returning its output (which *will* contain `Some(lines)`, not a None)
+    pub fn wait(mut self) -> Result<CmdOutput, Error> {
+        let (mut lines, polled_kill_reason) = self.read_polled()?;
+        let (status, kill_reason) = match polled_kill_reason {
+            Some(reason) => (self.reap()?, Some(reason)),
+            None => self.wait_for_exit()?,
+        };
+        self.exited = true;
+        // so there aren't any more once this returns
+        self.heartbeats.take();
+        let end = Instant::now();
+        #[cfg(unix)]
+        let kill_reason = kill_reason.or_else(|| {
+            let forwarded = self.forwarder.as_ref().is_some_and(|f| f.forwarded());
+            return forwarded.then_some(KillReason::Interrupted);
+        });
+
+        for reader in self.readers.drain(..) {
+            lines.append(&mut reader.join()?);
+        }
+        self.capture.flush_sinks();
+        lines.sort();
+
+        let mut output = CmdOutput::new(
+            self.command_line.clone(),
+            Some(lines),
+            status.code(),
+            self.start,
+            end,
+        );
+        output.set_exit_status(status);
+        output.kill_reason = kill_reason;
+        if kill_reason == Some(KillReason::PatternMatched) {
+            output.kill_match = self.capture.kill_switch.matched();
+        }
+        output.io_stats = self.io_stats;
+        output.resource_usage = self.resource_usage;
+        output.shutdown = self.shutdown;
+        output.limit_exceeded =
+            crate::rlimit::exceeded(&self.limits, output.signal, output.resource_usage);
+        output.truncated = self.capture.truncated.load(Ordering::Relaxed);
+        #[cfg(feature = "cgroups")]
+        {
+            output.cgroup_stats = self.cgroup.as_ref().map(|cgroup| cgroup.stats());
+        }
+        output.original_locale = self.original_locale.take();
+        #[cfg(feature = "tracing")]
+        if let Some(span) = &self.capture.span {
+            span.finished(&output);
+        }
+        output.envs_diff = std::mem::take(&mut self.envs_diff);
+        output.workdir = self.workdir.take();
+        for hooks in &self.capture.hooks {
+            hooks.exited(&output);
+        }
+        if let Some(audit) = &self.audit {
+            audit.record(&output);
+        }
+        #[cfg(feature = "metrics")]
+        crate::telemetry::record(&self.program, &output);
+        if output.kill_reason == Some(KillReason::LineTooLong) {
+            return Err(Error::LineTooLong(Box::new(output)));
+        }
+        return Ok(output);
+    }
+
+    /// Checks whether the child has exited, without waiting
+    pub(crate) fn has_exited(&mut self) -> io::Result<bool> {
+        return Ok(self.try_reap()?.is_some());
+    }
+
+    /// Whether [`RunningCommand::wait`] has anything to do yet: the command has exited, or needs killing for a timeout
+    /// or the like
+    pub(crate) fn ready_to_wait(&mut self) -> io::Result<bool> {
+        let out_of_time = self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline);
+        if out_of_time || self.capture.kill_switch.reason().is_some() {
+            return Ok(true);
+        }
+        return self.has_exited();
+    }
+
+    /// Whether every stream has been read until it was closed
+    pub(crate) fn readers_finished(&self) -> bool {
+        return self.readers.iter().all(|reader| match reader {
+            Reader::Thread(handle) => handle.is_finished(),
+            // only used by `CommandPool::run`, which waits for the command straight away
+            Reader::Pooled(_) => true,
+        });
+    }
+
+    /// With [`Backend::Poll`](crate::Backend::Poll), reads the output until it's closed, killing the child if it hits
+    /// the deadline or something reading the output asks for it
+    #[cfg(all(unix, feature = "poll"))]
+    fn read_polled(&mut self) -> io::Result<(Vec<Line>, Option<KillReason>)> {
+        let Some(streams) = self.polled.take() else {
+            return Ok((Vec::new(), None));
+        };
+        let capture = self.capture.clone();
+        let deadline = self.deadline;
+        // taken so it can be used while `self` is borrowed for cancelling
+        let mut forwarder = self.forwarder.take();
+        let read = crate::backend::read_polled(
+            streams,
+            &capture,
+            deadline.is_some() || capture.can_kill() || forwarder.is_some(),
+            |now| {
+                if let Some(forwarder) = &mut forwarder {
+                    // there's nowhere to report this, and the next check will try again
+                    let _ = forwarder.forward();
+                }
+                return match deadline {
+                    Some(deadline) if now >= deadline => Some(KillReason::Timeout),
+                    _ => capture.kill_switch.reason(),
+                };
+            },
+            || self.cancel(),
+        );
+        self.forwarder = forwarder;
+        return read;
+    }
+
+    #[cfg(not(all(unix, feature = "poll")))]
+    fn read_polled(&mut self) -> io::Result<(Vec<Line>, Option<KillReason>)> {
+        return Ok((Vec::new(), None));
+    }
+
+    /// Waits for the child to exit, killing it if it hits the deadline or something reading the output asks for it
+    fn wait_for_exit(&mut self) -> io::Result<(ExitStatus, Option<KillReason>)> {
+        #[cfg(unix)]
+        let forwarding = self.forwarder.is_some();
+        #[cfg(not(unix))]
+        let forwarding = false;
+        if self.deadline.is_none() && !self.capture.can_kill() && !forwarding {
+            return Ok((self.reap()?, None));
+        }
+
+        loop {
+            if let Some(status) = self.try_reap()? {
+                return Ok((status, None));
+            }
+            #[cfg(unix)]
+            if let Some(forwarder) = &mut self.forwarder {
+                forwarder.forward()?;
+            }
+
+            let now = Instant::now();
+            let reason = match self.deadline {
+                Some(deadline) if now >= deadline => Some(KillReason::Timeout),
+                _ => self.capture.kill_switch.reason(),
+            };
+            if let Some(reason) = reason {
+                self.cancel()?;
+                return Ok((self.reap()?, Some(reason)));
+            }
+
+            let mut sleep_for = POLL_INTERVAL;
+            if let Some(deadline) = self.deadline {
+                sleep_for = sleep_for.min(deadline - now);
+            }
+            thread::sleep(sleep_for);
+        }
+    }
+
+    /// Waits for the child to exit, collecting anything that has to be read before it's reaped
+    fn reap(&mut self) -> io::Result<ExitStatus> {
+        if let Some(status) = self.reaped {
+            return Ok(status);
+        }
+        #[cfg(target_os = "linux")]
+        if self.collect_io_stats && self.io_stats.is_none() {
+            crate::io_stats::wait_exited(self.id(), true)?;
+            self.io_stats = crate::io_stats::read(self.id()).ok();
+        }
+        #[cfg(unix)]
+        {
+            let (status, usage) = crate::rusage::wait4(self.id(), true)?.unwrap();
+            self.reaped = Some(status);
+            self.resource_usage = Some(usage);
+            return Ok(status);
+        }
+        #[cfg(not(unix))]
+        {
+            let status = self.child.wait()?;
+            self.reaped = Some(status);
+            return Ok(status);
+        }
+    }
+
+    /// Same as [`RunningCommand::reap`], but returns `None` instead of waiting if the child hasn't exited yet
+    fn try_reap(&mut self) -> io::Result<Option<ExitStatus>> {
+        if self.reaped.is_some() {
+            return Ok(self.reaped);
+        }
+        #[cfg(target_os = "linux")]
+        if self.collect_io_stats && self.io_stats.is_none() {
+            if !crate::io_stats::wait_exited(self.id(), false)? {
+                return Ok(None);
+            }
+            self.io_stats = crate::io_stats::read(self.id()).ok();
+        }
+        #[cfg(unix)]
+        {
+            let Some((status, usage)) = crate::rusage::wait4(self.id(), false)? else {
+                return Ok(None);
+            };
+            self.reaped = Some(status);
+            self.resource_usage = Some(usage);
+            return Ok(Some(status));
+        }
+        #[cfg(not(unix))]
+        {
+            self.reaped = self.child.try_wait()?;
+            return Ok(self.reaped);
+        }
+    }
+}
+
+/// Something reading one of the command's streams in the background
+#[derive(Debug)]
+pub(crate) enum Reader {
+    Thread(JoinHandle<io::Result<Vec<Line>>>),
+    /// A job on a [`CommandPool`](crate::CommandPool)'s thread, which sends the lines back once it's done
+    Pooled(Receiver<io::Result<Vec<Line>>>),
+}
+
+impl Reader {
+    /// Waits for the stream to be closed, returning the lines read from it
+    fn join(self) -> io::Result<Vec<Line>> {
+        return match self {
+            Reader::Thread(handle) => handle.join().unwrap(),
+            Reader::Pooled(receiver) => receiver.recv().unwrap(),
+        };
+    }
+}
+
+impl Drop for RunningCommand {
+    fn drop(&mut self) {
+        if self.exited || self.detach_on_drop {
+            return;
+        }
+        // the reader threads aren't joined, since anything else still holding the pipes open would block this forever
+        let _ = self.kill_tree();
+        let _ = self.reap();
+    }
+}
@@ -0,0 +1,70 @@
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+#[cfg(unix)]
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// How much CPU time and memory a command used, from `wait4(2)` (see [`CmdOutput::resource_usage`](crate::CmdOutput::resource_usage))
+///
+/// Like [`IoStats`](crate::IoStats), these only cover the command's own process, not anything it started (unless it
+/// waited for them itself).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResourceUsage {
+    /// CPU time spent running the command's own code
+    pub user_time: Duration,
+    /// CPU time spent in the kernel on the command's behalf
+    pub system_time: Duration,
+    /// The most memory the command had in RAM at once (its peak resident set size), in bytes
+    pub max_rss: u64,
+}
+
+#[cfg(unix)]
+impl ResourceUsage {
+    fn from_rusage(usage: &libc::rusage) -> ResourceUsage {
+        let time = |time: libc::timeval| {
+            return Duration::from_secs(time.tv_sec as u64)
+                + Duration::from_micros(time.tv_usec as u64);
+        };
+        // Linux reports this in kilobytes, macOS in bytes
+        let max_rss = if cfg!(target_vendor = "apple") {
+            usage.ru_maxrss as u64
+        } else {
+            usage.ru_maxrss as u64 * 1024
+        };
+        return ResourceUsage {
+            user_time: time(usage.ru_utime),
+            system_time: time(usage.ru_stime),
+            max_rss,
+        };
+    }
+}
+
+/// Reaps the process with `wait4`, returning its status and resource usage, or `None` if `block` is false and it's
+/// still running
+#[cfg(unix)]
+pub(crate) fn wait4(pid: u32, block: bool) -> io::Result<Option<(ExitStatus, ResourceUsage)>> {
+    let options = if block { 0 } else { libc::WNOHANG };
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        match unsafe { libc::wait4(pid as libc::pid_t, &mut status, options, &mut usage) } {
+            -1 => {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            0 => return Ok(None),
+            _ => {
+                return Ok(Some((
+                    ExitStatus::from_raw(status),
+                    ResourceUsage::from_rusage(&usage),
+                )))
+            }
+        }
+    }
+}
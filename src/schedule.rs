@@ -0,0 +1,270 @@
+//! Running commands regularly
+use crate::{Backend, CmdOutput, Error, Runner, RunningCommand};
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub(crate) type Handler = Box<dyn FnMut(Result<CmdOutput, Error>) + Send>;
+
+/// How often a run is checked on to see whether it's finished
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What a [`Schedule`] does when it's time to run the command, but the last run hasn't finished yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overlap {
+    /// Skips this run
+    #[default]
+    Skip,
+    /// Runs it as soon as the last run finishes
+    ///
+    /// <small>Only one run is queued at a time, so a command which always takes longer than the interval just runs
+    /// back to back</small>
+    Queue,
+    /// Cancels the last run (see [`RunningCommand::cancel`]) and starts a new one
+    KillPrevious,
+}
+
+/// Runs a command over and over, at a fixed interval, e.g. for health checks
+///
+/// The command is run straight away, then every interval after that (measured from when the schedule started, so
+/// it doesn't drift). Only one run happens at a time; see [`Overlap`] for what happens when a run takes longer than
+/// the interval.
+///
+/// ```
+/// use better_commands::Schedule;
+/// use std::process::Command;
+/// use std::sync::{Arc, Mutex};
+/// use std::time::Duration;
+///
+/// let outputs = Arc::new(Mutex::new(Vec::new()));
+/// let recorded = outputs.clone();
+/// let schedule = Schedule::every(Duration::from_millis(100)).run(Command::new("true"), move |output| {
+///     recorded.lock().unwrap().push(output.unwrap());
+/// });
+///
+/// std::thread::sleep(Duration::from_millis(250));
+/// let runs = schedule.runs();
+/// schedule.stop();
+/// // it runs at 0ms, 100ms, and 200ms
+/// assert!(runs >= 2);
+/// assert_eq!(runs as usize, outputs.lock().unwrap().len());
+/// ```
+///
+/// <small>Commands are always run with [`Backend::Threads`], since the schedule needs to check on them while they're
+/// running.</small>
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    interval: Duration,
+    runner: Runner,
+    overlap: Overlap,
+}
+
+impl Schedule {
+    /// Creates a schedule running a command every `interval`
+    ///
+    /// Panics if `interval` is 0.
+    pub fn every(interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "a Schedule's interval can't be 0");
+        return Schedule {
+            interval,
+            runner: Runner::new().backend(Backend::Threads).retries(0),
+            overlap: Overlap::default(),
+        };
+    }
+
+    /// Sets the options to run the command with, e.g. a timeout for each run
+    ///
+    /// <small>Its retries aren't used</small>
+    pub fn runner(mut self, runner: Runner) -> Self {
+        self.runner = runner.backend(Backend::Threads).retries(0);
+        return self;
+    }
+
+    /// Sets what to do when the last run hasn't finished yet ([`Overlap::Skip`] by default)
+    pub fn overlap(mut self, overlap: Overlap) -> Self {
+        self.overlap = overlap;
+        return self;
+    }
+
+    /// Starts running `command` in the background, calling `handler` with the output of each run
+    ///
+    /// `handler` gets an error if the command couldn't be started, and is called from the thread running the schedule,
+    /// so it shouldn't take too long. This keeps going until [`ScheduleHandle::stop`] is called, or the handle is
+    /// dropped.
+    pub fn run(
+        self,
+        command: Command,
+        handler: impl FnMut(Result<CmdOutput, Error>) + Send + 'static,
+    ) -> ScheduleHandle {
+        let (stop, stopped) = mpsc::channel();
+        let runs = Runs::new(command, self.runner.clone(), Box::new(handler));
+        let count = runs.count.clone();
+        let mut scheduled = Scheduled {
+            schedule: self,
+            runs,
+            queued: false,
+        };
+        let thread = thread::spawn(move || scheduled.run(&stopped));
+        return ScheduleHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+            runs: count,
+        };
+    }
+}
+
+/// A running [`Schedule`], which stops when this is dropped
+#[derive(Debug)]
+pub struct ScheduleHandle {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    runs: Arc<AtomicU32>,
+}
+
+impl ScheduleHandle {
+    /// Stops running the command, cancelling the current run if there is one
+    ///
+    /// This waits for the current run to finish being cancelled, and for the handler to get its output.
+    pub fn stop(mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            if let Err(panic) = thread.join() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+    }
+
+    /// Returns how many times the command has been run (or tried to be) so far
+    pub fn runs(&self) -> u32 {
+        return self.runs.load(Ordering::SeqCst);
+    }
+}
+
+impl Drop for ScheduleHandle {
+    /// Stops running the command, like [`ScheduleHandle::stop`]
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Everything the thread running the schedule needs
+struct Scheduled {
+    schedule: Schedule,
+    runs: Runs,
+    /// Set with [`Overlap::Queue`] when a run was due while the last one was still going
+    queued: bool,
+}
+
+impl Scheduled {
+    /// Keeps running the command until `stopped` gets a message or disconnects
+    fn run(&mut self, stopped: &Receiver<()>) {
+        let mut next = Instant::now();
+        loop {
+            let now = Instant::now();
+            if now >= next {
+                // any runs missed while this was busy are just skipped
+                while next <= now {
+                    next += self.schedule.interval;
+                }
+                if !self.runs.is_running() {
+                    self.runs.start();
+                } else {
+                    match self.schedule.overlap {
+                        Overlap::Skip => {}
+                        Overlap::Queue => self.queued = true,
+                        Overlap::KillPrevious => {
+                            self.runs.finish(true);
+                            self.runs.start();
+                        }
+                    }
+                }
+            }
+
+            if self.runs.finish_if_done() && self.queued {
+                self.queued = false;
+                self.runs.start();
+            }
+
+            let until_next = next.saturating_duration_since(Instant::now());
+            let timeout = match self.runs.is_running() {
+                true => until_next.min(POLL_INTERVAL),
+                false => until_next,
+            };
+            if stopped.recv_timeout(timeout) != Err(RecvTimeoutError::Timeout) {
+                self.runs.finish(true);
+                return;
+            }
+        }
+    }
+}
+
+/// Runs a command in the background one run at a time, passing the output of each run to a handler
+///
+/// This is shared by [`Schedule`] and `Watch`, which just decide when to start each run.
+pub(crate) struct Runs {
+    command: Command,
+    runner: Runner,
+    handler: Handler,
+    /// How many runs have been started
+    pub(crate) count: Arc<AtomicU32>,
+    current: Option<RunningCommand>,
+}
+
+impl Runs {
+    pub(crate) fn new(command: Command, runner: Runner, handler: Handler) -> Self {
+        return Runs {
+            command,
+            runner,
+            handler,
+            count: Arc::default(),
+            current: None,
+        };
+    }
+
+    /// Whether a run has been started and not finished yet
+    pub(crate) fn is_running(&self) -> bool {
+        return self.current.is_some();
+    }
+
+    /// Starts a run, passing the error to the handler if it can't be
+    pub(crate) fn start(&mut self) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        match self.runner.spawn(&mut self.command) {
+            Ok(running) => self.current = Some(running),
+            Err(e) => (self.handler)(Err(e)),
+        }
+    }
+
+    /// Finishes the current run if it's exited (or timed out or the like), returning whether it did
+    pub(crate) fn finish_if_done(&mut self) -> bool {
+        let done = self
+            .current
+            .as_mut()
+            .is_some_and(|running| running.ready_to_wait().unwrap_or(true));
+        if done {
+            self.finish(false);
+        }
+        return done;
+    }
+
+    /// Waits for the current run, if there is one, and passes its output to the handler
+    pub(crate) fn finish(&mut self, cancel: bool) {
+        let Some(mut running) = self.current.take() else {
+            return;
+        };
+        if cancel {
+            // it's killed when it's dropped anyway, if this doesn't work
+            if let Err(e) = running.cancel() {
+                (self.handler)(Err(e.into()));
+                return;
+            }
+        }
+        (self.handler)(running.wait());
+    }
+}
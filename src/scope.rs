@@ -0,0 +1,100 @@
+use crate::{CmdOutput, Error, Runner, RunningCommand};
+use std::io;
+use std::process::Command;
+use std::sync::Mutex;
+
+/// Runs `f` with a [`CommandScope`], making sure every command spawned in it has exited before returning
+///
+/// This works like [`std::thread::scope`], but for commands: once `f` returns (or panics), any command spawned in the
+/// scope that hasn't been waited for is killed (along with its process group, see [`Runner::process_group`]) and
+/// reaped. The handles can't be moved out of the scope, so a function using one can't leak its helpers.
+///
+/// ```
+/// use better_commands::{scope, Runner};
+/// use std::process::Command;
+///
+/// let runner = Runner::new();
+/// let output = scope(|s| {
+///     // this is killed when the scope ends, since it's never waited for
+///     s.spawn(&runner, Command::new("sleep").arg("60")).unwrap();
+///
+///     let echo = s.spawn(&runner, Command::new("echo").arg("hi")).unwrap();
+///     return echo.wait().unwrap();
+/// });
+/// assert_eq!("hi", output.lines().unwrap()[0].content);
+/// ```
+pub fn scope<T>(f: impl FnOnce(&CommandScope) -> T) -> T {
+    let scope = CommandScope {
+        commands: Mutex::new(Vec::new()),
+    };
+    // if `f` panics, dropping `scope` still cleans up
+    return f(&scope);
+}
+
+/// A scope to spawn commands in, from [`scope`]
+#[derive(Debug)]
+pub struct CommandScope {
+    /// Each command spawned in the scope, which is taken out once it's been waited for
+    commands: Mutex<Vec<Option<RunningCommand>>>,
+}
+
+impl CommandScope {
+    /// Spawns `command` with `runner` (see [`Runner::spawn`]), tying it to this scope
+    pub fn spawn(
+        &self,
+        runner: &Runner,
+        command: &mut Command,
+    ) -> Result<ScopedCommand<'_>, Error> {
+        let running = runner.spawn(command)?;
+        let id = running.id();
+        let mut commands = self.commands.lock().unwrap();
+        commands.push(Some(running));
+        return Ok(ScopedCommand {
+            scope: self,
+            index: commands.len() - 1,
+            id,
+        });
+    }
+}
+
+impl Drop for CommandScope {
+    fn drop(&mut self) {
+        let commands = self.commands.get_mut().unwrap_or_else(|e| e.into_inner());
+        // dropping a `RunningCommand` kills and reaps it
+        commands.clear();
+    }
+}
+
+/// A handle to a command running in a [`CommandScope`]
+#[derive(Debug)]
+pub struct ScopedCommand<'scope> {
+    scope: &'scope CommandScope,
+    index: usize,
+    id: u32,
+}
+
+impl ScopedCommand<'_> {
+    /// Returns the OS-assigned process ID of the command
+    pub fn id(&self) -> u32 {
+        return self.id;
+    }
+
+    /// Kills the command (see [`RunningCommand::kill_tree`])
+    ///
+    /// The output is still available from [`ScopedCommand::wait`] afterwards.
+    pub fn kill(&self) -> io::Result<()> {
+        let mut commands = self.scope.commands.lock().unwrap();
+        return match commands[self.index].as_mut() {
+            Some(running) => running.kill_tree(),
+            None => Ok(()),
+        };
+    }
+
+    /// Waits for the command to exit, returning its output (see [`RunningCommand::wait`])
+    pub fn wait(self) -> Result<CmdOutput, Error> {
+        let running = self.scope.commands.lock().unwrap()[self.index]
+            .take()
+            .unwrap();
+        return running.wait();
+    }
+}
@@ -0,0 +1,87 @@
+//! Searching through captured lines
+use crate::{CmdOutput, Line, LineType, Pattern};
+use regex::{Captures, Regex};
+
+impl CmdOutput {
+    /// Whether any line contains `needle`
+    pub fn contains<S: AsRef<str>>(&self, needle: S) -> bool {
+        return self
+            .iter()
+            .any(|line| line.content.contains(needle.as_ref()));
+    }
+
+    /// Whether any line printed to `stream` contains `needle`
+    pub fn contains_in<S: AsRef<str>>(&self, stream: LineType, needle: S) -> bool {
+        return self
+            .stream(stream)
+            .into_iter()
+            .any(|line| line.content.contains(needle.as_ref()));
+    }
+
+    /// Returns every line matching `pattern` (text to look for, or a [`Regex`]), in the order they were printed
+    ///
+    /// ```
+    /// use better_commands::{regex::Regex, run, LineType};
+    /// use std::process::Command;
+    ///
+    /// let output = run(Command::new("bash").arg("-c").arg("echo 'warning: a'; echo ok; sleep 0.1; >&2 echo 'warning: b'"));
+    /// assert_eq!(2, output.grep("warning").len());
+    /// assert_eq!(1, output.grep_in(LineType::Stderr, "warning").len());
+    ///
+    /// let versions = Regex::new(r"(\w+): (\w+)").unwrap();
+    /// let names: Vec<&str> = output
+    ///     .capture_all(&versions)
+    ///     .iter()
+    ///     .map(|captures| captures.get(2).unwrap().as_str())
+    ///     .collect();
+    /// assert_eq!(vec!["a", "b"], names);
+    /// ```
+    pub fn grep<P: Into<Pattern>>(&self, pattern: P) -> Vec<&Line> {
+        let pattern = pattern.into();
+        return self
+            .iter()
+            .filter(|line| pattern.is_match(&line.content))
+            .collect();
+    }
+
+    /// Returns every line printed to `stream` matching `pattern`
+    pub fn grep_in<P: Into<Pattern>>(&self, stream: LineType, pattern: P) -> Vec<&Line> {
+        let pattern = pattern.into();
+        return self
+            .stream(stream)
+            .into_iter()
+            .filter(|line| pattern.is_match(&line.content))
+            .collect();
+    }
+
+    /// Returns the captures for every match of `regex`, in the order they were printed
+    ///
+    /// <small>Each line is searched on its own, so matches can't span lines</small>
+    pub fn capture_all<'a>(&'a self, regex: &Regex) -> Vec<Captures<'a>> {
+        return self
+            .iter()
+            .flat_map(|line| regex.captures_iter(&line.content))
+            .collect();
+    }
+
+    /// Returns the captures for every match of `regex` in lines printed to `stream`
+    pub fn capture_all_in<'a>(&'a self, stream: LineType, regex: &Regex) -> Vec<Captures<'a>> {
+        return self
+            .stream(stream)
+            .into_iter()
+            .flat_map(|line| regex.captures_iter(&line.content))
+            .collect();
+    }
+
+    fn stream(&self, stream: LineType) -> Vec<&Line> {
+        return match stream {
+            LineType::Stdout => self.stdout().unwrap_or_default().iter().collect(),
+            LineType::Stderr => self.stderr().unwrap_or_default().iter().collect(),
+            other => self
+                .other_lines
+                .iter()
+                .filter(|line| line.printed_to == other)
+                .collect(),
+        };
+    }
+}
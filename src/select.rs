@@ -0,0 +1,55 @@
+//! Picking out parts of the captured lines, e.g. the last few before the command exited
+use crate::{CmdOutput, Line};
+use std::time::{Duration, Instant};
+
+impl CmdOutput {
+    /// Returns the first `n` lines printed, to either stream
+    pub fn head(&self, n: usize) -> Vec<&Line> {
+        return self.iter().take(n).collect();
+    }
+
+    /// Returns the last `n` lines printed, to either stream
+    ///
+    /// ```
+    /// use better_commands::run;
+    /// use std::process::Command;
+    ///
+    /// let output = run(Command::new("seq").arg("100"));
+    /// let last: Vec<&str> = output.tail(2).iter().map(|line| line.content.as_str()).collect();
+    /// assert_eq!(vec!["99", "100"], last);
+    /// ```
+    pub fn tail(&self, n: usize) -> Vec<&Line> {
+        let skip = self.len().saturating_sub(n);
+        return self.iter().skip(skip).collect();
+    }
+
+    /// Returns the lines printed from `start` to `end`, including both
+    pub fn lines_between(&self, start: Instant, end: Instant) -> Vec<&Line> {
+        return self
+            .iter()
+            .filter(|line| line.time >= start && line.time <= end)
+            .collect();
+    }
+
+    /// Returns the lines printed from `start` to `end` after the command started, including both
+    ///
+    /// ```
+    /// use better_commands::run;
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    ///
+    /// let output = run(Command::new("bash").arg("-c").arg("echo early; sleep 1; echo late"));
+    ///
+    /// // everything in the last half second (the last lines can be read just after it exits)
+    /// let duration = output.clone().duration();
+    /// let last = output.lines_between_offsets(duration.saturating_sub(Duration::from_millis(500)), Duration::MAX);
+    /// assert_eq!(1, last.len());
+    /// assert_eq!("late", last[0].content);
+    /// ```
+    pub fn lines_between_offsets(&self, start: Duration, end: Duration) -> Vec<&Line> {
+        return self
+            .iter()
+            .filter(|line| line.offset >= start && line.offset <= end)
+            .collect();
+    }
+}
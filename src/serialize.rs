@@ -0,0 +1,261 @@
+//! [`serde`] support for [`CmdOutput`]
+//!
+//! [`Instant`]s can't be serialized, so times are stored relative to when the command started. When loading, the start
+//! time becomes the time it was loaded at, so the times in a loaded [`CmdOutput`] are only meaningful relative to each other.
+use crate::workdir::TempDir;
+use crate::{
+    CmdOutput, EnvChange, IoStats, KillReason, Line, LineType, ResourceUsage, Rlimit, Severity,
+    Shutdown,
+};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The version of the format [`CmdOutput`] is serialized in
+///
+/// This is stored alongside the output, so outputs written by older versions of this crate can still be loaded.
+/// It's bumped whenever the format changes in a way that needs migrating.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The steps for upgrading output from each older version to the next, starting from version 1
+///
+/// When bumping [`SCHEMA_VERSION`], add a step here converting the JSON from the previous version, e.g. renaming a
+/// field.
+const MIGRATIONS: &[fn(&mut Value)] = &[];
+// a step can't be forgotten when bumping the version
+const _: () = assert!(MIGRATIONS.len() as u32 == SCHEMA_VERSION - 1);
+
+/// Upgrades output written in an older format to the current one, including its failed attempts
+fn migrate(mut value: Value) -> Result<Value, String> {
+    let version = value
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .ok_or("missing schema version")?;
+    if version > SCHEMA_VERSION as u64 {
+        return Err(format!(
+            "output was written with schema version {}, but only versions up to {} are supported",
+            version, SCHEMA_VERSION
+        ));
+    }
+    if version == 0 {
+        return Err("invalid schema version 0".to_string());
+    }
+    for step in &MIGRATIONS[version as usize - 1..] {
+        step(&mut value);
+    }
+    value["schema_version"] = SCHEMA_VERSION.into();
+
+    if let Some(attempts) = value
+        .get_mut("failed_attempts")
+        .and_then(Value::as_array_mut)
+    {
+        for attempt in attempts {
+            *attempt = migrate(attempt.take())?;
+        }
+    }
+    return Ok(value);
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedCmdOutput {
+    schema_version: u32,
+    command: String,
+    lines: Option<Vec<SerializedLine>>,
+    #[serde(default = "default_sorted", skip_serializing_if = "is_sorted")]
+    sorted: bool,
+    status_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signal: Option<i32>,
+    duration: Duration,
+    kill_reason: Option<KillReason>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kill_match: Option<SerializedLine>,
+    failed_attempts: Vec<SerializedCmdOutput>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    io_stats: Option<IoStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resource_usage: Option<ResourceUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shutdown: Option<Shutdown>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    limit_exceeded: Option<Rlimit>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+    #[cfg(feature = "cgroups")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cgroup_stats: Option<crate::CgroupStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    original_locale: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    envs_diff: Vec<EnvChange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    workdir: Option<PathBuf>,
+}
+
+fn default_sorted() -> bool {
+    return true;
+}
+
+fn is_sorted(sorted: &bool) -> bool {
+    return *sorted;
+}
+
+fn default_terminated() -> bool {
+    return true;
+}
+
+fn is_terminated(terminated: &bool) -> bool {
+    return *terminated;
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedLine {
+    printed_to: LineType,
+    offset: Duration,
+    #[serde(default)]
+    seq: u64,
+    // old outputs without these are numbered when they're loaded
+    #[serde(default)]
+    line_number: u64,
+    #[serde(default)]
+    global_index: u64,
+    content: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+    #[serde(default = "default_terminated", skip_serializing_if = "is_terminated")]
+    terminated: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    severity: Option<Severity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw: Option<Vec<u8>>,
+}
+
+impl SerializedLine {
+    fn from_line(line: &Line) -> Self {
+        return SerializedLine {
+            printed_to: line.printed_to.clone(),
+            offset: line.offset,
+            seq: line.seq,
+            line_number: line.line_number,
+            global_index: line.global_index,
+            content: line.content.clone(),
+            truncated: line.truncated,
+            terminated: line.terminated,
+            annotations: line.annotations.clone(),
+            severity: line.severity(),
+            raw: line.raw.clone(),
+        };
+    }
+
+    fn into_line(self, start_time: Instant) -> Line {
+        let mut line = Line::new(
+            self.printed_to,
+            start_time + self.offset,
+            self.seq,
+            self.content,
+        );
+        line.offset = self.offset;
+        line.line_number = self.line_number;
+        line.global_index = self.global_index;
+        line.truncated = self.truncated;
+        line.terminated = self.terminated;
+        line.annotations = self.annotations;
+        line.severity = self.severity;
+        line.raw = self.raw;
+        return line;
+    }
+}
+
+impl SerializedCmdOutput {
+    fn from_output(output: &CmdOutput) -> Self {
+        return SerializedCmdOutput {
+            schema_version: SCHEMA_VERSION,
+            command: output.command.clone(),
+            lines: output
+                .stdout
+                .as_ref()
+                .map(|_| output.iter().map(SerializedLine::from_line).collect()),
+            sorted: output.sorted,
+            status_code: output.status_code,
+            signal: output.signal,
+            duration: output.duration,
+            kill_reason: output.kill_reason,
+            kill_match: output.kill_match.as_ref().map(SerializedLine::from_line),
+            failed_attempts: output
+                .failed_attempts
+                .iter()
+                .map(SerializedCmdOutput::from_output)
+                .collect(),
+            io_stats: output.io_stats,
+            resource_usage: output.resource_usage,
+            shutdown: output.shutdown,
+            limit_exceeded: output.limit_exceeded,
+            truncated: output.truncated,
+            #[cfg(feature = "cgroups")]
+            cgroup_stats: output.cgroup_stats,
+            original_locale: output.original_locale.clone(),
+            envs_diff: output.envs_diff.clone(),
+            workdir: output.workdir().map(|dir| dir.to_path_buf()),
+        };
+    }
+
+    fn into_output(self, start_time: Instant) -> CmdOutput {
+        let lines = self.lines.map(|lines| {
+            lines
+                .into_iter()
+                .map(|line| line.into_line(start_time))
+                .collect()
+        });
+        let mut output = CmdOutput::with_order(
+            self.command,
+            lines,
+            self.sorted,
+            self.status_code,
+            start_time,
+            start_time + self.duration,
+        );
+        output.signal = self.signal;
+        output.kill_reason = self.kill_reason;
+        output.kill_match = self.kill_match.map(|line| line.into_line(start_time));
+        output.io_stats = self.io_stats;
+        output.resource_usage = self.resource_usage;
+        output.shutdown = self.shutdown;
+        output.limit_exceeded = self.limit_exceeded;
+        output.truncated = self.truncated;
+        #[cfg(feature = "cgroups")]
+        {
+            output.cgroup_stats = self.cgroup_stats;
+        }
+        output.original_locale = self.original_locale;
+        output.envs_diff = self.envs_diff;
+        // a loaded output doesn't own the directory, so it's never removed
+        output.workdir = self.workdir.map(|dir| Arc::new(TempDir::existing(dir)));
+        output.failed_attempts = self
+            .failed_attempts
+            .into_iter()
+            .map(|attempt| attempt.into_output(start_time))
+            .collect();
+        return output;
+    }
+}
+
+impl Serialize for CmdOutput {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return SerializedCmdOutput::from_output(self).serialize(serializer);
+    }
+}
+
+impl<'de> Deserialize<'de> for CmdOutput {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // the version has to be checked before the fields are, since they may have been renamed or changed since
+        let value = migrate(Value::deserialize(deserializer)?).map_err(D::Error::custom)?;
+        return Ok(SerializedCmdOutput::deserialize(value)
+            .map_err(D::Error::custom)?
+            .into_output(Instant::now()));
+    }
+}
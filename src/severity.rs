@@ -0,0 +1,130 @@
+//! Tagging stderr lines as errors, warnings, or notes, for build tools and the like
+use crate::{CmdOutput, Line};
+use regex::Regex;
+use std::fmt;
+
+/// How serious a line is, from [`Line::severity`]
+///
+/// These are ordered from least to most serious, so the worst of several can be found with [`Iterator::max`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Severity {
+    /// Extra information, like a compiler's `note:` or `help:`
+    Note,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    /// Writes `note`, `warning`, or `error`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Severity::Note => write!(f, "note"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        };
+    }
+}
+
+/// Rules for working out the [`Severity`] of each line a command prints to stderr, given to
+/// [`Runner::classify_severity`](crate::Runner::classify_severity)
+///
+/// Each rule is a regex; a line gets the severity of the first rule that matches it, or none if nothing does.
+///
+/// ```
+/// use better_commands::regex::Regex;
+/// use better_commands::{Severity, SeverityRules};
+///
+/// let rules = SeverityRules::defaults().rule(Regex::new(r"^FATAL\b").unwrap(), Severity::Error);
+///
+/// assert_eq!(Some(Severity::Error), rules.classify("main.c:3:5: error: expected ';'"));
+/// assert_eq!(Some(Severity::Warning), rules.classify("warning: unused variable: `x`"));
+/// assert_eq!(Some(Severity::Error), rules.classify("FATAL out of memory"));
+/// assert_eq!(None, rules.classify("Compiling foo v0.1.0"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SeverityRules {
+    rules: Vec<(Regex, Severity)>,
+}
+
+impl SeverityRules {
+    /// Creates an empty set of rules, which doesn't tag anything
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Creates a set of rules for the messages printed by common tools:
+    ///
+    /// - gcc, clang, and rustc: `error:`, `warning:`, and `note:`, with or without a `file:line:column: ` before them
+    ///   (or an error code like `error[E0425]:`), and rustc's `= note:` and `= help:`
+    /// - Python: `Traceback (most recent call last):`, the exception at the end of one (e.g. `KeyError: 'x'`), and
+    ///   warnings (e.g. `script.py:3: DeprecationWarning: ...`)
+    pub fn defaults() -> Self {
+        // `file:line: ` or `file:line:column: `, from gcc and clang
+        let location = r"(?:[^:\s]+:\d+:(?:\d+:)? )?";
+        return SeverityRules::new()
+            .rule(
+                Regex::new(&format!(r"^{}(?:fatal )?error(?:\[\w+\])?:", location)).unwrap(),
+                Severity::Error,
+            )
+            .rule(
+                Regex::new(&format!(r"^{}warning(?:\[\w+\])?:", location)).unwrap(),
+                Severity::Warning,
+            )
+            .rule(
+                Regex::new(&format!(r"^(?:{}note|\s*= (?:note|help)):", location)).unwrap(),
+                Severity::Note,
+            )
+            .rule(
+                Regex::new(r"^Traceback \(most recent call last\):").unwrap(),
+                Severity::Error,
+            )
+            .rule(
+                Regex::new(r"^[A-Za-z_][\w.]*(?:Error|Exception)(?::|$)").unwrap(),
+                Severity::Error,
+            )
+            .rule(
+                Regex::new(r"^\S+:\d+: \w*Warning: ").unwrap(),
+                Severity::Warning,
+            );
+    }
+
+    /// Adds a rule, tagging lines matching `pattern` with `severity` (unless an earlier rule matches them first)
+    pub fn rule(mut self, pattern: Regex, severity: Severity) -> Self {
+        self.rules.push((pattern, severity));
+        return self;
+    }
+
+    /// Returns the severity of the first rule matching `text`, if any do
+    pub fn classify(&self, text: &str) -> Option<Severity> {
+        return self
+            .rules
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(text))
+            .map(|(_, severity)| *severity);
+    }
+}
+
+impl CmdOutput {
+    /// Returns every line tagged as an error by [`Runner::classify_severity`](crate::Runner::classify_severity), in the
+    /// order they were printed
+    ///
+    /// ```
+    /// use better_commands::{Runner, SeverityRules};
+    /// use std::process::Command;
+    ///
+    /// let output = Runner::new()
+    ///     .classify_severity(SeverityRules::defaults())
+    ///     .run(Command::new("bash").arg("-c").arg("echo 'warning: unused' >&2; echo 'error: oops' >&2"))
+    ///     .unwrap();
+    ///
+    /// assert_eq!(1, output.errors().len());
+    /// assert_eq!("error: oops", output.errors()[0].content);
+    /// ```
+    pub fn errors(&self) -> Vec<&Line> {
+        return self
+            .iter()
+            .filter(|line| line.severity() == Some(Severity::Error))
+            .collect();
+    }
+}
@@ -0,0 +1,124 @@
+//! Sending signals to commands, and forwarding the ones this process gets to them (Unix only)
+use std::io;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// The signals forwarded by [`Runner::forward_signals`](crate::Runner::forward_signals)
+const FORWARDED: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+/// The last signal this process got, and how many it's gotten, set by [`handle`]
+static LAST_SIGNAL: AtomicI32 = AtomicI32::new(0);
+static RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+/// How many commands are forwarding signals, and the handlers to put back once none are
+static HANDLERS: Mutex<(usize, Vec<(libc::c_int, libc::sigaction)>)> = Mutex::new((0, Vec::new()));
+
+extern "C" fn handle(signal: libc::c_int) {
+    // only atomics are safe to use in a signal handler
+    LAST_SIGNAL.store(signal, Ordering::SeqCst);
+    RECEIVED.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Sends `signal` to `pid`, or its whole process group if `group` is set (in which case it has to be the leader)
+pub(crate) fn send(pid: u32, group: bool, signal: libc::c_int) -> io::Result<()> {
+    let result = match group {
+        true => unsafe { libc::killpg(pid as libc::pid_t, signal) },
+        false => unsafe { libc::kill(pid as libc::pid_t, signal) },
+    };
+    if result == -1 {
+        let e = io::Error::last_os_error();
+        // it (or the whole group) has already exited
+        if e.raw_os_error() != Some(libc::ESRCH) {
+            return Err(e);
+        }
+    }
+    return Ok(());
+}
+
+/// Keeps this process's `SIGINT` and `SIGTERM` handlers replaced with [`handle`] while it exists
+#[derive(Debug)]
+pub(crate) struct Handlers {
+    /// How many signals had been received when these were installed
+    received: u64,
+}
+
+impl Handlers {
+    /// Installs the handlers, if they aren't already
+    pub(crate) fn install() -> io::Result<Handlers> {
+        let mut handlers = HANDLERS.lock().unwrap();
+        if handlers.0 == 0 {
+            for signal in FORWARDED {
+                let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+                action.sa_sigaction = handle as *const () as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                let mut previous: libc::sigaction = unsafe { std::mem::zeroed() };
+                unsafe { libc::sigemptyset(&mut action.sa_mask) };
+                if unsafe { libc::sigaction(signal, &action, &mut previous) } == -1 {
+                    let e = io::Error::last_os_error();
+                    restore(&mut handlers.1);
+                    return Err(e);
+                }
+                handlers.1.push((signal, previous));
+            }
+        }
+        handlers.0 += 1;
+        return Ok(Handlers {
+            received: RECEIVED.load(Ordering::SeqCst),
+        });
+    }
+}
+
+impl Drop for Handlers {
+    fn drop(&mut self) {
+        let mut handlers = HANDLERS.lock().unwrap();
+        handlers.0 -= 1;
+        if handlers.0 == 0 {
+            restore(&mut handlers.1);
+        }
+    }
+}
+
+fn restore(previous: &mut Vec<(libc::c_int, libc::sigaction)>) {
+    for (signal, action) in previous.drain(..) {
+        unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) };
+    }
+}
+
+/// Forwards signals this process gets to a running command
+#[derive(Debug)]
+pub(crate) struct Forwarder {
+    pid: u32,
+    group: bool,
+    seen: u64,
+    forwarded: bool,
+    _handlers: Handlers,
+}
+
+impl Forwarder {
+    pub(crate) fn new(handlers: Handlers, pid: u32, group: bool) -> Forwarder {
+        return Forwarder {
+            pid,
+            group,
+            // anything received since they were installed (e.g. while it was starting) still gets forwarded
+            seen: handlers.received,
+            forwarded: false,
+            _handlers: handlers,
+        };
+    }
+
+    /// Sends the command the last signal this process got, if it's gotten any since this was last called
+    pub(crate) fn forward(&mut self) -> io::Result<()> {
+        let received = RECEIVED.load(Ordering::SeqCst);
+        if received != self.seen {
+            self.seen = received;
+            self.forwarded = true;
+            send(self.pid, self.group, LAST_SIGNAL.load(Ordering::SeqCst))?;
+        }
+        return Ok(());
+    }
+
+    /// Whether any signals have been forwarded
+    pub(crate) fn forwarded(&self) -> bool {
+        return self.forwarded;
+    }
+}
@@ -0,0 +1,241 @@
+use crate::{Line, LineType};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+
+/// Somewhere to send lines as they're captured (see [`Runner::sink`](crate::Runner::sink))
+///
+/// There are implementations for:
+/// - [`Vec<Line>`], which collects the lines
+/// - `Arc<Mutex<S>>` for any sink `S`, so you can keep a handle to a sink (e.g. to get the lines from a `Vec`)
+/// - [`Sender<Line>`] and [`SyncSender<Line>`], which send them over a channel
+/// - [`FileSink`], which writes them to a file
+/// - [`WriteSink`], which writes them to anything implementing [`Write`], like a socket or a compressor
+/// - [`Tee`], which sends them to two other sinks
+/// - [`NullSink`], which throws them away
+///
+/// ```
+/// use better_commands::{Line, LineSink, Runner};
+/// use std::process::Command;
+///
+/// /// Counts how many lines contain "error"
+/// struct ErrorCounter(usize);
+///
+/// impl LineSink for ErrorCounter {
+///     fn accept(&mut self, line: Line) {
+///         if line.content.contains("error") {
+///             self.0 += 1;
+///         }
+///     }
+/// }
+///
+/// let runner = Runner::new().sink(ErrorCounter(0));
+/// ```
+pub trait LineSink: Send {
+    /// Handles a single line; this is called from the threads reading the command's output
+    fn accept(&mut self, line: Line);
+
+    /// Called after each command exits, once all its lines have been accepted
+    fn flush(&mut self) {}
+}
+
+impl LineSink for Vec<Line> {
+    fn accept(&mut self, line: Line) {
+        self.push(line);
+    }
+}
+
+impl<S: LineSink> LineSink for Arc<Mutex<S>> {
+    fn accept(&mut self, line: Line) {
+        self.lock().unwrap().accept(line);
+    }
+
+    fn flush(&mut self) {
+        self.lock().unwrap().flush();
+    }
+}
+
+impl LineSink for Sender<Line> {
+    /// Sends the line, ignoring it if the receiver's been dropped
+    fn accept(&mut self, line: Line) {
+        let _ = self.send(line);
+    }
+}
+
+impl LineSink for SyncSender<Line> {
+    /// Sends the line (waiting if the channel is full), ignoring it if the receiver's been dropped
+    fn accept(&mut self, line: Line) {
+        let _ = self.send(line);
+    }
+}
+
+/// A [`LineSink`] which writes each line's content to a file
+///
+/// Since [`LineSink::accept`] can't return errors, once writing fails, the rest of the lines are skipped; the error is
+/// available from [`FileSink::error`].
+#[derive(Debug)]
+pub struct FileSink {
+    file: BufWriter<File>,
+    error: Option<io::Error>,
+}
+
+impl FileSink {
+    /// Creates a sink writing to `file`
+    pub fn new(file: File) -> Self {
+        return FileSink {
+            file: BufWriter::new(file),
+            error: None,
+        };
+    }
+
+    /// Creates (or truncates) the file at `path` and writes to it
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        return Ok(FileSink::new(File::create(path)?));
+    }
+
+    /// Returns the error that stopped lines being written, if there was one
+    pub fn error(&self) -> Option<&io::Error> {
+        return self.error.as_ref();
+    }
+}
+
+impl LineSink for FileSink {
+    fn accept(&mut self, line: Line) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.file, "{}", line.content) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.error.is_none() {
+            if let Err(e) = self.file.flush() {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+/// A [`LineSink`] which writes each line's content to anything implementing [`Write`], followed by a newline
+///
+/// Like [`FileSink`], once writing fails, the rest of the lines are skipped; the error is available from
+/// [`WriteSink::error`]. Nothing is buffered here, so wrap the writer in a [`BufWriter`] if it needs it.
+///
+/// ```
+/// use better_commands::{Runner, WriteSink};
+/// use std::net::TcpStream;
+///
+/// # fn connect() -> std::io::Result<()> {
+/// let socket = TcpStream::connect("127.0.0.1:5170")?;
+/// let runner = Runner::new().sink(WriteSink::new(socket));
+/// # return Ok(());
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct WriteSink<W: Write + Send> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write + Send> WriteSink<W> {
+    /// Creates a sink writing to `writer`
+    pub fn new(writer: W) -> Self {
+        return WriteSink {
+            writer,
+            error: None,
+        };
+    }
+
+    /// Returns the error that stopped lines being written, if there was one
+    pub fn error(&self) -> Option<&io::Error> {
+        return self.error.as_ref();
+    }
+
+    /// Returns the writer, e.g. to finish a compressed stream
+    pub fn into_inner(self) -> W {
+        return self.writer;
+    }
+}
+
+impl<W: Write + Send> LineSink for WriteSink<W> {
+    fn accept(&mut self, line: Line) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.writer, "{}", line.content) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.error.is_none() {
+            if let Err(e) = self.writer.flush() {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+/// Only passes along lines printed to one stream (see [`Runner::stdout_sink`](crate::Runner::stdout_sink))
+#[derive(Debug)]
+pub(crate) struct StreamSink<S: LineSink> {
+    pub(crate) stream: LineType,
+    pub(crate) sink: S,
+}
+
+impl<S: LineSink> LineSink for StreamSink<S> {
+    fn accept(&mut self, line: Line) {
+        if line.printed_to == self.stream {
+            self.sink.accept(line);
+        }
+    }
+
+    fn flush(&mut self) {
+        self.sink.flush();
+    }
+}
+
+/// A [`LineSink`] which sends every line to two other sinks
+///
+/// ```
+/// use better_commands::{NullSink, Runner, Tee};
+/// use std::sync::{mpsc, Arc, Mutex};
+///
+/// let (sender, receiver) = mpsc::channel();
+/// let lines = Arc::new(Mutex::new(Vec::new()));
+/// let runner = Runner::new().sink(Tee::new(sender, Tee::new(lines.clone(), NullSink)));
+/// ```
+#[derive(Debug)]
+pub struct Tee<A: LineSink, B: LineSink> {
+    first: A,
+    second: B,
+}
+
+impl<A: LineSink, B: LineSink> Tee<A, B> {
+    /// Creates a sink sending each line to `first`, then `second`
+    pub fn new(first: A, second: B) -> Self {
+        return Tee { first, second };
+    }
+}
+
+impl<A: LineSink, B: LineSink> LineSink for Tee<A, B> {
+    fn accept(&mut self, line: Line) {
+        self.first.accept(line.clone());
+        self.second.accept(line);
+    }
+
+    fn flush(&mut self) {
+        self.first.flush();
+        self.second.flush();
+    }
+}
+
+/// A [`LineSink`] which throws away every line
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullSink;
+
+impl LineSink for NullSink {
+    fn accept(&mut self, _line: Line) {}
+}
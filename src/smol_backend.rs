@@ -0,0 +1,192 @@
+//! Running commands natively from async code with `async-process`, the process library used by smol and async-std
+use crate::audit::Audit;
+use crate::{check_not_empty, command_line, CmdOutput, Error, Line};
+use async_process::{Child, ChildStderr, ChildStdout, Stdio};
+use futures_lite::io::{AsyncBufRead, BufReader, Lines};
+use futures_lite::{AsyncBufReadExt, Stream, StreamExt};
+use std::io;
+use std::pin::Pin;
+use std::process::Command;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Runs a command from async code, returning a [`CmdOutput`] (which *will* contain `Some(lines)`, not a None)
+///
+/// Unlike [`run_stream_async`](crate::run_stream_async), this doesn't need any threads of its own. It still works with
+/// any runtime, not just smol or async-std, since `async-process` runs its own reactor when it has to.
+///
+/// ```
+/// use better_commands::run_async;
+/// use std::process::Command;
+///
+/// let mut command = Command::new("bash");
+/// command.arg("-c").arg("echo hi; >&2 echo there");
+/// let output = futures_lite::future::block_on(run_async(command)).unwrap();
+///
+/// assert_eq!("hi", output.stdout().unwrap()[0].content);
+/// assert_eq!("there", output.stderr().unwrap()[0].content);
+/// ```
+///
+/// <small>This needs the `smol` feature</small>
+pub async fn run_async(command: Command) -> Result<CmdOutput, Error> {
+    return spawn_async(command)?.wait().await;
+}
+
+/// Starts a command from async code, returning an [`AsyncChild`] to get its lines from as they're printed
+///
+/// <small>This needs the `smol` feature</small>
+pub fn spawn_async(command: Command) -> Result<AsyncChild, Error> {
+    check_not_empty(&command)?;
+    let command_line = command_line(&command);
+    let audit = Audit::new(&command, None, None);
+    #[cfg(feature = "metrics")]
+    let program = crate::telemetry::program(&command);
+    let start = Instant::now();
+    let mut child = async_process::Command::from(command)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(Error::Spawn)?;
+
+    let stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+    let stderr = BufReader::new(child.stderr.take().unwrap()).lines();
+    return Ok(AsyncChild {
+        child,
+        command_line,
+        start,
+        stdout: Some(stdout),
+        stderr: Some(stderr),
+        stderr_first: false,
+        lines: Vec::new(),
+        error: None,
+        audit,
+        #[cfg(feature = "metrics")]
+        program,
+    });
+}
+
+/// A command started with [`spawn_async`]
+///
+/// It's a [`Stream`] of the lines the command prints, as they're printed. Use [`AsyncChild::wait`] to get its output
+/// once it's done; that has every line, including the ones which were already streamed.
+///
+/// ```
+/// use better_commands::spawn_async;
+/// use futures_lite::StreamExt;
+/// use std::process::Command;
+///
+/// futures_lite::future::block_on(async {
+///     let mut command = Command::new("seq");
+///     command.arg("3");
+///     let mut child = spawn_async(command).unwrap();
+///     assert_eq!("1", child.next().await.unwrap().content);
+///
+///     let output = child.wait().await.unwrap();
+///     assert_eq!(3, output.lines().unwrap().len());
+/// });
+/// ```
+///
+/// <small>This needs the `smol` feature</small>
+#[derive(Debug)]
+pub struct AsyncChild {
+    child: Child,
+    command_line: String,
+    start: Instant,
+    stdout: Option<Lines<BufReader<ChildStdout>>>,
+    stderr: Option<Lines<BufReader<ChildStderr>>>,
+    /// Set to whichever stream didn't have the last line, so one stream can't hold up the other
+    stderr_first: bool,
+    lines: Vec<Line>,
+    error: Option<io::Error>,
+    audit: Option<Audit>,
+    #[cfg(feature = "metrics")]
+    program: String,
+}
+
+impl AsyncChild {
+    /// Returns the OS-assigned process ID of the command
+    pub fn pid(&self) -> u32 {
+        return self.child.id();
+    }
+
+    /// Kills the command, if it's still running
+    pub fn kill(&mut self) -> io::Result<()> {
+        return self.child.kill();
+    }
+
+    /// Waits for the command to exit, returning its output
+    ///
+    /// Any lines which haven't been streamed yet are still read, so this will wait for its output to be closed too.
+    pub async fn wait(mut self) -> Result<CmdOutput, Error> {
+        while self.next().await.is_some() {}
+        if let Some(e) = self.error.take() {
+            return Err(Error::Io(e));
+        }
+        let status = self.child.status().await?;
+        let end = Instant::now();
+
+        let mut output = CmdOutput::new(
+            self.command_line,
+            Some(self.lines),
+            status.code(),
+            self.start,
+            end,
+        );
+        output.set_exit_status(status);
+        if let Some(audit) = &self.audit {
+            audit.record(&output);
+        }
+        #[cfg(feature = "metrics")]
+        crate::telemetry::record(&self.program, &output);
+        return Ok(output);
+    }
+}
+
+impl Stream for AsyncChild {
+    type Item = Line;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Line>> {
+        let this = &mut *self;
+        for stderr in [this.stderr_first, !this.stderr_first] {
+            let content = match stderr {
+                true => poll_lines(&mut this.stderr, cx, &mut this.error),
+                false => poll_lines(&mut this.stdout, cx, &mut this.error),
+            };
+            if let Some(content) = content {
+                let line = match stderr {
+                    true => Line::from_stderr(content),
+                    false => Line::from_stdout(content),
+                };
+                this.lines.push(line.clone());
+                this.stderr_first = !stderr;
+                return Poll::Ready(Some(line));
+            }
+        }
+
+        if this.stdout.is_none() && this.stderr.is_none() {
+            return Poll::Ready(None);
+        }
+        return Poll::Pending;
+    }
+}
+
+/// Returns the next line from one of the command's streams if it's ready, closing the stream once it's done
+///
+/// If reading it fails, the error's kept for [`AsyncChild::wait`] to return, and the stream's closed too.
+fn poll_lines<R: AsyncBufRead + Unpin>(
+    lines: &mut Option<Lines<R>>,
+    cx: &mut Context<'_>,
+    error: &mut Option<io::Error>,
+) -> Option<String> {
+    let stream = lines.as_mut()?;
+    match Pin::new(stream).poll_next(cx) {
+        Poll::Ready(Some(Ok(content))) => return Some(content),
+        Poll::Ready(Some(Err(e))) => {
+            error.get_or_insert(e);
+            *lines = None;
+        }
+        Poll::Ready(None) => *lines = None,
+        Poll::Pending => {}
+    }
+    return None;
+}
@@ -0,0 +1,85 @@
+//! Comparing output against snapshots saved to files
+use crate::{ansi, diff, CmdOutput};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The environment variable which makes [`CmdOutput::matches_snapshot`] update snapshots instead of checking them
+pub const UPDATE_SNAPSHOTS_VAR: &str = "BETTER_COMMANDS_UPDATE_SNAPSHOTS";
+
+impl CmdOutput {
+    /// Checks that stdout matches the snapshot saved at `path`, panicking with a diff if it doesn't
+    ///
+    /// Stdout is normalized before comparing: ANSI escape sequences and trailing whitespace are removed from each line,
+    /// as are blank lines at the end.
+    ///
+    /// If the [`UPDATE_SNAPSHOTS_VAR`] environment variable (`BETTER_COMMANDS_UPDATE_SNAPSHOTS`) is set to anything
+    /// other than `0`, the snapshot is written instead, creating any missing directories. That's also how snapshots are
+    /// created in the first place; a missing snapshot is a failure otherwise, so CI doesn't silently pass without one.
+    ///
+    /// ```no_run
+    /// use better_commands::run;
+    /// use std::process::Command;
+    ///
+    /// // run with BETTER_COMMANDS_UPDATE_SNAPSHOTS=1 to create or update it
+    /// run(Command::new("my-cli").arg("--help")).matches_snapshot("tests/snapshots/help.txt");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If the snapshot doesn't match, doesn't exist, or can't be read or written
+    #[track_caller]
+    pub fn matches_snapshot<P: AsRef<Path>>(&self, path: P) {
+        let path = path.as_ref();
+        let actual = normalize(&self.stdout_string().unwrap_or_default());
+
+        let update = std::env::var_os(UPDATE_SNAPSHOTS_VAR).is_some_and(|value| value != "0");
+        if update {
+            if let Err(e) = write_snapshot(path, &actual) {
+                panic!("failed to write snapshot {}: {}", path.display(), e);
+            }
+            return;
+        }
+
+        let expected = match fs::read_to_string(path) {
+            Ok(expected) => expected,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => panic!(
+                "snapshot {} doesn't exist; run with {}=1 to create it",
+                path.display(),
+                UPDATE_SNAPSHOTS_VAR
+            ),
+            Err(e) => panic!("failed to read snapshot {}: {}", path.display(), e),
+        };
+        let expected = normalize(&expected);
+        if expected != actual {
+            let expected: Vec<&str> = expected.lines().collect();
+            let actual: Vec<&str> = actual.lines().collect();
+            panic!(
+                "stdout of `{}` doesn't match snapshot {} (- snapshot, + stdout); run with {}=1 to update it\n{}",
+                self.command,
+                path.display(),
+                UPDATE_SNAPSHOTS_VAR,
+                diff::unified(&expected, &actual)
+            );
+        }
+    }
+}
+
+/// Removes ANSI escapes and trailing whitespace from each line, and blank lines from the end
+fn normalize(text: &str) -> String {
+    let mut lines: Vec<String> = text
+        .lines()
+        .map(|line| ansi::strip(line).trim_end().to_string())
+        .collect();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    return lines.join("\n");
+}
+
+fn write_snapshot(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    return fs::write(path, format!("{}\n", contents));
+}
@@ -0,0 +1,279 @@
+//! Splitting a stream of bytes into lines
+//!
+//! Everything that captures output feeds the bytes it reads through a [`LineSplitter`], however they happen to be
+//! chunked, so every way of reading gives the same lines.
+
+/// A line split out of a stream, before it's decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RawLine {
+    /// The line's bytes, without the delimiter (or the `\r` before a `\n`)
+    pub(crate) bytes: Vec<u8>,
+    /// How many bytes of the stream this line took up, including the delimiter and anything cut off
+    pub(crate) len: usize,
+    /// Whether anything was cut off, for being longer than the splitter's `max_len`
+    pub(crate) truncated: bool,
+    /// Whether it was ended by the delimiter (or a `\r`), rather than the stream ending (or being flushed) partway
+    /// through it
+    pub(crate) terminated: bool,
+}
+
+impl RawLine {
+    /// Decodes the line, replacing invalid UTF-8 rather than failing, so one bad byte doesn't lose the whole output
+    pub(crate) fn decode(&self) -> String {
+        return String::from_utf8_lossy(&self.bytes).into_owned();
+    }
+}
+
+/// Splits bytes into lines as they're read
+///
+/// The states are:
+/// - in a line: bytes are added to the current line until the delimiter (or `\r`, depending on `carriage_returns`)
+/// - past the length limit: bytes are counted but thrown away until the delimiter
+/// - partway through a delimiter: with a delimiter longer than a byte, the end of what's been read might be the start of
+///   one, so it's held back until the next read says whether it is
+/// - just after a `\r`: if the next byte is `\n`, the two are one newline, otherwise the `\r` ended the line
+/// - finished: once [`LineSplitter::finish`] is called, whatever's left is flushed as a final (unterminated) line
+#[derive(Debug, Clone)]
+pub(crate) struct LineSplitter {
+    delimiter: Vec<u8>,
+    max_len: Option<usize>,
+    carriage_returns: CarriageReturns,
+    /// The line so far, up to `max_len` bytes
+    partial: Vec<u8>,
+    /// How many bytes of the stream the line so far has taken up
+    partial_len: usize,
+    /// The end of what's been read, if it's the start of the delimiter
+    pending: Vec<u8>,
+    /// Whether the last byte was a `\r` which ended a line
+    after_cr: bool,
+    /// With [`CarriageReturns::Coalesce`], the last line ended by a `\r`, which is kept until it's either redrawn or
+    /// turns out to be final
+    redrawn: Option<RawLine>,
+}
+
+impl LineSplitter {
+    /// Creates a splitter ending lines at `delimiter` (which can't be empty), keeping at most `max_len` bytes of each
+    /// line
+    pub(crate) fn new(delimiter: &[u8], max_len: Option<usize>) -> Self {
+        assert!(!delimiter.is_empty(), "lines need a delimiter");
+        return LineSplitter {
+            delimiter: delimiter.to_vec(),
+            max_len,
+            carriage_returns: CarriageReturns::default(),
+            partial: Vec::new(),
+            partial_len: 0,
+            pending: Vec::new(),
+            after_cr: false,
+            redrawn: None,
+        };
+    }
+
+    /// Sets how a `\r` that isn't part of a `\r\n` is handled
+    pub(crate) fn carriage_returns(mut self, carriage_returns: CarriageReturns) -> Self {
+        self.carriage_returns = carriage_returns;
+        return self;
+    }
+
+    /// Adds `bytes` to the stream, calling `on_line` for each line it finishes
+    ///
+    /// With [`CarriageReturns::Split`], a `\n` right after a `\r` that ended a line isn't counted in any line's `len`.
+    pub(crate) fn push(&mut self, bytes: &[u8], mut on_line: impl FnMut(RawLine)) {
+        let joined;
+        let mut bytes = match self.pending.is_empty() {
+            true => bytes,
+            false => {
+                joined = [std::mem::take(&mut self.pending).as_slice(), bytes].concat();
+                joined.as_slice()
+            }
+        };
+        while !bytes.is_empty() {
+            if std::mem::take(&mut self.after_cr) && self.delimiter == b"\n" && bytes[0] == b'\n' {
+                // "\r\n" is just a newline, so the line the "\r" ended wasn't redrawn after all
+                if let Some(mut line) = self.redrawn.take() {
+                    line.len += 1;
+                    on_line(line);
+                }
+                bytes = &bytes[1..];
+                continue;
+            }
+
+            let Some((end, delimited)) = self.find_end(bytes) else {
+                let pending = self.partial_delimiter(bytes);
+                self.extend(&bytes[..bytes.len() - pending]);
+                self.pending = bytes[bytes.len() - pending..].to_vec();
+                return;
+            };
+            self.extend(&bytes[..end]);
+            let terminator_len = if delimited { self.delimiter.len() } else { 1 };
+            // count the delimiter too
+            self.partial_len += terminator_len;
+            let mut line = self.take_line(terminator_len);
+            if delimited {
+                // anything redrawn was replaced by this line
+                if let Some(redrawn) = self.redrawn.take() {
+                    line.len += redrawn.len;
+                }
+                on_line(line);
+            } else {
+                self.after_cr = true;
+                match self.carriage_returns {
+                    CarriageReturns::Split if !line.bytes.is_empty() => on_line(line),
+                    CarriageReturns::Coalesce => {
+                        if let Some(redrawn) = self.redrawn.take() {
+                            line.len += redrawn.len;
+                        }
+                        self.redrawn = Some(line);
+                    }
+                    _ => {}
+                }
+            }
+            bytes = &bytes[end + terminator_len..];
+        }
+    }
+
+    /// Finds where the next line ends in `bytes`, and whether it's ended by the delimiter (rather than a `\r`)
+    fn find_end(&self, bytes: &[u8]) -> Option<(usize, bool)> {
+        let split_cr = self.carriage_returns != CarriageReturns::Keep;
+        if let [delimiter] = self.delimiter[..] {
+            return bytes
+                .iter()
+                .position(|&b| b == delimiter || (split_cr && b == b'\r'))
+                .map(|end| (end, bytes[end] == delimiter));
+        }
+        return (0..bytes.len()).find_map(|i| {
+            if bytes[i..].starts_with(&self.delimiter) {
+                return Some((i, true));
+            }
+            return (split_cr && bytes[i] == b'\r').then_some((i, false));
+        });
+    }
+
+    /// Returns how many bytes at the end of `bytes` could be the start of the delimiter
+    fn partial_delimiter(&self, bytes: &[u8]) -> usize {
+        return (1..self.delimiter.len())
+            .rev()
+            .find(|&len| bytes.ends_with(&self.delimiter[..len]))
+            .unwrap_or(0);
+    }
+
+    /// Ends the stream (or flushes what's been read so far), returning the last line if it wasn't terminated
+    pub(crate) fn finish(&mut self) -> Option<RawLine> {
+        // it turned out not to be a delimiter after all
+        let pending = std::mem::take(&mut self.pending);
+        self.extend(&pending);
+        if self.partial_len == 0 {
+            // the last thing drawn is what's left on the screen
+            return self.redrawn.take();
+        }
+        let mut line = self.take_line(0);
+        if let Some(redrawn) = self.redrawn.take() {
+            line.len += redrawn.len;
+        }
+        return Some(line);
+    }
+
+    /// Whether the line so far is already too long, i.e. the next line will be truncated
+    pub(crate) fn is_over_limit(&self) -> bool {
+        return self.max_len.is_some_and(|max| self.partial_len > max);
+    }
+
+    /// Adds bytes without a delimiter to the current line, dropping anything past `max_len`
+    fn extend(&mut self, chunk: &[u8]) {
+        self.partial_len += chunk.len();
+        let keep = match self.max_len {
+            Some(max) => chunk.len().min(max.saturating_sub(self.partial.len())),
+            None => chunk.len(),
+        };
+        self.partial.extend_from_slice(&chunk[..keep]);
+    }
+
+    /// Takes the line so far, which was ended by a delimiter (or `\r`) `terminator_len` bytes long
+    fn take_line(&mut self, terminator_len: usize) -> RawLine {
+        let mut bytes = std::mem::take(&mut self.partial);
+        let len = std::mem::take(&mut self.partial_len);
+        let terminated = terminator_len > 0;
+        let truncated = len - terminator_len > bytes.len();
+        // same as `BufRead::lines()`, "\r\n" counts as a newline
+        if terminated && !truncated && self.delimiter == b"\n" && bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+        if truncated {
+            // don't leave half a character at the end
+            if let Err(e) = std::str::from_utf8(&bytes) {
+                if e.error_len().is_none() {
+                    bytes.truncate(e.valid_up_to());
+                }
+            }
+        }
+        return RawLine {
+            bytes,
+            len,
+            truncated,
+            terminated,
+        };
+    }
+}
+
+/// What separates one line from the next (see [`Runner::delimiter`](crate::Runner::delimiter)), which is `\n` by
+/// default
+///
+/// This can be made from a byte (`b'\0'`), a byte string (`b"\r\n\r\n"`), or a `&str`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delimiter(pub(crate) Vec<u8>);
+
+impl Default for Delimiter {
+    fn default() -> Self {
+        return Delimiter(vec![b'\n']);
+    }
+}
+
+impl From<u8> for Delimiter {
+    fn from(byte: u8) -> Self {
+        return Delimiter(vec![byte]);
+    }
+}
+
+impl From<&[u8]> for Delimiter {
+    fn from(bytes: &[u8]) -> Self {
+        return Delimiter(bytes.to_vec());
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for Delimiter {
+    fn from(bytes: &[u8; N]) -> Self {
+        return Delimiter(bytes.to_vec());
+    }
+}
+
+impl From<&str> for Delimiter {
+    fn from(text: &str) -> Self {
+        return Delimiter(text.as_bytes().to_vec());
+    }
+}
+
+/// What to do with lines longer than [`Runner::max_line_length`](crate::Runner::max_line_length)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LongLines {
+    /// Keeps the start of the line, and sets [`Line::truncated`](crate::Line::truncated)
+    #[default]
+    Truncate,
+    /// Kills the command, making it fail with [`Error::LineTooLong`](crate::Error::LineTooLong)
+    Fail,
+}
+
+/// What to do with a `\r` in the output which isn't part of a `\r\n` (see [`Runner::carriage_returns`](crate::Runner::carriage_returns))
+///
+/// Progress bars (e.g. from curl, pip, or cargo) are usually redrawn by printing `\r` and then the new state, without
+/// any newlines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CarriageReturns {
+    /// Keeps it as part of the line's content
+    #[default]
+    Keep,
+    /// Treats it as the end of a line, so each redraw is its own line
+    ///
+    /// Empty lines from a `\r` right at the start of a line are skipped.
+    Split,
+    /// Treats it as the end of a line, but only keeps the last redraw, i.e. what would've been left on the screen
+    Coalesce,
+}
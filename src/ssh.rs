@@ -0,0 +1,255 @@
+//! Running commands on other machines over SSH
+use crate::{command_line, CmdOutput, CommandRunner, Error, Runner};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Counts connections, so each one gets its own control socket
+static CONNECTIONS: AtomicU32 = AtomicU32::new(0);
+
+/// Options for connecting with [`SshRunner::connect_with`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshOptions {
+    program: PathBuf,
+    port: Option<u16>,
+    identity_file: Option<PathBuf>,
+    options: Vec<String>,
+}
+
+impl Default for SshOptions {
+    fn default() -> Self {
+        return SshOptions::new();
+    }
+}
+
+impl SshOptions {
+    /// Creates the default options, which are whatever's in `~/.ssh/config`
+    pub fn new() -> Self {
+        return SshOptions {
+            program: PathBuf::from("ssh"),
+            port: None,
+            identity_file: None,
+            options: Vec::new(),
+        };
+    }
+
+    /// Sets the port to connect to
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        return self;
+    }
+
+    /// Sets the private key to log in with
+    pub fn identity_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.identity_file = Some(path.as_ref().to_path_buf());
+        return self;
+    }
+
+    /// Adds an option in the format used in `ssh_config`, e.g. `StrictHostKeyChecking=accept-new`
+    pub fn option<S: Into<String>>(mut self, option: S) -> Self {
+        self.options.push(option.into());
+        return self;
+    }
+
+    /// Sets the `ssh` program to use (`ssh` from the `PATH` by default)
+    pub fn program<P: AsRef<Path>>(mut self, program: P) -> Self {
+        self.program = program.as_ref().to_path_buf();
+        return self;
+    }
+}
+
+/// Runs commands on another machine over SSH, returning the same [`CmdOutput`] as running them locally
+///
+/// This uses the system's `ssh`, with one connection (a `ControlMaster`) shared by every command, so only the first
+/// one has to wait for it to connect and log in. It's closed when this is dropped. Since it implements
+/// [`CommandRunner`], code taking a `&impl CommandRunner` can run commands locally or remotely without any changes.
+///
+/// The command's program, arguments, working directory, and environment variables are sent over; everything else
+/// comes from the [`Runner`] (see [`SshRunner::runner`]), e.g. [`Runner::stdin`] to give the command some input.
+///
+/// ```no_run
+/// use better_commands::{SshRunner, StdinMode, Runner};
+/// use std::process::Command;
+///
+/// let ssh = SshRunner::connect("deploy@example.com").unwrap();
+/// let output = ssh.run(Command::new("systemctl").arg("restart").arg("my-app")).unwrap();
+/// assert_eq!(Some(0), output.status_code());
+///
+/// let ssh = ssh.runner(Runner::new().stdin(StdinMode::piped("new config")));
+/// ssh.run(Command::new("tee").arg("/etc/my-app.conf")).unwrap();
+/// ```
+///
+/// <small>An exit code of 255 might be from `ssh` itself, e.g. if the connection dropped. Killing a command (e.g. for
+/// a timeout) only kills the local `ssh`, so the remote command might keep running until it notices its output's gone
+/// nowhere. The times include the time taken to send everything over the network.</small>
+#[derive(Debug)]
+pub struct SshRunner {
+    destination: String,
+    options: SshOptions,
+    socket: PathBuf,
+    runner: Runner,
+}
+
+impl SshRunner {
+    /// Connects to `destination` (e.g. `user@host`, or a host from `~/.ssh/config`) with the default options
+    ///
+    /// This fails with [`Error::Spawn`] if it can't connect or log in; since there's no way to type a password, that
+    /// needs a key (or an agent).
+    pub fn connect<S: Into<String>>(destination: S) -> Result<Self, Error> {
+        return SshRunner::connect_with(destination, SshOptions::new());
+    }
+
+    /// Connects to `destination` with `options`
+    pub fn connect_with<S: Into<String>>(
+        destination: S,
+        options: SshOptions,
+    ) -> Result<Self, Error> {
+        let socket = std::env::temp_dir().join(format!(
+            "better-commands-ssh-{}-{}",
+            std::process::id(),
+            CONNECTIONS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let ssh = SshRunner {
+            destination: destination.into(),
+            options,
+            socket,
+            runner: Runner::new(),
+        };
+        // the connection outlives this, so it'd keep a pipe open forever; its errors go to a file instead
+        let errors_path = ssh.socket.with_extension("err");
+        let errors = File::create(&errors_path).map_err(Error::Spawn)?;
+        // `-f` makes it go into the background once it's logged in
+        let status = ssh
+            .command()
+            .args([
+                "-o",
+                "ControlMaster=yes",
+                "-o",
+                "ControlPersist=yes",
+                "-o",
+                "BatchMode=yes",
+            ])
+            .args(["-f", "-N", "--"])
+            .arg(&ssh.destination)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(errors)
+            .status();
+        let errors = std::fs::read_to_string(&errors_path).unwrap_or_default();
+        let _ = std::fs::remove_file(&errors_path);
+        if !status.map_err(Error::Spawn)?.success() {
+            let message = format!("couldn't connect to {}: {}", ssh.destination, errors.trim());
+            return Err(Error::Spawn(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                message,
+            )));
+        }
+        return Ok(ssh);
+    }
+
+    /// Sets the runner used to run `ssh`, e.g. to give each command a timeout
+    pub fn runner(mut self, runner: Runner) -> Self {
+        self.runner = runner;
+        return self;
+    }
+
+    /// Returns where the commands are run, as it was given to [`SshRunner::connect`]
+    pub fn destination(&self) -> &str {
+        return &self.destination;
+    }
+
+    /// Runs `command` on the other machine, returning its output
+    ///
+    /// [`CmdOutput::command`] is the command as it would be run locally, not the `ssh` command running it.
+    pub fn run(&self, command: &mut Command) -> Result<CmdOutput, Error> {
+        crate::check_not_empty(command)?;
+        let mut ssh = self.command();
+        ssh.args(["-o", "ControlMaster=no", "-T", "--"])
+            .arg(&self.destination)
+            .arg(remote_command_line(command));
+        let mut output = self.runner.run(&mut ssh)?;
+        let command_line = command_line(command);
+        for attempt in &mut output.failed_attempts {
+            attempt.command = command_line.clone();
+        }
+        output.command = command_line;
+        return Ok(output);
+    }
+
+    /// Returns an `ssh` command using the shared connection, without the destination or anything to run
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.options.program);
+        command.arg("-o").arg(control_path(&self.socket));
+        if let Some(port) = self.options.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        if let Some(identity_file) = &self.options.identity_file {
+            command.arg("-i").arg(identity_file);
+        }
+        for option in &self.options.options {
+            command.arg("-o").arg(option);
+        }
+        return command;
+    }
+}
+
+impl CommandRunner for SshRunner {
+    fn run(&self, command: &mut Command) -> Result<CmdOutput, Error> {
+        return SshRunner::run(self, command);
+    }
+}
+
+impl Drop for SshRunner {
+    /// Closes the connection
+    fn drop(&mut self) {
+        let _ = self
+            .command()
+            .args(["-O", "exit", "--"])
+            .arg(&self.destination)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+fn control_path(socket: &Path) -> String {
+    return format!("ControlPath={}", socket.display());
+}
+
+/// Turns `command` into a line for the remote shell to run, including its working directory and environment
+fn remote_command_line(command: &Command) -> String {
+    let mut removed = Vec::new();
+    let mut set = Vec::new();
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => {
+                let assignment = format!("{}={}", key.to_string_lossy(), value.to_string_lossy());
+                set.push(quote(OsStr::new(&assignment)));
+            }
+            None => removed.push(format!("-u {}", quote(key))),
+        }
+    }
+    let mut parts = Vec::new();
+    if !removed.is_empty() || !set.is_empty() {
+        parts.push("env".to_string());
+        parts.append(&mut removed);
+        parts.append(&mut set);
+    }
+    parts.push(quote(command.get_program()));
+    parts.extend(command.get_args().map(quote));
+    let line = parts.join(" ");
+    return match command.get_current_dir() {
+        Some(dir) => format!("cd {} && {}", quote(dir.as_os_str()), line),
+        None => line,
+    };
+}
+
+/// Quotes `text` for a POSIX shell
+fn quote(text: &OsStr) -> String {
+    return format!("'{}'", text.to_string_lossy().replace('\'', r"'\''"));
+}
@@ -0,0 +1,132 @@
+//! Converting to and from [`std::process::Output`], for working with code that uses it
+use crate::split::LineSplitter;
+use crate::{CmdOutput, Line, LineType, NEXT_SEQ};
+use std::process::{ExitStatus, Output};
+use std::sync::atomic;
+use std::time::Instant;
+
+impl CmdOutput {
+    /// Creates a [`CmdOutput`] from the output of [`Command::output`](std::process::Command::output), splitting it
+    /// into lines
+    ///
+    /// `Output` doesn't say when anything was printed, so every line gets the same time, and stdout and stderr aren't
+    /// interleaved; [`CmdOutput::iter`] goes through all of stdout and then all of stderr. Invalid UTF-8 is replaced
+    /// in [`Line::content`], with the original bytes kept in [`Line::raw`].
+    ///
+    /// ```
+    /// use better_commands::CmdOutput;
+    /// use std::process::Command;
+    ///
+    /// let std_output = Command::new("bash").arg("-c").arg("echo hi; echo bye >&2; exit 3").output().unwrap();
+    /// let output = CmdOutput::from_std_output("bash -c ...", std_output);
+    ///
+    /// assert_eq!("hi", output.stdout_string().unwrap());
+    /// assert_eq!("bye", output.stderr_string().unwrap());
+    /// assert_eq!(Some(3), output.status_code());
+    /// ```
+    pub fn from_std_output<S: AsRef<str>>(command: S, output: Output) -> CmdOutput {
+        let now = Instant::now();
+        let mut lines = split_lines(&output.stdout, LineType::Stdout, now);
+        lines.extend(split_lines(&output.stderr, LineType::Stderr, now));
+        let mut cmd_output = CmdOutput::with_order(
+            command.as_ref().to_string(),
+            Some(lines),
+            false,
+            output.status.code(),
+            now,
+            now,
+        );
+        cmd_output.set_exit_status(output.status);
+        return cmd_output;
+    }
+
+    /// Converts this back to an [`Output`], like [`Command::output`](std::process::Command::output) would've returned
+    ///
+    /// Each line is followed by a newline unless it wasn't originally (see [`Line::terminated`]), and uses
+    /// [`Line::raw`] if it was kept, so output from [`CmdOutput::from_std_output`] converts back exactly. Lines which
+    /// weren't printed to stdout or stderr are left out, as are the lines of [`run_funcs`](crate::run_funcs), which
+    /// doesn't capture them.
+    ///
+    /// ```
+    /// use better_commands::run;
+    /// use std::process::Command;
+    ///
+    /// let output = run(Command::new("bash").arg("-c").arg("echo hi; printf bye >&2")).to_std_output();
+    ///
+    /// assert_eq!(b"hi\n", output.stdout.as_slice());
+    /// assert_eq!(b"bye", output.stderr.as_slice());
+    /// assert!(output.status.success());
+    /// ```
+    ///
+    /// <small>An [`ExitStatus`] can't be empty, so if this has no exit status (see [`CmdOutput::exit_status`]), the
+    /// status is 1</small>
+    pub fn to_std_output(&self) -> Output {
+        return Output {
+            status: self
+                .exit_status
+                .unwrap_or_else(|| exit_status(self.status_code, self.signal)),
+            stdout: join_bytes(self.stdout().unwrap_or_default()),
+            stderr: join_bytes(self.stderr().unwrap_or_default()),
+        };
+    }
+}
+
+impl From<CmdOutput> for Output {
+    fn from(output: CmdOutput) -> Self {
+        return output.to_std_output();
+    }
+}
+
+/// Splits `bytes` into lines, the same way they'd have been captured
+fn split_lines(bytes: &[u8], printed_to: LineType, time: Instant) -> Vec<Line> {
+    let mut splitter = LineSplitter::new(b"\n", None);
+    let mut raw_lines = Vec::new();
+    splitter.push(bytes, |raw| raw_lines.push(raw));
+    raw_lines.extend(splitter.finish());
+    return raw_lines
+        .into_iter()
+        .map(|raw| {
+            let seq = NEXT_SEQ.fetch_add(1, atomic::Ordering::Relaxed);
+            let mut line = Line::new(printed_to.clone(), time, seq, raw.decode());
+            line.terminated = raw.terminated;
+            // only kept when it's needed to get the original back, i.e. when it's not valid UTF-8
+            if line.content.as_bytes() != raw.bytes {
+                line.raw = Some(raw.bytes);
+            }
+            return line;
+        })
+        .collect();
+}
+
+/// Joins `lines` back into the bytes that were printed
+fn join_bytes(lines: &[Line]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for line in lines {
+        bytes.extend_from_slice(line.raw.as_deref().unwrap_or(line.content.as_bytes()));
+        if line.terminated {
+            bytes.push(b'\n');
+        }
+    }
+    return bytes;
+}
+
+/// Builds an [`ExitStatus`] for a command that exited with `status_code` or was killed by `signal`, for outputs which
+/// don't have the original one
+#[cfg(unix)]
+pub(crate) fn exit_status(status_code: Option<i32>, signal: Option<i32>) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+
+    // in the format `waitpid` gives it
+    return match (status_code, signal) {
+        (Some(code), _) => ExitStatus::from_raw((code & 0xff) << 8),
+        (None, Some(signal)) => ExitStatus::from_raw(signal & 0x7f),
+        (None, None) => ExitStatus::from_raw(1 << 8),
+    };
+}
+
+#[cfg(windows)]
+pub(crate) fn exit_status(status_code: Option<i32>, _signal: Option<i32>) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+
+    return ExitStatus::from_raw(status_code.unwrap_or(1) as u32);
+}
@@ -0,0 +1,61 @@
+//! Where a command's standard streams go
+use std::process::Stdio;
+
+/// What the command gets as stdin (see [`Runner::stdin`](crate::Runner::stdin))
+///
+/// Without one, whatever was set with [`Command::stdin`](std::process::Command::stdin) is left alone (which is
+/// inheriting this process's stdin, if nothing was).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StdinMode {
+    /// Reads this process's stdin
+    Inherit,
+    /// Reads nothing, like `< /dev/null`
+    Null,
+    /// Reads these bytes, then the end of the input
+    ///
+    /// <small>They're written from another thread, so the command can read as much or as little as it wants. Since
+    /// stdin is closed once they're written, [`Cancellation::Stdin`](crate::Cancellation::Stdin) can't send its
+    /// message, and just waits for the command to exit.</small>
+    Piped(Vec<u8>),
+}
+
+impl StdinMode {
+    /// Same as [`StdinMode::Piped`]
+    pub fn piped<B: AsRef<[u8]>>(bytes: B) -> Self {
+        return StdinMode::Piped(bytes.as_ref().to_vec());
+    }
+
+    pub(crate) fn stdio(&self) -> Stdio {
+        return match self {
+            StdinMode::Inherit => Stdio::inherit(),
+            StdinMode::Null => Stdio::null(),
+            StdinMode::Piped(_) => Stdio::piped(),
+        };
+    }
+}
+
+/// Where the command's stdout or stderr goes (see [`Runner::stdout`](crate::Runner::stdout) and
+/// [`Runner::stderr`](crate::Runner::stderr))
+///
+/// Only captured streams end up in [`CmdOutput::lines`](crate::CmdOutput::lines); the timing and exit status are
+/// recorded either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StdoutMode {
+    /// Captures each line
+    #[default]
+    Capture,
+    /// Prints straight to this process's stdout or stderr, without capturing anything
+    Inherit,
+    /// Throws it away, like `> /dev/null`
+    Null,
+}
+
+impl StdoutMode {
+    pub(crate) fn stdio(&self) -> Stdio {
+        return match self {
+            StdoutMode::Capture => Stdio::piped(),
+            StdoutMode::Inherit => Stdio::inherit(),
+            StdoutMode::Null => Stdio::null(),
+        };
+    }
+}
@@ -0,0 +1,110 @@
+//! Running commands from async code, getting their lines as a [`Stream`]
+use crate::{line_channel, Backpressure, CmdOutput, Error, Line, LineReceiver, Runner};
+use futures_core::Stream;
+use std::future::Future;
+use std::pin::Pin;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+/// How many lines can be waiting in a [`LineStream`] before reading the command's output is held up
+const STREAM_CAPACITY: usize = 1024;
+
+/// Runs a command on its own thread, returning a [`Stream`] of the lines it prints, and a [`Future`] resolving to its
+/// output once it's exited
+///
+/// This is the same as [`Runner::run_stream_async`] with the default options.
+///
+/// ```
+/// use better_commands::run_stream_async;
+/// use futures::StreamExt;
+/// use std::process::Command;
+///
+/// futures::executor::block_on(async {
+///     let mut command = Command::new("bash");
+///     command.arg("-c").arg("echo hi; >&2 echo there");
+///     let (lines, output) = run_stream_async(command);
+///
+///     let lines: Vec<String> = lines.map(|line| line.content).collect().await;
+///     assert_eq!(vec!["hi", "there"], lines);
+///     assert_eq!(Some(0), output.await.unwrap().status_code());
+/// });
+/// ```
+pub fn run_stream_async(command: Command) -> (LineStream, OutputFuture) {
+    return Runner::new().run_stream_async(command);
+}
+
+impl Runner {
+    /// Runs a command on its own thread, returning a [`Stream`] of the lines it prints, and a [`Future`] resolving to
+    /// its output once it's exited
+    ///
+    /// Nothing here depends on a particular runtime, so it works with tokio, async-std, or anything else. The lines
+    /// are sent over a [`line_channel`] with [`Backpressure::Block`], so if the stream isn't read, the command's held
+    /// up once it fills; drop the stream if you only want the output.
+    ///
+    /// <small>This needs the `futures` feature</small>
+    pub fn run_stream_async(&self, mut command: Command) -> (LineStream, OutputFuture) {
+        let (sink, receiver) = line_channel(STREAM_CAPACITY, Backpressure::Block);
+        let runner = self.clone().sink(sink);
+        let slot = Arc::new(Mutex::new(OutputSlot {
+            result: None,
+            waker: None,
+        }));
+
+        let thread_slot = slot.clone();
+        thread::spawn(move || {
+            let result = runner.run(&mut command);
+            // the stream only ends once the sink's dropped
+            drop(runner);
+            let mut slot = thread_slot.lock().unwrap();
+            slot.result = Some(result);
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+        });
+
+        return (LineStream { receiver }, OutputFuture { slot });
+    }
+}
+
+/// The lines printed by a command run with [`run_stream_async`], as they're printed
+#[derive(Debug)]
+pub struct LineStream {
+    receiver: LineReceiver,
+}
+
+impl Stream for LineStream {
+    type Item = Line;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Line>> {
+        return self.receiver.poll_recv(cx.waker());
+    }
+}
+
+#[derive(Debug)]
+struct OutputSlot {
+    result: Option<Result<CmdOutput, Error>>,
+    waker: Option<Waker>,
+}
+
+/// The output of a command run with [`run_stream_async`], ready once it's exited
+#[derive(Debug)]
+pub struct OutputFuture {
+    slot: Arc<Mutex<OutputSlot>>,
+}
+
+impl Future for OutputFuture {
+    type Output = Result<CmdOutput, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.lock().unwrap();
+        return match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        };
+    }
+}
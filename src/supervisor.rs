@@ -0,0 +1,355 @@
+//! Keeping a command running, restarting it when it exits
+use crate::{Backend, Backoff, CmdOutput, Error, Line, Runner, RunningCommand};
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+type EventCallback = Arc<Mutex<dyn FnMut(SupervisorEvent<'_>) + Send>>;
+
+/// How often the supervisor checks whether the command has exited, or it's been asked to stop
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// When a [`Supervisor`] restarts its command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Restart {
+    /// Whenever it exits, even if it succeeded
+    Always,
+    /// Only when it fails, i.e. exits with anything other than 0 or is killed
+    #[default]
+    OnFailure,
+    /// Never; it's just run once
+    Never,
+}
+
+/// Something that happened to a [`Supervisor`]'s command, passed to [`Supervisor::on_event`]
+///
+/// `run` counts how many times the command has been started, from 0.
+#[derive(Debug)]
+pub enum SupervisorEvent<'a> {
+    /// The command was started
+    Started { run: u32, pid: u32 },
+    /// The command printed a line
+    Line { run: u32, line: &'a Line },
+    /// The command exited
+    Exited { run: u32, output: &'a CmdOutput },
+    /// The command is going to be started again after `delay`
+    Restarting { run: u32, delay: Duration },
+}
+
+/// Runs a command in the background, restarting it when it exits, like a tiny process manager
+///
+/// By default, the command is restarted whenever it fails, as many times as it takes, straight away; use
+/// [`Supervisor::restart`], [`Supervisor::max_restarts`], and [`Supervisor::backoff`] to change that. Each run uses the
+/// options from [`Supervisor::runner`], e.g. a timeout applies to each run separately.
+///
+/// ```
+/// use better_commands::{Backoff, Supervisor, SupervisorEvent};
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// let mut command = Command::new("bash");
+/// command.arg("-c").arg("echo starting; exit 1");
+/// let mut supervisor = Supervisor::new(command)
+///     .max_restarts(2)
+///     .backoff(Backoff::fixed(Duration::from_millis(10)))
+///     .on_event(|event| match event {
+///         SupervisorEvent::Line { run, line } => println!("[run {}] {}", run, line.content),
+///         SupervisorEvent::Restarting { delay, .. } => println!("restarting in {:?}", delay),
+///         _ => {}
+///     });
+///
+/// supervisor.start().unwrap();
+/// // it gives up after restarting it twice
+/// let output = supervisor.wait().unwrap().unwrap();
+/// assert_eq!(Some(1), output.status_code());
+/// assert_eq!(2, supervisor.restarts());
+/// ```
+///
+/// <small>Commands are always run with [`Backend::Threads`], since the supervisor needs to check on them while
+/// they're running.</small>
+pub struct Supervisor {
+    /// `None` while it's running, since it's being used by the thread running it
+    command: Option<Command>,
+    runner: Runner,
+    restart: Restart,
+    max_restarts: Option<u32>,
+    backoff: Backoff,
+    on_event: Option<EventCallback>,
+    running: Option<Supervision>,
+    restarts: Arc<AtomicU32>,
+    pid: Arc<Mutex<Option<u32>>>,
+}
+
+/// The thread running a [`Supervisor`]'s command
+struct Supervision {
+    stop: Sender<()>,
+    thread: JoinHandle<(Command, Result<CmdOutput, Error>)>,
+}
+
+impl std::fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f
+            .debug_struct("Supervisor")
+            .field("command", &self.command)
+            .field("runner", &self.runner)
+            .field("restart", &self.restart)
+            .field("max_restarts", &self.max_restarts)
+            .field("backoff", &self.backoff)
+            .field("running", &self.running.is_some())
+            .field("restarts", &self.restarts)
+            .finish_non_exhaustive();
+    }
+}
+
+impl Supervisor {
+    /// Creates a supervisor for `command`, which isn't started until [`Supervisor::start`] is called
+    pub fn new(command: Command) -> Self {
+        return Supervisor {
+            command: Some(command),
+            runner: Runner::new().backend(Backend::Threads).retries(0),
+            restart: Restart::default(),
+            max_restarts: None,
+            backoff: Backoff::none(),
+            on_event: None,
+            running: None,
+            restarts: Arc::default(),
+            pid: Arc::default(),
+        };
+    }
+
+    /// Sets the options to run the command with
+    ///
+    /// <small>Its retries aren't used; restarts replace them</small>
+    pub fn runner(mut self, runner: Runner) -> Self {
+        self.runner = runner.backend(Backend::Threads).retries(0);
+        return self;
+    }
+
+    /// Sets when the command is restarted ([`Restart::OnFailure`] by default)
+    pub fn restart(mut self, restart: Restart) -> Self {
+        self.restart = restart;
+        return self;
+    }
+
+    /// Gives up after restarting the command this many times
+    pub fn max_restarts(mut self, max_restarts: u32) -> Self {
+        self.max_restarts = Some(max_restarts);
+        return self;
+    }
+
+    /// Sets how long to wait before each restart (not waiting at all by default)
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        return self;
+    }
+
+    /// Calls `callback` for every line the command prints and whenever it starts or exits, across every run
+    ///
+    /// It's called from the threads reading the output and running the command, but only ever one at a time.
+    pub fn on_event(mut self, callback: impl FnMut(SupervisorEvent<'_>) + Send + 'static) -> Self {
+        self.on_event = Some(Arc::new(Mutex::new(callback)));
+        return self;
+    }
+
+    /// Starts the command in the background, restarting it as needed until [`Supervisor::stop`] is called or it gives
+    /// up
+    ///
+    /// This does nothing if it's already running. It can be started again after being stopped (or giving up), which
+    /// resets [`Supervisor::restarts`].
+    pub fn start(&mut self) -> Result<(), Error> {
+        if self.is_running() {
+            return Ok(());
+        }
+        self.finish();
+
+        let mut command = self.command.take().unwrap();
+        let (stop, stopped) = mpsc::channel();
+        let run = Arc::new(AtomicU32::new(0));
+        let mut runner = self.runner.clone();
+        if let Some(on_event) = &self.on_event {
+            let on_event = on_event.clone();
+            let run = run.clone();
+            let on_line = runner.capture.on_line.take();
+            runner = runner.on_line(move |line| {
+                if let Some(on_line) = &on_line {
+                    (on_line.lock().unwrap())(line);
+                }
+                let run = run.load(Ordering::SeqCst);
+                (on_event.lock().unwrap())(SupervisorEvent::Line { run, line });
+            });
+        }
+
+        self.restarts.store(0, Ordering::SeqCst);
+        let supervise = Supervise {
+            runner,
+            restart: self.restart,
+            max_restarts: self.max_restarts,
+            backoff: self.backoff,
+            on_event: self.on_event.clone(),
+            stopped,
+            run,
+            restarts: self.restarts.clone(),
+            pid: self.pid.clone(),
+        };
+        // the first run is started here, so an error starting it can be returned straight away
+        let first = match supervise.spawn(&mut command, 0) {
+            Ok(first) => first,
+            Err(e) => {
+                self.command = Some(command);
+                return Err(e);
+            }
+        };
+        let thread = thread::spawn(move || {
+            let result = supervise.run(&mut command, first);
+            *supervise.pid.lock().unwrap() = None;
+            return (command, result);
+        });
+        self.running = Some(Supervision { stop, thread });
+        return Ok(());
+    }
+
+    /// Stops the command (see [`RunningCommand::cancel`](crate::RunningCommand::cancel)), without restarting it,
+    /// returning the output of the last run
+    ///
+    /// This returns `None` if it was never started.
+    pub fn stop(&mut self) -> Result<Option<CmdOutput>, Error> {
+        if let Some(running) = &self.running {
+            // it might've already given up, and stopped listening
+            let _ = running.stop.send(());
+        }
+        return self.wait();
+    }
+
+    /// Waits for the supervisor to give up, returning the output of the last run
+    ///
+    /// With [`Restart::Always`] and no [`Supervisor::max_restarts`], this only returns if the command can't be
+    /// started again. It returns `None` if it was never started.
+    pub fn wait(&mut self) -> Result<Option<CmdOutput>, Error> {
+        return self.finish().transpose();
+    }
+
+    /// Whether the command is running, or waiting to be restarted
+    pub fn is_running(&self) -> bool {
+        return self
+            .running
+            .as_ref()
+            .is_some_and(|running| !running.thread.is_finished());
+    }
+
+    /// Returns the PID of the command, if it's running
+    pub fn pid(&self) -> Option<u32> {
+        return *self.pid.lock().unwrap();
+    }
+
+    /// Returns how many times the command has been restarted since it was started
+    pub fn restarts(&self) -> u32 {
+        return self.restarts.load(Ordering::SeqCst);
+    }
+
+    /// Waits for the thread running the command to finish, if there is one, and takes the command back
+    fn finish(&mut self) -> Option<Result<CmdOutput, Error>> {
+        let running = self.running.take()?;
+        let (command, result) = match running.thread.join() {
+            Ok(finished) => finished,
+            Err(panic) => std::panic::resume_unwind(panic),
+        };
+        self.command = Some(command);
+        return Some(result);
+    }
+}
+
+impl Drop for Supervisor {
+    /// Stops the command
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// Everything the thread running the command needs
+struct Supervise {
+    runner: Runner,
+    restart: Restart,
+    max_restarts: Option<u32>,
+    backoff: Backoff,
+    on_event: Option<EventCallback>,
+    /// Gets a message (or disconnects) when the supervisor should stop
+    stopped: Receiver<()>,
+    /// The current run, for the `on_line` callback
+    run: Arc<AtomicU32>,
+    restarts: Arc<AtomicU32>,
+    pid: Arc<Mutex<Option<u32>>>,
+}
+
+impl Supervise {
+    /// Keeps running the command, starting with `first`, until it's stopped or gives up
+    fn run(&self, command: &mut Command, first: RunningCommand) -> Result<CmdOutput, Error> {
+        let mut running = first;
+        let mut run = 0;
+        loop {
+            let mut stopping = false;
+            loop {
+                match self.stopped.recv_timeout(POLL_INTERVAL) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => {
+                        stopping = true;
+                        running.cancel()?;
+                        break;
+                    }
+                }
+                if running.ready_to_wait()? {
+                    break;
+                }
+            }
+            let output = running.wait()?;
+            self.emit(SupervisorEvent::Exited {
+                run,
+                output: &output,
+            });
+
+            let restart = match self.restart {
+                Restart::Always => true,
+                Restart::OnFailure => !output.success(),
+                Restart::Never => false,
+            };
+            if stopping || !restart || self.max_restarts.is_some_and(|max| run >= max) {
+                return Ok(output);
+            }
+            let delay = self.backoff.delay(run);
+            run += 1;
+            self.emit(SupervisorEvent::Restarting { run, delay });
+            if self.stopped.recv_timeout(delay) != Err(RecvTimeoutError::Timeout) {
+                return Ok(output);
+            }
+            self.restarts.store(run, Ordering::SeqCst);
+            running = self.spawn(command, run)?;
+        }
+    }
+
+    /// Starts the command for the `run`th time
+    fn spawn(&self, command: &mut Command, run: u32) -> Result<RunningCommand, Error> {
+        self.run.store(run, Ordering::SeqCst);
+        let Some(on_event) = &self.on_event else {
+            let running = self.runner.spawn(command)?;
+            *self.pid.lock().unwrap() = Some(running.id());
+            return Ok(running);
+        };
+        // held until `Started` is sent, so it comes before any lines
+        let mut on_event = on_event.lock().unwrap();
+        let running = self.runner.spawn(command)?;
+        *self.pid.lock().unwrap() = Some(running.id());
+        on_event(SupervisorEvent::Started {
+            run,
+            pid: running.id(),
+        });
+        return Ok(running);
+    }
+
+    fn emit(&self, event: SupervisorEvent<'_>) {
+        if let Some(on_event) = &self.on_event {
+            (on_event.lock().unwrap())(event);
+        }
+    }
+}
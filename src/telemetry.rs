@@ -0,0 +1,38 @@
+//! Metrics for every command that's run, through the [`metrics`](https://docs.rs/metrics) facade
+//!
+//! Everything is labeled with `program`, the file name of the program that was run (e.g. `git` for `/usr/bin/git`):
+//!
+//! - `better_commands_runs_total`: a counter of commands which have exited
+//! - `better_commands_failures_total`: a counter of those which failed (exited with anything other than 0, or were
+//!   killed)
+//! - `better_commands_duration_seconds`: a histogram of how long each one ran
+//! - `better_commands_output_bytes`: a histogram of how much each one printed, across all its streams
+use crate::CmdOutput;
+use std::path::Path;
+use std::process::Command;
+
+/// Returns the label for the program `command` runs
+pub(crate) fn program(command: &Command) -> String {
+    let program = Path::new(command.get_program());
+    return program
+        .file_name()
+        .unwrap_or(program.as_os_str())
+        .to_string_lossy()
+        .into_owned();
+}
+
+/// Records the metrics for a command that's exited
+pub(crate) fn record(program: &str, output: &CmdOutput) {
+    let labels = [("program", program.to_string())];
+    ::metrics::counter!("better_commands_runs_total", &labels).increment(1);
+    if !output.success() {
+        ::metrics::counter!("better_commands_failures_total", &labels).increment(1);
+    }
+    ::metrics::histogram!("better_commands_duration_seconds", &labels)
+        .record(output.duration.as_secs_f64());
+    let bytes: usize = output
+        .iter()
+        .map(|line| line.content.len() + usize::from(line.terminated))
+        .sum();
+    ::metrics::histogram!("better_commands_output_bytes", &labels).record(bytes as f64);
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// An error from expanding a template (see [`Runner::env_templated`](crate::Runner::env_templated))
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A `{name}` placeholder wasn't in the context
+    UnknownVariable(String),
+    /// A `{` was never closed, or a `}` was never opened (use `{{` and `}}` for literal braces)
+    UnmatchedBrace,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::UnknownVariable(name) => {
+                write!(f, "unknown template variable `{}`", name)
+            }
+            TemplateError::UnmatchedBrace => write!(f, "unmatched brace in template"),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// A template with `{name}` placeholders (see [`Runner::env_templated`](crate::Runner::env_templated))
+///
+/// Templates are checked when they're parsed, so a typo like an unclosed brace is caught straight away rather than when
+/// a command is run. Use `{{` and `}}` for literal braces.
+///
+/// ```
+/// use better_commands::{Template, TemplateError};
+///
+/// assert!(Template::parse("{home}/.cache/{app}").is_ok());
+/// assert_eq!(Err(TemplateError::UnmatchedBrace), Template::parse("{home/.cache"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    Variable(String),
+}
+
+impl Template {
+    /// Parses a template, returning an error if its braces don't match up
+    pub fn parse(template: &str) -> Result<Template, TemplateError> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError::UnmatchedBrace),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(Piece::Variable(name));
+                }
+                '}' => return Err(TemplateError::UnmatchedBrace),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        return Ok(Template { pieces });
+    }
+
+    /// Replaces each `{name}` with its value from `context`
+    pub(crate) fn expand(
+        &self,
+        context: &HashMap<String, String>,
+    ) -> Result<String, TemplateError> {
+        let mut expanded = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(text) => expanded.push_str(text),
+                Piece::Variable(name) => match context.get(name) {
+                    Some(value) => expanded.push_str(value),
+                    None => return Err(TemplateError::UnknownVariable(name.clone())),
+                },
+            }
+        }
+        return Ok(expanded);
+    }
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        return Template::parse(template);
+    }
+}
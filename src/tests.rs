@@ -1,14 +1,36 @@
+// the original tests are kept as they were written
+#![allow(
+    clippy::needless_borrow,
+    clippy::unused_io_amount,
+    clippy::ineffective_open_options
+)]
 #[cfg(test)]
 use crate::*;
 use std::io::Write;
 use std::os::unix::fs::FileExt;
 use std::process::Command;
+use std::sync::{mpsc, Arc, Mutex};
 use std::{
     fs::remove_file,
     hash::{BuildHasher, Hasher, RandomState},
 };
 use std::{fs::File, thread::sleep};
 
+/// A writer which can be read from after being given away, for checking what was written
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return Ok(());
+    }
+}
+
 /// Tests what stdout prints
 #[test]
 fn stdout_content() {
@@ -20,8 +42,8 @@ hiiiiiiiiiiiii",
     ))
     .stdout()
     .unwrap()
-    .into_iter()
-    .map(|line| line.content)
+    .iter()
+    .map(|line| line.content.clone())
     .collect::<Vec<String>>();
 
     assert_eq!(expected, output);
@@ -39,8 +61,8 @@ fn stderr_content() {
             .arg("echo -n 'helloooooooooo\nhiiiiiiiiiiiii' >&2"))
         .stderr()
         .unwrap()
-        .into_iter()
-        .map(|line| { line.content })
+        .iter()
+        .map(|line| { line.content.clone() })
         .collect::<Vec<String>>()
     );
 }
@@ -65,7 +87,8 @@ fn test_output_is_sorted_sort_works() {
         .arg("-c")
         .arg("echo hi; echo hi; echo hi; echo hi; echo hi"))
     .stdout()
-    .unwrap();
+    .unwrap()
+    .to_vec();
     let mut sorted = cmd.clone();
     // To avoid an accidental bogosort
     while sorted.is_sorted() {
@@ -88,6 +111,7 @@ fn shuffle_vec<T>(vec: &mut [T]) {
 }
 
 #[test]
+#[rustfmt::skip]
 fn test_run_funcs() {
     // TODO: Add error handling to delete the file on exit
     File::create_new("./tmp-run_funcs").unwrap();
@@ -144,6 +168,7 @@ fn test_run_funcs() {
 }
 
 #[test]
+#[rustfmt::skip]
 fn test_run_funcs_with_lines() {
     // TODO: Add error handling to delete the file on exit
     File::create_new("./tmp-run_funcs_with_lines").unwrap();
@@ -209,3 +234,3358 @@ fn test_run_funcs_with_lines() {
     assert_eq!(output.clone().lines().unwrap()[0].content, "hi");
     assert_eq!(output.lines().unwrap()[1].content, "hello");
 }
+
+/// Tests that redaction covers both the lines and the command
+#[test]
+fn test_redacted() {
+    let rules = RedactionRules::new()
+        .literal("hunter2")
+        .regex(regex::Regex::new(r"token=\S+").unwrap());
+    let output = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo 'pw: hunter2'; >&2 echo 'token=abc123 ok'"))
+    .redacted(&rules);
+
+    assert_eq!(
+        "bash -c echo 'pw: [REDACTED]'; >&2 echo '[REDACTED] ok'",
+        output.command()
+    );
+    let mut contents = output
+        .lines()
+        .unwrap()
+        .into_iter()
+        .map(|line| line.content)
+        .collect::<Vec<String>>();
+    contents.sort();
+    assert_eq!(vec!["[REDACTED] ok", "pw: [REDACTED]"], contents);
+}
+
+/// Tests that a failing command is retried until it succeeds, keeping each failed attempt
+#[test]
+fn test_runner_retries() {
+    // TODO: Add error handling to delete the file on exit
+    File::create_new("./tmp-runner_retries").unwrap();
+    let output =
+        Runner::new()
+            .retries(5)
+            .backoff(Backoff::fixed(Duration::from_millis(50)))
+            .run(Command::new("bash").arg("-c").arg(
+                "echo attempt >> ./tmp-runner_retries; [ $(wc -l < ./tmp-runner_retries) -ge 3 ]",
+            ))
+            .unwrap();
+    remove_file("./tmp-runner_retries").unwrap();
+
+    assert_eq!(Some(0), output.clone().status_code());
+    assert_eq!(2, output.failed_attempts().len());
+    assert_eq!(Some(1), output.failed_attempts()[0].clone().status_code());
+}
+
+/// Tests that a command running past its timeout gets killed
+#[test]
+fn test_runner_timeout() {
+    let output = Runner::new()
+        .timeout(Duration::from_millis(200))
+        .run(Command::new("sleep").arg("5"))
+        .unwrap();
+
+    assert_eq!(Some(KillReason::Timeout), output.kill_reason());
+    assert!(output.duration() < Duration::from_secs(2));
+}
+
+/// Tests that serializing and deserializing keeps the output intact
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip() {
+    let output = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo hi; sleep 0.1; >&2 echo hello"));
+    let json = serde_json::to_string(&output).unwrap();
+    assert!(json.contains(&format!("\"schema_version\":{}", SCHEMA_VERSION)));
+
+    let loaded: CmdOutput = serde_json::from_str(&json).unwrap();
+    assert_eq!(output.command(), loaded.command());
+    assert_eq!(output.clone().duration(), loaded.clone().duration());
+    let original = output.clone().lines().unwrap();
+    let loaded_lines = loaded.clone().lines().unwrap();
+    assert_eq!(original.len(), loaded_lines.len());
+    for (original, loaded_line) in original.iter().zip(&loaded_lines) {
+        assert_eq!(original.content, loaded_line.content);
+        assert_eq!(
+            original.time - output.clone().start_time(),
+            loaded_line.time - loaded.clone().start_time()
+        );
+    }
+
+    let future = json.replace(
+        &format!("\"schema_version\":{}", SCHEMA_VERSION),
+        &format!("\"schema_version\":{}", SCHEMA_VERSION + 1),
+    );
+    assert!(serde_json::from_str::<CmdOutput>(&future).is_err());
+}
+
+/// Tests that a rate limiter set to error refuses to run too many commands at once
+#[test]
+fn test_rate_limit_concurrent() {
+    let runner = Runner::new().rate_limit(RateLimiter::new().max_concurrent(1).error_on_exceed());
+    let first = {
+        let runner = runner.clone();
+        thread::spawn(move || runner.run(Command::new("sleep").arg("1")))
+    };
+    sleep(Duration::from_millis(200));
+
+    assert!(matches!(
+        runner.run(&mut Command::new("true")),
+        Err(Error::RateLimited)
+    ));
+    assert!(first.join().unwrap().is_ok());
+    assert!(runner.run(&mut Command::new("true")).is_ok());
+}
+
+/// Tests that a limit of 0 is rejected up front, rather than every command waiting forever
+#[test]
+fn test_rate_limit_zero() {
+    assert!(std::panic::catch_unwind(|| RateLimiter::new().max_concurrent(0)).is_err());
+    assert!(std::panic::catch_unwind(|| RateLimiter::new().max_spawns_per_second(0)).is_err());
+    assert!(std::panic::catch_unwind(|| RateLimiter::new().max_concurrent(1)).is_ok());
+}
+
+/// Tests that a pipeline passes output between stages, keeping each stage's stderr and exit code
+#[test]
+fn test_pipeline() {
+    let first = Command::new("yes");
+    let mut second = Command::new("bash");
+    second.arg("-c").arg("head -n 2; >&2 echo second; exit 3");
+
+    let output = Pipeline::new(first).pipe(second).run().unwrap();
+
+    // `yes` only exits (due to SIGPIPE) because `head` closed the pipe
+    assert_eq!(vec![None, Some(3)], output.status_codes());
+    assert_eq!(Some(3), output.status_code());
+    let lines: Vec<(usize, String)> = output
+        .lines()
+        .into_iter()
+        .map(|(stage, line)| (stage, line.content.clone()))
+        .collect();
+    assert!(lines.contains(&(1, "y".to_string())));
+    assert!(lines.contains(&(1, "second".to_string())));
+    assert_eq!(3, output.stages()[1].clone().lines().unwrap().len());
+}
+
+/// Tests that a chain only runs commands whose conditions are met
+#[test]
+fn test_chain() {
+    let mut exit_3 = Command::new("bash");
+    exit_3.arg("-c").arg("exit 3");
+    let mut echo = Command::new("echo");
+    echo.arg("cleanup");
+
+    let output = Chain::new(Command::new("true"))
+        .and_then(exit_3)
+        .and_then(Command::new("true"))
+        .or_else(echo)
+        .run()
+        .unwrap();
+
+    let ran: Vec<bool> = output
+        .stages()
+        .iter()
+        .map(|stage| stage.is_some())
+        .collect();
+    assert_eq!(vec![true, true, false, true], ran);
+    assert_eq!(Some(3), output.stages()[1].clone().unwrap().status_code());
+    assert_eq!(
+        "cleanup",
+        output.last_output().clone().lines().unwrap()[0].content
+    );
+    assert!(output.success());
+}
+
+/// Tests that only stderr is captured, with stdout going wherever the command says
+#[test]
+fn test_run_stderr_only() {
+    let output = run_stderr_only(
+        Command::new("bash")
+            .arg("-c")
+            .arg("echo hi; >&2 echo hello")
+            .stdout(Stdio::null()),
+    );
+
+    assert_eq!(Some(0), output.clone().status_code());
+    assert_eq!(Some(&[][..]), output.stdout());
+    assert_eq!("hello", output.lines().unwrap()[0].content);
+}
+
+/// Tests that dropping a running command kills it, unless it's detached
+#[test]
+fn test_running_command_drop() {
+    let running = Runner::new()
+        .spawn(Command::new("sleep").arg("60"))
+        .unwrap();
+    let pid = running.id().to_string();
+    drop(running);
+    assert_eq!(
+        Some(1),
+        run(Command::new("kill").arg("-0").arg(&pid)).status_code()
+    );
+
+    let mut running = Runner::new()
+        .spawn(Command::new("sleep").arg("60"))
+        .unwrap();
+    running.detach_on_drop();
+    let pid = running.id().to_string();
+    drop(running);
+    assert_eq!(
+        Some(0),
+        run(Command::new("kill").arg("-0").arg(&pid)).status_code()
+    );
+    run(Command::new("kill").arg(&pid));
+}
+
+/// Tests that a timeout kills grandchildren too when using a process group
+#[test]
+fn test_process_group_timeout() {
+    let start = Instant::now();
+    let output = Runner::new()
+        .timeout(Duration::from_millis(200))
+        .process_group(true)
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("sleep 60 & sleep 60 & wait"),
+        )
+        .unwrap();
+
+    assert_eq!(Some(KillReason::Timeout), output.kill_reason());
+    // without the process group, the `sleep`s would keep the pipes open for a minute
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // it's only for that run, not every later one
+    #[cfg(target_os = "linux")]
+    {
+        let mut command = Command::new("bash");
+        command
+            .arg("-c")
+            .arg(r#"read -a stat < /proc/$$/stat; [ "${stat[0]}" = "${stat[4]}" ]"#);
+        let output = Runner::new().process_group(true).run(&mut command).unwrap();
+        assert!(output.success());
+        assert!(!run(&mut command).success());
+    }
+}
+
+/// Tests that a command printing too much gets killed
+#[test]
+fn test_kill_after_lines() {
+    let output = Runner::new()
+        .kill_after_lines(100)
+        .run(&mut Command::new("yes"))
+        .unwrap();
+
+    assert_eq!(Some(KillReason::OutputLimit), output.kill_reason());
+    assert!(output.lines().unwrap().len() > 100);
+}
+
+/// Tests that each line knows how long after the start it was printed
+#[test]
+fn test_line_offset() {
+    let lines = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo a; sleep 0.3; echo b"))
+    .lines()
+    .unwrap();
+
+    assert!(lines[0].offset < Duration::from_millis(300));
+    assert!(lines[1].offset >= Duration::from_millis(300));
+}
+
+/// Tests that lines are sent to every sink
+#[test]
+fn test_sinks() {
+    let path = "./tmp-sinks";
+    let (sender, receiver) = mpsc::channel();
+    let collected = Arc::new(Mutex::new(Vec::new()));
+    let runner = Runner::new()
+        .sink(Tee::new(sender, collected.clone()))
+        .sink(FileSink::create(path).unwrap());
+
+    runner
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo hi; >&2 echo hello"),
+        )
+        .unwrap();
+    let file = std::fs::read_to_string(path).unwrap();
+    remove_file(path).unwrap();
+
+    let received: Vec<String> = receiver.try_iter().map(|line| line.content).collect();
+    assert_eq!(2, received.len());
+    assert_eq!(2, collected.lock().unwrap().len());
+    assert!(file.contains("hi\n") && file.contains("hello\n"));
+}
+
+/// Tests that I/O statistics are read from /proc
+#[cfg(target_os = "linux")]
+#[test]
+fn test_io_stats() {
+    let output = Runner::new()
+        .io_stats(true)
+        .timeout(Duration::from_secs(10))
+        .run(
+            Command::new("head")
+                .arg("-c")
+                .arg("100000")
+                .arg("/dev/zero"),
+        )
+        .unwrap();
+    assert!(output.io_stats().unwrap().write_chars >= 100000);
+
+    let output = Runner::new()
+        .io_stats(true)
+        .run(
+            Command::new("head")
+                .arg("-c")
+                .arg("100000")
+                .arg("/dev/zero"),
+        )
+        .unwrap();
+    assert!(output.io_stats().unwrap().read_chars >= 100000);
+}
+
+/// Tests that lines printed at the same time are ordered by their sequence number
+#[test]
+fn test_line_seq_order() {
+    let first = Line::from_stderr("first");
+    let mut second = Line::from_stdout("second");
+    second.time = first.time;
+
+    assert!(first.seq < second.seq);
+    assert!(first < second);
+    let mut lines = vec![second.clone(), first.clone()];
+    lines.sort();
+    assert_eq!(vec![first, second], lines);
+}
+
+/// Tests that lines are numbered within their own stream, and indexed across every stream
+#[test]
+fn test_line_numbers() {
+    let output = Runner::new()
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo a; sleep 0.05; echo b >&2; sleep 0.05; echo c"),
+        )
+        .unwrap();
+
+    let lines = output.lines().unwrap();
+    let numbers: Vec<_> = lines
+        .iter()
+        .map(|line| (line.content.as_str(), line.line_number, line.global_index))
+        .collect();
+    assert_eq!(vec![("a", 1, 0), ("b", 1, 1), ("c", 2, 2)], numbers);
+}
+
+/// Tests that lines from other sources can be added in with the command's output, in order
+#[test]
+fn test_system_lines() {
+    let mut output = Runner::new()
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo a; sleep 0.1; echo b >&2"),
+        )
+        .unwrap();
+    let mut retry = Line::system("--- retry 2 ---");
+    retry.time = output.stdout().unwrap()[0].time + Duration::from_millis(50);
+    output.insert_line(retry);
+    output.insert_line(Line::custom("harness", "done"));
+
+    assert_eq!(
+        "[stdout] a\n[system] --- retry 2 ---\n[stderr] b\n[harness] done",
+        output.merged_string().unwrap()
+    );
+    let numbers: Vec<_> = output
+        .iter()
+        .map(|line| (line.line_number, line.global_index))
+        .collect();
+    assert_eq!(vec![(1, 0), (1, 1), (1, 2), (1, 3)], numbers);
+    assert_eq!(
+        1,
+        output
+            .grep_in(LineType::Custom("harness".to_string()), "done")
+            .len()
+    );
+}
+
+/// Tests writing lines and outputs for people to read
+#[test]
+fn test_display() {
+    let mut line = Line::from_stderr("oops");
+    line.offset = Duration::from_millis(1203);
+    assert_eq!("[+1.203s stderr] oops", line.to_string());
+
+    let pretty = run_funcs(&mut Command::new("true"), |_| {}, |_| {}).pretty();
+    assert!(pretty.starts_with("`true` succeeded after "));
+    assert!(pretty.ends_with("s\n(output wasn't captured)"));
+}
+
+/// Tests that long lines are cut short, or make the command fail
+#[test]
+fn test_max_line_length() {
+    let output = Runner::new()
+        .max_line_length(3, LongLines::Truncate)
+        .run(Command::new("printf").arg("short\nok\nnaïve"))
+        .unwrap();
+    let lines: Vec<_> = output
+        .lines()
+        .unwrap()
+        .into_iter()
+        .map(|line| (line.content, line.truncated))
+        .collect();
+    assert_eq!(
+        vec![
+            ("sho".to_string(), true),
+            ("ok".to_string(), false),
+            // "ï" is two bytes, so there's only room for half of it
+            ("na".to_string(), true)
+        ],
+        lines
+    );
+
+    // never prints a newline, so this only finishes if it's killed partway through the line
+    let result = Runner::new()
+        .max_line_length(1024, LongLines::Fail)
+        .timeout(Duration::from_secs(10))
+        .run(Command::new("cat").arg("/dev/zero"));
+    match result {
+        Err(Error::LineTooLong(output)) => {
+            assert_eq!(Some(KillReason::LineTooLong), output.kill_reason())
+        }
+        other => panic!("expected Error::LineTooLong, got {:?}", other),
+    }
+}
+
+/// Tests that output past the limits is thrown away, without stopping the command
+#[test]
+fn test_max_output() {
+    let output = Runner::new()
+        .max_output_lines(3)
+        .run(Command::new("seq").arg("10"))
+        .unwrap();
+    assert!(output.truncated());
+    assert_eq!(Some("1\n2\n3".to_string()), output.stdout_string());
+    assert_eq!(Some(0), output.status_code());
+
+    // "1\n" to "4\n" is 8 bytes
+    let output = Runner::new()
+        .max_output_bytes(8)
+        .run(Command::new("seq").arg("5"))
+        .unwrap();
+    assert!(output.truncated());
+    assert_eq!(4, output.len());
+
+    let output = Runner::new()
+        .max_output_bytes(100)
+        .run(Command::new("seq").arg("5"))
+        .unwrap();
+    assert!(!output.truncated());
+    assert_eq!(5, output.len());
+}
+
+/// Tests splitting the output on something other than newlines
+#[test]
+fn test_delimiter() {
+    let dir = std::env::temp_dir().join("better-commands-test_delimiter");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("with\nnewline"), "").unwrap();
+    std::fs::write(dir.join("plain"), "").unwrap();
+    let output = Runner::new()
+        .delimiter(b'\0')
+        .run(
+            Command::new("find")
+                .arg(".")
+                .arg("-type")
+                .arg("f")
+                .arg("-print0")
+                .current_dir(&dir),
+        )
+        .unwrap();
+    let mut files: Vec<String> = output.iter().map(|line| line.content.clone()).collect();
+    files.sort();
+    assert_eq!(vec!["./plain", "./with\nnewline"], files);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // records separated by blank lines, written a bit at a time so the delimiter's split between reads
+    let output = Runner::new()
+        .delimiter("\n\n")
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("printf 'a\\nb\\n'; sleep 0.05; printf '\\nc'"),
+        )
+        .unwrap();
+    let records: Vec<String> = output.iter().map(|line| line.content.clone()).collect();
+    assert_eq!(vec!["a\nb", "c"], records);
+}
+
+/// Tests that a last line without a newline is still captured, and marked as such
+#[test]
+fn test_terminated() {
+    let terminated = |output: CmdOutput| -> Vec<(String, bool)> {
+        return output
+            .lines()
+            .unwrap()
+            .into_iter()
+            .map(|line| (line.content, line.terminated))
+            .collect();
+    };
+    let expected = vec![("a".to_string(), true), ("b".to_string(), false)];
+
+    let output = Runner::new()
+        .run(Command::new("printf").arg("a\\nb"))
+        .unwrap();
+    assert_eq!(expected, terminated(output));
+    assert_eq!(
+        expected,
+        terminated(run(Command::new("printf").arg("a\\nb")))
+    );
+    let output = Runner::new()
+        .delimiter(b'\0')
+        .run(Command::new("printf").arg("a\\0b"))
+        .unwrap();
+    assert_eq!(expected, terminated(output));
+}
+
+/// Tests that functions handling the output can fail without taking everything down with them
+#[test]
+fn test_try_run_funcs() {
+    let output = try_run_funcs_with_lines(
+        Command::new("bash").arg("-c").arg("echo out; >&2 echo err"),
+        |lines| lines.map(|line| Ok(Line::from_stdout(line?))).collect(),
+        |lines| {
+            lines
+                .map(|line| Ok(Line::from_stderr(line?)))
+                .collect::<std::io::Result<_>>()
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        "[stdout] out\n[stderr] err",
+        output.merged_string().unwrap()
+    );
+
+    // the function gives up while the command is still printing, which shouldn't leave the command stuck
+    let result = try_run_funcs(
+        Command::new("bash").arg("-c").arg("seq 1000000; exit 3"),
+        |mut lines| match lines.next() {
+            Some(_) => Err("didn't want any output"),
+            None => Ok(()),
+        },
+        |_| Ok(()),
+    );
+    match result {
+        Err(Error::Callback(e)) => {
+            assert_eq!(LineType::Stdout, e.printed_to);
+            assert_eq!(
+                "stdout handler failed: didn't want any output",
+                e.to_string()
+            );
+            assert_eq!(Some(3), e.output.status_code());
+        }
+        other => panic!("expected Error::Callback, got {:?}", other),
+    }
+
+    assert!(matches!(
+        try_run_funcs(&mut Command::new(""), |_| Ok::<(), String>(()), |_| Ok(())),
+        Err(Error::EmptyCommand)
+    ));
+}
+
+/// Tests that the functions handling the output can kill the command
+#[test]
+fn test_run_funcs_with_control() {
+    let (sender, receiver) = mpsc::channel();
+    let output = run_funcs_with_control(
+        Command::new("bash")
+            .arg("-c")
+            .arg(">&2 echo fatal error; exec sleep 10"),
+        |_, _| {},
+        move |lines, control| {
+            for line in lines {
+                if line.unwrap().contains("fatal") {
+                    sender.send((control.pid(), control.elapsed())).unwrap();
+                    control.kill().unwrap();
+                }
+            }
+            // it's already exited, so this doesn't do anything
+            control.kill().unwrap();
+        },
+    );
+
+    let (pid, elapsed) = receiver.recv().unwrap();
+    assert!(pid > 0);
+    assert!(elapsed < Duration::from_secs(10));
+    assert!(output.clone().duration() < Duration::from_secs(10));
+    assert_eq!(Some(libc::SIGKILL), output.signal());
+}
+
+/// Tests that lines sent over a full channel are held up, or dropped, depending on the backpressure policy
+#[test]
+fn test_line_channel() {
+    let (sink, receiver) = line_channel(2, Backpressure::Block);
+    let runner = Runner::new().sink(sink);
+    let handle = std::thread::spawn(move || {
+        return runner.run(Command::new("seq").arg("5")).unwrap();
+    });
+    sleep(Duration::from_millis(200));
+    // the channel's full, so the command can't be done yet
+    assert!(!handle.is_finished());
+    let lines: Vec<String> = receiver.map(|line| line.content).collect();
+    assert_eq!(vec!["1", "2", "3", "4", "5"], lines);
+    assert_eq!(5, handle.join().unwrap().stdout().unwrap().len());
+
+    let (sink, receiver) = line_channel(2, Backpressure::DropNewest);
+    Runner::new()
+        .sink(sink)
+        .run(Command::new("seq").arg("5"))
+        .unwrap();
+    assert_eq!(3, receiver.dropped());
+    assert_eq!("1", receiver.recv().unwrap().content);
+    assert_eq!("2", receiver.try_recv().unwrap().content);
+    assert!(receiver.recv_timeout(Duration::from_millis(10)).is_none());
+}
+
+/// Tests that lines can be streamed from async code, and that the output's still there if the stream's dropped
+#[cfg(feature = "futures")]
+#[test]
+fn test_run_stream_async() {
+    use futures::StreamExt;
+
+    futures::executor::block_on(async {
+        let mut command = Command::new("bash");
+        command
+            .arg("-c")
+            .arg("for i in {1..2000}; do echo $i; done");
+        let (lines, output) = Runner::new().run_stream_async(command);
+        let lines: Vec<Line> = lines.collect().await;
+        assert_eq!(2000, lines.len());
+        assert_eq!("2000", lines[1999].content);
+        assert_eq!(2000, output.await.unwrap().stdout().unwrap().len());
+
+        // more lines than the stream holds, so this would never finish if dropping it didn't unblock the command
+        let mut command = Command::new("seq");
+        command.arg("5000");
+        let (lines, output) = run_stream_async(command);
+        drop(lines);
+        assert_eq!(5000, output.await.unwrap().stdout().unwrap().len());
+
+        let (_, output) = run_stream_async(Command::new(""));
+        assert!(matches!(output.await, Err(Error::EmptyCommand)));
+    });
+}
+
+/// Tests running commands natively with `async-process`, streaming their lines and killing them
+#[cfg(feature = "smol")]
+#[test]
+fn test_run_async() {
+    use futures::StreamExt;
+
+    futures::executor::block_on(async {
+        let mut command = Command::new("bash");
+        command
+            .arg("-c")
+            .arg("echo a; sleep 0.1; >&2 echo b; sleep 0.1; echo c; exit 3");
+        let output = run_async(command).await.unwrap();
+        assert_eq!(Some(3), output.clone().status_code());
+        assert_eq!(1, output.stderr().unwrap()[0].line_number);
+        let lines: Vec<String> = output
+            .lines()
+            .unwrap()
+            .into_iter()
+            .map(|line| line.content)
+            .collect();
+        assert_eq!(vec!["a", "b", "c"], lines);
+
+        let mut command = Command::new("bash");
+        command.arg("-c").arg("echo ready; exec sleep 10");
+        let mut child = spawn_async(command).unwrap();
+        assert!(child.pid() > 0);
+        assert_eq!("ready", child.next().await.unwrap().content);
+        child.kill().unwrap();
+        let output = child.wait().await.unwrap();
+        assert!(output.clone().duration() < Duration::from_secs(10));
+        assert_eq!(Some(libc::SIGKILL), output.signal());
+        assert_eq!(1, output.stdout().unwrap().len());
+
+        assert!(matches!(
+            run_async(Command::new("")).await,
+            Err(Error::EmptyCommand)
+        ));
+        assert!(matches!(
+            run_async(Command::new("/nonexistent")).await,
+            Err(Error::Spawn(_))
+        ));
+    });
+}
+
+/// Tests that hooks are run for each stage of a command, including for retries and several sets of hooks
+#[test]
+fn test_hooks() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let (spawned, lined, exited) = (events.clone(), events.clone(), events.clone());
+    let hooks = Hooks::new()
+        .on_spawn(move |pid| spawned.lock().unwrap().push(format!("spawn {}", pid > 0)))
+        .on_line(move |line| lined.lock().unwrap().push(format!("line {}", line.content)))
+        .on_exit(move |output| {
+            exited
+                .lock()
+                .unwrap()
+                .push(format!("exit {:?}", output.clone().status_code()));
+        });
+    let exits = Arc::new(Mutex::new(0));
+    let counter = exits.clone();
+
+    let output = Runner::new()
+        .hooks(hooks)
+        .hooks(Hooks::new().on_exit(move |_| *counter.lock().unwrap() += 1))
+        .retries(1)
+        .run(Command::new("bash").arg("-c").arg("echo hi; exit 1"))
+        .unwrap();
+
+    assert_eq!(1, output.failed_attempts().len());
+    let attempt = vec!["spawn true", "line hi", "exit Some(1)"];
+    assert_eq!([attempt.clone(), attempt].concat(), *events.lock().unwrap());
+    assert_eq!(2, *exits.lock().unwrap());
+}
+
+/// Tests that commands are recorded to audit logs, both a runner's and the global one
+#[test]
+fn test_audit_log() {
+    let dir = std::env::temp_dir().join("better-commands-test_audit_log");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("audit.log");
+
+    // other tests are running too, so only this test's commands are kept
+    let global = Arc::new(Mutex::new(Vec::new()));
+    let records = global.clone();
+    set_global_audit_log(AuditLog::callback(move |record| {
+        if record.command.contains("test_audit_log") {
+            records.lock().unwrap().push(record.clone());
+        }
+        return Ok(());
+    }));
+    let runner = Runner::new().audit_log(AuditLog::file(&path).unwrap());
+    runner
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("exit 2 # test_audit_log")
+                .current_dir(&dir),
+        )
+        .unwrap();
+    run(Command::new("echo").arg("test_audit_log"));
+    clear_global_audit_log();
+    run(Command::new("echo").arg("test_audit_log"));
+
+    let global = global.lock().unwrap();
+    assert_eq!(2, global.len());
+    assert_eq!("bash -c exit 2 # test_audit_log", global[0].command);
+    assert_eq!(dir, global[0].cwd);
+    assert_eq!(Some(2), global[0].status_code);
+    assert!(global[0].start <= global[0].end);
+    assert!(!global[0].user.is_empty());
+    assert_eq!("echo test_audit_log", global[1].command);
+
+    // only the runner's command is in its own log
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(1, log.lines().count());
+    assert!(log.contains(r#"status="2""#));
+    assert!(log.ends_with("command=\"bash -c exit 2 # test_audit_log\"\n"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Tests that metrics are recorded for each command, labeled by program
+#[cfg(feature = "metrics")]
+#[test]
+fn test_metrics() {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use std::collections::HashMap;
+
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    // only records metrics from this thread, so other tests don't get in the way
+    metrics::with_local_recorder(&recorder, || {
+        run(Command::new("/usr/bin/printf").arg("ab\\ncd"));
+        run(Command::new("printf").arg("x"));
+        Runner::new()
+            .run(Command::new("bash").arg("-c").arg("exit 1"))
+            .unwrap();
+    });
+
+    let metrics: HashMap<(String, String), DebugValue> = snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .map(|(key, _, _, value)| {
+            let key = key.key();
+            let program = key.labels().next().unwrap().value().to_string();
+            return ((key.name().to_string(), program), value);
+        })
+        .collect();
+    let metric = |name: &str, program: &str| &metrics[&(name.to_string(), program.to_string())];
+
+    assert_eq!(
+        &DebugValue::Counter(2),
+        metric("better_commands_runs_total", "printf")
+    );
+    assert_eq!(
+        &DebugValue::Counter(1),
+        metric("better_commands_failures_total", "bash")
+    );
+    assert!(!metrics.contains_key(&(
+        "better_commands_failures_total".to_string(),
+        "printf".to_string()
+    )));
+    assert_eq!(
+        &DebugValue::Histogram(vec![5.0.into(), 1.0.into()]),
+        metric("better_commands_output_bytes", "printf")
+    );
+    let DebugValue::Histogram(durations) = metric("better_commands_duration_seconds", "bash")
+    else {
+        panic!("duration isn't a histogram");
+    };
+    assert_eq!(1, durations.len());
+}
+
+/// Tests converting to and from `std::process::Output`, including invalid UTF-8, missing newlines, and signals
+#[test]
+fn test_std_output() {
+    let std_output = Command::new("bash")
+        .arg("-c")
+        .arg("printf 'a\\n\\xffb\\n'; printf 'c\\nd' >&2; exit 2")
+        .output()
+        .unwrap();
+    let output = CmdOutput::from_std_output("bash", std_output.clone());
+
+    assert_eq!("a\n\u{fffd}b", output.stdout_string().unwrap());
+    assert_eq!(None, output.stdout().unwrap()[0].raw);
+    assert_eq!(Some(b"\xffb".to_vec()), output.stdout().unwrap()[1].raw);
+    assert!(!output.stderr().unwrap()[1].terminated);
+    assert_eq!(
+        "[stdout] a\n[stdout] \u{fffd}b\n[stderr] c\n[stderr] d",
+        output.merged_string().unwrap()
+    );
+    assert_eq!(std_output, output.to_std_output());
+
+    let killed: std::process::Output = run(Command::new("bash").arg("-c").arg("kill -9 $$")).into();
+    assert_eq!(None, killed.status.code());
+    assert_eq!(
+        Some(9),
+        std::os::unix::process::ExitStatusExt::signal(&killed.status)
+    );
+}
+
+/// Tests that the real exit status is kept, and that `exit_ok` describes why a command failed
+#[test]
+fn test_exit_status() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let output = run(Command::new("bash").arg("-c").arg("exit 4"));
+    assert!(!output.success());
+    assert_eq!(Some(4), output.exit_status().unwrap().code());
+    assert_eq!(Some(4), output.exit_ok().unwrap_err().code());
+    assert!(run(&mut Command::new("true")).exit_ok().is_ok());
+
+    let killed = run(Command::new("bash").arg("-c").arg("kill -9 $$"));
+    assert_eq!(Some(9), killed.exit_status().unwrap().signal());
+    assert_eq!(
+        "`bash -c kill -9 $$` was killed by signal 9",
+        killed.exit_ok().unwrap_err().to_string()
+    );
+
+    let timed_out = Runner::new()
+        .timeout(Duration::from_millis(100))
+        .run(Command::new("sleep").arg("5"))
+        .unwrap();
+    let error = timed_out.exit_ok().unwrap_err();
+    assert_eq!(Some(KillReason::Timeout), error.kill_reason());
+    assert_eq!("`sleep 5` timed out", error.to_string());
+
+    let faked = FakeRunner::new()
+        .respond("make", 2, Vec::new())
+        .run(&mut Command::new("make"))
+        .unwrap();
+    assert_eq!(Some(2), faked.exit_status().unwrap().code());
+}
+
+/// Tests that stderr lines from compilers are tagged with their severity, and stdout lines are left alone
+#[test]
+fn test_classify_severity() {
+    let script = "echo 'error: on stdout'
+        echo 'src/main.rs:3:5: error[E0425]: cannot find value' >&2
+        echo '  = help: did you mean `y`?' >&2
+        echo 'main.c:7: warning: unused variable' >&2
+        echo 'main.c:2:1: note: declared here' >&2
+        echo 'Compiling foo' >&2";
+    let output = Runner::new()
+        .classify_severity(SeverityRules::defaults())
+        .run(Command::new("bash").arg("-c").arg(script))
+        .unwrap();
+
+    assert_eq!(None, output.stdout().unwrap()[0].severity());
+    let severities: Vec<Option<Severity>> = output
+        .stderr()
+        .unwrap()
+        .iter()
+        .map(|line| line.severity())
+        .collect();
+    assert_eq!(
+        vec![
+            Some(Severity::Error),
+            Some(Severity::Note),
+            Some(Severity::Warning),
+            Some(Severity::Note),
+            None
+        ],
+        severities
+    );
+    assert_eq!(1, output.errors().len());
+
+    let untagged = run(Command::new("bash").arg("-c").arg(script));
+    assert!(untagged.errors().is_empty());
+}
+
+/// Tests that each progress format is recognized, and that things which only look like counts aren't
+#[test]
+fn test_progress() {
+    assert_eq!(Some(0.125), parse_progress("pip: 12.5 % done"));
+    assert_eq!(Some(1.0), parse_progress("120%"));
+    assert_eq!(Some(0.5), parse_progress("[ 2/4] Building"));
+    assert_eq!(Some(0.5), parse_progress("512 KiB / 1 MiB"));
+    assert_eq!(Some(0.5), parse_progress("0.5kB/1000B, 3/10 files"));
+    assert_eq!(None, parse_progress("[0/0]"));
+    assert_eq!(
+        None,
+        parse_progress("released 10/12/2024, see /usr/lib/1/2")
+    );
+    assert_eq!(None, parse_progress("version 1.2/3"));
+
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let seen = progress.clone();
+    let output = Runner::new()
+        .on_progress(move |fraction| seen.lock().unwrap().push(fraction))
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo '[1/4]'; echo 'hi' >&2; echo '3/4'"),
+        )
+        .unwrap();
+    assert_eq!(vec![0.25, 0.75], *progress.lock().unwrap());
+    assert_eq!(3, output.len());
+}
+
+/// Tests that the schema version of serialized output is checked before anything else is
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_schema_version() {
+    let current = r#"{
+        "schema_version": 1,
+        "command": "echo hi",
+        "lines": [{"printed_to": "Stdout", "offset": {"secs": 0, "nanos": 5000}, "content": "hi"}],
+        "status_code": 0,
+        "duration": {"secs": 1, "nanos": 0},
+        "kill_reason": null,
+        "failed_attempts": []
+    }"#;
+    let loaded: CmdOutput = serde_json::from_str(current).unwrap();
+    assert_eq!("echo hi", loaded.command());
+    assert_eq!(Some("hi".to_string()), loaded.stdout_string());
+    assert_eq!(Duration::from_secs(1), loaded.duration());
+
+    // fields which changed in a later version don't get in the way of saying so
+    let future = r#"{"schema_version": 2, "command": ["echo", "hi"], "exit": {"code": 0}}"#;
+    let error = serde_json::from_str::<CmdOutput>(future).unwrap_err();
+    assert!(error.to_string().contains("schema version 2"), "{}", error);
+
+    let error = serde_json::from_str::<CmdOutput>(r#"{"schema_version": 0}"#).unwrap_err();
+    assert!(
+        error.to_string().contains("invalid schema version 0"),
+        "{}",
+        error
+    );
+    let error = serde_json::from_str::<CmdOutput>(r#"{"command": "echo hi"}"#).unwrap_err();
+    assert!(
+        error.to_string().contains("missing schema version"),
+        "{}",
+        error
+    );
+
+    // failed attempts are checked too
+    let nested = current.replace(
+        r#""failed_attempts": []"#,
+        &format!(r#""failed_attempts": [{}]"#, future),
+    );
+    let error = serde_json::from_str::<CmdOutput>(&nested).unwrap_err();
+    assert!(error.to_string().contains("schema version 2"), "{}", error);
+}
+
+/// Tests that templates are expanded, and that bad templates are caught before running anything
+#[test]
+fn test_env_templated() {
+    let runner = Runner::new()
+        .template_context([("name", "world")])
+        .env_templated(
+            "GREETING",
+            Template::parse("hello {name} {{literal}}").unwrap(),
+        );
+    let output = runner
+        .run(Command::new("bash").arg("-c").arg("echo $GREETING"))
+        .unwrap();
+    assert_eq!("hello world {literal}", output.lines().unwrap()[0].content);
+
+    let runner =
+        Runner::new().env_templated("GREETING", Template::parse("hello {missing}").unwrap());
+    assert!(matches!(
+        runner.run(&mut Command::new("true")),
+        Err(Error::Template(TemplateError::UnknownVariable(name))) if name == "missing"
+    ));
+    assert_eq!(
+        Err(TemplateError::UnmatchedBrace),
+        Template::parse("hello {name")
+    );
+    assert_eq!(
+        Err(TemplateError::UnmatchedBrace),
+        Template::parse("hello name}")
+    );
+}
+
+/// Tests that a scope kills its commands when it ends, even if it panics
+#[test]
+fn test_command_scope() {
+    let runner = Runner::new();
+    let (sender, receiver) = mpsc::channel();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        scope(|s| {
+            let sleep = s.spawn(&runner, Command::new("sleep").arg("60")).unwrap();
+            sender.send(sleep.id().to_string()).unwrap();
+            panic!("oops");
+        })
+    }));
+    assert!(result.is_err());
+    let pid = receiver.recv().unwrap();
+    assert_eq!(
+        Some(1),
+        run(Command::new("kill").arg("-0").arg(&pid)).status_code()
+    );
+
+    let output = scope(|s| {
+        let echo = s.spawn(&runner, Command::new("echo").arg("hi")).unwrap();
+        return echo.wait().unwrap();
+    });
+    assert_eq!(Some(0), output.status_code());
+}
+
+/// Tests joining the captured lines into strings
+#[test]
+fn test_output_strings() {
+    let output = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo a; echo b; sleep 0.1; >&2 echo c"));
+    assert_eq!(Some("a\nb".to_string()), output.stdout_string());
+    assert_eq!(Some("c".to_string()), output.stderr_string());
+    assert_eq!(
+        Some("[stdout] a\n[stdout] b\n[stderr] c".to_string()),
+        output.merged_string()
+    );
+}
+
+/// Tests how long it took for the first line of each stream to be printed
+#[test]
+fn test_first_line_latency() {
+    let output = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo a; sleep 0.2; echo b"));
+    let latency = output.first_line_latency(LineType::Stdout).unwrap();
+    assert!(latency < Duration::from_millis(200));
+    assert_eq!(None, output.first_line_latency(LineType::Stderr));
+}
+
+/// Tests iterating over an output's lines
+#[test]
+fn test_output_iter() {
+    let output = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo a; sleep 0.1; >&2 echo b; sleep 0.1; echo c"));
+    let contents: Vec<&str> = output.iter().map(|line| line.content.as_str()).collect();
+    assert_eq!(vec!["a", "b", "c"], contents);
+    assert_eq!(3, (&output).into_iter().count());
+    assert_eq!(3, output.len());
+    assert!(!output.is_empty());
+    assert_eq!("c", output.last_line().unwrap().content);
+}
+
+/// Tests pinning the locale, and that the original one is recorded
+#[test]
+fn test_pin_locale() {
+    let output = Runner::new()
+        .pin_locale("C")
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo $LC_ALL $LANG")
+                .env_remove("LC_ALL")
+                .env("LANG", "de_DE.UTF-8"),
+        )
+        .unwrap();
+    assert_eq!("C C", output.stdout().unwrap()[0].content);
+    assert_eq!(Some("de_DE.UTF-8"), output.original_locale());
+
+    // it's only for that run, including each retry
+    let mut command = Command::new("bash");
+    command
+        .arg("-c")
+        .arg("echo $LC_ALL $LANG; exit 1")
+        .env_remove("LC_ALL")
+        .env("LANG", "de_DE.UTF-8");
+    let output = Runner::new()
+        .pin_locale("C")
+        .retries(1)
+        .run(&mut command)
+        .unwrap();
+    assert_eq!(Some("de_DE.UTF-8"), output.original_locale());
+    assert_eq!(
+        Some("de_DE.UTF-8"),
+        output.failed_attempts()[0].original_locale()
+    );
+    assert_eq!(
+        "de_DE.UTF-8",
+        run(&mut command).stdout().unwrap()[0].content
+    );
+}
+
+/// Tests that the poll backend gives the same output, and still handles timeouts
+#[test]
+#[cfg(feature = "poll")]
+fn test_poll_backend() {
+    let runner = Runner::new().backend(Backend::Poll);
+    let output = runner
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo a; sleep 0.1; >&2 echo b; sleep 0.1; echo -n c"),
+        )
+        .unwrap();
+    let contents: Vec<&str> = output.iter().map(|line| line.content.as_str()).collect();
+    assert_eq!(vec!["a", "b", "c"], contents);
+    assert_eq!(LineType::Stderr, output.stderr().unwrap()[0].printed_to);
+
+    let output = runner
+        .clone()
+        .timeout(Duration::from_millis(100))
+        .run(Command::new("sleep").arg("60"))
+        .unwrap();
+    assert_eq!(Some(KillReason::Timeout), output.kill_reason());
+}
+
+/// Splits `bytes` into lines, feeding them to the splitter in chunks ending at each of `cuts`
+fn split_chunked(
+    bytes: &[u8],
+    cuts: &[usize],
+    delimiter: &[u8],
+    max_len: Option<usize>,
+    carriage_returns: CarriageReturns,
+) -> Vec<split::RawLine> {
+    let mut splitter =
+        split::LineSplitter::new(delimiter, max_len).carriage_returns(carriage_returns);
+    let mut lines = Vec::new();
+    let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut % (bytes.len() + 1)).collect();
+    cuts.push(bytes.len());
+    cuts.sort();
+    let mut start = 0;
+    for cut in cuts {
+        splitter.push(&bytes[start..cut], |line| lines.push(line));
+        start = cut;
+    }
+    lines.extend(splitter.finish());
+    return lines;
+}
+
+/// Bytes with plenty of newlines and carriage returns, so there are lots of lines and edge cases
+fn line_bytes() -> impl proptest::strategy::Strategy<Value = Vec<u8>> {
+    use proptest::prelude::*;
+    return proptest::collection::vec(
+        prop_oneof![Just(b'\n'), Just(b'\r'), Just(b'\0'), any::<u8>()],
+        0..512,
+    );
+}
+
+proptest::proptest! {
+    /// Tests that lines are split the same way however the bytes are chunked
+    #[test]
+    fn test_line_splitter_chunking(
+        bytes in line_bytes(),
+        cuts in proptest::collection::vec(0..1024usize, 0..16),
+        max_len in proptest::option::of(0..64usize),
+        delimiter in proptest::prop_oneof![
+            proptest::strategy::Just(&b"\n"[..]),
+            proptest::strategy::Just(&b"\0"[..]),
+            // longer ones can be split between reads, and overlap themselves
+            proptest::strategy::Just(&b"\0\n"[..]),
+            proptest::strategy::Just(&b"\0\0"[..]),
+        ],
+        carriage_returns in proptest::prop_oneof![
+            proptest::strategy::Just(CarriageReturns::Keep),
+            proptest::strategy::Just(CarriageReturns::Split),
+            proptest::strategy::Just(CarriageReturns::Coalesce),
+        ],
+    ) {
+        let whole = split_chunked(&bytes, &[], delimiter, max_len, carriage_returns);
+        proptest::prop_assert_eq!(&whole, &split_chunked(&bytes, &cuts, delimiter, max_len, carriage_returns));
+
+        let total: usize = whole.iter().map(|line| line.len).sum();
+        if carriage_returns == CarriageReturns::Keep {
+            // every byte is accounted for exactly once
+            proptest::prop_assert_eq!(bytes.len(), total);
+        } else {
+            // except for bytes which don't end up in any line
+            proptest::prop_assert!(total <= bytes.len());
+        }
+        for line in &whole {
+            proptest::prop_assert!(!line.bytes.windows(delimiter.len()).any(|bytes| bytes == delimiter));
+            proptest::prop_assert!(line.bytes.len() <= max_len.unwrap_or(usize::MAX));
+            if carriage_returns != CarriageReturns::Keep {
+                proptest::prop_assert!(!line.bytes.contains(&b'\r'));
+            }
+        }
+    }
+
+    /// Tests that joining the lines back together gives the original bytes, other than "\r\n" becoming "\n"
+    #[test]
+    fn test_line_splitter_round_trip(bytes in line_bytes()) {
+        let lines = split_chunked(&bytes, &[], b"\n", None, CarriageReturns::Keep);
+        let mut joined = Vec::new();
+        for line in &lines {
+            joined.extend_from_slice(&line.bytes);
+            if line.len > line.bytes.len() {
+                joined.push(b'\n');
+            }
+        }
+        let mut expected = Vec::new();
+        for (i, &byte) in bytes.iter().enumerate() {
+            if !(byte == b'\r' && bytes.get(i + 1) == Some(&b'\n')) {
+                expected.push(byte);
+            }
+        }
+        proptest::prop_assert_eq!(expected, joined);
+    }
+}
+
+/// Tests running lots of commands at once on a pool smaller than the number of commands
+#[test]
+fn test_command_pool() {
+    let pool = CommandPool::new(2);
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let pool = &pool;
+                scope.spawn(move || {
+                    pool.run(
+                        Command::new("bash")
+                            .arg("-c")
+                            .arg(format!("echo {i}; >&2 echo err")),
+                    )
+                    .unwrap()
+                })
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            let output = handle.join().unwrap();
+            assert_eq!(Some(i.to_string()), output.stdout_string());
+            assert_eq!(Some("err".to_string()), output.stderr_string());
+        }
+    });
+}
+
+/// Tests reading output in chunks, with a tiny buffer
+#[test]
+fn test_read_chunks() {
+    let runner = Runner::new()
+        .read_mode(ReadMode::Chunks)
+        .read_buffer_size(2);
+    let output = runner
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo -n abc; sleep 0.1; echo d"),
+        )
+        .unwrap();
+    let contents: Vec<&str> = output.iter().map(|line| line.content.as_str()).collect();
+    assert_eq!(vec!["ab", "c", "d"], contents);
+
+    let output = Runner::new()
+        .read_buffer_size(2)
+        .run(Command::new("echo").arg("abcdef"))
+        .unwrap();
+    assert_eq!(Some("abcdef".to_string()), output.stdout_string());
+}
+
+/// Tests that run_funcs_with_lines_unsorted keeps the lines in the order they're returned
+#[test]
+fn test_run_funcs_with_lines_unsorted() {
+    let output = run_funcs_with_lines_unsorted(
+        Command::new("bash")
+            .arg("-c")
+            .arg("echo a; echo b; >&2 echo c"),
+        |stdout_lines| {
+            let mut lines: Vec<Line> = stdout_lines
+                .map(|line| Line::from_stdout(line.unwrap()))
+                .collect();
+            lines.reverse();
+            return lines;
+        },
+        |stderr_lines| {
+            return stderr_lines
+                .map(|line| Line::from_stderr(line.unwrap()))
+                .collect();
+        },
+    );
+    let contents: Vec<&str> = output.iter().map(|line| line.content.as_str()).collect();
+    assert_eq!(vec!["b", "a", "c"], contents);
+    assert_eq!("c", output.last_line().unwrap().content);
+}
+
+/// Tests keeping the raw bytes of each line
+#[test]
+fn test_keep_raw() {
+    let output = Runner::new()
+        .keep_raw(true)
+        .run(Command::new("printf").arg("ok\\r\\n\\xff\\xfe"))
+        .unwrap();
+    let lines = output.stdout().unwrap();
+    assert_eq!(Some(b"ok".to_vec()), lines[0].raw);
+    assert_eq!(Some(vec![0xff, 0xfe]), lines[1].raw);
+    assert_eq!("\u{FFFD}\u{FFFD}", lines[1].content);
+
+    let output = run(Command::new("echo").arg("hi"));
+    assert_eq!(None, output.stdout().unwrap()[0].raw);
+}
+
+/// Tests splitting and coalescing progress lines redrawn with "\r"
+#[test]
+fn test_carriage_returns() {
+    let bytes = b"\r10%\r50%\r\n\r100%\ndone\r\nstill going\r";
+    let contents = |carriage_returns| {
+        return split_chunked(bytes, &[4, 9, 10], b"\n", None, carriage_returns)
+            .into_iter()
+            .map(|line| String::from_utf8(line.bytes).unwrap())
+            .collect::<Vec<String>>();
+    };
+    assert_eq!(
+        vec!["10%", "50%", "100%", "done", "still going"],
+        contents(CarriageReturns::Split)
+    );
+    assert_eq!(
+        vec!["50%", "100%", "done", "still going"],
+        contents(CarriageReturns::Coalesce)
+    );
+    assert_eq!(
+        vec!["\r10%\r50%", "\r100%", "done", "still going\r"],
+        contents(CarriageReturns::Keep)
+    );
+}
+
+/// Tests removing ANSI escape sequences
+#[test]
+fn test_strip_ansi() {
+    assert_eq!("plain", ansi::strip("plain"));
+    assert_eq!(
+        "red bold, moved, titled, link",
+        ansi::strip(
+            "\x1b[31mred\x1b[0m \x1b[1mbold\x1b[22m, \x1b[2Kmoved\x1b[10;5H, \x1b]0;title\x07titled, \x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\\x1b(B\x1b7\x1b[",
+        )
+    );
+
+    let output = Runner::new()
+        .strip_ansi(true)
+        .keep_raw(true)
+        .run(Command::new("printf").arg("\\033[32mok\\033[0m\\n"))
+        .unwrap();
+    let line = &output.stdout().unwrap()[0];
+    assert_eq!("ok", line.content);
+    assert_eq!(Some(b"\x1b[32mok\x1b[0m".to_vec()), line.raw);
+}
+
+/// Tests that empty commands give a clear error instead of an OS error
+#[test]
+fn test_empty_command() {
+    assert!(matches!(
+        Runner::new().run(&mut Command::new("")),
+        Err(Error::EmptyCommand)
+    ));
+    assert!(matches!(
+        Pipeline::new(Command::new("echo"))
+            .pipe(Command::new(" "))
+            .run(),
+        Err(Error::EmptyCommand)
+    ));
+}
+
+/// Tests waiting for a line to be printed, and giving up if it isn't
+#[test]
+fn test_run_until_match() {
+    let (running, info) = run_until_match(
+        Command::new("bash")
+            .arg("-c")
+            .arg("echo starting; sleep 0.2; >&2 echo ready; sleep 0.2; echo done"),
+        "ready",
+        Duration::from_secs(10),
+    )
+    .unwrap();
+    assert_eq!(LineType::Stderr, info.line.printed_to);
+    assert!(info.elapsed >= Duration::from_millis(200));
+    let output = running.wait().unwrap();
+    assert_eq!(Some("starting\ndone".to_string()), output.stdout_string());
+
+    let result = run_until_match(
+        Command::new("sleep").arg("60"),
+        "ready",
+        Duration::from_millis(100),
+    );
+    assert!(matches!(
+        result,
+        Err(Error::NoMatch(output)) if output.kill_reason() == Some(KillReason::Timeout)
+    ));
+
+    let result = run_until_match(
+        Command::new("echo").arg("nope"),
+        "ready",
+        Duration::from_secs(10),
+    );
+    assert!(matches!(
+        result,
+        Err(Error::NoMatch(output)) if output.kill_reason().is_none()
+    ));
+}
+
+/// Tests writing transcripts as a directory and as a tar file
+#[test]
+fn test_transcript_archive() {
+    let build = run(Command::new("echo").arg("building \"things\""));
+    let test = run(Command::new("bash")
+        .arg("-c")
+        .arg(">&2 echo failed; exit 1"));
+    let mut archive = TranscriptArchive::new();
+    archive.add("build", &build, &[]);
+    archive.add("unit tests", &test, &["build"]);
+
+    let dir = std::env::temp_dir().join("better-commands-test_transcript_archive");
+    let _ = std::fs::remove_dir_all(&dir);
+    archive.write_dir(&dir).unwrap();
+    let index = std::fs::read_to_string(dir.join("index.json")).unwrap();
+    assert!(index.contains(r#""command": "echo building \"things\"""#));
+    assert!(index.contains(r#""depends_on": ["build"]"#));
+    let transcript = std::fs::read_to_string(dir.join("02-unit_tests/transcript.txt")).unwrap();
+    assert!(transcript.ends_with("[stderr] failed\n"));
+
+    let tar_path = dir.join("archive.tar");
+    archive.write_tar(File::create(&tar_path).unwrap()).unwrap();
+    let listing = run(Command::new("tar").arg("-tf").arg(&tar_path));
+    assert_eq!(
+        Some(
+            "index.json\n01-build/transcript.txt\n01-build/metadata.json\n02-unit_tests/transcript.txt\n02-unit_tests/metadata.json"
+                .to_string()
+        ),
+        listing.stdout_string()
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// Tests that fingerprints ignore details which change between runs
+#[test]
+fn test_fingerprint() {
+    let failing = |id: &str| {
+        return run(Command::new("bash").arg("-c").arg(format!(
+            "echo working; >&2 echo 'note: retrying'; >&2 echo 'ERROR 2024-01-02T03:04:05Z build {} failed at 0x7ffe12'; exit 3",
+            id
+        )));
+    };
+    let first = failing("3f9a2c1").fingerprint().unwrap();
+    let second = failing("81bb0de").fingerprint().unwrap();
+    assert_eq!(Some(3), first.status_code);
+    assert_eq!(
+        Some("ERROR <time> build <hex> failed at <addr>".to_string()),
+        first.error_line
+    );
+    assert_eq!(first.id(), second.id());
+
+    assert_eq!(None, run(&mut Command::new("true")).fingerprint());
+
+    let killed = run(Command::new("bash").arg("-c").arg("kill -9 $$"));
+    assert_eq!(Some(9), killed.signal());
+    assert_eq!("signal 9", killed.fingerprint().unwrap().to_string());
+}
+
+/// Tests killing a command once it prints a matching line
+#[test]
+fn test_kill_on_match() {
+    let start = Instant::now();
+    let output = Runner::new()
+        .kill_on_match(regex::Regex::new(r"^result: \d+$").unwrap())
+        .process_group(true)
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo working; >&2 echo 'result: 42'; sleep 60; echo never"),
+        )
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_eq!(Some(KillReason::PatternMatched), output.kill_reason());
+    let matched = output.kill_match().unwrap();
+    assert_eq!("result: 42", matched.content);
+    assert_eq!(LineType::Stderr, matched.printed_to);
+
+    let output = Runner::new()
+        .kill_on_match("FATAL")
+        .run(Command::new("echo").arg("fine"))
+        .unwrap();
+    assert_eq!(None, output.kill_reason());
+    assert_eq!(None, output.kill_match());
+}
+
+/// Tests cancelling commands by writing to their stdin before killing them
+#[test]
+fn test_stdin_cancellation() {
+    let script = "echo ready; while read -r line; do if [ \"$line\" = q ]; then echo bye; exit 0; fi; done; exit 5";
+    let output = Runner::new()
+        .cancellation(Cancellation::stdin("q\n", Duration::from_secs(10)))
+        .timeout(Duration::from_millis(200))
+        .run(Command::new("bash").arg("-c").arg(script))
+        .unwrap();
+    assert_eq!(Some(KillReason::Timeout), output.kill_reason());
+    assert_eq!(Some("ready\nbye".to_string()), output.stdout_string());
+    assert_eq!(Some(0), output.clone().status_code());
+
+    // ignores stdin, so it's killed once the grace period runs out
+    let start = Instant::now();
+    let mut running = Runner::new()
+        .cancellation(Cancellation::close_stdin(Duration::from_millis(200)))
+        .spawn(Command::new("sleep").arg("60"))
+        .unwrap();
+    running.cancel().unwrap();
+    let output = running.wait().unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_eq!(Some(9), output.signal());
+}
+
+/// Tests parsing JSON-lines output
+#[cfg(feature = "serde")]
+#[test]
+fn test_run_json() {
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Progress {
+        done: u32,
+        total: u32,
+    }
+
+    let script = r#"echo '{"done": 1, "total": 2}'; echo; >&2 echo '{"done": 9, "total": 9}'; echo '{"done": "two"}'; echo '{"done": 2, "total": 2}'"#;
+    let parsed = run_json::<Progress>(Command::new("bash").arg("-c").arg(script)).unwrap();
+    assert_eq!(
+        vec![
+            Progress { done: 1, total: 2 },
+            Progress { done: 2, total: 2 }
+        ],
+        parsed.values
+    );
+    assert_eq!(1, parsed.errors.len());
+    assert_eq!(r#"{"done": "two"}"#, parsed.errors[0].line.content);
+    assert_eq!(5, parsed.output.len());
+}
+
+/// Tests parsing lines as they're captured
+#[test]
+fn test_run_parsed() {
+    struct Percentages {
+        last: u8,
+    }
+
+    impl LineParser for Percentages {
+        type Out = u8;
+
+        /// Only returns percentages which went up
+        fn parse(&mut self, line: &Line) -> Option<u8> {
+            let percent: u8 = line.content.strip_suffix('%')?.parse().ok()?;
+            if percent <= self.last {
+                return None;
+            }
+            self.last = percent;
+            return Some(percent);
+        }
+    }
+
+    let parsed = Runner::new()
+        .on_line(|line| line.content = line.content.trim().to_string())
+        .run_parsed(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo ' 10%'; sleep 0.01; >&2 echo '50%'; sleep 0.01; echo 40%; echo done; echo 100%"),
+            Percentages { last: 0 },
+        )
+        .unwrap();
+    assert_eq!(vec![10, 50, 100], parsed.values);
+    assert_eq!(5, parsed.output.len());
+}
+
+/// Tests giving each stream's function its own data
+#[test]
+fn test_run_funcs_with_data() {
+    let (stdout_sender, receiver) = mpsc::channel();
+    let stderr_sender = stdout_sender.clone();
+    let mut count = 0;
+    let output = run_funcs_with_data(
+        Command::new("bash")
+            .arg("-c")
+            .arg("echo a; >&2 echo b; echo c"),
+        stdout_sender,
+        |lines, sender| {
+            for line in lines {
+                sender.send(format!("stdout: {}", line.unwrap())).unwrap();
+                count += 1;
+            }
+        },
+        stderr_sender,
+        |lines, sender| {
+            for line in lines {
+                sender.send(format!("stderr: {}", line.unwrap())).unwrap();
+            }
+        },
+    );
+    assert_eq!(None, output.stdout());
+    assert_eq!(2, count);
+    let mut received: Vec<String> = receiver.iter().collect();
+    received.sort();
+    assert_eq!(vec!["stderr: b", "stdout: a", "stdout: c"], received);
+}
+
+/// Tests clearing the environment, loading `.env` files, and reporting what changed
+#[test]
+fn test_env_helpers() {
+    let path = std::env::temp_dir().join("better-commands-test_env_helpers.env");
+    std::fs::write(
+        &path,
+        "# settings\nexport NAME=world # a comment\nQUOTED=\"a \\\"b\\\"\\nc\"\n\nLITERAL='$NAME \\n'\n",
+    )
+    .unwrap();
+    let output = Runner::new()
+        .env_clear_except(["PATH"])
+        .env_from_file(&path)
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo \"$NAME|$QUOTED|$LITERAL|${HOME:-unset}\""),
+        )
+        .unwrap();
+    assert_eq!(
+        Some("world|a \"b\"\nc|$NAME \\n|unset".to_string()),
+        output.stdout_string()
+    );
+    let diff = output.envs_diff();
+    let name = diff.iter().find(|change| change.key == "NAME").unwrap();
+    assert_eq!(Some("world".to_string()), name.after);
+    assert!(!diff.iter().any(|change| change.key == "PATH"));
+    if let Ok(home) = std::env::var("HOME") {
+        let home_change = diff.iter().find(|change| change.key == "HOME").unwrap();
+        assert_eq!(
+            (Some(home), None),
+            (home_change.before.clone(), home_change.after.clone())
+        );
+    }
+
+    // running the same command again gets its environment as it was
+    let mut command = Command::new("bash");
+    command.arg("-c").arg("echo ${NAME:-unset} ${HOME:-unset}");
+    let runner = Runner::new()
+        .env_clear_except(["PATH"])
+        .env_from_file(&path);
+    for _ in 0..2 {
+        let output = runner.run(&mut command).unwrap();
+        assert_eq!(Some("world unset".to_string()), output.stdout_string());
+    }
+    let home = std::env::var("HOME").unwrap_or("unset".to_string());
+    assert_eq!(
+        Some(format!("unset {}", home)),
+        run(&mut command).stdout_string()
+    );
+
+    std::fs::write(&path, "NAME\n").unwrap();
+    let result = Runner::new()
+        .env_from_file(&path)
+        .run(&mut Command::new("true"));
+    assert!(matches!(result, Err(Error::EnvFile(_, _))));
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Tests being told when the output passes each watermark
+#[test]
+fn test_on_watermark() {
+    let (sender, receiver) = mpsc::channel();
+    let sender = Mutex::new(sender);
+    // each line is 100 bytes, including the newline
+    let output = Runner::new()
+        .on_watermark(250, move |watermark| {
+            sender.lock().unwrap().send(watermark).unwrap();
+        })
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("for i in $(seq 10); do printf '%099d\\n' $i; done"),
+        )
+        .unwrap();
+    assert_eq!(10, output.len());
+    let watermarks: Vec<Watermark> = receiver.try_iter().collect();
+    let seen: Vec<(u64, u64, u64)> = watermarks
+        .iter()
+        .map(|watermark| (watermark.threshold, watermark.bytes, watermark.lines))
+        .collect();
+    assert_eq!(
+        vec![
+            (250, 300, 3),
+            (500, 500, 5),
+            (750, 800, 8),
+            (1000, 1000, 10)
+        ],
+        seen
+    );
+}
+
+/// Tests running commands in their own temporary directories
+#[test]
+fn test_current_dir_temp() {
+    let runner = Runner::new().current_dir_temp();
+    let first = runner
+        .run(Command::new("bash").arg("-c").arg("ls -A; touch made"))
+        .unwrap();
+    let second = runner.run(&mut Command::new("pwd")).unwrap();
+    assert_eq!(Some(String::new()), first.stdout_string());
+    let dir = first.workdir().unwrap().to_path_buf();
+    assert!(dir.join("made").exists());
+    assert_ne!(Some(dir.as_path()), second.workdir());
+    assert_eq!(
+        second.workdir().unwrap().to_string_lossy(),
+        second.stdout().unwrap()[0].content
+    );
+
+    // only removed once every clone is gone
+    let copy = first.clone();
+    drop(first);
+    assert!(dir.exists());
+    drop(copy);
+    assert!(!dir.exists());
+
+    let kept = Runner::new()
+        .keep_temp_dir(true)
+        .run(&mut Command::new("true"))
+        .unwrap();
+    let dir = kept.workdir().unwrap().to_path_buf();
+    drop(kept);
+    assert!(dir.exists());
+    std::fs::remove_dir(dir).unwrap();
+    assert_eq!(None, run(&mut Command::new("true")).workdir());
+
+    // the command goes back to where it was, since the directory's gone once the output is dropped
+    let mut command = Command::new("pwd");
+    drop(runner.run(&mut command).unwrap());
+    let output = run(&mut command);
+    assert_eq!(
+        std::env::current_dir().unwrap().to_string_lossy(),
+        output.stdout().unwrap()[0].content
+    );
+    command.current_dir("/");
+    drop(runner.run(&mut command).unwrap());
+    assert_eq!(Some(Path::new("/")), command.get_current_dir());
+}
+
+/// Tests recording CPU time and memory usage
+#[test]
+fn test_resource_usage() {
+    let output = Runner::new()
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("x=0; for i in $(seq 200000); do x=$((x + i)); done"),
+        )
+        .unwrap();
+    let usage = output.resource_usage().unwrap();
+    assert!(usage.user_time + usage.system_time > Duration::from_millis(10));
+    assert!(usage.user_time + usage.system_time <= output.clone().duration() * 2);
+    // bash needs at least a megabyte
+    assert!(usage.max_rss > 1024 * 1024);
+
+    // still recorded when killed
+    let output = Runner::new()
+        .timeout(Duration::from_millis(50))
+        .run(Command::new("sleep").arg("10"))
+        .unwrap();
+    assert_eq!(Some(9), output.signal());
+    assert!(output.resource_usage().is_some());
+}
+
+/// Tests the statistics from benchmarking a command
+#[test]
+fn test_bench() {
+    let counter = std::env::temp_dir().join("better-commands-test_bench");
+    let _ = std::fs::remove_file(&counter);
+    let report = Runner::new()
+        .bench(
+            Command::new("bash").arg("-c").arg(format!(
+                "echo x >> {0}; [ $(wc -l < {0}) -ne 3 ]",
+                counter.display()
+            )),
+            2,
+            4,
+        )
+        .unwrap();
+    // the warmup runs aren't in the report, but still ran
+    assert_eq!(4, report.runs().len());
+    assert_eq!(1, report.failures());
+    assert_eq!(Some(1), report.runs()[0].clone().status_code());
+    std::fs::remove_file(counter).unwrap();
+
+    let report = BenchReport::new(
+        [30, 10, 20, 40]
+            .into_iter()
+            .map(|millis| {
+                let start = Instant::now();
+                CmdOutput::new(
+                    "test".to_string(),
+                    None,
+                    Some(0),
+                    start,
+                    start + Duration::from_millis(millis),
+                )
+            })
+            .collect(),
+    );
+    assert_eq!(Duration::from_millis(10), report.min());
+    assert_eq!(Duration::from_millis(40), report.max());
+    assert_eq!(Duration::from_millis(25), report.mean());
+    assert_eq!(Duration::from_millis(25), report.median());
+    assert_eq!(11180, report.stddev().as_micros());
+}
+
+/// Tests that each line is logged as an event in the command's span
+#[cfg(feature = "tracing")]
+#[test]
+fn test_tracing() {
+    let buffer = SharedBuffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_ansi(false)
+        .without_time()
+        .with_writer(move || writer.clone())
+        .finish();
+    tracing::subscriber::with_default(subscriber, || {
+        Runner::new()
+            .run(
+                Command::new("bash")
+                    .arg("-c")
+                    .arg("echo out; sleep 0.01; >&2 echo err"),
+            )
+            .unwrap();
+    });
+
+    let logged = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = logged.lines().collect();
+    assert_eq!(2, lines.len(), "{}", logged);
+    assert!(lines[0]
+        .starts_with("DEBUG command{command=\"bash -c echo out; sleep 0.01; >&2 echo err\" pid="));
+    assert!(lines[0].ends_with(": out stream=\"stdout\""));
+    assert!(lines[1].starts_with(" WARN command{"));
+    assert!(lines[1].ends_with(": err stream=\"stderr\""));
+}
+
+/// Tests logging each stream at its own level
+#[cfg(feature = "log")]
+#[test]
+fn test_log_output() {
+    static LOGGED: Mutex<Vec<(log::Level, String, String)>> = Mutex::new(Vec::new());
+
+    struct Logger;
+
+    impl log::Log for Logger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            return true;
+        }
+
+        fn log(&self, record: &log::Record) {
+            if record.target().starts_with("better_commands") || record.target() == "builds" {
+                LOGGED.lock().unwrap().push((
+                    record.level(),
+                    record.target().to_string(),
+                    record.args().to_string(),
+                ));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    log::set_logger(&Logger).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    Runner::new()
+        .log_output(log::Level::Debug, log::Level::Error)
+        .run(
+            Command::new("/bin/bash")
+                .arg("-c")
+                .arg("echo out; sleep 0.01; >&2 echo err"),
+        )
+        .unwrap();
+    Runner::new()
+        .log_target("builds")
+        .run(Command::new("echo").arg("custom"))
+        .unwrap();
+
+    assert_eq!(
+        vec![
+            (
+                log::Level::Debug,
+                "better_commands::bash".to_string(),
+                "out".to_string()
+            ),
+            (
+                log::Level::Error,
+                "better_commands::bash".to_string(),
+                "err".to_string()
+            ),
+            (log::Level::Info, "builds".to_string(), "custom".to_string()),
+        ],
+        *LOGGED.lock().unwrap()
+    );
+}
+
+/// Tests writing each stream to its own writer
+#[test]
+fn test_stream_sinks() {
+    let stdout = Arc::new(Mutex::new(WriteSink::new(Vec::new())));
+    let stderr = Arc::new(Mutex::new(Vec::new()));
+    let output = Runner::new()
+        .stdout_sink(stdout.clone())
+        .stderr_sink(stderr.clone())
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo a; >&2 echo b; echo c"),
+        )
+        .unwrap();
+    assert_eq!(3, output.len());
+    let written = std::mem::replace(&mut *stdout.lock().unwrap(), WriteSink::new(Vec::new()));
+    assert_eq!(b"a\nc\n".to_vec(), written.into_inner());
+    let stderr: Vec<String> = stderr
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|line| line.content.clone())
+        .collect();
+    assert_eq!(vec!["b"], stderr);
+}
+
+/// Tests printing several commands' output with aligned prefixes
+#[test]
+fn test_multiplex_printer() {
+    let buffer = SharedBuffer::default();
+    let printer = MultiplexPrinter::with_writer(buffer.clone());
+    let web = Runner::new().sink(printer.sink("web"));
+    let database = Runner::new().sink(printer.sink("database"));
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            web.run(
+                Command::new("bash")
+                    .arg("-c")
+                    .arg("for i in 1 2 3; do echo request $i; done"),
+            )
+        });
+        scope.spawn(|| {
+            database.run(
+                Command::new("bash")
+                    .arg("-c")
+                    .arg("for i in 1 2 3; do >&2 echo query $i; done"),
+            )
+        });
+    });
+
+    let printed = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let mut lines: Vec<&str> = printed.lines().collect();
+    lines.sort();
+    assert_eq!(
+        vec![
+            "database | query 1",
+            "database | query 2",
+            "database | query 3",
+            "web      | request 1",
+            "web      | request 2",
+            "web      | request 3",
+        ],
+        lines
+    );
+
+    let buffer = SharedBuffer::default();
+    let printer = MultiplexPrinter::with_writer(buffer.clone()).colors(true);
+    Runner::new()
+        .sink(printer.sink("a"))
+        .run(Command::new("echo").arg("hi"))
+        .unwrap();
+    assert_eq!(
+        b"\x1b[36ma |\x1b[0m hi\n".to_vec(),
+        *buffer.0.lock().unwrap()
+    );
+}
+
+/// Tests generating a JUnit report
+#[test]
+fn test_junit_report() {
+    let outputs = [
+        run(Command::new("bash")
+            .arg("-c")
+            .arg("echo '<ok> & \"done\"'; >&2 echo warning")),
+        Runner::new()
+            .timeout(Duration::from_millis(50))
+            .run(Command::new("sleep").arg("10"))
+            .unwrap(),
+    ];
+    let xml = report::junit(&outputs);
+    let lines: Vec<&str> = xml.lines().collect();
+    assert_eq!(r#"<?xml version="1.0" encoding="UTF-8"?>"#, lines[0]);
+    assert!(
+        lines[1].starts_with(r#"<testsuite name="better-commands" tests="2" failures="1" time=""#)
+    );
+    assert!(lines[2].starts_with(r#"  <testcase name="bash -c echo &apos;&lt;ok&gt; &amp; &quot;done&quot;&apos;; &gt;&amp;2 echo warning" classname="better_commands" time=""#));
+    assert_eq!(
+        "    <system-out>&lt;ok&gt; &amp; &quot;done&quot;</system-out>",
+        lines[3]
+    );
+    assert_eq!("    <system-err>warning</system-err>", lines[4]);
+    assert_eq!(
+        r#"    <failure message="killed by signal 9 (timed out)"/>"#,
+        lines[7]
+    );
+    assert_eq!("</testsuite>", *lines.last().unwrap());
+}
+
+/// Tests generating an HTML report
+#[test]
+fn test_html_report() {
+    let outputs = [
+        run(Command::new("bash")
+            .arg("-c")
+            .arg("echo '<b>'; >&2 echo warning")),
+        run(Command::new("bash").arg("-c").arg("exit 2")),
+    ];
+    let html = report::html(&outputs);
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<h1>2 commands, 1 failed, "));
+    assert!(html.contains("<td>exited with status 2</td>"));
+    assert!(html.contains("<summary>stdout (1 lines)</summary>"));
+    assert!(html.contains("s</span> &lt;b&gt;\n"));
+    assert!(html.contains("s</span> warning\n"));
+    assert!(html.contains("<section id=\"command-1\" class=\"failed\">"));
+    assert!(html.ends_with("</html>\n"));
+}
+
+/// Tests replaying captured output with its original timing
+#[test]
+fn test_replay() {
+    let output = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo a; sleep 0.4; >&2 echo b; sleep 0.4; echo c"));
+
+    let mut replayed = Vec::new();
+    let start = Instant::now();
+    output.replay(&mut replayed, 2.0).unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(b"a\nb\nc\n", replayed.as_slice());
+    assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(800), "{:?}", elapsed);
+}
+
+/// Tests recording outputs to a cassette and playing them back
+#[cfg(feature = "serde")]
+#[test]
+fn test_cassette() {
+    let path = std::env::temp_dir().join(format!("cassette-{}.json", std::process::id()));
+    let cassette = Cassette::record(&path).runner(Runner::new().strip_ansi(true));
+    assert_eq!(CassetteMode::Record, cassette.mode());
+    for i in 0..2 {
+        let output = cassette
+            .run(Command::new("bash").arg("-c").arg("date +%N; >&2 echo err"))
+            .unwrap();
+        assert_eq!(1, output.stdout().unwrap().len(), "run {}", i);
+    }
+    cassette
+        .run(Command::new("bash").arg("-c").arg("exit 4"))
+        .unwrap();
+    cassette.save().unwrap();
+    let recorded = Cassette::replay(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let first = recorded
+        .run(Command::new("bash").arg("-c").arg("date +%N; >&2 echo err"))
+        .unwrap();
+    let second = recorded
+        .run(Command::new("bash").arg("-c").arg("date +%N; >&2 echo err"))
+        .unwrap();
+    let third = recorded
+        .run(Command::new("bash").arg("-c").arg("date +%N; >&2 echo err"))
+        .unwrap();
+    assert_ne!(first.stdout_string(), second.stdout_string());
+    assert_eq!(second.stdout_string(), third.stdout_string());
+    assert_eq!(Some("err".to_string()), first.stderr_string());
+    assert_eq!(
+        Some(4),
+        recorded
+            .run(Command::new("bash").arg("-c").arg("exit 4"))
+            .unwrap()
+            .status_code()
+    );
+    assert!(matches!(
+        recorded.run(&mut Command::new("true")),
+        Err(Error::NotRecorded(command)) if command == "true"
+    ));
+}
+
+/// Tests that a fake runner returns the outputs it was given and keeps what it was asked to run
+#[test]
+fn test_fake_runner() {
+    fn count_files(runner: &dyn CommandRunner) -> Result<usize, Error> {
+        return Ok(runner.run(Command::new("ls").arg("-1"))?.len());
+    }
+
+    let fake = FakeRunner::new()
+        .respond(
+            "ls -1",
+            0,
+            vec![Line::from_stdout("a"), Line::from_stdout("b")],
+        )
+        .respond("ls -1", 2, vec![Line::from_stderr("ls: oops")]);
+    assert_eq!(2, count_files(&fake).unwrap());
+    assert_eq!(1, count_files(&fake).unwrap());
+    let output = fake.run(Command::new("ls").arg("-1")).unwrap();
+    assert_eq!(Some(2), output.clone().status_code());
+    assert_eq!(Some("ls: oops".to_string()), output.stderr_string());
+    assert!(matches!(
+        fake.run(Command::new("rm").arg("-rf").arg("/")),
+        Err(Error::NotRecorded(command)) if command == "rm -rf /"
+    ));
+    assert_eq!(
+        vec!["ls -1", "ls -1", "ls -1", "rm -rf /"],
+        fake.invocations()
+    );
+
+    assert!(count_files(&Runner::new()).unwrap() > 0);
+}
+
+/// Tests the assertion helpers, and that failures show the output
+#[test]
+fn test_assert() {
+    let output = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo hello; echo world; sleep 0.1; >&2 echo 'took 12ms'; exit 2"));
+    output
+        .assert()
+        .failure()
+        .code(2)
+        .stdout_contains("hello\nworld")
+        .stdout_matches(regex::Regex::new("^hello").unwrap())
+        .stderr_contains("took")
+        .stderr_matches(regex::Regex::new(r"took \d+ms").unwrap());
+
+    let panic = std::panic::catch_unwind(|| {
+        output.assert().success();
+    })
+    .unwrap_err();
+    let message = panic.downcast_ref::<String>().unwrap();
+    let lines: Vec<&str> = message.lines().collect();
+    assert!(lines[0].starts_with("command `bash -c echo hello; echo world; sleep 0.1; >&2 echo 'took 12ms'; exit 2` exited with status 2 after "));
+    assert!(lines[0].ends_with("s, expected it to succeed"));
+    assert!(lines[1].starts_with("  +") && lines[1].ends_with("s stdout | hello"));
+    assert!(lines[3].ends_with("s stderr | took 12ms"));
+
+    let panic = std::panic::catch_unwind(|| {
+        run(&mut Command::new("true")).assert().stdout_contains("x");
+    })
+    .unwrap_err();
+    assert!(panic
+        .downcast_ref::<String>()
+        .unwrap()
+        .ends_with("expected stdout to contain \"x\"\n(it didn't print anything)\n"));
+}
+
+/// Tests creating, checking, and updating snapshots
+#[test]
+fn test_snapshot() {
+    let path = std::env::temp_dir()
+        .join(format!("snapshots-{}", std::process::id()))
+        .join("greeting.txt");
+    let greeting = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo hello; echo world; >&2 echo ignored"));
+
+    let missing = std::panic::catch_unwind(|| greeting.matches_snapshot(&path)).unwrap_err();
+    assert!(missing
+        .downcast_ref::<String>()
+        .unwrap()
+        .ends_with("doesn't exist; run with BETTER_COMMANDS_UPDATE_SNAPSHOTS=1 to create it"));
+
+    std::env::set_var(UPDATE_SNAPSHOTS_VAR, "1");
+    greeting.matches_snapshot(&path);
+    std::env::remove_var(UPDATE_SNAPSHOTS_VAR);
+    assert_eq!("hello\nworld\n", std::fs::read_to_string(&path).unwrap());
+
+    // colors and trailing whitespace don't count
+    run(Command::new("bash")
+        .arg("-c")
+        .arg("printf '\\e[1mhello\\e[0m  \\nworld\\n\\n'"))
+    .matches_snapshot(&path);
+
+    let changed = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo hi; echo world; echo !"));
+    let mismatch = std::panic::catch_unwind(|| changed.matches_snapshot(&path)).unwrap_err();
+    let message = mismatch.downcast_ref::<String>().unwrap();
+    assert!(
+        message.ends_with("\n- hello\n+ hi\n  world\n+ !\n"),
+        "{}",
+        message
+    );
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+/// Tests diffing two outputs
+#[test]
+fn test_output_diff() {
+    let old = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo a; echo b; echo c; >&2 echo warning"));
+    let new = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo a; echo c; echo d; >&2 echo warning"));
+    assert!(old.diff(&old.clone()).is_empty());
+    assert_eq!("", old.diff(&old).pretty());
+
+    let diff = old.diff(&new);
+    assert!(!diff.is_empty());
+    assert_eq!(
+        vec![
+            LineChange::Unchanged("a".to_string()),
+            LineChange::Removed("b".to_string()),
+            LineChange::Unchanged("c".to_string()),
+            LineChange::Added("d".to_string()),
+        ],
+        diff.stdout
+    );
+    assert_eq!(
+        vec![LineChange::Unchanged("warning".to_string())],
+        diff.stderr
+    );
+    assert_eq!("--- stdout ---\n  a\n- b\n  c\n+ d\n", diff.pretty());
+
+    let empty = run(&mut Command::new("true"));
+    assert_eq!(
+        vec![
+            LineChange::Added("a".to_string()),
+            LineChange::Added("c".to_string()),
+            LineChange::Added("d".to_string()),
+        ],
+        empty.diff(&new).stdout
+    );
+}
+
+/// Tests searching through the captured lines
+#[test]
+fn test_search() {
+    let output = run(Command::new("bash").arg("-c").arg(
+        "echo 'built foo v1.2'; sleep 0.05; >&2 echo 'error: foo v1.3 required'; sleep 0.05; echo 'built bar v0.1'",
+    ));
+    assert!(output.contains("bar"));
+    assert!(!output.contains("baz"));
+    assert!(output.contains_in(LineType::Stderr, "error"));
+    assert!(!output.contains_in(LineType::Stdout, "error"));
+
+    let foo: Vec<&str> = output
+        .grep("foo")
+        .iter()
+        .map(|line| line.content.as_str())
+        .collect();
+    assert_eq!(vec!["built foo v1.2", "error: foo v1.3 required"], foo);
+    let built = output.grep_in(LineType::Stdout, regex::Regex::new("^built").unwrap());
+    assert_eq!(2, built.len());
+
+    let version = regex::Regex::new(r"(\w+) v(\d+)\.(\d+)").unwrap();
+    let versions: Vec<(&str, &str)> = output
+        .capture_all(&version)
+        .iter()
+        .map(|captures| {
+            (
+                captures.get(1).unwrap().as_str(),
+                captures.get(3).unwrap().as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(vec![("foo", "2"), ("foo", "3"), ("bar", "1")], versions);
+    assert_eq!(1, output.capture_all_in(LineType::Stderr, &version).len());
+}
+
+/// Tests picking out the first and last lines, and the lines printed in a window of time
+#[test]
+fn test_head_tail_between() {
+    let output = run(Command::new("bash")
+        .arg("-c")
+        .arg("echo 1; sleep 0.05; >&2 echo 2; sleep 0.05; echo 3; sleep 0.5; >&2 echo 4; sleep 0.05; echo 5"));
+    let contents = |lines: Vec<&Line>| -> Vec<String> {
+        return lines.iter().map(|line| line.content.clone()).collect();
+    };
+    assert_eq!(vec!["1", "2"], contents(output.head(2)));
+    assert_eq!(vec!["4", "5"], contents(output.tail(2)));
+    assert_eq!(5, output.tail(10).len());
+    assert!(output.head(0).is_empty());
+
+    let late = output.lines_between_offsets(Duration::from_millis(400), Duration::MAX);
+    assert_eq!(vec!["4", "5"], contents(late));
+    let start = output.clone().start_time();
+    let early = output.lines_between(start, start + Duration::from_millis(400));
+    assert_eq!(vec!["1", "2", "3"], contents(early));
+}
+
+/// Tests stopping commands with SIGTERM, and killing them if they ignore it
+#[test]
+fn test_shutdown() {
+    let runner = Runner::new()
+        .process_group(true)
+        .cancellation(Cancellation::terminate(Duration::from_millis(500)))
+        .timeout(Duration::from_millis(200));
+
+    let output = runner
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("trap 'echo cleaning up; exit 3' TERM; while true; do sleep 0.01; done"),
+        )
+        .unwrap();
+    assert_eq!(Some(KillReason::Timeout), output.kill_reason());
+    assert_eq!(Some(Shutdown::Exited), output.shutdown());
+    assert_eq!(Some("cleaning up".to_string()), output.stdout_string());
+    assert_eq!(Some(3), output.clone().status_code());
+
+    let start = Instant::now();
+    let output = runner
+        .run(Command::new("bash").arg("-c").arg("trap '' TERM; sleep 10"))
+        .unwrap();
+    assert_eq!(Some(Shutdown::Killed), output.shutdown());
+    assert_eq!(Some(9), output.signal());
+    assert!(start.elapsed() >= Duration::from_millis(700));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let output = Runner::new()
+        .timeout(Duration::from_millis(50))
+        .run(Command::new("sleep").arg("10"))
+        .unwrap();
+    assert_eq!(Some(Shutdown::Killed), output.shutdown());
+    assert_eq!(None, run(&mut Command::new("true")).shutdown());
+}
+
+/// Tests that signals this process gets are passed on to the command, and the handlers are put back afterwards
+#[test]
+fn test_forward_signals() {
+    let sender = thread::spawn(|| {
+        sleep(Duration::from_millis(300));
+        unsafe { libc::kill(libc::getpid(), libc::SIGINT) };
+    });
+    let output = Runner::new()
+        .forward_signals(true)
+        .process_group(true)
+        .run(Command::new("bash").arg("-c").arg(
+            "trap 'echo interrupted; exit 130' INT; echo started; while true; do sleep 0.01; done",
+        ))
+        .unwrap();
+    sender.join().unwrap();
+    assert_eq!(Some(KillReason::Interrupted), output.kill_reason());
+    assert_eq!(Some(130), output.clone().status_code());
+    assert_eq!(
+        Some("started\ninterrupted".to_string()),
+        output.stdout_string()
+    );
+
+    let mut handler: libc::sigaction = unsafe { std::mem::zeroed() };
+    unsafe { libc::sigaction(libc::SIGINT, std::ptr::null(), &mut handler) };
+    assert_eq!(libc::SIG_DFL, handler.sa_sigaction);
+}
+
+/// Tests starting a detached command with its output saved to a file
+#[test]
+fn test_spawn_detached() {
+    let log = std::env::temp_dir().join(format!("detached-{}.log", std::process::id()));
+    let child = DetachOptions::new()
+        .stdout(&log)
+        .stderr(&log)
+        .spawn(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo $$; >&2 echo err; exec sleep 10"),
+        )
+        .unwrap();
+    assert_eq!(Some(log.as_path()), child.stdout_path());
+
+    let start = Instant::now();
+    while std::fs::read_to_string(&log).unwrap().lines().count() < 2 {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        sleep(Duration::from_millis(10));
+    }
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(format!("{}\nerr\n", child.pid()), logged);
+
+    // it's not this process's child, and it's in a session of its own
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", child.pid())).unwrap();
+    let fields: Vec<&str> = stat
+        .rsplit_once(')')
+        .unwrap()
+        .1
+        .split_whitespace()
+        .collect();
+    assert_ne!(std::process::id().to_string(), fields[1]);
+    assert_ne!(
+        unsafe { libc::getsid(0) }.to_string(),
+        fields[3],
+        "should have its own session"
+    );
+    unsafe { libc::kill(child.pid() as libc::pid_t, libc::SIGKILL) };
+    std::fs::remove_file(&log).unwrap();
+
+    assert!(matches!(
+        spawn_detached(&mut Command::new("does-not-exist")),
+        Err(Error::Spawn(_))
+    ));
+}
+
+/// Tests that the command gets the parent death signal when the thread which started it exits
+#[cfg(target_os = "linux")]
+#[test]
+fn test_parent_death_signal() {
+    let pid = thread::spawn(|| {
+        let mut running = Runner::new()
+            .parent_death_signal(libc::SIGKILL)
+            .spawn(Command::new("sleep").arg("10"))
+            .unwrap();
+        running.detach_on_drop();
+        return running.id();
+    })
+    .join()
+    .unwrap();
+
+    let start = Instant::now();
+    let mut status = 0;
+    assert_eq!(pid as i32, unsafe {
+        libc::waitpid(pid as libc::pid_t, &mut status, 0)
+    });
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(libc::WIFSIGNALED(status));
+    assert_eq!(libc::SIGKILL, libc::WTERMSIG(status));
+}
+
+/// Tests running commands as another user
+#[test]
+fn test_run_as_user() {
+    assert!(matches!(
+        Runner::new()
+            .user("no-such-user-here")
+            .run(&mut Command::new("true")),
+        Err(Error::Spawn(e)) if e.kind() == std::io::ErrorKind::NotFound
+    ));
+    if unsafe { libc::getuid() } != 0 {
+        return;
+    }
+
+    let output = Runner::new()
+        .user("nobody")
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("id -u; id -g; echo $USER $HOME"),
+        )
+        .unwrap();
+    let nobody = Command::new("bash")
+        .arg("-c")
+        .arg("id -u nobody; id -g nobody; getent passwd nobody | cut -d: -f6")
+        .output()
+        .unwrap();
+    let nobody: Vec<String> = String::from_utf8(nobody.stdout)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    assert_eq!(
+        Some(format!(
+            "{}\n{}\nnobody {}",
+            nobody[0], nobody[1], nobody[2]
+        )),
+        output.stdout_string()
+    );
+
+    let output = Runner::new()
+        .uid(12345)
+        .gid(23456)
+        .retries(1)
+        .retry_if(|_| true)
+        .run(Command::new("bash").arg("-c").arg("id -u; id -g; id -G"))
+        .unwrap();
+    assert_eq!(1, output.failed_attempts().len());
+    assert_eq!(
+        Some("12345\n23456\n23456".to_string()),
+        output.stdout_string()
+    );
+}
+
+/// Tests setting resource limits, and finding out which one a command was killed for going over
+#[test]
+fn test_rlimits() {
+    let output = Runner::new()
+        .limit(Rlimit::NoFile(100))
+        .limit(Rlimit::NoFile(64))
+        .limit(Rlimit::As(Rlimit::GIB))
+        .run(Command::new("bash").arg("-c").arg("ulimit -n; ulimit -v"))
+        .unwrap();
+    assert_eq!(
+        Some(format!("64\n{}", Rlimit::GIB / Rlimit::KIB)),
+        output.stdout_string()
+    );
+    assert_eq!(None, output.limit_exceeded());
+
+    let output = Runner::new()
+        .limit(Rlimit::Cpu(1))
+        .run(Command::new("bash").arg("-c").arg("while true; do :; done"))
+        .unwrap();
+    assert_eq!(Some(Rlimit::Cpu(1)), output.limit_exceeded());
+
+    let path = std::env::temp_dir().join(format!("rlimit-{}", std::process::id()));
+    let output = Runner::new()
+        .limit(Rlimit::FileSize(1024))
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg(format!("exec head -c 4096 /dev/zero > {}", path.display())),
+        )
+        .unwrap();
+    assert_eq!(Some(Rlimit::FileSize(1024)), output.limit_exceeded());
+    assert_eq!(1024, std::fs::metadata(&path).unwrap().len());
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Tests running a command in its own cgroup
+#[cfg(all(feature = "cgroups", target_os = "linux"))]
+#[test]
+fn test_cgroup() {
+    use crate::CgroupLimits;
+
+    let output = Runner::new()
+        .cgroup(CgroupLimits::new())
+        .run(Command::new("cat").arg("/proc/self/cgroup"))
+        .unwrap();
+    let stdout = output.stdout_string().unwrap();
+    let path = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .unwrap();
+    assert!(path.contains("better-commands-"), "{}", path);
+    assert!(output.cgroup_stats().unwrap().cpu_time.is_some());
+    assert!(!output.cgroup_stats().unwrap().oom_killed);
+
+    // limits need the memory controller, which might not be available
+    match Runner::new()
+        .cgroup(CgroupLimits::new().memory_max(Rlimit::GIB))
+        .run(Command::new("cat").arg("/proc/self/cgroup"))
+    {
+        Ok(output) => assert!(output.stdout_string().unwrap().contains("better-commands-")),
+        Err(Error::Spawn(_)) => {}
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Tests running a command in new namespaces
+#[cfg(all(feature = "namespaces", target_os = "linux"))]
+#[test]
+fn test_isolate() {
+    use crate::Isolation;
+
+    // these work without being root, as long as unprivileged user namespaces are allowed
+    let output = match Runner::new()
+        .isolate(Isolation::User | Isolation::Network | Isolation::Pid | Isolation::Mount)
+        .run(
+            Command::new("bash")
+                .arg("-c")
+                .arg("echo $$; id -u; grep -c : /proc/net/dev; echo /proc/[0-9]*; exit 3"),
+        ) {
+        Ok(output) => output,
+        // they can be turned off, or blocked by seccomp in containers
+        Err(Error::Spawn(e)) if e.kind() == std::io::ErrorKind::PermissionDenied => return,
+        Err(e) => panic!("{}", e),
+    };
+    // it's PID 1 and root, there's only loopback, and it's the only process
+    assert_eq!(
+        Some("1\n0\n1\n/proc/1".to_string()),
+        output.stdout_string(),
+        "{:?}",
+        output.stderr_string()
+    );
+    assert_eq!(Some(3), output.status_code());
+
+    // killing it from outside still works, even though PID 1 can't be killed from inside
+    let output = Runner::new()
+        .isolate(Isolation::User | Isolation::Pid)
+        .timeout(Duration::from_millis(200))
+        .run(Command::new("sleep").arg("10"))
+        .unwrap();
+    assert_eq!(Some(libc::SIGKILL), output.signal());
+    assert!(output.duration() < Duration::from_secs(5));
+}
+
+/// Tests setting the nice value and CPU affinity
+#[cfg(unix)]
+#[test]
+fn test_nice_and_cpu_affinity() {
+    let output = Runner::new()
+        .nice(10)
+        .run(&mut Command::new("nice"))
+        .unwrap();
+    assert_eq!(Some("10".to_string()), output.stdout_string());
+
+    // running the same command again doesn't use the options from before
+    let base = run(&mut Command::new("nice")).stdout_string();
+    let mut command = Command::new("nice");
+    for nice in [10, 5] {
+        let output = Runner::new().nice(nice).run(&mut command).unwrap();
+        assert_eq!(Some(nice.to_string()), output.stdout_string());
+    }
+    assert_eq!(base, run(&mut command).stdout_string());
+
+    #[cfg(target_os = "linux")]
+    {
+        let output = Runner::new()
+            .cpu_affinity(&[0])
+            .run(
+                Command::new("grep")
+                    .arg("Cpus_allowed_list")
+                    .arg("/proc/self/status"),
+            )
+            .unwrap();
+        assert_eq!(
+            Some("0".to_string()),
+            output
+                .stdout_string()
+                .and_then(|line| Some(line.split_whitespace().last()?.to_string()))
+        );
+
+        let result = Runner::new()
+            .cpu_affinity(&[100_000])
+            .run(&mut Command::new("true"));
+        assert!(matches!(result, Err(Error::Spawn(_))));
+    }
+}
+
+/// Tests choosing what the command's stdin is, and not capturing its output
+#[test]
+fn test_stdio_modes() {
+    use crate::{StdinMode, StdoutMode};
+
+    let output = Runner::new()
+        .stdin(StdinMode::piped("b\na\n"))
+        .run(&mut Command::new("sort"))
+        .unwrap();
+    assert_eq!(Some("a\nb".to_string()), output.stdout_string());
+
+    let output = Runner::new()
+        .stdin(StdinMode::Null)
+        .run(&mut Command::new("cat"))
+        .unwrap();
+    assert_eq!(Some(0), output.lines().map(|lines| lines.len()));
+
+    #[allow(unused_mut)]
+    let mut backends = vec![Backend::Threads];
+    #[cfg(all(unix, feature = "poll"))]
+    backends.push(Backend::Poll);
+    for backend in backends {
+        let output = Runner::new()
+            .backend(backend)
+            .stdout(StdoutMode::Null)
+            .run(
+                Command::new("bash")
+                    .arg("-c")
+                    .arg("echo out; echo err >&2; exit 2"),
+            )
+            .unwrap();
+        assert_eq!(Some(String::new()), output.clone().stdout_string());
+        assert_eq!(Some("err".to_string()), output.clone().stderr_string());
+        assert_eq!(Some(2), output.status_code());
+
+        let output = Runner::new()
+            .backend(backend)
+            .stdout(StdoutMode::Null)
+            .stderr(StdoutMode::Null)
+            .run(Command::new("echo").arg("hi"))
+            .unwrap();
+        assert_eq!(Some(0), output.clone().lines().map(|lines| lines.len()));
+        assert_eq!(Some(0), output.status_code());
+    }
+}
+
+/// Tests capturing lines from an extra file descriptor
+#[cfg(unix)]
+#[test]
+fn test_capture_fd() {
+    #[allow(unused_mut)]
+    let mut backends = vec![Backend::Threads];
+    #[cfg(feature = "poll")]
+    backends.push(Backend::Poll);
+    for backend in backends {
+        let output = Runner::new()
+            .backend(backend)
+            .capture_fd(3)
+            .capture_fd(5)
+            .run(
+                Command::new("bash")
+                    .arg("-c")
+                    .arg("echo out; sleep 0.1; echo status >&3; sleep 0.1; echo other >&5"),
+            )
+            .unwrap();
+        assert_eq!(
+            Some("[stdout] out\n[fd 3] status\n[fd 5] other".to_string()),
+            output.merged_string()
+        );
+        assert_eq!("status", output.fd_lines(3)[0].content);
+        assert!(output.contains_in(LineType::Fd(5), "other"));
+        assert_eq!(Some("out".to_string()), output.stdout_string());
+        assert_eq!(3, output.len());
+    }
+
+    // the pipes are new for each attempt
+    let output = Runner::new()
+        .capture_fd(3)
+        .retries(2)
+        .run(Command::new("bash").arg("-c").arg("echo try >&3; exit 1"))
+        .unwrap();
+    assert_eq!(1, output.fd_lines(3).len());
+    assert_eq!(2, output.failed_attempts().len());
+}
+
+/// Tests merging stderr into stdout, and that lines are timestamped when they're read
+#[test]
+fn test_merge_stderr() {
+    #[allow(unused_mut)]
+    let mut backends = vec![Backend::Threads];
+    #[cfg(all(unix, feature = "poll"))]
+    backends.push(Backend::Poll);
+    for backend in backends {
+        // without a sleep between them, these are usually out of order when read separately
+        let output = Runner::new()
+            .backend(backend)
+            .merge_stderr(true)
+            .run(
+                Command::new("bash")
+                    .arg("-c")
+                    .arg("for i in 1 2 3 4 5; do echo $i; echo $i >&2; done"),
+            )
+            .unwrap();
+        assert_eq!(
+            Some("1\n1\n2\n2\n3\n3\n4\n4\n5\n5".to_string()),
+            output.stdout_string()
+        );
+        assert_eq!(Some(String::new()), output.stderr_string());
+    }
+
+    // the command isn't left with its output thrown away
+    let mut command = Command::new("bash");
+    command.arg("-c").arg("echo a; echo b >&2");
+    Runner::new().merge_stderr(true).run(&mut command).unwrap();
+    let output = command.output().unwrap();
+    assert_eq!(b"a\n", output.stdout.as_slice());
+    assert_eq!(b"b\n", output.stderr.as_slice());
+
+    // slow callbacks don't push back the timestamps of lines read at the same time
+    let output = Runner::new()
+        .on_line(|_| thread::sleep(Duration::from_millis(50)))
+        .run(Command::new("printf").arg("a\\nb\\nc\\n"))
+        .unwrap();
+    let lines = output.lines().unwrap();
+    assert!(lines[2].time - lines[0].time < Duration::from_millis(50));
+}
+
+/// Tests killing commands at an absolute deadline
+#[test]
+fn test_deadline() {
+    let deadline = Instant::now() + Duration::from_millis(300);
+    let output = Runner::new()
+        .deadline(deadline)
+        .timeout(Duration::from_secs(10))
+        .run(Command::new("sleep").arg("5"))
+        .unwrap();
+    assert_eq!(Some(KillReason::Timeout), output.kill_reason());
+    assert!(output.end_time() < deadline + Duration::from_secs(1));
+
+    // the earlier of the two is used
+    let output = Runner::new()
+        .deadline(Instant::now() + Duration::from_secs(10))
+        .timeout(Duration::from_millis(100))
+        .run(Command::new("sleep").arg("5"))
+        .unwrap();
+    assert_eq!(Some(KillReason::Timeout), output.kill_reason());
+
+    // no more retries once it's passed
+    let output = Runner::new()
+        .deadline(Instant::now() + Duration::from_millis(100))
+        .retries(5)
+        .run(Command::new("sleep").arg("5"))
+        .unwrap();
+    assert_eq!(0, output.failed_attempts().len());
+
+    // or while waiting between them
+    let start = Instant::now();
+    let output = Runner::new()
+        .deadline(start + Duration::from_millis(300))
+        .retries(5)
+        .backoff(Backoff::fixed(Duration::from_secs(10)))
+        .run(&mut Command::new("false"))
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert!(!output.success());
+    assert_eq!(0, output.failed_attempts().len());
+}
+
+/// Tests getting regular updates while a command is running
+#[test]
+fn test_heartbeat() {
+    use crate::Heartbeat;
+
+    let heartbeats: Arc<Mutex<Vec<Heartbeat>>> = Arc::default();
+    let recorded = heartbeats.clone();
+    let output = Runner::new()
+        .heartbeat(Duration::from_millis(50), move |heartbeat| {
+            recorded.lock().unwrap().push(heartbeat)
+        })
+        .run(Command::new("bash").arg("-c").arg("echo hi; sleep 0.5"))
+        .unwrap();
+    assert!(output.success());
+
+    let count = heartbeats.lock().unwrap().len();
+    thread::sleep(Duration::from_millis(150));
+    let heartbeats = heartbeats.lock().unwrap();
+    // none after it's finished
+    assert_eq!(count, heartbeats.len());
+    assert!(heartbeats.len() >= 5, "{:?}", heartbeats);
+    let last = heartbeats.last().unwrap();
+    assert_eq!(1, last.lines);
+    assert_eq!(3, last.bytes);
+    assert!(last.since_output >= Duration::from_millis(300));
+    assert!(last.elapsed > last.since_output);
+}
+
+/// Tests restarting commands with a supervisor
+#[test]
+fn test_supervisor() {
+    use crate::{Backoff, Restart, Supervisor, SupervisorEvent};
+
+    let events: Arc<Mutex<Vec<String>>> = Arc::default();
+    let recorded = events.clone();
+    let mut command = Command::new("bash");
+    command.arg("-c").arg("echo hi; exit 3");
+    let mut supervisor = Supervisor::new(command)
+        .max_restarts(2)
+        .backoff(Backoff::fixed(Duration::from_millis(10)))
+        .on_event(move |event| {
+            let event = match event {
+                SupervisorEvent::Started { run, .. } => format!("started {}", run),
+                SupervisorEvent::Line { run, line } => format!("{} {}", run, line.content),
+                SupervisorEvent::Exited { run, output } => {
+                    format!("exited {} {:?}", run, output.clone().status_code())
+                }
+                SupervisorEvent::Restarting { run, .. } => format!("restarting {}", run),
+            };
+            recorded.lock().unwrap().push(event);
+        });
+    supervisor.start().unwrap();
+    let output = supervisor.wait().unwrap().unwrap();
+    assert_eq!(Some(3), output.status_code());
+    assert_eq!(2, supervisor.restarts());
+    assert!(!supervisor.is_running());
+    assert_eq!(None, supervisor.pid());
+    let events = events.lock().unwrap().clone();
+    let expected: Vec<String> = (0..3)
+        .flat_map(|run| {
+            let mut events = vec![
+                format!("started {}", run),
+                format!("{} hi", run),
+                format!("exited {} Some(3)", run),
+            ];
+            if run < 2 {
+                events.push(format!("restarting {}", run + 1));
+            }
+            events
+        })
+        .collect();
+    assert_eq!(expected, events);
+
+    // succeeding doesn't restart it, unless it's told to
+    let mut supervisor = Supervisor::new(Command::new("true"));
+    supervisor.start().unwrap();
+    assert!(supervisor.wait().unwrap().unwrap().success());
+    assert_eq!(0, supervisor.restarts());
+
+    // stopping it
+    let mut command = Command::new("sleep");
+    command.arg("5");
+    let mut supervisor = Supervisor::new(command).restart(Restart::Always);
+    supervisor.start().unwrap();
+    assert!(supervisor.is_running());
+    assert!(supervisor.pid().is_some());
+    let start = Instant::now();
+    let output = supervisor.stop().unwrap().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert!(!output.success());
+    assert!(!supervisor.is_running());
+    assert_eq!(0, supervisor.restarts());
+
+    // it can be started again
+    supervisor.start().unwrap();
+    assert!(supervisor.is_running());
+    supervisor.stop().unwrap();
+}
+
+/// Tests running commands on a schedule
+#[test]
+fn test_schedule() {
+    use crate::{Overlap, Schedule};
+
+    let run = |overlap: Overlap, script: &str| {
+        let outputs: Arc<Mutex<Vec<CmdOutput>>> = Arc::default();
+        let recorded = outputs.clone();
+        let mut command = Command::new("bash");
+        command.arg("-c").arg(script);
+        let schedule = Schedule::every(Duration::from_millis(100))
+            .overlap(overlap)
+            .run(command, move |output| {
+                recorded.lock().unwrap().push(output.unwrap())
+            });
+        thread::sleep(Duration::from_millis(450));
+        let runs = schedule.runs();
+        schedule.stop();
+        let outputs = outputs.lock().unwrap().clone();
+        assert_eq!(runs as usize, outputs.len());
+        return outputs;
+    };
+
+    // runs at 0ms and 300ms, skipping the rest
+    let outputs = run(Overlap::Skip, "sleep 0.25");
+    assert_eq!(2, outputs.len());
+    assert!(outputs[0].clone().success());
+    // the last one is cancelled when it's stopped
+    assert!(!outputs[1].clone().success());
+
+    // queued runs start as soon as the last one finishes
+    let outputs = run(Overlap::Queue, "sleep 0.15");
+    assert!(outputs.len() >= 3);
+    for pair in outputs.windows(2) {
+        let gap = pair[1].clone().start_time() - pair[0].clone().end_time();
+        assert!(gap < Duration::from_millis(50), "{:?}", gap);
+    }
+
+    // every run is killed by the next one
+    let outputs = run(Overlap::KillPrevious, "sleep 5");
+    assert!(outputs.len() >= 4);
+    assert!(outputs.iter().all(|output| !output.clone().success()));
+
+    // errors go to the handler too
+    let errors: Arc<Mutex<Vec<Error>>> = Arc::default();
+    let recorded = errors.clone();
+    let schedule = Schedule::every(Duration::from_millis(100))
+        .run(Command::new("this-command-does-not-exist"), move |output| {
+            recorded.lock().unwrap().push(output.unwrap_err())
+        });
+    thread::sleep(Duration::from_millis(150));
+    drop(schedule);
+    assert_eq!(2, errors.lock().unwrap().len());
+}
+
+/// Tests re-running commands when files change
+#[cfg(feature = "notify")]
+#[test]
+fn test_watch() {
+    use crate::{Watch, WatchError};
+
+    let dir =
+        std::env::temp_dir().join(format!("better-commands-test_watch-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+
+    let outputs: Arc<Mutex<Vec<CmdOutput>>> = Arc::default();
+    let recorded = outputs.clone();
+    let mut command = Command::new("echo");
+    command.arg("ran");
+    let watch = Watch::new()
+        .path(&dir)
+        .glob("*.txt")
+        .debounce(Duration::from_millis(100))
+        .run(command, move |output| {
+            recorded.lock().unwrap().push(output.unwrap())
+        })
+        .unwrap();
+    // it runs straight away
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(1, watch.runs());
+
+    // not matching the glob
+    std::fs::write(dir.join("ignored.log"), "hi").unwrap();
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(1, watch.runs());
+
+    // lots of changes at once only re-run it once, including ones in subdirectories
+    for i in 0..5 {
+        std::fs::write(dir.join("sub").join(format!("{}.txt", i)), "hi").unwrap();
+    }
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(2, watch.runs());
+    watch.stop();
+    let outputs = outputs.lock().unwrap();
+    assert_eq!(2, outputs.len());
+    assert!(outputs.iter().all(|output| output.clone().success()));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(matches!(
+        Watch::new()
+            .path(".")
+            .glob("[")
+            .run(Command::new("true"), |_| {}),
+        Err(WatchError::Glob(_))
+    ));
+    assert!(matches!(
+        Watch::new()
+            .path("/this/path/does/not/exist")
+            .run(Command::new("true"), |_| {}),
+        Err(WatchError::Notify(_))
+    ));
+}
+
+/// Tests running commands with dependencies between them
+#[test]
+fn test_task_graph() {
+    use crate::TaskGraph;
+
+    let sleep = || {
+        let mut command = Command::new("sleep");
+        command.arg("0.3");
+        return command;
+    };
+    let start = Instant::now();
+    let output = TaskGraph::new()
+        .task("a", sleep(), &[])
+        .task("b", sleep(), &[])
+        .task("c", Command::new("true"), &["a", "b"])
+        .parallelism(2)
+        .run()
+        .unwrap();
+    assert!(output.success());
+    // `a` and `b` ran at the same time
+    assert!(start.elapsed() < Duration::from_millis(550));
+    let c = output.get("c").unwrap().clone().start_time();
+    assert!(c >= output.get("a").unwrap().clone().end_time());
+    assert!(c >= output.get("b").unwrap().clone().end_time());
+
+    // one at a time by default
+    let start = Instant::now();
+    let output = TaskGraph::new()
+        .task("a", sleep(), &[])
+        .task("b", sleep(), &[])
+        .run()
+        .unwrap();
+    assert!(output.success());
+    assert!(start.elapsed() >= Duration::from_millis(600));
+
+    // failures only skip what depends on them
+    let output = TaskGraph::new()
+        .task("fails", Command::new("false"), &[])
+        .task("independent", Command::new("true"), &[])
+        .task("dependent", Command::new("true"), &["fails"])
+        .task(
+            "indirect",
+            Command::new("true"),
+            &["dependent", "independent"],
+        )
+        .run()
+        .unwrap();
+    assert_eq!(vec!["fails"], output.failed());
+    assert_eq!(vec!["dependent", "indirect"], output.skipped());
+    assert!(output.get("independent").is_some());
+    assert_eq!(4, output.tasks().len());
+
+    assert!(matches!(
+        TaskGraph::new()
+            .task("missing", Command::new("this-command-does-not-exist"), &[])
+            .run(),
+        Err(Error::Spawn(_))
+    ));
+    let unknown = std::panic::catch_unwind(|| {
+        TaskGraph::new().task("a", Command::new("true"), &["b"]);
+    });
+    assert!(unknown.is_err());
+}
+
+/// Tests reusing outputs from a cache
+#[cfg(feature = "serde")]
+#[test]
+fn test_cache() {
+    use crate::CacheDir;
+
+    let dir =
+        std::env::temp_dir().join(format!("better-commands-test_cache-{}", std::process::id()));
+    let cache = CacheDir::new(dir.join("cache"));
+    let input = dir.join("input.txt");
+    let runner = Runner::new().cache(cache.clone()).cache_key_files([&input]);
+    let counter = dir.join("counter");
+    std::fs::create_dir_all(&dir).unwrap();
+    let command = |arg: &str| {
+        let mut command = Command::new("bash");
+        command
+            .arg("-c")
+            .arg(format!("echo x >> {}; echo {}", counter.display(), arg));
+        return command;
+    };
+    let runs = || std::fs::read_to_string(&counter).unwrap().lines().count();
+
+    let first = runner.run(&mut command("a")).unwrap();
+    assert!(!first.cached());
+    let second = runner.run(&mut command("a")).unwrap();
+    assert!(second.cached());
+    assert_eq!(first.clone().stdout_string(), second.stdout_string());
+    assert_eq!(1, runs());
+
+    // different arguments, environment, and inputs all count
+    assert!(!runner.run(&mut command("b")).unwrap().cached());
+    assert!(!runner.run(command("a").env("FOO", "1")).unwrap().cached());
+    assert!(runner.run(command("a").env("FOO", "1")).unwrap().cached());
+    std::fs::write(&input, "created").unwrap();
+    assert!(!runner.run(&mut command("a")).unwrap().cached());
+    std::fs::write(&input, "changed").unwrap();
+    assert!(!runner.run(&mut command("a")).unwrap().cached());
+    assert!(runner.run(&mut command("a")).unwrap().cached());
+    assert_eq!(5, runs());
+
+    // failures aren't stored
+    let mut failing = Command::new("false");
+    assert!(!runner.run(&mut failing).unwrap().cached());
+    assert!(!runner.run(&mut failing).unwrap().cached());
+
+    // arguments aren't just joined together
+    let mut spaced = Command::new("echo");
+    spaced.arg("a b");
+    let mut split = Command::new("echo");
+    split.args(["a", "b"]);
+    assert_ne!(
+        cache.key(&spaced, &[]).unwrap(),
+        cache.key(&split, &[]).unwrap()
+    );
+
+    cache.clear().unwrap();
+    assert!(!runner.run(&mut command("a")).unwrap().cached());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Tests running commands over SSH, with a fake `ssh` which runs them locally
+#[cfg(all(feature = "ssh", unix))]
+#[test]
+fn test_ssh() {
+    use crate::{CommandRunner, SshOptions, SshRunner, StdinMode};
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("better-commands-test_ssh-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("log");
+    let fake_ssh = dir.join("ssh");
+    std::fs::write(
+        &fake_ssh,
+        format!(
+            r#"#!/bin/sh
+echo "$@" >> {}
+# connecting and disconnecting don't run anything
+for arg; do case "$arg" in -N|-O) exit 0;; esac; done
+for arg; do last="$arg"; done
+exec sh -c "$last"
+"#,
+            log.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&fake_ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let ssh = SshRunner::connect_with("user@host", SshOptions::new().program(&fake_ssh).port(2222))
+        .unwrap();
+    assert_eq!("user@host", ssh.destination());
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(r#"echo "$FOO" "$PWD"; sleep 0.1; echo "it's" >&2; exit 3"#)
+        .env("FOO", "a 'b'")
+        .current_dir("/");
+    let output = CommandRunner::run(&ssh, &mut command).unwrap();
+    assert_eq!(Some(3), output.clone().status_code());
+    assert_eq!(command_line(&command), output.command());
+    let lines = output.lines().unwrap();
+    assert_eq!("a 'b' /", lines[0].content);
+    assert_eq!("it's", lines[1].content);
+    assert_eq!(LineType::Stderr, lines[1].printed_to);
+
+    let ssh = ssh.runner(Runner::new().stdin(StdinMode::piped("hello")));
+    let output = ssh.run(&mut Command::new("cat")).unwrap();
+    assert_eq!(Some("hello".to_string()), output.stdout_string());
+    drop(ssh);
+
+    let log = std::fs::read_to_string(&log).unwrap();
+    let invocations: Vec<&str> = log.lines().collect();
+    assert_eq!(4, invocations.len());
+    // every command shares the connection
+    let control_path = invocations[0].split(' ').nth(1).unwrap();
+    assert!(control_path.starts_with("ControlPath="));
+    assert!(invocations
+        .iter()
+        .all(|line| line.contains(control_path) && line.contains("-p 2222")));
+    assert!(invocations[0].contains("-N"));
+    assert!(invocations[3].contains("-O exit"));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(matches!(
+        SshRunner::connect_with("user@host", SshOptions::new().program("false")),
+        Err(Error::Spawn(_))
+    ));
+}
+
+/// Tests running commands in containers, with a fake `docker` which runs them locally
+#[cfg(unix)]
+#[test]
+fn test_container() {
+    use crate::{CommandRunner, ContainerRunner, StdinMode};
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!(
+        "better-commands-test_container-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    let fake_docker = dir.join("docker");
+    std::fs::write(
+        &fake_docker,
+        r#"#!/bin/sh
+shift
+while [ "$1" != "--" ]; do
+    case "$1" in
+        --workdir) cd "$2"; shift 2;;
+        --env) export "$2"; shift 2;;
+        *) shift;;
+    esac
+done
+shift
+if [ "$1" != "box" ]; then echo "Error: No such container: $1" >&2; exit 125; fi
+shift
+exec "$@"
+"#,
+    )
+    .unwrap();
+    std::fs::set_permissions(&fake_docker, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let container = ContainerRunner::with_program(&fake_docker, "box").user("root");
+    assert_eq!("box", container.container());
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(r#"echo "$FOO" "$PWD"; echo oops >&2; exit 3"#)
+        .env("FOO", "a b")
+        .current_dir("/");
+    let output = CommandRunner::run(&container, &mut command).unwrap();
+    assert_eq!(Some(3), output.clone().status_code());
+    assert_eq!(command_line(&command), output.command());
+    let lines = output.lines().unwrap();
+    assert_eq!("a b /", lines[0].content);
+    assert_eq!(LineType::Stdout, lines[0].printed_to);
+    assert_eq!("oops", lines[1].content);
+    assert_eq!(LineType::Stderr, lines[1].printed_to);
+
+    let container = container.runner(Runner::new().stdin(StdinMode::piped("hello")));
+    let output = container.run(&mut Command::new("cat")).unwrap();
+    assert_eq!(Some("hello".to_string()), output.stdout_string());
+
+    let missing = ContainerRunner::with_program(&fake_docker, "missing");
+    match missing.run(&mut Command::new("true")) {
+        Err(Error::Spawn(e)) => assert!(e.to_string().contains("No such container: missing")),
+        other => panic!("{:?}", other),
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Tests running commands in another root directory
+#[cfg(target_os = "linux")]
+#[test]
+fn test_chroot() {
+    let dir = std::env::temp_dir().join(format!(
+        "better-commands-test_chroot-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(matches!(
+        Runner::new().chroot(&dir).run(&mut Command::new("true")),
+        Err(Error::Spawn(e)) if e.to_string().contains("doesn't exist")
+    ));
+    if unsafe { libc::getuid() } != 0 {
+        assert!(matches!(
+            Runner::new().chroot("/").run(&mut Command::new("true")),
+            Err(Error::Spawn(e)) if e.to_string().contains("CAP_SYS_CHROOT")
+        ));
+        return;
+    }
+
+    // a tiny root with just `sh` and what it needs to run
+    std::fs::create_dir_all(dir.join("bin")).unwrap();
+    std::fs::create_dir_all(dir.join("work")).unwrap();
+    std::fs::copy("/bin/sh", dir.join("bin/sh")).unwrap();
+    let libraries = Command::new("ldd").arg("/bin/sh").output().unwrap();
+    for library in String::from_utf8(libraries.stdout)
+        .unwrap()
+        .split_whitespace()
+    {
+        if library.starts_with('/') {
+            let inside = dir.join(library.trim_start_matches('/'));
+            std::fs::create_dir_all(inside.parent().unwrap()).unwrap();
+            std::fs::copy(library, inside).unwrap();
+        }
+    }
+    std::fs::write(dir.join("marker"), "inside").unwrap();
+
+    let runner = Runner::new().chroot(&dir);
+    let output = runner
+        .run(
+            Command::new("/bin/sh")
+                .arg("-c")
+                .arg("read marker < /marker; echo $marker; cd -P . && echo $PWD"),
+        )
+        .unwrap();
+    assert_eq!(Some("inside\n/".to_string()), output.stdout_string());
+    let output = runner
+        .run(
+            Command::new("/bin/sh")
+                .arg("-c")
+                .arg("cd -P . && echo $PWD")
+                .current_dir(dir.join("work")),
+        )
+        .unwrap();
+    assert_eq!(Some("/work".to_string()), output.stdout_string());
+    assert!(matches!(
+        runner.run(&mut Command::new("/bin/bash")),
+        Err(Error::Spawn(e)) if e.kind() == std::io::ErrorKind::NotFound
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,59 @@
+//! [`tracing`] support: a span for each command, and an event for each line
+use crate::capture::Capture;
+use crate::{CmdOutput, Line, LineType};
+use tracing::{Dispatch, Span};
+
+/// The span for one run of a command, along with the subscriber it was started under
+///
+/// Lines are captured on other threads, which won't have the same default subscriber, so it's kept here to send the
+/// events to.
+#[derive(Debug, Clone)]
+pub(crate) struct CommandSpan {
+    span: Span,
+    dispatch: Dispatch,
+}
+
+impl CommandSpan {
+    fn new(command_line: &str, pid: u32) -> CommandSpan {
+        let span = tracing::info_span!(
+            "command",
+            command = command_line,
+            pid,
+            status_code = tracing::field::Empty,
+        );
+        return CommandSpan {
+            span,
+            dispatch: tracing::dispatcher::get_default(Dispatch::clone),
+        };
+    }
+
+    /// Logs a line, at `DEBUG` for stdout and `WARN` for stderr
+    pub(crate) fn line(&self, line: &Line) {
+        tracing::dispatcher::with_default(&self.dispatch, || {
+            let _entered = self.span.enter();
+            match &line.printed_to {
+                LineType::Stdout => tracing::debug!(stream = "stdout", "{}", line.content),
+                LineType::Stderr => tracing::warn!(stream = "stderr", "{}", line.content),
+                LineType::Fd(fd) => tracing::debug!(stream = "fd", fd = *fd, "{}", line.content),
+                LineType::System => tracing::info!(stream = "system", "{}", line.content),
+                LineType::Custom(source) => {
+                    tracing::debug!(stream = source.as_str(), "{}", line.content)
+                }
+            }
+        });
+    }
+
+    pub(crate) fn finished(&self, output: &CmdOutput) {
+        if let Some(code) = output.status_code {
+            self.span.record("status_code", code);
+        }
+    }
+}
+
+impl Capture {
+    /// Returns these options with a new span for a command that was just started
+    pub(crate) fn traced(mut self, command_line: &str, pid: u32) -> Capture {
+        self.span = Some(CommandSpan::new(command_line, pid));
+        return self;
+    }
+}
@@ -0,0 +1,307 @@
+//! Re-running commands when files change
+use crate::schedule::{Runs, POLL_INTERVAL};
+use crate::{Backend, CmdOutput, Error, Runner};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Runs a command whenever files change, like `cargo watch`
+///
+/// The command is run straight away, then again whenever something changes in the watched directories (recursively),
+/// once they've stopped changing for the debounce period. Only one run happens at a time: by default, changes during a
+/// run start another one once it finishes; use [`Watch::restart`] to cancel it and start again straight away instead.
+///
+/// ```no_run
+/// use better_commands::Watch;
+/// use std::process::Command;
+///
+/// let mut command = Command::new("cargo");
+/// command.arg("test");
+/// let watch = Watch::new()
+///     .path("src")
+///     .glob("*.rs")
+///     .restart(true)
+///     .run(command, |output| {
+///         let output = output.unwrap();
+///         println!("tests finished: {:?}", output.status_code());
+///     })
+///     .unwrap();
+///
+/// // ...
+/// watch.stop();
+/// ```
+///
+/// <small>Commands are always run with [`Backend::Threads`], since the watcher needs to check on them while they're
+/// running.</small>
+#[derive(Debug, Clone)]
+pub struct Watch {
+    paths: Vec<PathBuf>,
+    globs: Vec<String>,
+    debounce: Duration,
+    restart: bool,
+    runner: Runner,
+}
+
+impl Default for Watch {
+    fn default() -> Self {
+        return Watch::new();
+    }
+}
+
+impl Watch {
+    /// Creates a watch with no paths; add some with [`Watch::path`]
+    pub fn new() -> Self {
+        return Watch {
+            paths: Vec::new(),
+            globs: Vec::new(),
+            debounce: Duration::from_millis(200),
+            restart: false,
+            runner: Runner::new().backend(Backend::Threads).retries(0),
+        };
+    }
+
+    /// Watches `path`, and everything in it if it's a directory
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.paths.push(path.as_ref().to_path_buf());
+        return self;
+    }
+
+    /// Only re-runs the command when a path matching `pattern` changes
+    ///
+    /// Patterns are matched against the changed path relative to the watched path it's in, and `*` matches across
+    /// directories, so `*.rs` matches `src/main.rs` too. Without any, every change counts.
+    pub fn glob<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.globs.push(pattern.into());
+        return self;
+    }
+
+    /// Sets how long files have to stop changing for before the command is re-run (200ms by default)
+    ///
+    /// This means saving lots of files at once (or a command writing lots of files) only re-runs it once.
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        return self;
+    }
+
+    /// Sets whether a run that's still going when files change is cancelled (see
+    /// [`RunningCommand::cancel`](crate::RunningCommand::cancel)) so the command can start again straight away
+    /// (`false` by default)
+    pub fn restart(mut self, restart: bool) -> Self {
+        self.restart = restart;
+        return self;
+    }
+
+    /// Sets the options to run the command with, e.g. a timeout for each run
+    ///
+    /// <small>Its retries aren't used</small>
+    pub fn runner(mut self, runner: Runner) -> Self {
+        self.runner = runner.backend(Backend::Threads).retries(0);
+        return self;
+    }
+
+    /// Starts watching, and running `command` in the background, calling `handler` with the output of each run
+    ///
+    /// `handler` gets an error if the command couldn't be started, and is called from the thread running the command,
+    /// so it shouldn't take too long. This keeps going until [`WatchHandle::stop`] is called, or the handle is
+    /// dropped.
+    pub fn run(
+        self,
+        command: Command,
+        handler: impl FnMut(Result<CmdOutput, Error>) + Send + 'static,
+    ) -> Result<WatchHandle, WatchError> {
+        let mut globs = GlobSetBuilder::new();
+        for pattern in &self.globs {
+            globs.add(Glob::new(pattern).map_err(WatchError::Glob)?);
+        }
+        let globs = globs.build().map_err(WatchError::Glob)?;
+        // the paths in events are absolute, so these have to be too for the globs to work
+        let roots = self
+            .paths
+            .iter()
+            .map(|path| path.canonicalize())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| WatchError::Notify(notify::Error::io(e)))?;
+
+        let (sender, receiver) = mpsc::channel();
+        let changes = sender.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                // errors just mean a change might be missed
+                if let Ok(event) = event {
+                    if !event.kind.is_access() {
+                        let _ = changes.send(Message::Changed(event.paths));
+                    }
+                }
+            })
+            .map_err(WatchError::Notify)?;
+        for root in &roots {
+            watcher
+                .watch(root, RecursiveMode::Recursive)
+                .map_err(WatchError::Notify)?;
+        }
+
+        let runs = Runs::new(command, self.runner.clone(), Box::new(handler));
+        let count = runs.count.clone();
+        let mut watching = Watching {
+            watch: self,
+            roots,
+            globs,
+            runs,
+        };
+        let thread = thread::spawn(move || watching.run(&receiver));
+        return Ok(WatchHandle {
+            watcher: Some(watcher),
+            stop: sender,
+            thread: Some(thread),
+            runs: count,
+        });
+    }
+}
+
+/// An error from starting a [`Watch`]
+#[derive(Debug)]
+pub enum WatchError {
+    /// One of the globs isn't valid
+    Glob(globset::Error),
+    /// One of the paths couldn't be watched
+    Notify(notify::Error),
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchError::Glob(e) => write!(f, "invalid glob: {}", e),
+            WatchError::Notify(e) => write!(f, "failed to watch for changes: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WatchError::Glob(e) => Some(e),
+            WatchError::Notify(e) => Some(e),
+        }
+    }
+}
+
+/// A running [`Watch`], which stops when this is dropped
+#[derive(Debug)]
+pub struct WatchHandle {
+    watcher: Option<RecommendedWatcher>,
+    stop: Sender<Message>,
+    thread: Option<JoinHandle<()>>,
+    runs: Arc<AtomicU32>,
+}
+
+impl WatchHandle {
+    /// Stops watching, cancelling the current run if there is one
+    ///
+    /// This waits for the current run to finish being cancelled, and for the handler to get its output.
+    pub fn stop(mut self) {
+        if let Some(thread) = self.finish() {
+            if let Err(panic) = thread.join() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+    }
+
+    /// Returns how many times the command has been run (or tried to be) so far
+    pub fn runs(&self) -> u32 {
+        return self.runs.load(Ordering::SeqCst);
+    }
+
+    /// Stops watching and tells the thread running the command to stop, returning it to wait for
+    fn finish(&mut self) -> Option<JoinHandle<()>> {
+        self.watcher.take();
+        let _ = self.stop.send(Message::Stop);
+        return self.thread.take();
+    }
+}
+
+impl Drop for WatchHandle {
+    /// Stops watching, like [`WatchHandle::stop`]
+    fn drop(&mut self) {
+        if let Some(thread) = self.finish() {
+            let _ = thread.join();
+        }
+    }
+}
+
+enum Message {
+    Changed(Vec<PathBuf>),
+    Stop,
+}
+
+/// Everything the thread running the command needs
+struct Watching {
+    watch: Watch,
+    roots: Vec<PathBuf>,
+    globs: GlobSet,
+    runs: Runs,
+}
+
+impl Watching {
+    /// Keeps running the command when things change, until it gets [`Message::Stop`]
+    fn run(&mut self, messages: &Receiver<Message>) {
+        self.runs.start();
+        // when the last relevant change was, until the command is re-run for it
+        let mut changed: Option<Instant> = None;
+        loop {
+            let rerun_at = changed.map(|changed| changed + self.watch.debounce);
+            let timeout = match (rerun_at, self.runs.is_running()) {
+                (Some(rerun_at), false) => Some(rerun_at.saturating_duration_since(Instant::now())),
+                (Some(_), true) | (None, true) => Some(POLL_INTERVAL),
+                (None, false) => None,
+            };
+            let message = match timeout {
+                Some(timeout) => messages.recv_timeout(timeout),
+                None => messages.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match message {
+                Ok(Message::Changed(paths)) => {
+                    if paths.iter().any(|path| self.matches(path)) {
+                        changed = Some(Instant::now());
+                    }
+                }
+                Ok(Message::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                    self.runs.finish(true);
+                    return;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+
+            self.runs.finish_if_done();
+            let due =
+                changed.is_some_and(|changed| Instant::now() >= changed + self.watch.debounce);
+            if due && (!self.runs.is_running() || self.watch.restart) {
+                self.runs.finish(true);
+                changed = None;
+                self.runs.start();
+            }
+        }
+    }
+
+    /// Whether a change to `path` should re-run the command
+    fn matches(&self, path: &Path) -> bool {
+        if self.globs.is_empty() {
+            return true;
+        }
+        return self.roots.iter().any(|root| {
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            // watching a single file means there's nothing left of it
+            let relative = match relative.as_os_str().is_empty() {
+                true => path.file_name().map_or(path, Path::new),
+                false => relative,
+            };
+            return self.globs.is_match(relative);
+        });
+    }
+}
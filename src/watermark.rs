@@ -0,0 +1,15 @@
+//! Noticing when a command prints more than expected
+use std::time::Duration;
+
+/// How much a command had printed when it passed a watermark (see [`Runner::on_watermark`](crate::Runner::on_watermark))
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watermark {
+    /// The watermark that was passed, as a multiple of the interval it was registered with
+    pub threshold: u64,
+    /// The total bytes printed so far (across stdout and stderr)
+    pub bytes: u64,
+    /// The total lines printed so far (across stdout and stderr)
+    pub lines: u64,
+    /// How long the command had been running for
+    pub elapsed: Duration,
+}
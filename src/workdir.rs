@@ -0,0 +1,63 @@
+//! Running commands in their own temporary directories
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Used to give each directory made by this process a different name
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A directory a command was run in, which is removed once it's dropped if `cleanup` is set
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct TempDir {
+    path: PathBuf,
+    cleanup: bool,
+}
+
+impl TempDir {
+    /// Creates a new, empty directory in the system's temporary directory
+    pub(crate) fn create(cleanup: bool) -> io::Result<TempDir> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        loop {
+            let name = format!(
+                "better-commands-{}-{}-{:08x}",
+                process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed),
+                nanos
+            );
+            let path = std::env::temp_dir().join(name);
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(TempDir { path, cleanup }),
+                // left over from an earlier process with the same ID
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Wraps a directory which isn't ours to remove, e.g. one from a deserialized output
+    #[cfg(feature = "serde")]
+    pub(crate) fn existing(path: PathBuf) -> TempDir {
+        return TempDir {
+            path,
+            cleanup: false,
+        };
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        return &self.path;
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if self.cleanup {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Tests for `configure`, in their own binary since it changes the defaults for the whole process, which would get in
+//! the way of every other test running at the same time
+use better_commands::{configure, Defaults, KillReason, Runner};
+use std::process::Command;
+use std::time::Duration;
+
+/// Tests that `configure` changes what `Runner::new` starts with, but not `Runner::default` or a `Supervisor`
+#[test]
+fn test_configure() {
+    configure(Defaults {
+        timeout: Some(Duration::from_millis(100)),
+        retries: 2,
+        ..Defaults::default()
+    });
+    let output = Runner::new().run(Command::new("sleep").arg("5")).unwrap();
+    assert_eq!(Some(KillReason::Timeout), output.kill_reason());
+    let output = Runner::new().run(&mut Command::new("false")).unwrap();
+    assert_eq!(2, output.failed_attempts().len());
+    let output = Runner::default().run(&mut Command::new("false")).unwrap();
+    assert!(output.failed_attempts().is_empty());
+
+    // a chatty command would fill its pipe and get stuck if the supervisor used `Backend::Poll`
+    #[cfg(all(unix, feature = "poll"))]
+    {
+        use better_commands::{Backend, Supervisor};
+
+        configure(Defaults {
+            retries: 2,
+            backend: Backend::Poll,
+            ..Defaults::default()
+        });
+        let mut command = Command::new("bash");
+        command.arg("-c").arg("seq 100000; exit 1");
+        let mut supervisor = Supervisor::new(command).max_restarts(0);
+        supervisor.start().unwrap();
+        let output = supervisor.wait().unwrap().unwrap();
+        assert_eq!(100_000, output.len());
+        assert!(output.failed_attempts().is_empty());
+    }
+
+    configure(Defaults::default());
+    let output = Runner::new().run(Command::new("sleep").arg("0.3")).unwrap();
+    assert_eq!(None, output.kill_reason());
+}